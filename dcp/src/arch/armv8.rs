@@ -2,15 +2,16 @@ use std::collections::HashMap;
 
 use capstone::{
     arch::{
-        arm64::{Arm64CC, Arm64Insn, Arm64OpMem, Arm64Operand, Arm64OperandType, Arm64Reg},
+        arm64::{Arm64CC, Arm64Extender, Arm64Insn, Arm64OpMem, Arm64Operand, Arm64OperandType, Arm64Reg, Arm64Shift, Arm64Vas},
         ArchOperand,
     },
     prelude::*,
 };
 
-use crate::{expr, lir, ty, dataflow::Abi};
+use crate::{expr, lir, ty, dataflow::Abi, Image};
 
-const CMP: &'static str = "cmp";
+/// The condition flags, which hold the comparison that would set them as they are
+const NZCV: &'static str = "nzcv";
 
 pub const X: &[&'static str] = &[
     "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
@@ -18,6 +19,13 @@ pub const X: &[&'static str] = &[
     "x28", "fp", "lr", "sp",
 ];
 
+/// The floating point and SIMD registers, named by their 64 bit view
+pub const D: &[&str] = &[
+    "d0", "d1", "d2", "d3", "d4", "d5", "d6", "d7", "d8", "d9", "d10", "d11", "d12", "d13", "d14",
+    "d15", "d16", "d17", "d18", "d19", "d20", "d21", "d22", "d23", "d24", "d25", "d26", "d27",
+    "d28", "d29", "d30", "d31",
+];
+
 pub fn abi() -> Abi {
     Abi {
         callee_saved: {
@@ -25,31 +33,62 @@ pub fn abi() -> Abi {
             regs.push(X[29]);
             regs.push(X[30]);
             regs.push(X[31]);
+            // Only the low 64 bits of these are saved, which is all scalar code uses
+            regs.extend(&D[8..=15]);
             regs
         },
         global: vec![],
         args: (0..=7).map(|x| X[x]).collect(),
+        float_args: (0..=7).map(|x| D[x]).collect(),
+        results: vec![X[0]],
+        float_results: vec![D[0]],
         eliminate: vec![X[29], X[31]],
-        base_reg: Some(X[31])
+        base_reg: Some(X[31]),
+        calls_clobber: true
     }
 }
 
-fn name(reg: RegId) -> expr::Expr {
-    expr::Expr::Name(match reg.0 as u32 {
-        Arm64Reg::ARM64_REG_WZR | Arm64Reg::ARM64_REG_XZR => panic!("Zero reg"),
-
-        Arm64Reg::ARM64_REG_FP => X[29].to_string(),
-        Arm64Reg::ARM64_REG_LR => X[30].to_string(),
-        Arm64Reg::ARM64_REG_SP => X[31].to_string(),
+/// The index into X of a general purpose register, with w and x views of a register being the same
+fn reg_index(reg: RegId) -> Option<usize> {
+    match reg.0 as u32 {
+        Arm64Reg::ARM64_REG_FP => Some(29),
+        Arm64Reg::ARM64_REG_LR => Some(30),
+        Arm64Reg::ARM64_REG_SP => Some(31),
 
-        x @ 216..=244 => X[x as usize - 216].to_string(),
-        x @ 185..=213 => X[x as usize - 185].to_string(),
-        _ => panic!("bad reg {:?}", reg),
-    })
+        x @ 216..=244 => Some(x as usize - 216),
+        x @ 185..=213 => Some(x as usize - 185),
+        _ => None,
+    }
 }
 
-fn reg_size(reg: RegId) -> ty::Size {
+/// The index into D of a floating point or SIMD register, with every view of a register being the same
+fn fp_reg_index(reg: RegId) -> Option<usize> {
     match reg.0 as u32 {
+        x @ Arm64Reg::ARM64_REG_B0..=Arm64Reg::ARM64_REG_B31 => Some((x - Arm64Reg::ARM64_REG_B0) as usize),
+        x @ Arm64Reg::ARM64_REG_D0..=Arm64Reg::ARM64_REG_D31 => Some((x - Arm64Reg::ARM64_REG_D0) as usize),
+        x @ Arm64Reg::ARM64_REG_H0..=Arm64Reg::ARM64_REG_H31 => Some((x - Arm64Reg::ARM64_REG_H0) as usize),
+        x @ Arm64Reg::ARM64_REG_Q0..=Arm64Reg::ARM64_REG_Q31 => Some((x - Arm64Reg::ARM64_REG_Q0) as usize),
+        x @ Arm64Reg::ARM64_REG_S0..=Arm64Reg::ARM64_REG_S31 => Some((x - Arm64Reg::ARM64_REG_S0) as usize),
+        x @ Arm64Reg::ARM64_REG_V0..=Arm64Reg::ARM64_REG_V31 => Some((x - Arm64Reg::ARM64_REG_V0) as usize),
+        _ => None,
+    }
+}
+
+/// Whether reg is the whole 128 bits of a SIMD register
+fn is_vector_reg(reg: RegId) -> bool {
+    matches!(reg.0 as u32, Arm64Reg::ARM64_REG_Q0..=Arm64Reg::ARM64_REG_Q31 | Arm64Reg::ARM64_REG_V0..=Arm64Reg::ARM64_REG_V31)
+}
+
+fn name(reg: RegId) -> Result<expr::Expr, String> {
+    let index = match reg.0 as u32 {
+        Arm64Reg::ARM64_REG_WZR | Arm64Reg::ARM64_REG_XZR => None,
+        _ => fp_reg_index(reg).map(|index| D[index]).or_else(|| reg_index(reg).map(|index| X[index]))
+    };
+    index.map(|name| expr::Expr::Name(name.to_string())).ok_or_else(|| format!("Unsupported register {:?}", reg))
+}
+
+fn reg_size(reg: RegId) -> Result<ty::Size, String> {
+    Ok(match reg.0 as u32 {
         Arm64Reg::ARM64_REG_WZR => ty::Size::Size32,
         Arm64Reg::ARM64_REG_XZR => ty::Size::Size64,
 
@@ -59,13 +98,17 @@ fn reg_size(reg: RegId) -> ty::Size {
 
         216..=244 => ty::Size::Size64,
         185..=213 => ty::Size::Size32,
-        _ => panic!("bad reg {:?}", reg),
-    }
+
+        Arm64Reg::ARM64_REG_B0..=Arm64Reg::ARM64_REG_B31 => ty::Size::Size8,
+        Arm64Reg::ARM64_REG_H0..=Arm64Reg::ARM64_REG_H31 => ty::Size::Size16,
+        Arm64Reg::ARM64_REG_S0..=Arm64Reg::ARM64_REG_S31 => ty::Size::Size32,
+        Arm64Reg::ARM64_REG_D0..=Arm64Reg::ARM64_REG_D31 => ty::Size::Size64,
+        _ => return Err(format!("Unsupported register {:?}", reg)),
+    })
 }
 
 fn cc_to_lir(cc: Arm64CC) -> Option<expr::UnaryOp> {
     match cc {
-        Arm64CC::ARM64_CC_INVALID => None,
         Arm64CC::ARM64_CC_EQ => Some(expr::UnaryOp::CmpEq),
         Arm64CC::ARM64_CC_NE => Some(expr::UnaryOp::CmpNe),
         Arm64CC::ARM64_CC_LT => Some(expr::UnaryOp::CmpLt),
@@ -73,84 +116,342 @@ fn cc_to_lir(cc: Arm64CC) -> Option<expr::UnaryOp> {
         Arm64CC::ARM64_CC_LE => Some(expr::UnaryOp::CmpLe),
         Arm64CC::ARM64_CC_GE => Some(expr::UnaryOp::CmpGe),
         
-        Arm64CC::ARM64_CC_LO => Some(expr::UnaryOp::CmpLt),
-        Arm64CC::ARM64_CC_HI => Some(expr::UnaryOp::CmpGt),
+        Arm64CC::ARM64_CC_LO => Some(expr::UnaryOp::CmpLtU),
+        Arm64CC::ARM64_CC_HI => Some(expr::UnaryOp::CmpGtU),
+        Arm64CC::ARM64_CC_LS => Some(expr::UnaryOp::CmpLeU),
+        Arm64CC::ARM64_CC_HS => Some(expr::UnaryOp::CmpGeU),
+
+        Arm64CC::ARM64_CC_MI => Some(expr::UnaryOp::CmpMi),
+        Arm64CC::ARM64_CC_PL => Some(expr::UnaryOp::CmpPl),
+
+        _ => None
+    }
+}
 
-        _ => todo!()
+/// The test of the flags for cc, or None if it always holds
+fn cond(cc: Arm64CC) -> Option<expr::Expr> {
+    let flags = || Box::new(expr::Expr::Name(NZCV.to_string()));
+    match cc {
+        // Overflow is when the true comparison and the sign of the difference disagree
+        Arm64CC::ARM64_CC_VS | Arm64CC::ARM64_CC_VC => Some(expr::Expr::Binary {
+            op: if cc == Arm64CC::ARM64_CC_VS { expr::BinaryOp::Ne } else { expr::BinaryOp::Eq },
+            lhs: Box::new(expr::Expr::Unary { op: expr::UnaryOp::CmpLt, expr: flags() }),
+            rhs: Box::new(expr::Expr::Unary { op: expr::UnaryOp::CmpMi, expr: flags() }),
+        }),
+        _ => cc_to_lir(cc).map(|op| expr::Expr::Unary { op, expr: flags() })
     }
 }
 
-fn op_to_mem_addr(op: &ArchOperand) -> expr::Expr {
-    let op = match op {
-        ArchOperand::Arm64Operand(op) => op,
-        _ => panic!("not arm64?")
+/// The comparison of constants setting the flags closest to those given by an immediate,
+/// since a comparison can't have Z set without C
+fn flags_value(nzcv: i64) -> expr::Expr {
+    let (n, z, c, v) = (nzcv & 8 != 0, nzcv & 4 != 0, nzcv & 2 != 0, nzcv & 1 != 0);
+    let (lhs, rhs) = match (z, n != v, c) {
+        (true, _, _) => (0, 0),
+        (false, true, true) => (-1, 0),
+        (false, true, false) => (0, 1),
+        (false, false, true) => (1, 0),
+        (false, false, false) => (1, -1),
     };
+    binary(expr::BinaryOp::Cmp, expr::Expr::Num(lhs), expr::Expr::Num(rhs))
+}
 
-    match op.op_type {
-        Arm64OperandType::Mem(mem) => mem_to_lir_addr(mem),
-        _ => todo!("Not a mem op: {:?}", op)
+/// The float of the given size with the given bits
+fn float_bits(bits: i64, size: ty::Size) -> expr::Expr {
+    match size {
+        ty::Size::Size32 => expr::Expr::Float(f32::from_bits(bits as u32) as f64),
+        _ => expr::Expr::Float(f64::from_bits(bits as u64)),
     }
 }
 
-fn op_reg_size(op: &ArchOperand) -> ty::Size {
-    let op = match op {
-        ArchOperand::Arm64Operand(op) => op,
-        _ => panic!("not arm64?")
-    };
+/// The negation of expr, as compared against by flag setting additions
+fn negated(expr: expr::Expr) -> expr::Expr {
+    match expr {
+        expr::Expr::Num(x) => expr::Expr::Num(x.wrapping_neg()),
+        expr => expr::Expr::Unary { op: expr::UnaryOp::Neg, expr: Box::new(expr) }
+    }
+}
+
+fn arm64_op(op: &ArchOperand) -> Result<&Arm64Operand, String> {
+    match op {
+        ArchOperand::Arm64Operand(op) => Ok(op),
+        _ => Err(format!("Not an arm64 operand: {:?}", op))
+    }
+}
 
+fn op_to_mem_addr(op: &ArchOperand) -> Result<expr::Expr, String> {
+    let op = arm64_op(op)?;
+    match op.op_type {
+        Arm64OperandType::Mem(mem) => mem_to_lir_addr(mem, op),
+        _ => Err(format!("Not a memory operand: {:?}", op))
+    }
+}
+
+fn op_reg_size(op: &ArchOperand) -> Result<ty::Size, String> {
+    let op = arm64_op(op)?;
     match op.op_type {
         Arm64OperandType::Reg(reg) => reg_size(reg),
-        _ => todo!("Operand: {:?}", op)
+        _ => Err(format!("Not a register operand: {:?}", op))
+    }
+}
+
+/// Whether op is a floating point or SIMD register
+fn is_fp_op(op: &ArchOperand) -> bool {
+    matches!(op, ArchOperand::Arm64Operand(Arm64Operand { op_type: Arm64OperandType::Reg(reg), .. }) if fp_reg_index(*reg).is_some())
+}
+
+/// Whether op is a whole SIMD register
+fn is_vector_op(op: &ArchOperand) -> bool {
+    matches!(op, ArchOperand::Arm64Operand(Arm64Operand { op_type: Arm64OperandType::Reg(reg), .. }) if is_vector_reg(*reg))
+}
+
+/// The shape of the lanes of a vector register operand, as in `v0.4s`, for the 128 bit arrangements
+fn op_shape(op: &ArchOperand, float: bool) -> Option<ty::Shape> {
+    let ArchOperand::Arm64Operand(op) = op else {
+        return None;
+    };
+
+    match op.vas {
+        Arm64Vas::ARM64_VAS_16B if !float => Some(ty::Shape::I8x16),
+        Arm64Vas::ARM64_VAS_8H if !float => Some(ty::Shape::I16x8),
+        Arm64Vas::ARM64_VAS_4S => Some(if float { ty::Shape::F32x4 } else { ty::Shape::I32x4 }),
+        Arm64Vas::ARM64_VAS_2D => Some(if float { ty::Shape::F64x2 } else { ty::Shape::I64x2 }),
+        _ => None
     }
 }
 
-fn op_to_non_mem(op: &ArchOperand) -> expr::Expr {
-    let op = match op {
-        ArchOperand::Arm64Operand(op) => op,
-        _ => panic!("not arm64?")
+/// The intrinsic for a lanewise operator on a 64 bit arrangement, as in `v0.8b`, which wasm has no shapes for
+fn half_vector_op(op: &ArchOperand, binop: expr::BinaryOp) -> Option<expr::SimdOp> {
+    use expr::BinaryOp::*;
+    let ArchOperand::Arm64Operand(op) = op else {
+        return None;
     };
 
-    match op.op_type {
+    let name = match (op.vas, binop) {
+        (Arm64Vas::ARM64_VAS_8B, And) => "v64_and",
+        (Arm64Vas::ARM64_VAS_8B, Or) => "v64_or",
+        (Arm64Vas::ARM64_VAS_8B, Xor) => "v64_xor",
+        (Arm64Vas::ARM64_VAS_8B, Add) => "i8x8_add",
+        (Arm64Vas::ARM64_VAS_8B, Sub) => "i8x8_sub",
+        (Arm64Vas::ARM64_VAS_8B, Mul) => "i8x8_mul",
+        (Arm64Vas::ARM64_VAS_4H, Add) => "i16x4_add",
+        (Arm64Vas::ARM64_VAS_4H, Sub) => "i16x4_sub",
+        (Arm64Vas::ARM64_VAS_4H, Mul) => "i16x4_mul",
+        (Arm64Vas::ARM64_VAS_2S, Add) => "i32x2_add",
+        (Arm64Vas::ARM64_VAS_2S, Sub) => "i32x2_sub",
+        (Arm64Vas::ARM64_VAS_2S, Mul) => "i32x2_mul",
+        (Arm64Vas::ARM64_VAS_2S, FAdd) => "f32x2_add",
+        (Arm64Vas::ARM64_VAS_2S, FSub) => "f32x2_sub",
+        (Arm64Vas::ARM64_VAS_2S, FMul) => "f32x2_mul",
+        (Arm64Vas::ARM64_VAS_2S, FDiv) => "f32x2_div",
+        _ => return None
+    };
+    Some(expr::SimdOp::Named(name, true))
+}
+
+/// The lane size of a 64 bit arrangement, as in `v0.2s`
+fn half_lane_size(op: &ArchOperand) -> Option<ty::Size> {
+    match op {
+        ArchOperand::Arm64Operand(Arm64Operand { vas: Arm64Vas::ARM64_VAS_8B, .. }) => Some(ty::Size::Size8),
+        ArchOperand::Arm64Operand(Arm64Operand { vas: Arm64Vas::ARM64_VAS_4H, .. }) => Some(ty::Size::Size16),
+        ArchOperand::Arm64Operand(Arm64Operand { vas: Arm64Vas::ARM64_VAS_2S, .. }) => Some(ty::Size::Size32),
+        ArchOperand::Arm64Operand(Arm64Operand { vas: Arm64Vas::ARM64_VAS_1D, .. }) => Some(ty::Size::Size64),
+        _ => None
+    }
+}
+
+/// The low `lane` bits of imm repeated across 64 bits, which is the low half of a 64 bit arrangement with it in every lane
+fn replicated(imm: i64, lane: ty::Size) -> i64 {
+    let bits = lane.byte_count() * 8;
+    let imm = imm & low_bits(bits as i64);
+    (0..64 / bits).fold(0, |acc, i| acc | imm << (i * bits))
+}
+
+/// The shape a single lane operand is taken from, as in `v1.s[1]`
+fn lane_shape(op: &ArchOperand) -> Option<ty::Shape> {
+    match op {
+        ArchOperand::Arm64Operand(Arm64Operand { vas: Arm64Vas::ARM64_VAS_1B, .. }) => Some(ty::Shape::I8x16),
+        ArchOperand::Arm64Operand(Arm64Operand { vas: Arm64Vas::ARM64_VAS_1H, .. }) => Some(ty::Shape::I16x8),
+        ArchOperand::Arm64Operand(Arm64Operand { vas: Arm64Vas::ARM64_VAS_1S, .. }) => Some(ty::Shape::I32x4),
+        ArchOperand::Arm64Operand(Arm64Operand { vas: Arm64Vas::ARM64_VAS_1D, .. }) => Some(ty::Shape::I64x2),
+        _ => None
+    }
+}
+
+fn simd_call(op: expr::SimdOp, args: Vec<expr::Expr>) -> expr::Expr {
+    expr::Expr::Call { func: Box::new(expr::Expr::BuiltIn(expr::BuiltIn::Simd(op))), args }
+}
+
+fn op_to_non_mem(op: &ArchOperand) -> Result<expr::Expr, String> {
+    let op = arm64_op(op)?;
+    Ok(match op.op_type {
         Arm64OperandType::Reg(reg) =>
             if reg.0 == Arm64Reg::ARM64_REG_WZR as u16 || reg.0 == Arm64Reg::ARM64_REG_XZR as u16 {
                 expr::Expr::Num(0)
             } else {
-                name(reg)
+                shifted(extended(name(reg)?, op.ext), op.shift)
             }
-        Arm64OperandType::Imm(imm) => expr::Expr::Num(imm),
-        Arm64OperandType::Mem(_) => panic!("Memory operand"),
-        _ => todo!("Operand: {:?}", op)
+        Arm64OperandType::Imm(imm) => match op.shift {
+            Arm64Shift::Lsl(shift) => expr::Expr::Num(imm << shift),
+            _ => expr::Expr::Num(imm)
+        },
+        Arm64OperandType::Fp(fp) => expr::Expr::Float(fp),
+        _ => return Err(format!("Unsupported operand {:?}", op))
+    })
+}
+
+fn binary(op: expr::BinaryOp, lhs: expr::Expr, rhs: expr::Expr) -> expr::Expr {
+    expr::Expr::Binary {
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    }
+}
+
+/// Sets the flags as a comparison of lhs with rhs would
+fn set_flags(block: &mut lir::LirFuncBuilder, lhs: expr::Expr, rhs: expr::Expr) {
+    block.push(lir::Lir::Assign {
+        dst: expr::Expr::Name(NZCV.to_string()),
+        src: binary(expr::BinaryOp::Cmp, lhs, rhs),
+    });
+}
+
+/// A register operand after the extender applied to it, as in `add x0, x1, w2, sxtw`
+fn extended(expr: expr::Expr, ext: Arm64Extender) -> expr::Expr {
+    let sign_extend = |size| expr::Expr::Unary { op: expr::UnaryOp::SignExtend(size), expr: Box::new(expr.clone()) };
+    match ext {
+        Arm64Extender::ARM64_EXT_UXTB => binary(expr::BinaryOp::And, expr, expr::Expr::Num(0xff)),
+        Arm64Extender::ARM64_EXT_UXTH => binary(expr::BinaryOp::And, expr, expr::Expr::Num(0xffff)),
+        Arm64Extender::ARM64_EXT_UXTW => binary(expr::BinaryOp::And, expr, expr::Expr::Num(0xffffffff)),
+        Arm64Extender::ARM64_EXT_SXTB => sign_extend(ty::Size::Size8),
+        Arm64Extender::ARM64_EXT_SXTH => sign_extend(ty::Size::Size16),
+        Arm64Extender::ARM64_EXT_SXTW => sign_extend(ty::Size::Size32),
+        _ => expr
     }
 }
 
-fn op_to_expr(op: &ArchOperand, size: ty::Size) -> expr::Expr {
-    let op = match op {
-        ArchOperand::Arm64Operand(op) => op,
-        _ => panic!("not arm64?")
+/// A register operand after the shift applied to it, as in `orr x0, x1, x2, lsl #3`
+fn shifted(expr: expr::Expr, shift: Arm64Shift) -> expr::Expr {
+    match shift {
+        Arm64Shift::Lsl(0) | Arm64Shift::Lsr(0) | Arm64Shift::Asr(0) | Arm64Shift::Ror(0) => expr,
+        Arm64Shift::Lsl(shift) => binary(expr::BinaryOp::Shl, expr, expr::Expr::Num(shift as i64)),
+        Arm64Shift::Lsr(shift) => binary(expr::BinaryOp::Shr, expr, expr::Expr::Num(shift as i64)),
+        Arm64Shift::Asr(shift) => binary(expr::BinaryOp::Asr, expr, expr::Expr::Num(shift as i64)),
+        Arm64Shift::Ror(shift) => expr::Expr::Call {
+            func: Box::new(expr::Expr::BuiltIn(expr::BuiltIn::Rotr)),
+            args: vec![expr, expr::Expr::Num(shift as i64)],
+        },
+        _ => expr
+    }
+}
+
+/// The low `width` bits set
+fn low_bits(width: i64) -> i64 {
+    if width >= 64 { -1 } else { (1 << width) - 1 }
+}
+
+/// The bits of a register of the given size
+fn size_mask(size: ty::Size) -> i64 {
+    low_bits(size.byte_count() as i64 * 8)
+}
+
+/// A signed operation on W registers works on the sign extended sources, with the result cut back to 32 bits
+fn signed_op(size: ty::Size, op: impl FnOnce(&dyn Fn(expr::Expr) -> expr::Expr, ty::Size) -> expr::Expr) -> expr::Expr {
+    if size != ty::Size::Size32 {
+        return op(&|src| src, size);
+    }
+    let result = op(&|src| extended(src, Arm64Extender::ARM64_EXT_SXTW), ty::Size::Size64);
+    binary(expr::BinaryOp::And, result, expr::Expr::Num(size_mask(size)))
+}
+
+/// The low 32 bits of value
+fn low_32(value: expr::Expr) -> expr::Expr {
+    let fits = |n: &expr::Expr| matches!(n, expr::Expr::Num(n) if (0..=0xffffffff).contains(n));
+    match value {
+        expr::Expr::Num(n) => expr::Expr::Num(n & 0xffffffff),
+        expr::Expr::Deref { size: ty::Size::Size8 | ty::Size::Size16 | ty::Size::Size32, .. } => value,
+        expr::Expr::Unary { op, .. } if op.is_cmp() => value,
+        expr::Expr::Binary { op: expr::BinaryOp::And, ref lhs, ref rhs } if fits(lhs) || fits(rhs) => value,
+        value => binary(expr::BinaryOp::And, value, expr::Expr::Num(0xffffffff))
+    }
+}
+
+/// Writing a W register clears the top half of its X register, so the values an instruction gives its W destinations are cut to 32 bits
+fn zero_extend_written(insn: &Decoded, mnemonic: &str, code: &mut [lir::Lir]) {
+    // Stores only read their register operands, though they may write back to the base
+    if mnemonic.starts_with("st") {
+        return;
+    }
+    let count = match insn.id {
+        Arm64Insn::ARM64_INS_LDP | Arm64Insn::ARM64_INS_LDNP | Arm64Insn::ARM64_INS_LDXP | Arm64Insn::ARM64_INS_LDAXP => 2,
+        _ => 1
     };
+    let dsts: Vec<_> = insn.ops.iter().take(count).filter_map(|op| match op.op_type {
+        Arm64OperandType::Reg(reg) if (185..=213).contains(&(reg.0 as u32)) => name(reg).ok(),
+        _ => None
+    }).collect();
 
-    match op.op_type {
+    for stmt in code {
+        if let lir::Lir::Assign { dst, src } = stmt && dsts.contains(dst) {
+            *src = low_32(src.take());
+        }
+    }
+}
+
+/// The `width` bits of src starting at `lsb`, moved down to bit 0 and sign extended if signed
+fn bitfield(src: expr::Expr, lsb: i64, width: i64, size: ty::Size, signed: bool) -> expr::Expr {
+    if !signed {
+        let src = if lsb == 0 { src } else { binary(expr::BinaryOp::Shr, src, expr::Expr::Num(lsb)) };
+        return binary(expr::BinaryOp::And, src, expr::Expr::Num(low_bits(width)));
+    }
+
+    let bits = size.byte_count() as i64 * 8;
+    match ty::Size::from_byte_count(width as u64 / 8) {
+        Some(field) if width % 8 == 0 && width < bits => {
+            let src = if lsb == 0 { src } else { binary(expr::BinaryOp::Shr, src, expr::Expr::Num(lsb)) };
+            expr::Expr::Unary { op: expr::UnaryOp::SignExtend(field), expr: Box::new(src) }
+        }
+        _ => {
+            let top = bits - lsb - width;
+            let src = if top == 0 { src } else { binary(expr::BinaryOp::Shl, src, expr::Expr::Num(top)) };
+            binary(expr::BinaryOp::Asr, src, expr::Expr::Num(bits - width))
+        }
+    }
+}
+
+fn op_to_expr(op: &ArchOperand, size: ty::Size) -> Result<expr::Expr, String> {
+    let op = arm64_op(op)?;
+    Ok(match op.op_type {
         Arm64OperandType::Reg(reg) =>
             if reg.0 == Arm64Reg::ARM64_REG_WZR as u16 || reg.0 == Arm64Reg::ARM64_REG_XZR as u16 {
                 expr::Expr::Num(0)
             } else {
-                name(reg)
+                name(reg)?
             }
         Arm64OperandType::Imm(imm) => expr::Expr::Num(imm),
+        Arm64OperandType::Fp(fp) => expr::Expr::Float(fp),
         Arm64OperandType::Mem(mem) => expr::Expr::Deref {
-            ptr: Box::new(mem_to_lir_addr(mem)),
+            ptr: Box::new(mem_to_lir_addr(mem, op)?),
             size
         },
-        _ => todo!("Operand: {:?}", op)
-    }
+        _ => return Err(format!("Unsupported operand {:?}", op))
+    })
 }
 
-fn mem_to_lir_addr(mem: Arm64OpMem) -> expr::Expr {
-    assert_eq!(mem.index().0, 0);
-
+/// The address of a memory operand, with any index register extended and shifted as the operand says,
+/// as in `ldr x0, [x1, w2, sxtw #3]`
+fn mem_to_lir_addr(mem: Arm64OpMem, op: &Arm64Operand) -> Result<expr::Expr, String> {
     let mut expr = None;
     if mem.base().0 != 0 {
-        expr = Some(name(mem.base()));
+        expr = Some(name(mem.base())?);
+    }
+
+    if mem.index().0 != 0 {
+        let index = shifted(extended(name(mem.index())?, op.ext), op.shift);
+        expr = Some(match expr {
+            Some(expr) => binary(expr::BinaryOp::Add, expr, index),
+            None => index
+        });
     }
 
     if mem.disp() != 0 {
@@ -165,13 +466,332 @@ fn mem_to_lir_addr(mem: Arm64OpMem) -> expr::Expr {
         }
     }
 
-    match expr {
-        None => expr::Expr::Num(0),
-        Some(expr) => expr,
+    Ok(expr.unwrap_or(expr::Expr::Num(0)))
+}
+
+/// The value of a load of size bytes into reg, which is zero extended unless signed
+fn loaded(value: expr::Expr, size: ty::Size, signed: bool, reg: &ArchOperand) -> expr::Expr {
+    if is_fp_op(reg) {
+        expr::Expr::Unary { op: expr::UnaryOp::BitsToFloat(size), expr: Box::new(value) }
+    } else if signed {
+        expr::Expr::Unary { op: expr::UnaryOp::SignExtend(size), expr: Box::new(value) }
+    } else {
+        value
     }
 }
 
-pub fn to_lir(data: &[u8], base: u64, addr_to_func: &HashMap<u64, expr::FuncId>) -> Result<lir::LirFunc, String> {
+/// The value stored to memory from reg
+fn stored(reg: &ArchOperand, size: ty::Size) -> Result<expr::Expr, String> {
+    let value = op_to_non_mem(reg)?;
+    Ok(if is_fp_op(reg) {
+        expr::Expr::Unary { op: expr::UnaryOp::FloatToBits(size), expr: Box::new(value) }
+    } else {
+        value
+    })
+}
+
+/// The update to the base register of a pre or post-indexed access, to go after the access itself.
+/// Pre-indexed accesses have the offset in the memory operand, post-indexed ones in a trailing immediate.
+fn base_update(ops: &[ArchOperand], mem: usize, arch_detail: &capstone::arch::arm64::Arm64InsnDetail) -> Option<lir::Lir> {
+    if !arch_detail.writeback() {
+        return None;
+    }
+
+    let ArchOperand::Arm64Operand(Arm64Operand { op_type: Arm64OperandType::Mem(mem_op), .. }) = &ops[mem] else {
+        return None;
+    };
+
+    let offset = match ops.get(mem + 1) {
+        Some(ArchOperand::Arm64Operand(Arm64Operand { op_type: Arm64OperandType::Imm(imm), .. })) => *imm,
+        _ => mem_op.disp() as i64
+    };
+
+    let base = name(mem_op.base()).ok()?;
+    Some(lir::Lir::Assign {
+        dst: base.clone(),
+        src: expr::Expr::Binary {
+            op: expr::BinaryOp::Add,
+            lhs: Box::new(base),
+            rhs: Box::new(expr::Expr::Num(offset)),
+        },
+    })
+}
+
+/// What the jump table matcher needs to know about each instruction
+struct Decoded {
+    id: Arm64Insn,
+    ops: Vec<Arm64Operand>,
+    cc: Arm64CC
+}
+
+/// A `br` to `base + (table[index] << shift)`, lifted as comparisons of the index against each entry
+struct JumpTable {
+    index: expr::Expr,
+    /// Each distinct target, with the indices leading to it
+    cases: Vec<(u64, Vec<i64>)>,
+    /// Where the bounds check sends indices past the end of the table
+    default: u64,
+    /// The load of the entry and the add of it to the base, which the comparisons replace
+    dispatch: [usize; 2]
+}
+
+/// Jump tables larger than this are more likely to be a misreading of the code
+const MAX_TABLE_LEN: usize = 4096;
+
+fn op_reg(op: &Arm64Operand) -> Option<usize> {
+    match op.op_type {
+        Arm64OperandType::Reg(reg) => reg_index(reg),
+        _ => None
+    }
+}
+
+fn op_imm(op: &Arm64Operand) -> Option<i64> {
+    match op.op_type {
+        Arm64OperandType::Imm(imm) => Some(imm),
+        _ => None
+    }
+}
+
+fn op_shift(op: &Arm64Operand) -> Option<u32> {
+    match op.shift {
+        Arm64Shift::Invalid => Some(0),
+        Arm64Shift::Lsl(shift) => Some(shift),
+        _ => None
+    }
+}
+
+/// The last instruction before `before` to write to reg
+fn last_def(insns: &[Decoded], before: usize, reg: usize) -> Option<usize> {
+    (0..before).rev().find(|i| {
+        let insn = &insns[*i];
+        let writes_first = !matches!(insn.id,
+            Arm64Insn::ARM64_INS_CMP | Arm64Insn::ARM64_INS_CMN | Arm64Insn::ARM64_INS_TST |
+            Arm64Insn::ARM64_INS_CCMP | Arm64Insn::ARM64_INS_CCMN |
+            Arm64Insn::ARM64_INS_STR | Arm64Insn::ARM64_INS_STRB | Arm64Insn::ARM64_INS_STRH |
+            Arm64Insn::ARM64_INS_STUR | Arm64Insn::ARM64_INS_STP |
+            Arm64Insn::ARM64_INS_B | Arm64Insn::ARM64_INS_BL | Arm64Insn::ARM64_INS_BR | Arm64Insn::ARM64_INS_BLR |
+            Arm64Insn::ARM64_INS_CBZ | Arm64Insn::ARM64_INS_CBNZ | Arm64Insn::ARM64_INS_TBZ | Arm64Insn::ARM64_INS_TBNZ |
+            Arm64Insn::ARM64_INS_RET
+        );
+        writes_first && insn.ops.first().and_then(op_reg) == Some(reg)
+    })
+}
+
+/// The constant address in reg just before `before`, set by `adr`, or `adrp` and `add`
+fn address_in(insns: &[Decoded], before: usize, reg: usize) -> Option<u64> {
+    let def = last_def(insns, before, reg)?;
+    match (insns[def].id, insns[def].ops.as_slice()) {
+        (Arm64Insn::ARM64_INS_ADR | Arm64Insn::ARM64_INS_ADRP, [_, addr]) => Some(op_imm(addr)? as u64),
+        (Arm64Insn::ARM64_INS_ADD, [_, src, offset]) if op_shift(offset) == Some(0) =>
+            Some(address_in(insns, def, op_reg(src)?)?.wrapping_add(op_imm(offset)? as u64)),
+        _ => None
+    }
+}
+
+/// The number of entries, from the `cmp` and `b.hi` or `b.hs` guarding the index, and where they send larger indices
+fn table_bound(insns: &[Decoded], before: usize, index: usize) -> Option<(usize, u64)> {
+    // The index is often a copy of the register that was checked
+    let mut aliases = vec![index];
+    let mut guard = None;
+
+    for insn in insns[..before].iter().rev() {
+        match (insn.id, insn.ops.as_slice()) {
+            (Arm64Insn::ARM64_INS_MOV, [dst, src]) if op_reg(dst).is_some_and(|x| aliases.contains(&x)) => aliases.extend(op_reg(src)),
+            (Arm64Insn::ARM64_INS_B, [target]) if guard.is_none() && matches!(insn.cc, Arm64CC::ARM64_CC_HI | Arm64CC::ARM64_CC_HS) => {
+                guard = Some((insn.cc, op_imm(target)? as u64));
+            }
+            (Arm64Insn::ARM64_INS_CMP, [lhs, rhs]) => {
+                let (cc, default) = guard?;
+                if !op_reg(lhs).is_some_and(|x| aliases.contains(&x)) {
+                    return None;
+                }
+
+                let max = usize::try_from(op_imm(rhs)?).ok()?;
+                let len = if cc == Arm64CC::ARM64_CC_HI { max + 1 } else { max };
+                return (len <= MAX_TABLE_LEN).then_some((len, default));
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The size of memory a load or store of a register of reg_size accesses, and whether a load sign extends it
+fn access_kind(id: Arm64Insn, reg_size: ty::Size) -> Option<(ty::Size, bool)> {
+    match id {
+        Arm64Insn::ARM64_INS_LDRB | Arm64Insn::ARM64_INS_LDURB |
+        Arm64Insn::ARM64_INS_STRB | Arm64Insn::ARM64_INS_STURB => Some((ty::Size::Size8, false)),
+        Arm64Insn::ARM64_INS_LDRSB | Arm64Insn::ARM64_INS_LDURSB => Some((ty::Size::Size8, true)),
+        Arm64Insn::ARM64_INS_LDRH | Arm64Insn::ARM64_INS_LDURH |
+        Arm64Insn::ARM64_INS_STRH | Arm64Insn::ARM64_INS_STURH => Some((ty::Size::Size16, false)),
+        Arm64Insn::ARM64_INS_LDRSH | Arm64Insn::ARM64_INS_LDURSH => Some((ty::Size::Size16, true)),
+        Arm64Insn::ARM64_INS_LDRSW | Arm64Insn::ARM64_INS_LDURSW | Arm64Insn::ARM64_INS_LDPSW => Some((ty::Size::Size32, true)),
+        Arm64Insn::ARM64_INS_LDR | Arm64Insn::ARM64_INS_LDUR | Arm64Insn::ARM64_INS_LDP |
+        Arm64Insn::ARM64_INS_STR | Arm64Insn::ARM64_INS_STUR | Arm64Insn::ARM64_INS_STP => Some((reg_size, false)),
+        _ => None
+    }
+}
+
+/// The size and signedness of the entries read by a table load
+fn entry_kind(load: &Decoded) -> Option<(usize, bool)> {
+    if !matches!(load.id,
+        Arm64Insn::ARM64_INS_LDR | Arm64Insn::ARM64_INS_LDRB | Arm64Insn::ARM64_INS_LDRSB |
+        Arm64Insn::ARM64_INS_LDRH | Arm64Insn::ARM64_INS_LDRSH | Arm64Insn::ARM64_INS_LDRSW
+    ) {
+        return None;
+    }
+
+    let Arm64OperandType::Reg(reg) = load.ops.first()?.op_type else {
+        return None;
+    };
+    let (size, signed) = access_kind(load.id, reg_size(reg).ok()?)?;
+    Some((size.byte_count(), signed))
+}
+
+fn read_entry(bytes: &[u8], signed: bool) -> i64 {
+    let bits = bytes.len() as u32 * 8;
+    let value = bytes.iter().rev().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+    if signed && bits < 64 {
+        ((value << (64 - bits)) as i64) >> (64 - bits)
+    } else {
+        value as i64
+    }
+}
+
+/// The value of an operand after its extender, if it has one
+fn extend(value: i64, ext: Arm64Extender) -> i64 {
+    match ext {
+        Arm64Extender::ARM64_EXT_UXTB => value as u8 as i64,
+        Arm64Extender::ARM64_EXT_UXTH => value as u16 as i64,
+        Arm64Extender::ARM64_EXT_UXTW => value as u32 as i64,
+        Arm64Extender::ARM64_EXT_SXTB => value as i8 as i64,
+        Arm64Extender::ARM64_EXT_SXTH => value as i16 as i64,
+        Arm64Extender::ARM64_EXT_SXTW => value as i32 as i64,
+        _ => value
+    }
+}
+
+/// Matches the bounded jump tables compilers emit for switches:
+/// `cmp wI, #N; b.hi default; adr xB, base; ldrb wE, [xT, xI]; add xD, xB, xE, lsl #s; br xD`,
+/// with the entries read from the binary
+fn jump_table(insns: &[Decoded], br: usize, image: &Image, range: &std::ops::Range<u64>) -> Option<JumpTable> {
+    let add = last_def(insns, br, op_reg(insns[br].ops.first()?)?)?;
+    let (Arm64Insn::ARM64_INS_ADD, [_, base, entry]) = (insns[add].id, insns[add].ops.as_slice()) else {
+        return None;
+    };
+    let shift = op_shift(entry)?;
+    let base = address_in(insns, add, op_reg(base)?)?;
+
+    let load = last_def(insns, add, op_reg(entry)?)?;
+    let (size, signed) = entry_kind(&insns[load])?;
+    let mem_op = insns[load].ops.get(1)?;
+    let Arm64OperandType::Mem(mem) = mem_op.op_type else {
+        return None;
+    };
+    // Anything but the index scaled by the entry size isn't indexing a table of entries
+    if mem.disp() != 0 || 1 << op_shift(mem_op)? != size {
+        return None;
+    }
+
+    let table = address_in(insns, load, reg_index(mem.base())?)?;
+    let index = reg_index(mem.index())?;
+    let (len, default) = table_bound(insns, load, index)?;
+    let entries = image.read(table, len * size)?;
+
+    let mut cases: Vec<(u64, Vec<i64>)> = vec![];
+    for (i, bytes) in entries.chunks(size).enumerate() {
+        let offset = extend(read_entry(bytes, signed), entry.ext);
+        let target = base.wrapping_add((offset << shift) as u64);
+        if !range.contains(&target) {
+            return None;
+        }
+        if target == default {
+            continue;
+        }
+
+        match cases.iter_mut().find(|(x, _)| *x == target) {
+            Some((_, indices)) => indices.push(i as i64),
+            None => cases.push((target, vec![i as i64]))
+        }
+    }
+
+    if !range.contains(&default) {
+        return None;
+    }
+
+    Some(JumpTable {
+        index: expr::Expr::Name(X[index].to_string()),
+        cases,
+        default,
+        dispatch: [load, add]
+    })
+}
+
+fn label_at(addr: u64, block: &mut lir::LirFuncBuilder, addr_to_label: &mut HashMap<u64, lir::Label>) -> lir::Label {
+    *addr_to_label.entry(addr).or_insert_with(|| block.new_label())
+}
+
+/// The function called by a branch to an address or a register
+fn callee(op: &ArchOperand, addr_to_func: &HashMap<u64, expr::FuncId>) -> Result<expr::Expr, String> {
+    match op {
+        ArchOperand::Arm64Operand(Arm64Operand {
+            op_type: Arm64OperandType::Imm(val),
+            ..
+        }) => Ok(match addr_to_func.get(&(*val as u64)) {
+            Some(func) => expr::Expr::Func(*func),
+            None => expr::Expr::Num(*val),
+        }),
+        _ => op_to_non_mem(op)
+    }
+}
+
+/// Returns the result of calling func, if cond holds
+fn tail_call(func: expr::Expr, cond: Option<expr::Expr>, block: &mut lir::LirFuncBuilder) {
+    let end = block.new_label();
+    if let Some(cond) = cond {
+        let call = block.new_label();
+        block.push(lir::Lir::Branch { cond: Some(cond), target: call });
+        let next = block.new_label();
+        block.push(lir::Lir::Label(next));
+        block.push(lir::Lir::Branch { cond: None, target: end });
+        block.push(lir::Lir::Label(call));
+    }
+
+    block.push(lir::Lir::Assign {
+        dst: expr::Expr::Name(X[0].to_string()),
+        src: expr::Expr::Call {
+            func: Box::new(func),
+            args: vec![],
+        },
+    });
+    block.push(lir::Lir::Return(vec![expr::Expr::Name(X[0].to_string()), expr::Expr::Name(D[0].to_string())]));
+    block.push(lir::Lir::Label(end));
+}
+
+fn gen_jump_table(table: &JumpTable, block: &mut lir::LirFuncBuilder, addr_to_label: &mut HashMap<u64, lir::Label>) {
+    for (target, indices) in &table.cases {
+        let cond = indices.iter().map(|i| expr::Expr::Binary {
+            op: expr::BinaryOp::Eq,
+            lhs: Box::new(table.index.clone()),
+            rhs: Box::new(expr::Expr::Num(*i))
+        }).reduce(|lhs, rhs| expr::Expr::Binary {
+            op: expr::BinaryOp::Or,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs)
+        }).unwrap();
+
+        let target = label_at(*target, block, addr_to_label);
+        block.push(lir::Lir::Branch { cond: Some(cond), target });
+        let next = block.new_label();
+        block.push(lir::Lir::Label(next));
+    }
+
+    let default = label_at(table.default, block, addr_to_label);
+    block.push(lir::Lir::Branch { cond: None, target: default });
+}
+
+pub fn to_lir(data: &[u8], base: u64, addr_to_func: &HashMap<u64, expr::FuncId>, image: &Image) -> Result<lir::LirFunc, String> {
     let cs = Capstone::new()
         .arm64()
         .mode(arch::arm64::ArchMode::Arm)
@@ -184,12 +804,33 @@ pub fn to_lir(data: &[u8], base: u64, addr_to_func: &HashMap<u64, expr::FuncId>)
     let mut block = lir::LirFuncBuilder::new();
     let mut addr_to_label = HashMap::new();
 
-    for insn in insns.as_ref() {
+    let decoded: Vec<_> = insns.iter().map(|insn| {
+        let detail = cs
+            .insn_detail(insn)
+            .expect("Could not object cs instruction detail");
+        let arch_detail = detail.arch_detail();
+        let arch_detail = arch_detail.arm64().unwrap();
+        Decoded {
+            id: Arm64Insn::from(insn.id().0),
+            ops: arch_detail.operands().collect(),
+            cc: arch_detail.cc()
+        }
+    }).collect();
+
+    let range = base..base + data.len() as u64;
+    let mut tables = HashMap::new();
+    for (i, insn) in decoded.iter().enumerate() {
+        if insn.id == Arm64Insn::ARM64_INS_BR && let Some(table) = jump_table(&decoded, i, image, &range) {
+            tables.insert(i, table);
+        }
+    }
+    let dispatch: Vec<usize> = tables.values().flat_map(|x| x.dispatch).collect();
+
+    for (i, insn) in insns.iter().enumerate() {
         let detail = cs
             .insn_detail(insn)
             .expect("Could not object cs instruction detail");
         let arch_detail = detail.arch_detail();
-        let ops = arch_detail.operands();
         let arch_detail = arch_detail.arm64().unwrap();
 
         if let Some(label) = addr_to_label.get(&insn.address()) {
@@ -200,7 +841,18 @@ pub fn to_lir(data: &[u8], base: u64, addr_to_func: &HashMap<u64, expr::FuncId>)
             addr_to_label.insert(insn.address(), label);
         }
 
-        gen_insn(insn, &ops, arch_detail, &mut block, addr_to_func, &mut addr_to_label);
+        if dispatch.contains(&i) {
+            continue;
+        }
+
+        match tables.get(&i) {
+            Some(table) => gen_jump_table(table, &mut block, &mut addr_to_label),
+            None => {
+                let start = block.next_index();
+                gen_insn(insn, arch_detail, &mut block, addr_to_func, &mut addr_to_label, image, &range)?;
+                zero_extend_written(&decoded[i], insn.mnemonic().unwrap_or(""), block.since(start));
+            }
+        }
     }
 
     Ok(block.block())
@@ -208,15 +860,84 @@ pub fn to_lir(data: &[u8], base: u64, addr_to_func: &HashMap<u64, expr::FuncId>)
 
 fn gen_insn(
     insn: &capstone::Insn,
-    ops: &[capstone::arch::ArchOperand], arch_detail: &capstone::arch::arm64::Arm64InsnDetail,
+    arch_detail: &capstone::arch::arm64::Arm64InsnDetail,
     block: &mut lir::LirFuncBuilder,
-    addr_to_func: &HashMap<u64, expr::FuncId>, addr_to_label: &mut HashMap<u64, lir::Label>
-) {
+    addr_to_func: &HashMap<u64, expr::FuncId>, addr_to_label: &mut HashMap<u64, lir::Label>,
+    image: &Image, range: &std::ops::Range<u64>
+) -> Result<(), String> {
+    let ops: Vec<_> = arch_detail.operands().map(ArchOperand::Arm64Operand).collect();
     match Arm64Insn::from(insn.id().0) {
+        Arm64Insn::ARM64_INS_FADD | Arm64Insn::ARM64_INS_FSUB | Arm64Insn::ARM64_INS_FMUL | Arm64Insn::ARM64_INS_FDIV |
+        Arm64Insn::ARM64_INS_ADD | Arm64Insn::ARM64_INS_SUB | Arm64Insn::ARM64_INS_MUL |
+        Arm64Insn::ARM64_INS_AND | Arm64Insn::ARM64_INS_ORR | Arm64Insn::ARM64_INS_EOR if is_vector_op(&ops[0]) => {
+            let id = Arm64Insn::from(insn.id().0);
+            let (op, float) = match id {
+                Arm64Insn::ARM64_INS_FADD => (expr::BinaryOp::FAdd, true),
+                Arm64Insn::ARM64_INS_FSUB => (expr::BinaryOp::FSub, true),
+                Arm64Insn::ARM64_INS_FMUL => (expr::BinaryOp::FMul, true),
+                Arm64Insn::ARM64_INS_FDIV => (expr::BinaryOp::FDiv, true),
+                Arm64Insn::ARM64_INS_ADD => (expr::BinaryOp::Add, false),
+                Arm64Insn::ARM64_INS_SUB => (expr::BinaryOp::Sub, false),
+                Arm64Insn::ARM64_INS_MUL => (expr::BinaryOp::Mul, false),
+                Arm64Insn::ARM64_INS_AND => (expr::BinaryOp::And, false),
+                Arm64Insn::ARM64_INS_ORR => (expr::BinaryOp::Or, false),
+                _ => (expr::BinaryOp::Xor, false),
+            };
+            // The bitwise operations only come in byte arrangements, and any shape works for those on the whole register
+            let shape = match op {
+                expr::BinaryOp::And | expr::BinaryOp::Or | expr::BinaryOp::Xor => op_shape(&ops[0], float).map(|_| ty::Shape::I64x2),
+                _ => op_shape(&ops[0], float),
+            };
+            let simd_op = match shape {
+                Some(shape) => expr::SimdOp::Lanewise(shape, op),
+                None => half_vector_op(&ops[0], op).ok_or_else(|| format!(
+                    "Unimplemented vector arrangement: {} {}",
+                    insn.mnemonic().unwrap(),
+                    insn.op_str().unwrap()
+                ))?,
+            };
+
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: simd_call(simd_op, vec![src1, src2]),
+            });
+        }
+        // Whole vector registers are loaded and stored as wasm's are, a pair at a time for LDP and STP
+        Arm64Insn::ARM64_INS_LDR | Arm64Insn::ARM64_INS_LDUR | Arm64Insn::ARM64_INS_LDP | Arm64Insn::ARM64_INS_LD1 |
+        Arm64Insn::ARM64_INS_STR | Arm64Insn::ARM64_INS_STUR | Arm64Insn::ARM64_INS_STP | Arm64Insn::ARM64_INS_ST1 if is_vector_op(&ops[0]) => {
+            let id = Arm64Insn::from(insn.id().0);
+            let load = matches!(id, Arm64Insn::ARM64_INS_LDR | Arm64Insn::ARM64_INS_LDUR | Arm64Insn::ARM64_INS_LDP | Arm64Insn::ARM64_INS_LD1);
+            let mem = ops.iter().position(|op| matches!(op, ArchOperand::Arm64Operand(Arm64Operand { op_type: Arm64OperandType::Mem(_), .. })))
+                .ok_or("Vector memory operand".to_string())?;
+            let addr = op_to_mem_addr(&ops[mem])?;
+            // The 64 bit arrangements only use the low half, and clear the rest when loaded
+            let half = matches!(
+                ops[0],
+                ArchOperand::Arm64Operand(Arm64Operand { vas: Arm64Vas::ARM64_VAS_8B | Arm64Vas::ARM64_VAS_4H | Arm64Vas::ARM64_VAS_2S | Arm64Vas::ARM64_VAS_1D, .. })
+            );
+            let width = if half { 8 } else { 16 };
+
+            for (i, reg) in ops[..mem].iter().enumerate() {
+                let addr = if i == 0 { addr.clone() } else { binary(expr::BinaryOp::Add, addr.clone(), expr::Expr::Num(width * i as i64)) };
+                let reg = op_to_non_mem(reg)?;
+                match (load, half) {
+                    (true, false) => block.push(lir::Lir::Assign { dst: reg, src: simd_call(expr::SimdOp::Load("v128_load"), vec![addr]) }),
+                    (true, true) => block.push(lir::Lir::Assign { dst: reg, src: simd_call(expr::SimdOp::Load("v128_load64_zero"), vec![addr]) }),
+                    (false, false) => block.push(lir::Lir::Do(simd_call(expr::SimdOp::Store("v128_store"), vec![addr, reg]))),
+                    (false, true) => block.push(lir::Lir::Do(simd_call(expr::SimdOp::Store("v128_store64_lane"), vec![addr, reg, expr::Expr::Num(0)]))),
+                }
+            }
+            if let Some(update) = base_update(&ops, mem, arch_detail) {
+                block.push(update);
+            }
+        }
         Arm64Insn::ARM64_INS_SUB => {
-            let dst = op_to_non_mem(&ops[0]);
-            let src1 = op_to_non_mem(&ops[1]);
-            let src2 = op_to_non_mem(&ops[2]);
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
             block.push(lir::Lir::Assign {
                 dst,
                 src: expr::Expr::Binary {
@@ -227,9 +948,9 @@ fn gen_insn(
             });
         }
         Arm64Insn::ARM64_INS_ADD => {
-            let dst = op_to_non_mem(&ops[0]);
-            let src1 = op_to_non_mem(&ops[1]);
-            let src2 = op_to_non_mem(&ops[2]);
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
             block.push(lir::Lir::Assign {
                 dst,
                 src: expr::Expr::Binary {
@@ -239,46 +960,88 @@ fn gen_insn(
                 },
             });
         }
-        Arm64Insn::ARM64_INS_MOV | Arm64Insn::ARM64_INS_LDR | Arm64Insn::ARM64_INS_LDUR => {
-            let dst = op_to_non_mem(&ops[0]);
-            let src = op_to_expr(&ops[1], op_reg_size(&ops[0]));
+        // Literals are read from the pool next to the code, which is never written to
+        Arm64Insn::ARM64_INS_LDR if matches!(ops[1], ArchOperand::Arm64Operand(Arm64Operand { op_type: Arm64OperandType::Imm(_), .. })) => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let size = op_reg_size(&ops[0])?;
+            let addr = op_to_non_mem(&ops[1])?;
+            let src = if let expr::Expr::Num(addr) = addr && let Some(bytes) = image.read(addr as u64, size.byte_count()) {
+                let value = read_entry(bytes, false);
+                if is_fp_op(&ops[0]) { float_bits(value, size) } else { expr::Expr::Num(value) }
+            } else {
+                expr::Expr::Deref { ptr: Box::new(addr), size }
+            };
+            block.push(lir::Lir::Assign { dst, src });
+        }
+        Arm64Insn::ARM64_INS_MOV | Arm64Insn::ARM64_INS_MOVZ => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
             block.push(lir::Lir::Assign {
                 dst, src
             });
         }
-        Arm64Insn::ARM64_INS_STR | Arm64Insn::ARM64_INS_STUR => {
-            let dst = op_to_expr(&ops[1], op_reg_size(&ops[0]));
-            let src = op_to_non_mem(&ops[0]);
+        Arm64Insn::ARM64_INS_MOVN => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let expr::Expr::Num(imm) = op_to_non_mem(&ops[1])? else { return Err("Move operand type".to_string()) };
+            block.push(lir::Lir::Assign {
+                dst,
+                src: expr::Expr::Num(!imm & size_mask(op_reg_size(&ops[0])?)),
+            });
+        }
+        // Replaces one 16 bit piece, keeping the rest
+        Arm64Insn::ARM64_INS_MOVK => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            let shift = match &ops[1] {
+                ArchOperand::Arm64Operand(Arm64Operand { shift: Arm64Shift::Lsl(shift), .. }) => *shift as i64,
+                _ => 0
+            };
+            block.push(lir::Lir::Assign {
+                dst: dst.clone(),
+                src: binary(
+                    expr::BinaryOp::Or,
+                    binary(expr::BinaryOp::And, dst, expr::Expr::Num(!(0xffff << shift) & size_mask(op_reg_size(&ops[0])?))),
+                    src
+                ),
+            });
+        }
+        Arm64Insn::ARM64_INS_LDR | Arm64Insn::ARM64_INS_LDUR |
+        Arm64Insn::ARM64_INS_LDRB | Arm64Insn::ARM64_INS_LDURB | Arm64Insn::ARM64_INS_LDRSB | Arm64Insn::ARM64_INS_LDURSB |
+        Arm64Insn::ARM64_INS_LDRH | Arm64Insn::ARM64_INS_LDURH | Arm64Insn::ARM64_INS_LDRSH | Arm64Insn::ARM64_INS_LDURSH |
+        Arm64Insn::ARM64_INS_LDRSW | Arm64Insn::ARM64_INS_LDURSW => {
+            let (size, signed) = access_kind(Arm64Insn::from(insn.id().0), op_reg_size(&ops[0])?).unwrap();
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = loaded(op_to_expr(&ops[1], size)?, size, signed, &ops[0]);
             block.push(lir::Lir::Assign {
                 dst, src
             });
+            if let Some(update) = base_update(&ops, 1, arch_detail) {
+                block.push(update);
+            }
         }
-        Arm64Insn::ARM64_INS_CMP => {
-            let src1 = op_to_non_mem(&ops[0]);
-            let src2 = op_to_non_mem(&ops[1]);
-
+        Arm64Insn::ARM64_INS_STR | Arm64Insn::ARM64_INS_STUR |
+        Arm64Insn::ARM64_INS_STRB | Arm64Insn::ARM64_INS_STURB | Arm64Insn::ARM64_INS_STRH | Arm64Insn::ARM64_INS_STURH => {
+            let (size, _) = access_kind(Arm64Insn::from(insn.id().0), op_reg_size(&ops[0])?).unwrap();
+            let dst = op_to_expr(&ops[1], size)?;
+            let src = stored(&ops[0], size)?;
             block.push(lir::Lir::Assign {
-                dst: expr::Expr::Name(CMP.to_string()),
-                src: expr::Expr::Binary {
-                    op: expr::BinaryOp::Cmp,
-                    lhs: Box::new(src1.clone()),
-                    rhs: Box::new(src2.clone()),
-                },
+                dst, src
             });
+            if let Some(update) = base_update(&ops, 1, arch_detail) {
+                block.push(update);
+            }
+        }
+        Arm64Insn::ARM64_INS_CMP => {
+            let src1 = op_to_non_mem(&ops[0])?;
+            let src2 = op_to_non_mem(&ops[1])?;
+            set_flags(block, src1, src2);
         }
         Arm64Insn::ARM64_INS_SUBS => {
-            let dst = op_to_non_mem(&ops[0]);
-            let src1 = op_to_non_mem(&ops[1]);
-            let src2 = op_to_non_mem(&ops[2]);
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
 
-            block.push(lir::Lir::Assign {
-                dst: expr::Expr::Name(CMP.to_string()),
-                src: expr::Expr::Binary {
-                    op: expr::BinaryOp::Cmp,
-                    lhs: Box::new(src1.clone()),
-                    rhs: Box::new(src2.clone()),
-                },
-            });
+            set_flags(block, src1.clone(), src2.clone());
 
             block.push(lir::Lir::Assign {
                 dst,
@@ -289,21 +1052,37 @@ fn gen_insn(
                 },
             });
         }
+        Arm64Insn::ARM64_INS_ADR | Arm64Insn::ARM64_INS_ADRP => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            block.push(lir::Lir::Assign {
+                dst, src
+            });
+        }
         Arm64Insn::ARM64_INS_RET => {
-            block.push(lir::Lir::Return(expr::Expr::Name(X[0].to_string())));
-        }
-        Arm64Insn::ARM64_INS_CSEL => {
-            let cond = match cc_to_lir(arch_detail.cc()) {
-                None => None,
-                Some(op) => Some(expr::Expr::Unary {
-                    op,
-                    expr: Box::new(expr::Expr::Name(CMP.to_string())),
-                }),
-            };
+            block.push(lir::Lir::Return(vec![expr::Expr::Name(X[0].to_string()), expr::Expr::Name(D[0].to_string())]));
+        }
+        Arm64Insn::ARM64_INS_CSEL | Arm64Insn::ARM64_INS_FCSEL | Arm64Insn::ARM64_INS_CSINC | Arm64Insn::ARM64_INS_CSINV |
+        Arm64Insn::ARM64_INS_CSNEG | Arm64Insn::ARM64_INS_CINC | Arm64Insn::ARM64_INS_CINV | Arm64Insn::ARM64_INS_CNEG |
+        Arm64Insn::ARM64_INS_CSETM => {
+            let cond = cond(arch_detail.cc());
 
-            let dst = op_to_non_mem(&ops[0]);
-            let src1 = op_to_non_mem(&ops[1]);
-            let src2 = op_to_non_mem(&ops[2]);
+            let dst = op_to_non_mem(&ops[0])?;
+            let srcs = ops.iter().map(op_to_non_mem).collect::<Result<Vec<_>, _>>()?;
+            let src = |i: usize| srcs[i].clone();
+            let plus_one = |x| binary(expr::BinaryOp::Add, x, expr::Expr::Num(1));
+            let inverted = |x| binary(expr::BinaryOp::Xor, x, expr::Expr::Num(-1));
+            // The value when the condition holds, then when it doesn't. The aliases with one source carry the condition they print
+            let (src1, src2) = match Arm64Insn::from(insn.id().0) {
+                Arm64Insn::ARM64_INS_CSINC => (src(1), plus_one(src(2))),
+                Arm64Insn::ARM64_INS_CSINV => (src(1), inverted(src(2))),
+                Arm64Insn::ARM64_INS_CSNEG => (src(1), negated(src(2))),
+                Arm64Insn::ARM64_INS_CINC => (plus_one(src(1)), src(1)),
+                Arm64Insn::ARM64_INS_CINV => (inverted(src(1)), src(1)),
+                Arm64Insn::ARM64_INS_CNEG => (negated(src(1)), src(1)),
+                Arm64Insn::ARM64_INS_CSETM => (expr::Expr::Num(size_mask(op_reg_size(&ops[0])?)), expr::Expr::Num(0)),
+                _ => (src(1), src(2))
+            };
 
             let label1 = block.new_label();
             let label2 = block.new_label();
@@ -330,102 +1109,109 @@ fn gen_insn(
             block.push(lir::Lir::Label(label3));
         }
         Arm64Insn::ARM64_INS_CSET => {
-            let cond = match cc_to_lir(arch_detail.cc()) {
-                None => expr::Expr::Num(1),
-                Some(op) => expr::Expr::Unary {
-                    op,
-                    expr: Box::new(expr::Expr::Name(CMP.to_string())),
-                },
-            };
+            let cond = cond(arch_detail.cc()).unwrap_or(expr::Expr::Num(1));
 
-            let dst = op_to_non_mem(&ops[0]);
+            let dst = op_to_non_mem(&ops[0])?;
 
             block.push(lir::Lir::Assign {
                 dst: dst.clone(),
                 src: cond,
             });
         }
-        Arm64Insn::ARM64_INS_BL => {
-            let addr = match &ops[0] {
-                ArchOperand::Arm64Operand(Arm64Operand {
-                    op_type: Arm64OperandType::Imm(val),
-                    ..
-                }) =>
-                    match addr_to_func.get(&(*val as u64)) {
-                        Some(func) => expr::Expr::Func(*func),
-                        None => expr::Expr::Num(*val),
-                    },
-                _ => panic!("Branch operand type"),
-            };
-
+        Arm64Insn::ARM64_INS_BL | Arm64Insn::ARM64_INS_BLR => {
             block.push(lir::Lir::Assign {
                 dst: expr::Expr::Name(X[0].to_string()),
                 src: expr::Expr::Call {
-                    func: Box::new(addr),
+                    func: Box::new(callee(&ops[0], addr_to_func)?),
                     args: vec![],
                 },
             });
         }
+        // Not being a jump table, jumping to a register leaves the function
+        Arm64Insn::ARM64_INS_BR => {
+            tail_call(callee(&ops[0], addr_to_func)?, None, block);
+        }
         Arm64Insn::ARM64_INS_B => {
-            let cond = match cc_to_lir(arch_detail.cc()) {
-                None => None,
-                Some(op) => Some(expr::Expr::Unary {
-                    op,
-                    expr: Box::new(expr::Expr::Name(CMP.to_string())),
-                }),
-            };
+            let cond = cond(arch_detail.cc());
 
-            let target = match &ops[0] {
+            let addr = match &ops[0] {
                 ArchOperand::Arm64Operand(Arm64Operand {
                     op_type: Arm64OperandType::Imm(val),
                     ..
-                }) => {
-                    if let Some(label) = addr_to_label.get(&(*val as u64)) {
-                        *label
-                    } else {
-                        let label = block.new_label();
-                        addr_to_label.insert(*val as u64, label);
-                        label
-                    }
-                }
-                _ => panic!("Branch operand type"),
+                }) => *val as u64,
+                _ => return Err("Branch operand type".to_string()),
             };
 
+            // Jumping to another function is a tail call
+            if !range.contains(&addr) {
+                tail_call(callee(&ops[0], addr_to_func)?, cond, block);
+                return Ok(());
+            }
+
+            let target = label_at(addr, block, addr_to_label);
             block.push(lir::Lir::Branch {
                 cond,
                 target,
             });
         }
-        Arm64Insn::ARM64_INS_TBNZ => {
-            let src = op_to_non_mem(&ops[0]);
+        Arm64Insn::ARM64_INS_CBZ | Arm64Insn::ARM64_INS_CBNZ | Arm64Insn::ARM64_INS_TBZ | Arm64Insn::ARM64_INS_TBNZ => {
+            let id = Arm64Insn::from(insn.id().0);
+            let mut src = op_to_non_mem(&ops[0])?;
 
-            let target = match &ops[2] {
-                ArchOperand::Arm64Operand(Arm64Operand {
+            // The test bit branches take the bit before the target
+            if matches!(id, Arm64Insn::ARM64_INS_TBZ | Arm64Insn::ARM64_INS_TBNZ) {
+                let expr::Expr::Num(bit) = op_to_non_mem(&ops[1])? else { return Err("Test bit operand type".to_string()) };
+                src = binary(expr::BinaryOp::And, src, expr::Expr::Num(1 << bit));
+            }
+
+            let target = match ops.last() {
+                Some(ArchOperand::Arm64Operand(Arm64Operand {
                     op_type: Arm64OperandType::Imm(val),
                     ..
-                }) => {
-                    if let Some(label) = addr_to_label.get(&(*val as u64)) {
-                        *label
-                    } else {
-                        let label = block.new_label();
-                        addr_to_label.insert(*val as u64, label);
-                        label
-                    }
-                }
-                _ => panic!("Branch operand type"),
+                })) => label_at(*val as u64, block, addr_to_label),
+                _ => return Err("Branch operand type".to_string()),
             };
 
+            let op = if matches!(id, Arm64Insn::ARM64_INS_CBZ | Arm64Insn::ARM64_INS_TBZ) { expr::BinaryOp::Eq } else { expr::BinaryOp::Ne };
             block.push(lir::Lir::Branch {
-                cond: Some(src),
+                cond: Some(binary(op, src, expr::Expr::Num(0))),
                 target,
             });
         }
-        // FIXME: This is wrong. What if it updates itself?
+        // The flags are compared if cond holds, and otherwise set to the immediate
+        Arm64Insn::ARM64_INS_CCMP | Arm64Insn::ARM64_INS_CCMN => {
+            let src1 = op_to_non_mem(&ops[0])?;
+            let mut src2 = op_to_non_mem(&ops[1])?;
+            let expr::Expr::Num(nzcv) = op_to_non_mem(&ops[2])? else { return Err("Flags operand type".to_string()) };
+            if Arm64Insn::from(insn.id().0) == Arm64Insn::ARM64_INS_CCMN {
+                src2 = negated(src2);
+            }
+
+            let Some(cond) = cond(arch_detail.cc()) else {
+                set_flags(block, src1, src2);
+                return Ok(());
+            };
+
+            let compare = block.new_label();
+            let otherwise = block.new_label();
+            let end = block.new_label();
+
+            block.push(lir::Lir::Branch { cond: Some(cond), target: compare });
+            block.push(lir::Lir::Label(otherwise));
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Name(NZCV.to_string()),
+                src: flags_value(nzcv),
+            });
+            block.push(lir::Lir::Branch { cond: None, target: end });
+            block.push(lir::Lir::Label(compare));
+            set_flags(block, src1, src2);
+            block.push(lir::Lir::Label(end));
+        }
         Arm64Insn::ARM64_INS_STP => {
-            let src1 = op_to_non_mem(&ops[0]);
-            let src2 = op_to_non_mem(&ops[1]);
-            let dest = op_to_mem_addr(&ops[2]);
-            let size = op_reg_size(&ops[0]);
+            let size = op_reg_size(&ops[0])?;
+            let src1 = stored(&ops[0], size)?;
+            let src2 = stored(&ops[1], size)?;
+            let dest = op_to_mem_addr(&ops[2])?;
 
             block.push(lir::Lir::Assign {
                 dst: expr::Expr::Deref {
@@ -440,43 +1226,411 @@ fn gen_insn(
                     ptr: Box::new(expr::Expr::Binary {
                         op: expr::BinaryOp::Add,
                         lhs: Box::new(dest),
-                        rhs: Box::new(expr::Expr::Num(8)),
+                        rhs: Box::new(expr::Expr::Num(size.byte_count() as i64)),
                     }),
                     size
                 },
                 src: src2,
             });
+            if let Some(update) = base_update(&ops, 2, arch_detail) {
+                block.push(update);
+            }
         }
-        Arm64Insn::ARM64_INS_LDP => {
-            let src1 = op_to_non_mem(&ops[0]);
-            let src2 = op_to_non_mem(&ops[1]);
-            let dest = op_to_mem_addr(&ops[2]);
-            let size = op_reg_size(&ops[0]);
+        Arm64Insn::ARM64_INS_LDP | Arm64Insn::ARM64_INS_LDPSW => {
+            let src1 = op_to_non_mem(&ops[0])?;
+            let src2 = op_to_non_mem(&ops[1])?;
+            let dest = op_to_mem_addr(&ops[2])?;
+            let (size, signed) = access_kind(Arm64Insn::from(insn.id().0), op_reg_size(&ops[0])?).unwrap();
 
             block.push(lir::Lir::Assign {
                 dst: src1,
-                src: expr::Expr::Deref {
+                src: loaded(expr::Expr::Deref {
                     ptr: Box::new(dest.clone()),
                     size
-                },
+                }, size, signed, &ops[0]),
             });
 
             block.push(lir::Lir::Assign {
                 dst: src2,
-                src: expr::Expr::Deref {
+                src: loaded(expr::Expr::Deref {
                     ptr: Box::new(expr::Expr::Binary {
                         op: expr::BinaryOp::Add,
                         lhs: Box::new(dest),
-                        rhs: Box::new(expr::Expr::Num(8)),
+                        rhs: Box::new(expr::Expr::Num(size.byte_count() as i64)),
                     }),
                     size
+                }, size, signed, &ops[1]),
+            });
+            if let Some(update) = base_update(&ops, 2, arch_detail) {
+                block.push(update);
+            }
+        }
+        Arm64Insn::ARM64_INS_MUL | Arm64Insn::ARM64_INS_SDIV | Arm64Insn::ARM64_INS_UDIV |
+        Arm64Insn::ARM64_INS_AND | Arm64Insn::ARM64_INS_ORR | Arm64Insn::ARM64_INS_EOR |
+        Arm64Insn::ARM64_INS_LSL | Arm64Insn::ARM64_INS_LSR | Arm64Insn::ARM64_INS_ASR => {
+            let op = match Arm64Insn::from(insn.id().0) {
+                Arm64Insn::ARM64_INS_MUL => expr::BinaryOp::Mul,
+                Arm64Insn::ARM64_INS_SDIV => expr::BinaryOp::Div,
+                Arm64Insn::ARM64_INS_UDIV => expr::BinaryOp::DivU,
+                Arm64Insn::ARM64_INS_AND => expr::BinaryOp::And,
+                Arm64Insn::ARM64_INS_ORR => expr::BinaryOp::Or,
+                Arm64Insn::ARM64_INS_EOR => expr::BinaryOp::Xor,
+                Arm64Insn::ARM64_INS_LSL => expr::BinaryOp::Shl,
+                Arm64Insn::ARM64_INS_LSR => expr::BinaryOp::Shr,
+                _ => expr::BinaryOp::Asr,
+            };
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
+            let src = match op {
+                expr::BinaryOp::Div => signed_op(op_reg_size(&ops[0])?, |sext, _| binary(op, sext(src1), sext(src2))),
+                // Only the shifted value is signed
+                expr::BinaryOp::Asr => signed_op(op_reg_size(&ops[0])?, |sext, _| binary(op, sext(src1), src2)),
+                _ => binary(op, src1, src2),
+            };
+            block.push(lir::Lir::Assign { dst, src });
+        }
+        // The second operand is inverted first
+        Arm64Insn::ARM64_INS_BIC | Arm64Insn::ARM64_INS_ORN | Arm64Insn::ARM64_INS_EON => {
+            let op = match Arm64Insn::from(insn.id().0) {
+                Arm64Insn::ARM64_INS_BIC => expr::BinaryOp::And,
+                Arm64Insn::ARM64_INS_ORN => expr::BinaryOp::Or,
+                _ => expr::BinaryOp::Xor,
+            };
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: binary(op, src1, binary(expr::BinaryOp::Xor, src2, expr::Expr::Num(-1))),
+            });
+        }
+        Arm64Insn::ARM64_INS_MVN => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: binary(expr::BinaryOp::Xor, src, expr::Expr::Num(-1)),
+            });
+        }
+        Arm64Insn::ARM64_INS_ROR => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: expr::Expr::Call {
+                    func: Box::new(expr::Expr::BuiltIn(expr::BuiltIn::Rotr)),
+                    args: vec![src1, src2],
                 },
             });
         }
-        _ => todo!(
-            "Unimplented instruction: {} {}",
+        Arm64Insn::ARM64_INS_NEG | Arm64Insn::ARM64_INS_NEGS => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            if Arm64Insn::from(insn.id().0) == Arm64Insn::ARM64_INS_NEGS {
+                set_flags(block, expr::Expr::Num(0), src.clone());
+            }
+            block.push(lir::Lir::Assign {
+                dst,
+                src: binary(expr::BinaryOp::Sub, expr::Expr::Num(0), src),
+            });
+        }
+        Arm64Insn::ARM64_INS_ADDS => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
+            set_flags(block, src1.clone(), negated(src2.clone()));
+            block.push(lir::Lir::Assign {
+                dst,
+                src: binary(expr::BinaryOp::Add, src1, src2),
+            });
+        }
+        // Logical results only set the zero and sign flags
+        Arm64Insn::ARM64_INS_ANDS | Arm64Insn::ARM64_INS_BICS => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let mut src2 = op_to_non_mem(&ops[2])?;
+            if Arm64Insn::from(insn.id().0) == Arm64Insn::ARM64_INS_BICS {
+                src2 = binary(expr::BinaryOp::Xor, src2, expr::Expr::Num(-1));
+            }
+            let result = binary(expr::BinaryOp::And, src1, src2);
+            set_flags(block, result.clone(), expr::Expr::Num(0));
+            block.push(lir::Lir::Assign {
+                dst,
+                src: result,
+            });
+        }
+        Arm64Insn::ARM64_INS_TST => {
+            let src1 = op_to_non_mem(&ops[0])?;
+            let src2 = op_to_non_mem(&ops[1])?;
+            if src1 == src2 {
+                set_flags(block, src1, expr::Expr::Num(0));
+            } else {
+                set_flags(block, binary(expr::BinaryOp::And, src1, src2), expr::Expr::Num(0));
+            }
+        }
+        Arm64Insn::ARM64_INS_CMN => {
+            let src1 = op_to_non_mem(&ops[0])?;
+            let src2 = op_to_non_mem(&ops[1])?;
+            set_flags(block, src1, negated(src2));
+        }
+        Arm64Insn::ARM64_INS_MADD | Arm64Insn::ARM64_INS_MSUB | Arm64Insn::ARM64_INS_MNEG |
+        Arm64Insn::ARM64_INS_SMULL | Arm64Insn::ARM64_INS_SMADDL | Arm64Insn::ARM64_INS_SMSUBL | Arm64Insn::ARM64_INS_SMNEGL |
+        Arm64Insn::ARM64_INS_UMULL | Arm64Insn::ARM64_INS_UMADDL | Arm64Insn::ARM64_INS_UMSUBL | Arm64Insn::ARM64_INS_UMNEGL => {
+            let id = Arm64Insn::from(insn.id().0);
+            let dst = op_to_non_mem(&ops[0])?;
+            let mut src1 = op_to_non_mem(&ops[1])?;
+            let mut src2 = op_to_non_mem(&ops[2])?;
+
+            // The long forms multiply the 32 bit sources into a 64 bit result
+            if matches!(id, Arm64Insn::ARM64_INS_SMULL | Arm64Insn::ARM64_INS_SMADDL | Arm64Insn::ARM64_INS_SMSUBL | Arm64Insn::ARM64_INS_SMNEGL) {
+                src1 = extended(src1, Arm64Extender::ARM64_EXT_SXTW);
+                src2 = extended(src2, Arm64Extender::ARM64_EXT_SXTW);
+            } else if matches!(id, Arm64Insn::ARM64_INS_UMULL | Arm64Insn::ARM64_INS_UMADDL | Arm64Insn::ARM64_INS_UMSUBL | Arm64Insn::ARM64_INS_UMNEGL) {
+                src1 = extended(src1, Arm64Extender::ARM64_EXT_UXTW);
+                src2 = extended(src2, Arm64Extender::ARM64_EXT_UXTW);
+            }
+
+            let product = binary(expr::BinaryOp::Mul, src1, src2);
+            let src = match id {
+                Arm64Insn::ARM64_INS_MADD | Arm64Insn::ARM64_INS_SMADDL | Arm64Insn::ARM64_INS_UMADDL =>
+                    binary(expr::BinaryOp::Add, op_to_non_mem(&ops[3])?, product),
+                Arm64Insn::ARM64_INS_MSUB | Arm64Insn::ARM64_INS_SMSUBL | Arm64Insn::ARM64_INS_UMSUBL =>
+                    binary(expr::BinaryOp::Sub, op_to_non_mem(&ops[3])?, product),
+                Arm64Insn::ARM64_INS_MNEG | Arm64Insn::ARM64_INS_SMNEGL | Arm64Insn::ARM64_INS_UMNEGL =>
+                    binary(expr::BinaryOp::Sub, expr::Expr::Num(0), product),
+                _ => product
+            };
+            block.push(lir::Lir::Assign { dst, src });
+        }
+        Arm64Insn::ARM64_INS_SMULH | Arm64Insn::ARM64_INS_UMULH => {
+            let builtin = if Arm64Insn::from(insn.id().0) == Arm64Insn::ARM64_INS_SMULH { expr::BuiltIn::MulHigh } else { expr::BuiltIn::MulHighU };
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: expr::Expr::Call {
+                    func: Box::new(expr::Expr::BuiltIn(builtin)),
+                    args: vec![src1, src2],
+                },
+            });
+        }
+        Arm64Insn::ARM64_INS_SXTB | Arm64Insn::ARM64_INS_SXTH | Arm64Insn::ARM64_INS_SXTW |
+        Arm64Insn::ARM64_INS_UXTB | Arm64Insn::ARM64_INS_UXTH => {
+            let ext = match Arm64Insn::from(insn.id().0) {
+                Arm64Insn::ARM64_INS_SXTB => Arm64Extender::ARM64_EXT_SXTB,
+                Arm64Insn::ARM64_INS_SXTH => Arm64Extender::ARM64_EXT_SXTH,
+                Arm64Insn::ARM64_INS_SXTW => Arm64Extender::ARM64_EXT_SXTW,
+                Arm64Insn::ARM64_INS_UXTB => Arm64Extender::ARM64_EXT_UXTB,
+                _ => Arm64Extender::ARM64_EXT_UXTH,
+            };
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: extended(src, ext),
+            });
+        }
+        // Bitfield moves, taking (dst, src, lsb, width)
+        Arm64Insn::ARM64_INS_UBFX | Arm64Insn::ARM64_INS_SBFX | Arm64Insn::ARM64_INS_UBFIZ | Arm64Insn::ARM64_INS_SBFIZ |
+        Arm64Insn::ARM64_INS_BFI | Arm64Insn::ARM64_INS_BFXIL => {
+            let id = Arm64Insn::from(insn.id().0);
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            let expr::Expr::Num(lsb) = op_to_non_mem(&ops[2])? else { return Err("Bitfield lsb".to_string()) };
+            let expr::Expr::Num(width) = op_to_non_mem(&ops[3])? else { return Err("Bitfield width".to_string()) };
+            let size = op_reg_size(&ops[0])?;
+            let mask = low_bits(width);
+
+            let src = match id {
+                Arm64Insn::ARM64_INS_UBFX => bitfield(src, lsb, width, size, false),
+                Arm64Insn::ARM64_INS_SBFX => signed_op(size, |sext, size| bitfield(sext(src), lsb, width, size, true)),
+                Arm64Insn::ARM64_INS_UBFIZ => binary(expr::BinaryOp::Shl, bitfield(src, 0, width, size, false), expr::Expr::Num(lsb)),
+                Arm64Insn::ARM64_INS_SBFIZ => signed_op(size, |sext, size| {
+                    binary(expr::BinaryOp::Shl, bitfield(sext(src), 0, width, size, true), expr::Expr::Num(lsb))
+                }),
+                // The rest of dst is kept
+                Arm64Insn::ARM64_INS_BFI => binary(
+                    expr::BinaryOp::Or,
+                    binary(expr::BinaryOp::And, dst.clone(), expr::Expr::Num(!(mask << lsb) & size_mask(size))),
+                    binary(expr::BinaryOp::Shl, bitfield(src, 0, width, size, false), expr::Expr::Num(lsb))
+                ),
+                _ => binary(
+                    expr::BinaryOp::Or,
+                    binary(expr::BinaryOp::And, dst.clone(), expr::Expr::Num(!mask & size_mask(size))),
+                    bitfield(src, lsb, width, size, false)
+                ),
+            };
+            block.push(lir::Lir::Assign { dst, src });
+        }
+        // Constants in every lane. The scalar form, as in `movi d0, #0`, is most often a float
+        Arm64Insn::ARM64_INS_MOVI | Arm64Insn::ARM64_INS_MVNI => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let ArchOperand::Arm64Operand(Arm64Operand { op_type: Arm64OperandType::Imm(imm), shift, .. }) = ops[1] else {
+                return Err("Vector immediate operand type".to_string());
+            };
+            // MSL shifts in ones
+            let imm = match shift {
+                Arm64Shift::Lsl(shift) => imm << shift,
+                Arm64Shift::Msl(shift) => imm << shift | low_bits(shift as i64),
+                _ => imm
+            };
+            let imm = if Arm64Insn::from(insn.id().0) == Arm64Insn::ARM64_INS_MVNI { !imm } else { imm };
+
+            let src = if let Some(shape) = op_shape(&ops[0], false) {
+                simd_call(expr::SimdOp::Splat(shape), vec![expr::Expr::Num(imm & size_mask(shape.lane_size()))])
+            } else if let Some(lane) = half_lane_size(&ops[0]) {
+                expr::Expr::Num(replicated(imm, lane))
+            } else {
+                float_bits(imm, op_reg_size(&ops[0])?)
+            };
+            block.push(lir::Lir::Assign { dst, src });
+        }
+        Arm64Insn::ARM64_INS_FMOV if is_vector_op(&ops[0]) => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let ArchOperand::Arm64Operand(Arm64Operand { op_type: Arm64OperandType::Fp(value), .. }) = ops[1] else {
+                return Err("Vector immediate operand type".to_string());
+            };
+
+            let src = if let Some(shape) = op_shape(&ops[0], true) {
+                simd_call(expr::SimdOp::Splat(shape), vec![expr::Expr::Float(value)])
+            } else if half_lane_size(&ops[0]) == Some(ty::Size::Size32) {
+                expr::Expr::Num(replicated((value as f32).to_bits() as i64, ty::Size::Size32))
+            } else {
+                return Err(format!("Unimplemented vector arrangement: {} {}", insn.mnemonic().unwrap(), insn.op_str().unwrap()));
+            };
+            block.push(lir::Lir::Assign { dst, src });
+        }
+        // A register, or one lane of a vector as in `dup v0.4s, v1.s[1]`, in every lane. To a scalar it only takes the lane
+        Arm64Insn::ARM64_INS_DUP => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let mut src = op_to_non_mem(&ops[1])?;
+            if let ArchOperand::Arm64Operand(Arm64Operand { vector_index: Some(index), .. }) = ops[1] {
+                let shape = lane_shape(&ops[1]).ok_or_else(|| "Vector lane arrangement".to_string())?;
+                src = simd_call(expr::SimdOp::ExtractLane(shape), vec![src, expr::Expr::Num(index as i64)]);
+            }
+
+            let src = if let Some(shape) = op_shape(&ops[0], false) {
+                simd_call(expr::SimdOp::Splat(shape), vec![src])
+            } else if let Some(lane) = half_lane_size(&ops[0]) {
+                let name = match lane {
+                    ty::Size::Size8 => "i8x8_splat",
+                    ty::Size::Size16 => "i16x4_splat",
+                    ty::Size::Size32 => "i32x2_splat",
+                    _ => "i64x1_splat",
+                };
+                simd_call(expr::SimdOp::Named(name, true), vec![src])
+            } else {
+                expr::Expr::Unary { op: expr::UnaryOp::BitsToFloat(op_reg_size(&ops[0])?), expr: Box::new(src) }
+            };
+            block.push(lir::Lir::Assign { dst, src });
+        }
+        Arm64Insn::ARM64_INS_FMOV => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            let size = op_reg_size(&ops[0])?;
+            let src = match (is_fp_op(&ops[0]), is_fp_op(&ops[1]) || matches!(src, expr::Expr::Float(_)), src) {
+                // Only the zero register gives a number here
+                (true, false, expr::Expr::Num(bits)) => float_bits(bits, size),
+                (true, false, src) => expr::Expr::Unary { op: expr::UnaryOp::BitsToFloat(size), expr: Box::new(src) },
+                (false, true, src) => expr::Expr::Unary { op: expr::UnaryOp::FloatToBits(size), expr: Box::new(src) },
+                (_, _, src) => src
+            };
+            block.push(lir::Lir::Assign { dst, src });
+        }
+        Arm64Insn::ARM64_INS_FADD | Arm64Insn::ARM64_INS_FSUB | Arm64Insn::ARM64_INS_FMUL | Arm64Insn::ARM64_INS_FDIV => {
+            let op = match Arm64Insn::from(insn.id().0) {
+                Arm64Insn::ARM64_INS_FADD => expr::BinaryOp::FAdd,
+                Arm64Insn::ARM64_INS_FSUB => expr::BinaryOp::FSub,
+                Arm64Insn::ARM64_INS_FMUL => expr::BinaryOp::FMul,
+                _ => expr::BinaryOp::FDiv,
+            };
+            let dst = op_to_non_mem(&ops[0])?;
+            let src1 = op_to_non_mem(&ops[1])?;
+            let src2 = op_to_non_mem(&ops[2])?;
+            block.push(lir::Lir::Assign { dst, src: binary(op, src1, src2) });
+        }
+        // The product is only rounded once, which this ignores
+        Arm64Insn::ARM64_INS_FMADD | Arm64Insn::ARM64_INS_FMSUB => {
+            let op = if Arm64Insn::from(insn.id().0) == Arm64Insn::ARM64_INS_FMADD { expr::BinaryOp::FAdd } else { expr::BinaryOp::FSub };
+            let dst = op_to_non_mem(&ops[0])?;
+            let product = binary(expr::BinaryOp::FMul, op_to_non_mem(&ops[1])?, op_to_non_mem(&ops[2])?);
+            let addend = op_to_non_mem(&ops[3])?;
+            block.push(lir::Lir::Assign { dst, src: binary(op, addend, product) });
+        }
+        Arm64Insn::ARM64_INS_FNEG => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: expr::Expr::Unary { op: expr::UnaryOp::Neg, expr: Box::new(src) },
+            });
+        }
+        Arm64Insn::ARM64_INS_FABS | Arm64Insn::ARM64_INS_FSQRT | Arm64Insn::ARM64_INS_FMIN | Arm64Insn::ARM64_INS_FMAX |
+        Arm64Insn::ARM64_INS_FRINTP | Arm64Insn::ARM64_INS_FRINTM | Arm64Insn::ARM64_INS_FRINTZ | Arm64Insn::ARM64_INS_FRINTN => {
+            let builtin = match Arm64Insn::from(insn.id().0) {
+                Arm64Insn::ARM64_INS_FABS => expr::BuiltIn::Abs,
+                Arm64Insn::ARM64_INS_FSQRT => expr::BuiltIn::Sqrt,
+                Arm64Insn::ARM64_INS_FMIN => expr::BuiltIn::Min,
+                Arm64Insn::ARM64_INS_FMAX => expr::BuiltIn::Max,
+                Arm64Insn::ARM64_INS_FRINTP => expr::BuiltIn::Ceil,
+                Arm64Insn::ARM64_INS_FRINTM => expr::BuiltIn::Floor,
+                Arm64Insn::ARM64_INS_FRINTZ => expr::BuiltIn::Trunc,
+                _ => expr::BuiltIn::Nearest,
+            };
+            let dst = op_to_non_mem(&ops[0])?;
+            let args = ops[1..].iter().map(op_to_non_mem).collect::<Result<_, _>>()?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: expr::Expr::Call { func: Box::new(expr::Expr::BuiltIn(builtin)), args },
+            });
+        }
+        // Signalling NaNs are the only difference between these
+        Arm64Insn::ARM64_INS_FCMP | Arm64Insn::ARM64_INS_FCMPE => {
+            let src1 = op_to_non_mem(&ops[0])?;
+            let src2 = op_to_non_mem(&ops[1])?;
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Name(NZCV.to_string()),
+                src: binary(expr::BinaryOp::FCmp, src1, src2),
+            });
+        }
+        Arm64Insn::ARM64_INS_SCVTF | Arm64Insn::ARM64_INS_UCVTF => {
+            let signed = Arm64Insn::from(insn.id().0) == Arm64Insn::ARM64_INS_SCVTF;
+            let dst = op_to_non_mem(&ops[0])?;
+            let mut src = op_to_non_mem(&ops[1])?;
+            if op_reg_size(&ops[1])? == ty::Size::Size32 {
+                src = extended(src, if signed { Arm64Extender::ARM64_EXT_SXTW } else { Arm64Extender::ARM64_EXT_UXTW });
+            }
+            block.push(lir::Lir::Assign {
+                dst,
+                src: expr::Expr::Unary { op: expr::UnaryOp::IntToFloat(op_reg_size(&ops[0])?, signed), expr: Box::new(src) },
+            });
+        }
+        Arm64Insn::ARM64_INS_FCVTZS | Arm64Insn::ARM64_INS_FCVTZU => {
+            let signed = Arm64Insn::from(insn.id().0) == Arm64Insn::ARM64_INS_FCVTZS;
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: expr::Expr::Unary { op: expr::UnaryOp::FloatToInt(op_reg_size(&ops[0])?, signed), expr: Box::new(src) },
+            });
+        }
+        Arm64Insn::ARM64_INS_FCVT => {
+            let dst = op_to_non_mem(&ops[0])?;
+            let src = op_to_non_mem(&ops[1])?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: expr::Expr::Unary { op: expr::UnaryOp::FloatToFloat(op_reg_size(&ops[0])?), expr: Box::new(src) },
+            });
+        }
+        _ => return Err(format!(
+            "Unimplemented instruction: {} {}",
             insn.mnemonic().unwrap(),
             insn.op_str().unwrap()
-        ),
+        )),
     }
+
+    Ok(())
 }
@@ -1,2 +1,3 @@
 pub mod armv8;
 pub mod wasm;
+pub mod x86_64;
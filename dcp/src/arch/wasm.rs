@@ -4,17 +4,34 @@ use wasmparser::Operator;
 
 use crate::{dataflow::Abi, lir, expr, ty};
 
+/// The name given to the shadow stack pointer global, which functions keep their frames below
+pub const STACK_POINTER: &str = "sp";
+
 pub fn abi() -> Abi {
     Abi {
         args: vec!["l0", "l1", "l2", "l3", "l4", "l5", "l6", "l7"],
-        global: vec!["sp"],
-        base_reg: None,
+        float_args: vec![],
+        results: vec![],
+        float_results: vec![],
+        global: vec![STACK_POINTER.to_string()],
+        base_reg: Some(STACK_POINTER),
         callee_saved: vec![],
-        eliminate: vec![]
+        eliminate: vec![],
+        calls_clobber: false
     }
 }
 
-fn local_name(idx: usize) -> String {
+pub fn valtype_to_ty(ty: wasmparser::ValType) -> ty::Ty {
+    match ty {
+        wasmparser::ValType::I32 => ty::Ty::Unknown32,
+        wasmparser::ValType::F32 => ty::Ty::Float { size: ty::Size::Size32 },
+        wasmparser::ValType::F64 => ty::Ty::Float { size: ty::Size::Size64 },
+        wasmparser::ValType::V128 => ty::Ty::Vector(None),
+        _ => ty::Ty::Unknown64
+    }
+}
+
+pub fn local_name(idx: usize) -> String {
     format!("l{idx}")
 }
 
@@ -65,21 +82,88 @@ impl StackNaming {
         StackName(self.names.last().cloned().unwrap())
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.names.is_empty()
+    /// A name for a value which is not on the stack yet
+    pub fn fresh(&mut self) -> usize {
+        self.next += 1;
+        self.next - 1
+    }
+
+    pub fn push_name(&mut self, name: usize) {
+        self.names.push(name);
+    }
+
+    pub fn height(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn truncate(&mut self, height: usize) {
+        self.names.truncate(height);
+    }
+
+    /// The top `n` values, bottom first (fewer if the stack is not that deep)
+    pub fn peek_n(&self, n: usize) -> Vec<StackName> {
+        self.names[self.names.len().saturating_sub(n)..].iter().cloned().map(StackName).collect()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum BlockKind {
+    Block,
+    Loop,
+    /// An if, which branches to `otherwise` when its condition is false
+    If { otherwise: lir::Label, has_else: bool },
+    /// A try, whose code starts at index `at`, with where each catch so far starts and the tag it catches, or None for all
+    Try { at: lir::Index, handlers: Vec<(Option<u32>, lir::Label)> },
+    /// A try_table, whose code starts at index `at`, with the blocks its catches branch to
+    TryTable { at: lir::Index, catches: Vec<TableCatch> }
+}
+
+/// A catch of a try_table, which branches to a block with the values thrown with the tag it catches (or None for all),
+/// followed by the exception itself if it is a `_ref` catch
+#[derive(Clone, PartialEq, Eq)]
+struct TableCatch {
+    tag: Option<u32>,
+    with_ref: bool,
+    target: lir::Label,
+    values: Vec<usize>
+}
+
+/// A wasm block. Values are passed in and out of blocks in fixed names,
+/// so every branch to a block leaves its values in the same place
+struct Block {
+    start: lir::Label,
+    end: lir::Label,
+    kind: BlockKind,
+    /// Stack depth under the block's params
+    height: usize,
+    params: Vec<usize>,
+    results: Vec<usize>
+}
+
+impl Block {
+    fn target(&self) -> lir::Label {
+        if self.kind == BlockKind::Loop { self.start } else { self.end }
+    }
+
+    /// Where branches to this block put their values
+    fn target_values(&self) -> &[usize] {
+        if self.kind == BlockKind::Loop { &self.params } else { &self.results }
     }
 }
 
 struct BlockStack {
-    blocks: Vec<(usize, usize, bool)>,
-    next: usize
+    blocks: Vec<Block>,
+    next: usize,
+    /// Whether the code being translated can't be reached, because it follows a branch, return or trap
+    dead: bool
 }
 
 impl BlockStack {
-    pub fn new() -> BlockStack {
+    pub fn new(results: Vec<usize>) -> BlockStack {
         BlockStack {
-            blocks: vec![(0, 1, false)],
-            next: 2
+            blocks: vec![Block { start: lir::Label(0), end: lir::Label(1), kind: BlockKind::Block, height: 0, params: vec![], results }],
+            next: 2,
+            dead: false
         }
     }
 
@@ -88,31 +172,46 @@ impl BlockStack {
         lir::Label(self.next - 1)
     }
 
-    pub fn push_block(&mut self, loops: bool) -> lir::Label {
-        self.blocks.push((self.next, self.next + 1, loops));
+    pub fn push_block(&mut self, kind: BlockKind, height: usize, params: Vec<usize>, results: Vec<usize>) -> lir::Label {
+        self.blocks.push(Block { start: lir::Label(self.next), end: lir::Label(self.next + 1), kind, height, params, results });
         self.next += 2;
         lir::Label(self.next - 2)
     }
 
-    pub fn pop(&mut self) -> lir::Label {
-        let (_, end, _) = self.blocks.pop().expect("Empty block stack");
-        lir::Label(end)
+    pub fn pop(&mut self) -> Block {
+        self.blocks.pop().expect("Empty block stack")
+    }
+
+    pub fn top_mut(&mut self) -> &mut Block {
+        self.blocks.last_mut().expect("Empty block stack")
+    }
+
+    pub fn get_rel(&self, rel: usize) -> &Block {
+        &self.blocks[self.blocks.len() - 1 - rel]
     }
 
     pub fn branch_target_rel(&self, rel: usize) -> lir::Label {
-        let (start, end, loops) = self.blocks[self.blocks.len() - 1 - rel];
-        if loops { lir::Label(start) } else { lir::Label(end) }
+        self.get_rel(rel).target()
     }
 
-    pub fn ret(&self) -> lir::Label {
-        lir::Label(self.blocks[0].1)
+    /// Whether the code being translated is in the body of a try, which can be left for a catch at any throw or call
+    pub fn in_try(&self) -> bool {
+        self.blocks.iter().any(|x| match &x.kind {
+            BlockKind::Try { handlers, .. } => handlers.is_empty(),
+            BlockKind::TryTable { .. } => true,
+            _ => false
+        })
     }
 }
 
 pub enum TranslationError<'a> {
     UnknownInstruction(wasmparser::Operator<'a>),
     Decode,
-    BadFunctionIndex
+    BadFunctionIndex,
+    BadTypeIndex,
+    BadGlobalIndex,
+    BadTableIndex,
+    BadTagIndex
 }
 
 impl<'a> Display for TranslationError<'a> {
@@ -120,46 +219,579 @@ impl<'a> Display for TranslationError<'a> {
         match self {
             TranslationError::UnknownInstruction(insn) => write!(f, "do not know how to translate {:?}", insn),
             TranslationError::Decode => write!(f, "malformed code"),
-            TranslationError::BadFunctionIndex => write!(f, "function indexed a type which does not exist")
+            TranslationError::BadFunctionIndex => write!(f, "function indexed a type which does not exist"),
+            TranslationError::BadTypeIndex => write!(f, "block indexed a type which does not exist"),
+            TranslationError::BadGlobalIndex => write!(f, "global index out of range"),
+            TranslationError::BadTableIndex => write!(f, "table index out of range"),
+            TranslationError::BadTagIndex => write!(f, "exception tag index out of range")
         }
     }
 }
 
-pub fn to_lir<'a>(function: &'a wasmparser::FunctionBody, func_types: &[wasmparser::FuncType], raw_types: &[wasmparser::FuncType]) -> Result<lir::LirFunc, TranslationError<'a>> {
+/// What the code of a function can refer to in the rest of its module
+pub struct ModuleInfo<'m> {
+    /// Types of functions by function index
+    pub func_types: &'m [wasmparser::FuncType],
+    /// The type section, for block and indirect call types
+    pub raw_types: &'m [wasmparser::FuncType],
+    /// Names to give globals by global index
+    pub globals: &'m [String],
+    /// Function indices held by each table
+    pub tables: &'m [Vec<Option<usize>>],
+    /// Types of the values thrown with each exception tag
+    pub tags: &'m [wasmparser::FuncType]
+}
+
+pub fn to_lir<'a>(function: &'a wasmparser::FunctionBody, ty: &wasmparser::FuncType, module: &ModuleInfo) -> Result<lir::LirFunc, TranslationError<'a>> {
     let mut block = lir::LirFuncBuilder::new();
 
-    let mut blocks = BlockStack::new();
     let mut stack = StackNaming::new();
+    let results = ty.results().iter().map(|_| stack.fresh()).collect();
+    let mut blocks = BlockStack::new(results);
+
+    // Locals other than the params start out as zero
+    let mut idx = ty.params().len();
+    for locals in function.get_locals_reader().map_err(|_| TranslationError::Decode)? {
+        let (count, local_ty) = locals.map_err(|_| TranslationError::Decode)?;
+        for _ in 0..count {
+            let zero = match local_ty {
+                wasmparser::ValType::V128 => simd_call(expr::SimdOp::Build(ty::Shape::I64x2), vec![expr::Expr::Num(0), expr::Expr::Num(0)]),
+                _ => expr::Expr::Num(0)
+            };
+            block.push(lir::Lir::Assign { dst: local_ref(idx), src: zero });
+            idx += 1;
+        }
+    }
 
+    // Blocks opened in dead code, which are skipped along with it
+    let mut dead_blocks = 0;
     for insn in function.get_operators_reader().expect("Could not make operators reader") {
         let insn = match insn {
             Ok(insn) => insn,
             Err(_) => return Err(TranslationError::Decode)
         };
+
+        if blocks.dead {
+            match insn {
+                Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } | Operator::Try { .. } | Operator::TryTable { .. } => {
+                    dead_blocks += 1;
+                    continue;
+                }
+                Operator::End | Operator::Delegate { .. } if dead_blocks > 0 => {
+                    dead_blocks -= 1;
+                    continue;
+                }
+                Operator::Else | Operator::Catch { .. } | Operator::CatchAll if dead_blocks > 0 => continue,
+                Operator::End | Operator::Delegate { .. } | Operator::Else | Operator::Catch { .. } | Operator::CatchAll => {}
+                _ => continue
+            }
+        }
+
         // println!("{:?}", insn);
-        gen_insn(insn, &mut block, &mut blocks, &mut stack, func_types, raw_types)?;
+        gen_insn(insn, &mut block, &mut blocks, &mut stack, ty, module)?;
     }
 
-    if stack.is_empty() {
-        block.push(lir::Lir::Return(expr::Expr::Num(0)));
-    } else {
-        block.push(lir::Lir::Return(stack.pop().expr()));
-    }
+    block.push(lir::Lir::Return(stack.peek_n(ty.results().len()).iter().map(StackName::expr).collect()));
 
     Ok(block.block())
 }
 
+/// Numbers of params and results of a block
+fn block_arity<'a>(blockty: wasmparser::BlockType, raw_types: &[wasmparser::FuncType]) -> Result<(usize, usize), TranslationError<'a>> {
+    match blockty {
+        wasmparser::BlockType::Empty => Ok((0, 0)),
+        wasmparser::BlockType::Type(_) => Ok((0, 1)),
+        wasmparser::BlockType::FuncType(idx) => raw_types.get(idx as usize)
+            .map(|ty| (ty.params().len(), ty.results().len()))
+            .ok_or(TranslationError::BadTypeIndex)
+    }
+}
+
+/// Moves the values on top of the stack into the names `to`
+fn copy_values(block: &mut lir::LirFuncBuilder, from: &[StackName], to: &[usize]) {
+    for (from, to) in from.iter().zip(to) {
+        if from.0 != *to {
+            block.push(lir::Lir::Assign { src: from.expr(), dst: StackName(*to).expr() });
+        }
+    }
+}
+
+/// Branches to the block rel levels out, taking the values it expects with it
+fn gen_branch(block: &mut lir::LirFuncBuilder, blocks: &mut BlockStack, stack: &StackNaming, cond: Option<expr::Expr>, rel: usize) {
+    let target = blocks.get_rel(rel);
+    let (label, values) = (target.target(), target.target_values().to_vec());
+
+    if values.is_empty() {
+        block.push(lir::Lir::Branch { cond, target: label });
+        block.push(lir::Lir::Label(blocks.tmp_label()));
+        return;
+    }
+
+    // The values must only be moved if the branch is taken
+    let skip = cond.map(|cond| {
+        let skip = blocks.tmp_label();
+        block.push(lir::Lir::Branch {
+            cond: Some(expr::Expr::Unary { op: expr::UnaryOp::Not, expr: Box::new(cond) }),
+            target: skip
+        });
+        block.push(lir::Lir::Label(blocks.tmp_label()));
+        skip
+    });
+
+    copy_values(block, &stack.peek_n(values.len()), &values);
+    block.push(lir::Lir::Branch { cond: None, target: label });
+    block.push(lir::Lir::Label(skip.unwrap_or_else(|| blocks.tmp_label())));
+}
+
+fn gen_unary<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, op: expr::UnaryOp) -> Result<(), TranslationError<'a>> {
+    let src = stack.pop();
+    let dst = stack.push();
+    block.push(lir::Lir::Assign {
+        src: expr::Expr::Unary { op, expr: src.bexpr() },
+        dst: dst.expr()
+    });
+    Ok(())
+}
+
+fn gen_binary<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, op: expr::BinaryOp) -> Result<(), TranslationError<'a>> {
+    let src2 = stack.pop();
+    let src1 = stack.pop();
+    let dst = stack.push();
+    block.push(lir::Lir::Assign {
+        src: expr::Expr::Binary { op, lhs: src1.bexpr(), rhs: src2.bexpr() },
+        dst: dst.expr()
+    });
+    Ok(())
+}
+
+fn gen_builtin<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, builtin: expr::BuiltIn, argc: usize) -> Result<(), TranslationError<'a>> {
+    let mut args = Vec::new();
+    for _ in 0..argc {
+        args.insert(0, stack.pop().expr());
+    }
+
+    let dst = stack.push();
+    block.push(lir::Lir::Assign {
+        dst: dst.expr(),
+        src: expr::Expr::Call { func: Box::new(expr::Expr::BuiltIn(builtin)), args }
+    });
+    Ok(())
+}
+
+/// As `gen_builtin`, for builtins which give nothing back
+fn gen_builtin_effect<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, builtin: expr::BuiltIn, argc: usize) -> Result<(), TranslationError<'a>> {
+    let mut args = Vec::new();
+    for _ in 0..argc {
+        args.insert(0, stack.pop().expr());
+    }
+
+    block.push(lir::Lir::Do(expr::Expr::Call { func: Box::new(expr::Expr::BuiltIn(builtin)), args }));
+    Ok(())
+}
+
+fn mem_addr(addr: StackName, memarg: wasmparser::MemArg) -> Box<expr::Expr> {
+    Box::new(expr::Expr::Binary {
+        op: expr::BinaryOp::Add,
+        lhs: addr.bexpr(),
+        rhs: Box::new(expr::Expr::Num(memarg.offset as i64))
+    })
+}
+
+/// Loads size bytes, then widens or reinterprets them with conv
+fn gen_load<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, memarg: wasmparser::MemArg, size: ty::Size, conv: Option<expr::UnaryOp>) -> Result<(), TranslationError<'a>> {
+    let addr = stack.pop();
+    let dst = stack.push();
+    let value = expr::Expr::Deref { ptr: mem_addr(addr, memarg), size };
+    block.push(lir::Lir::Assign {
+        dst: dst.expr(),
+        src: match conv {
+            Some(op) => expr::Expr::Unary { op, expr: Box::new(value) },
+            None => value
+        }
+    });
+    Ok(())
+}
+
+/// Stores the low size bytes of a value, after converting it with conv
+fn gen_store<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, memarg: wasmparser::MemArg, size: ty::Size, conv: Option<expr::UnaryOp>) -> Result<(), TranslationError<'a>> {
+    let src = stack.pop();
+    let addr = stack.pop();
+    block.push(lir::Lir::Assign {
+        dst: expr::Expr::Deref { ptr: mem_addr(addr, memarg), size },
+        src: match conv {
+            Some(op) => expr::Expr::Unary { op, expr: src.bexpr() },
+            None => src.expr()
+        }
+    });
+    Ok(())
+}
+
+fn simd_call(op: expr::SimdOp, args: Vec<expr::Expr>) -> expr::Expr {
+    expr::Expr::Call { func: Box::new(expr::Expr::BuiltIn(expr::BuiltIn::Simd(op))), args }
+}
+
+/// A vector load, from an address and for lane loads into a lane of a vector
+fn gen_simd_load<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, memarg: wasmparser::MemArg, name: &'static str, lane: Option<u8>) -> Result<(), TranslationError<'a>> {
+    let into = lane.map(|lane| (stack.pop(), lane));
+    let addr = stack.pop();
+    let mut args = vec![*mem_addr(addr, memarg)];
+    if let Some((vector, lane)) = into {
+        args.push(vector.expr());
+        args.push(expr::Expr::Num(lane as i64));
+    }
+
+    let dst = stack.push();
+    block.push(lir::Lir::Assign { dst: dst.expr(), src: simd_call(expr::SimdOp::Load(name), args) });
+    Ok(())
+}
+
+/// A vector store, of the whole vector or one lane of it
+fn gen_simd_store<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, memarg: wasmparser::MemArg, name: &'static str, lane: Option<u8>) -> Result<(), TranslationError<'a>> {
+    let vector = stack.pop();
+    let addr = stack.pop();
+    let mut args = vec![*mem_addr(addr, memarg), vector.expr()];
+    if let Some(lane) = lane {
+        args.push(expr::Expr::Num(lane as i64));
+    }
+
+    block.push(lir::Lir::Do(simd_call(expr::SimdOp::Store(name), args)));
+    Ok(())
+}
+
+/// Takes a lane out of a vector, sign extending it for the signed forms
+fn gen_extract_lane<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, shape: ty::Shape, lane: u8, signed: bool) -> Result<(), TranslationError<'a>> {
+    let vector = stack.pop();
+    let dst = stack.push();
+    let value = simd_call(expr::SimdOp::ExtractLane(shape), vec![vector.expr(), expr::Expr::Num(lane as i64)]);
+    block.push(lir::Lir::Assign {
+        dst: dst.expr(),
+        src: if signed {
+            expr::Expr::Unary { op: expr::UnaryOp::SignExtend(shape.lane_size()), expr: Box::new(value) }
+        } else {
+            value
+        }
+    });
+    Ok(())
+}
+
+fn gen_replace_lane<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, shape: ty::Shape, lane: u8) -> Result<(), TranslationError<'a>> {
+    let value = stack.pop();
+    let vector = stack.pop();
+    let dst = stack.push();
+    block.push(lir::Lir::Assign {
+        dst: dst.expr(),
+        src: simd_call(expr::SimdOp::ReplaceLane(shape), vec![vector.expr(), expr::Expr::Num(lane as i64), value.expr()])
+    });
+    Ok(())
+}
+
+/// The name of a SIMD operator in the text format, with `_` for its `.`, from the name of its visitor
+macro_rules! define_simd_name {
+    (@name simd $visit:ident) => { Some(&stringify!($visit)["visit_".len()..]) };
+    (@name relaxed_simd $visit:ident) => { Some(&stringify!($visit)["visit_".len()..]) };
+    (@name $proposal:ident $visit:ident) => { None };
+    ($( @$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident ($($ann:tt)*))*) => {
+        fn simd_name(insn: &Operator) -> Option<&'static str> {
+            match insn {
+                $(Operator::$op { .. } => define_simd_name!(@name $proposal $visit),)*
+                _ => None
+            }
+        }
+    };
+}
+
+wasmparser::for_each_operator!(define_simd_name);
+
+/// The shape and scalar operator of SIMD operators that apply one to each lane
+fn lanewise(insn: &Operator) -> Option<(ty::Shape, expr::BinaryOp)> {
+    use ty::Shape::*;
+    use expr::BinaryOp::*;
+
+    Some(match insn {
+        Operator::V128And => (I64x2, And),
+        Operator::V128Or => (I64x2, Or),
+        Operator::V128Xor => (I64x2, Xor),
+        Operator::I8x16Add => (I8x16, Add),
+        Operator::I8x16Sub => (I8x16, Sub),
+        Operator::I8x16Shl => (I8x16, Shl),
+        Operator::I8x16ShrS => (I8x16, Asr),
+        Operator::I8x16ShrU => (I8x16, Shr),
+        Operator::I8x16Eq => (I8x16, Eq),
+        Operator::I8x16Ne => (I8x16, Ne),
+        Operator::I8x16LtS => (I8x16, Lt),
+        Operator::I8x16LtU => (I8x16, LtU),
+        Operator::I8x16GtS => (I8x16, Gt),
+        Operator::I8x16GtU => (I8x16, GtU),
+        Operator::I8x16LeS => (I8x16, Le),
+        Operator::I8x16LeU => (I8x16, LeU),
+        Operator::I8x16GeS => (I8x16, Ge),
+        Operator::I8x16GeU => (I8x16, GeU),
+        Operator::I16x8Add => (I16x8, Add),
+        Operator::I16x8Sub => (I16x8, Sub),
+        Operator::I16x8Mul => (I16x8, Mul),
+        Operator::I16x8Shl => (I16x8, Shl),
+        Operator::I16x8ShrS => (I16x8, Asr),
+        Operator::I16x8ShrU => (I16x8, Shr),
+        Operator::I16x8Eq => (I16x8, Eq),
+        Operator::I16x8Ne => (I16x8, Ne),
+        Operator::I16x8LtS => (I16x8, Lt),
+        Operator::I16x8LtU => (I16x8, LtU),
+        Operator::I16x8GtS => (I16x8, Gt),
+        Operator::I16x8GtU => (I16x8, GtU),
+        Operator::I16x8LeS => (I16x8, Le),
+        Operator::I16x8LeU => (I16x8, LeU),
+        Operator::I16x8GeS => (I16x8, Ge),
+        Operator::I16x8GeU => (I16x8, GeU),
+        Operator::I32x4Add => (I32x4, Add),
+        Operator::I32x4Sub => (I32x4, Sub),
+        Operator::I32x4Mul => (I32x4, Mul),
+        Operator::I32x4Shl => (I32x4, Shl),
+        Operator::I32x4ShrS => (I32x4, Asr),
+        Operator::I32x4ShrU => (I32x4, Shr),
+        Operator::I32x4Eq => (I32x4, Eq),
+        Operator::I32x4Ne => (I32x4, Ne),
+        Operator::I32x4LtS => (I32x4, Lt),
+        Operator::I32x4LtU => (I32x4, LtU),
+        Operator::I32x4GtS => (I32x4, Gt),
+        Operator::I32x4GtU => (I32x4, GtU),
+        Operator::I32x4LeS => (I32x4, Le),
+        Operator::I32x4LeU => (I32x4, LeU),
+        Operator::I32x4GeS => (I32x4, Ge),
+        Operator::I32x4GeU => (I32x4, GeU),
+        Operator::I64x2Add => (I64x2, Add),
+        Operator::I64x2Sub => (I64x2, Sub),
+        Operator::I64x2Mul => (I64x2, Mul),
+        Operator::I64x2Shl => (I64x2, Shl),
+        Operator::I64x2ShrS => (I64x2, Asr),
+        Operator::I64x2ShrU => (I64x2, Shr),
+        Operator::I64x2Eq => (I64x2, Eq),
+        Operator::I64x2Ne => (I64x2, Ne),
+        Operator::I64x2LtS => (I64x2, Lt),
+        Operator::I64x2GtS => (I64x2, Gt),
+        Operator::I64x2LeS => (I64x2, Le),
+        Operator::I64x2GeS => (I64x2, Ge),
+        Operator::F32x4Add => (F32x4, FAdd),
+        Operator::F32x4Sub => (F32x4, FSub),
+        Operator::F32x4Mul => (F32x4, FMul),
+        Operator::F32x4Div => (F32x4, FDiv),
+        Operator::F32x4Eq => (F32x4, FEq),
+        Operator::F32x4Ne => (F32x4, FNe),
+        Operator::F32x4Lt => (F32x4, FLt),
+        Operator::F32x4Gt => (F32x4, FGt),
+        Operator::F32x4Le => (F32x4, FLe),
+        Operator::F32x4Ge => (F32x4, FGe),
+        Operator::F64x2Add => (F64x2, FAdd),
+        Operator::F64x2Sub => (F64x2, FSub),
+        Operator::F64x2Mul => (F64x2, FMul),
+        Operator::F64x2Div => (F64x2, FDiv),
+        Operator::F64x2Eq => (F64x2, FEq),
+        Operator::F64x2Ne => (F64x2, FNe),
+        Operator::F64x2Lt => (F64x2, FLt),
+        Operator::F64x2Gt => (F64x2, FGt),
+        Operator::F64x2Le => (F64x2, FLe),
+        Operator::F64x2Ge => (F64x2, FGe),
+        _ => return None
+    })
+}
+
+/// Calls func with arguments from the stack, pushing what it returns
+fn gen_call<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, func: expr::Expr, ty: &wasmparser::FuncType) -> Result<(), TranslationError<'a>> {
+    let mut args = Vec::new();
+    for _ in ty.params() {
+        args.insert(0, stack.pop().expr());
+    }
+
+    let call = expr::Expr::Call { func: Box::new(func), args };
+    match ty.results().len() {
+        0 => block.push(lir::Lir::Do(call)),
+        1 => block.push(lir::Lir::Assign { dst: stack.push().expr(), src: call }),
+        // The values are taken one by one after the call, each to a name of its own
+        n => {
+            block.push(lir::Lir::Do(call));
+            for i in 0..n {
+                let src = expr::Expr::Call { func: Box::new(expr::Expr::BuiltIn(expr::BuiltIn::Result)), args: vec![expr::Expr::Num(i as i64)] };
+                block.push(lir::Lir::Assign { dst: stack.push().expr(), src });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Calls through an element of a table, which could be anything in it of the right type
+fn gen_call_indirect<'a>(block: &mut lir::LirFuncBuilder, stack: &mut StackNaming, module: &ModuleInfo, type_index: u32, table_index: u32) -> Result<(), TranslationError<'a>> {
+    let ty = module.raw_types.get(type_index as usize).ok_or(TranslationError::BadTypeIndex)?;
+    let table = module.tables.get(table_index as usize).ok_or(TranslationError::BadTableIndex)?;
+    let index = stack.pop();
+
+    let mut candidates = vec![];
+    for func in table.iter().flatten() {
+        if module.func_types.get(*func) == Some(ty) && !candidates.contains(&expr::FuncId(*func)) {
+            candidates.push(expr::FuncId(*func));
+        }
+    }
+
+    gen_call(block, stack, expr::Expr::Table { table: table_index, index: index.bexpr(), candidates }, ty)
+}
+
+fn gen_return(block: &mut lir::LirFuncBuilder, blocks: &mut BlockStack, stack: &StackNaming, ty: &wasmparser::FuncType) {
+    block.push(lir::Lir::Return(stack.peek_n(ty.results().len()).iter().map(StackName::expr).collect()));
+    block.push(lir::Lir::Label(blocks.tmp_label()));
+    blocks.dead = true;
+}
+
+fn exception_call(builtin: expr::BuiltIn, args: Vec<expr::Expr>) -> expr::Expr {
+    expr::Expr::Call { func: Box::new(expr::Expr::BuiltIn(builtin)), args }
+}
+
+/// Code for the start of a try, which runs the body at start if it doesn't throw, and otherwise tries the handlers in turn,
+/// with the exception thrown on if none take it
+fn gen_dispatch(blocks: &mut BlockStack, start: lir::Label, handlers: Vec<(Option<u32>, lir::Label)>) -> Vec<lir::Lir> {
+    let mut dispatch = vec![
+        lir::Lir::Branch {
+            cond: Some(expr::Expr::Unary { op: expr::UnaryOp::Not, expr: Box::new(exception_call(expr::BuiltIn::Catch, vec![])) }),
+            target: start
+        },
+        lir::Lir::Label(blocks.tmp_label())
+    ];
+    let mut catch_all = None;
+    for (tag, handler) in handlers {
+        match tag {
+            Some(tag) => {
+                dispatch.push(lir::Lir::Branch { cond: Some(exception_call(expr::BuiltIn::Catch, vec![expr::Expr::Num(tag as i64)])), target: handler });
+                dispatch.push(lir::Lir::Label(blocks.tmp_label()));
+            }
+            None => catch_all = Some(handler)
+        }
+    }
+    match catch_all {
+        Some(handler) => {
+            dispatch.push(lir::Lir::Branch { cond: None, target: handler });
+            dispatch.push(lir::Lir::Label(blocks.tmp_label()));
+        }
+        None => dispatch.push(lir::Lir::Do(exception_call(expr::BuiltIn::Rethrow, vec![])))
+    }
+    dispatch
+}
+
 fn gen_insn<'a>(
     insn: wasmparser::Operator<'a>,
     block: &mut lir::LirFuncBuilder,
     blocks: &mut BlockStack, stack: &mut StackNaming,
-    func_types: &[wasmparser::FuncType],
-    raw_types: &[wasmparser::FuncType]
+    ty: &wasmparser::FuncType,
+    module: &ModuleInfo
 ) -> Result<(), TranslationError<'a>> {
     match insn {
-        Operator::End => {
-            let end = blocks.pop();
-            block.push(lir::Lir::Label(end));
+        // A delegate hands what the try throws to a try further out, which is where it would go anyway without handlers here
+        Operator::End | Operator::Delegate { .. } => {
+            let ended = blocks.pop();
+            if !blocks.dead {
+                copy_values(block, &stack.peek_n(ended.results.len()), &ended.results);
+            }
+
+            // Without an else, the params are passed straight through when the condition is false
+            if let BlockKind::If { otherwise, has_else: false } = ended.kind {
+                if !ended.results.is_empty() {
+                    block.push(lir::Lir::Branch { cond: None, target: ended.end });
+                }
+                block.push(lir::Lir::Label(otherwise));
+                copy_values(block, &ended.params.iter().cloned().map(StackName).collect::<Vec<_>>(), &ended.results);
+            }
+
+            stack.truncate(ended.height);
+            for result in &ended.results {
+                stack.push_name(*result);
+            }
+            blocks.dead = false;
+
+            block.push(lir::Lir::Label(ended.end));
+
+            // The handlers go where the try starts
+            match ended.kind {
+                BlockKind::Try { at, handlers } if !handlers.is_empty() => {
+                    let dispatch = gen_dispatch(blocks, ended.start, handlers);
+                    block.insert(at, dispatch);
+                }
+                // The handlers of a try_table just pass what was thrown to the blocks its catches name
+                BlockKind::TryTable { at, catches } => {
+                    let handlers: Vec<_> = catches.iter().map(|catch| (catch.tag, blocks.tmp_label())).collect();
+                    let mut dispatch = gen_dispatch(blocks, ended.start, handlers.clone());
+                    for (catch, (_, handler)) in catches.into_iter().zip(handlers) {
+                        let thrown = match catch.tag {
+                            Some(tag) => module.tags.get(tag as usize).ok_or(TranslationError::BadTagIndex)?.params().len(),
+                            None => 0
+                        };
+                        let mut values: Vec<_> = (0..thrown).map(|i| exception_call(expr::BuiltIn::Exception, vec![expr::Expr::Num(i as i64)])).collect();
+                        if catch.with_ref {
+                            values.push(exception_call(expr::BuiltIn::Exception, vec![]));
+                        }
+
+                        dispatch.push(lir::Lir::Label(handler));
+                        for (src, dst) in values.into_iter().zip(catch.values) {
+                            dispatch.push(lir::Lir::Assign { dst: StackName(dst).expr(), src });
+                        }
+                        dispatch.push(lir::Lir::Branch { cond: None, target: catch.target });
+                        dispatch.push(lir::Lir::Label(blocks.tmp_label()));
+                    }
+                    block.insert(at, dispatch);
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+        Operator::Else => {
+            let dead = blocks.dead;
+            let top = blocks.top_mut();
+            let BlockKind::If { otherwise, .. } = top.kind else {
+                return Err(TranslationError::Decode);
+            };
+            top.kind = BlockKind::If { otherwise, has_else: true };
+            let (end, height, params, results) = (top.end, top.height, top.params.clone(), top.results.clone());
+
+            if !dead {
+                copy_values(block, &stack.peek_n(results.len()), &results);
+            }
+            block.push(lir::Lir::Branch { cond: None, target: end });
+            block.push(lir::Lir::Label(otherwise));
+
+            stack.truncate(height);
+            for param in params {
+                stack.push_name(param);
+            }
+            blocks.dead = false;
+            Ok(())
+        }
+        Operator::Catch { .. } | Operator::CatchAll => {
+            let tag = match insn {
+                Operator::Catch { tag_index } => Some(tag_index),
+                _ => None
+            };
+            let values = match tag {
+                Some(tag) => module.tags.get(tag as usize).ok_or(TranslationError::BadTagIndex)?.params().len(),
+                None => 0
+            };
+
+            let dead = blocks.dead;
+            let handler = blocks.tmp_label();
+            let top = blocks.top_mut();
+            let BlockKind::Try { handlers, .. } = &mut top.kind else {
+                return Err(TranslationError::Decode);
+            };
+            handlers.push((tag, handler));
+            let (end, height, results) = (top.end, top.height, top.results.clone());
+
+            if !dead {
+                copy_values(block, &stack.peek_n(results.len()), &results);
+            }
+            block.push(lir::Lir::Branch { cond: None, target: end });
+            block.push(lir::Lir::Label(handler));
+
+            stack.truncate(height);
+            for i in 0..values {
+                let dst = stack.push();
+                block.push(lir::Lir::Assign { dst: dst.expr(), src: exception_call(expr::BuiltIn::Exception, vec![expr::Expr::Num(i as i64)]) });
+            }
+            blocks.dead = false;
             Ok(())
         }
         Operator::LocalGet { local_index } => {
@@ -168,11 +800,18 @@ fn gen_insn<'a>(
             Ok(())
         }
         Operator::LocalSet { local_index } => {
+            // The handlers of a try are placed as if they ran before its body, so what the body leaves in locals for them must not be lost
+            if blocks.in_try() {
+                block.pin(local_name(local_index as usize));
+            }
             let src = stack.pop();
             block.push(lir::Lir::Assign { src: src.expr(), dst: local_ref(local_index as usize) });
             Ok(())
         }
         Operator::LocalTee { local_index } => {
+            if blocks.in_try() {
+                block.pin(local_name(local_index as usize));
+            }
             let src = stack.peek();
             block.push(lir::Lir::Assign { src: src.expr(), dst: local_ref(local_index as usize) });
             Ok(())
@@ -187,6 +826,16 @@ fn gen_insn<'a>(
             block.push(lir::Lir::Assign { src: expr::Expr::Num(value), dst: dst.expr() });
             Ok(())
         }
+        Operator::F32Const { value } => {
+            let dst = stack.push();
+            block.push(lir::Lir::Assign { src: expr::Expr::Float(f32::from_bits(value.bits()) as f64), dst: dst.expr() });
+            Ok(())
+        }
+        Operator::F64Const { value } => {
+            let dst = stack.push();
+            block.push(lir::Lir::Assign { src: expr::Expr::Float(f64::from_bits(value.bits())), dst: dst.expr() });
+            Ok(())
+        }
         Operator::Select => {
             let i = stack.pop();
             let v2 = stack.pop();
@@ -211,247 +860,293 @@ fn gen_insn<'a>(
             block.push(lir::Lir::Label(end));
             Ok(())
         }
-        Operator::I32Add | Operator::I64Add | Operator::F32Add | Operator::F64Add |
-        Operator::I32And | Operator::I64And |
-        Operator::I32Or | Operator::I64Or |
-        Operator::I32Shl | Operator::I64Shl |
-        Operator::I32ShrU | Operator::I64ShrU |
-        Operator::I32Xor | Operator::I64Xor |
-        Operator::I32Sub | Operator::I64Sub | Operator::F32Sub | Operator::F64Sub => {
-            let src2 = stack.pop();
-            let src1 = stack.pop();
+        Operator::I32Add | Operator::I64Add => gen_binary(block, stack, expr::BinaryOp::Add),
+        Operator::I32Sub | Operator::I64Sub => gen_binary(block, stack, expr::BinaryOp::Sub),
+        Operator::I32Mul | Operator::I64Mul => gen_binary(block, stack, expr::BinaryOp::Mul),
+        Operator::I32DivS | Operator::I64DivS => gen_binary(block, stack, expr::BinaryOp::Div),
+        Operator::I32DivU | Operator::I64DivU => gen_binary(block, stack, expr::BinaryOp::DivU),
+        Operator::I32RemS | Operator::I64RemS => gen_binary(block, stack, expr::BinaryOp::Rem),
+        Operator::I32RemU | Operator::I64RemU => gen_binary(block, stack, expr::BinaryOp::RemU),
+        Operator::I32And | Operator::I64And => gen_binary(block, stack, expr::BinaryOp::And),
+        Operator::I32Or | Operator::I64Or => gen_binary(block, stack, expr::BinaryOp::Or),
+        Operator::I32Xor | Operator::I64Xor => gen_binary(block, stack, expr::BinaryOp::Xor),
+        Operator::I32Shl | Operator::I64Shl => gen_binary(block, stack, expr::BinaryOp::Shl),
+        Operator::I32ShrS | Operator::I64ShrS => gen_binary(block, stack, expr::BinaryOp::Asr),
+        Operator::I32ShrU | Operator::I64ShrU => gen_binary(block, stack, expr::BinaryOp::Shr),
+        Operator::I32Rotl | Operator::I64Rotl => gen_builtin(block, stack, expr::BuiltIn::Rotl, 2),
+        Operator::I32Rotr | Operator::I64Rotr => gen_builtin(block, stack, expr::BuiltIn::Rotr, 2),
+        Operator::I32Clz | Operator::I64Clz => gen_builtin(block, stack, expr::BuiltIn::Clz, 1),
+        Operator::I32Ctz | Operator::I64Ctz => gen_builtin(block, stack, expr::BuiltIn::Ctz, 1),
+        Operator::I32Popcnt | Operator::I64Popcnt => gen_builtin(block, stack, expr::BuiltIn::Popcnt, 1),
+        Operator::F32Add | Operator::F64Add => gen_binary(block, stack, expr::BinaryOp::FAdd),
+        Operator::F32Sub | Operator::F64Sub => gen_binary(block, stack, expr::BinaryOp::FSub),
+        Operator::F32Mul | Operator::F64Mul => gen_binary(block, stack, expr::BinaryOp::FMul),
+        Operator::F32Div | Operator::F64Div => gen_binary(block, stack, expr::BinaryOp::FDiv),
+        Operator::F32Min | Operator::F64Min => gen_builtin(block, stack, expr::BuiltIn::Min, 2),
+        Operator::F32Max | Operator::F64Max => gen_builtin(block, stack, expr::BuiltIn::Max, 2),
+        Operator::F32Copysign | Operator::F64Copysign => gen_builtin(block, stack, expr::BuiltIn::Copysign, 2),
+        Operator::F32Abs | Operator::F64Abs => gen_builtin(block, stack, expr::BuiltIn::Abs, 1),
+        Operator::F32Neg | Operator::F64Neg => gen_unary(block, stack, expr::UnaryOp::Neg),
+        Operator::F32Ceil | Operator::F64Ceil => gen_builtin(block, stack, expr::BuiltIn::Ceil, 1),
+        Operator::F32Floor | Operator::F64Floor => gen_builtin(block, stack, expr::BuiltIn::Floor, 1),
+        Operator::F32Trunc | Operator::F64Trunc => gen_builtin(block, stack, expr::BuiltIn::Trunc, 1),
+        Operator::F32Nearest | Operator::F64Nearest => gen_builtin(block, stack, expr::BuiltIn::Nearest, 1),
+        Operator::F32Sqrt | Operator::F64Sqrt => gen_builtin(block, stack, expr::BuiltIn::Sqrt, 1),
+        Operator::GlobalGet { global_index } => {
+            let global = module.globals.get(global_index as usize).ok_or(TranslationError::BadGlobalIndex)?;
             let dst = stack.push();
-            block.push(lir::Lir::Assign {
-                src: expr::Expr::Binary {
-                    op: match insn {
-                        Operator::I32Add | Operator::I64Add | Operator::F32Add | Operator::F64Add => expr::BinaryOp::Add,
-                        Operator::I32And | Operator::I64And => expr::BinaryOp::And,
-                        Operator::I32Or | Operator::I64Or => expr::BinaryOp::Or,
-                        Operator::I32Sub | Operator::I64Sub | Operator::F32Sub | Operator::F64Sub => expr::BinaryOp::Sub,
-                        Operator::I32Shl | Operator::I64Shl => expr::BinaryOp::Shl,
-                        Operator::I32ShrU | Operator::I64ShrU => expr::BinaryOp::Shr,
-                        Operator::I32Xor | Operator::I64Xor => expr::BinaryOp::Xor,
-                        _ => unreachable!()
-                    },
-                    lhs: src1.bexpr(),
-                    rhs: src2.bexpr(),
-                },
-                dst: dst.expr()
-            });
+            block.push(lir::Lir::Assign { src: expr::Expr::Name(global.clone()), dst: dst.expr() });
             Ok(())
         }
-        Operator::I32Rotr | Operator::I64Rotr |
-        Operator::I32Rotl | Operator::I64Rotl => {
-            let src2 = stack.pop();
-            let src1 = stack.pop();
-            let dst = stack.push();
-
-            block.push(lir::Lir::Assign {
-                dst: dst.expr(),
-                src: expr::Expr::Call {
-                    func: Box::new(expr::Expr::BuiltIn(match insn {
-                        Operator::I32Rotr | Operator::I64Rotr => expr::BuiltIn::Rotr,
-                        Operator::I32Rotl | Operator::I64Rotl => expr::BuiltIn::Rotl,
-                        _ => unreachable!()
-                    })),
-                    args: vec![src1.expr(), src2.expr()]
-                }
-            });
+        Operator::GlobalSet { global_index } => {
+            let global = module.globals.get(global_index as usize).ok_or(TranslationError::BadGlobalIndex)?;
+            let src = stack.pop();
+            block.push(lir::Lir::Assign { src: src.expr(), dst: expr::Expr::Name(global.clone()) });
             Ok(())
         }
-        Operator::I32Ctz | Operator::I64Ctz |
-        Operator::I32Clz | Operator::I64Clz => {
-            let src1 = stack.pop();
+        Operator::I32Store { memarg } | Operator::I64Store32 { memarg } => gen_store(block, stack, memarg, ty::Size::Size32, None),
+        Operator::I64Store { memarg } => gen_store(block, stack, memarg, ty::Size::Size64, None),
+        Operator::I32Store8 { memarg } | Operator::I64Store8 { memarg } => gen_store(block, stack, memarg, ty::Size::Size8, None),
+        Operator::I32Store16 { memarg } | Operator::I64Store16 { memarg } => gen_store(block, stack, memarg, ty::Size::Size16, None),
+        Operator::F32Store { memarg } => gen_store(block, stack, memarg, ty::Size::Size32, Some(expr::UnaryOp::FloatToBits(ty::Size::Size32))),
+        Operator::F64Store { memarg } => gen_store(block, stack, memarg, ty::Size::Size64, Some(expr::UnaryOp::FloatToBits(ty::Size::Size64))),
+        Operator::I32Load { memarg } | Operator::I64Load32U { memarg } => gen_load(block, stack, memarg, ty::Size::Size32, None),
+        Operator::I64Load { memarg } => gen_load(block, stack, memarg, ty::Size::Size64, None),
+        Operator::I32Load8U { memarg } | Operator::I64Load8U { memarg } => gen_load(block, stack, memarg, ty::Size::Size8, None),
+        Operator::I32Load16U { memarg } | Operator::I64Load16U { memarg } => gen_load(block, stack, memarg, ty::Size::Size16, None),
+        Operator::I32Load8S { memarg } | Operator::I64Load8S { memarg } => gen_load(block, stack, memarg, ty::Size::Size8, Some(expr::UnaryOp::SignExtend(ty::Size::Size8))),
+        Operator::I32Load16S { memarg } | Operator::I64Load16S { memarg } => gen_load(block, stack, memarg, ty::Size::Size16, Some(expr::UnaryOp::SignExtend(ty::Size::Size16))),
+        Operator::I64Load32S { memarg } => gen_load(block, stack, memarg, ty::Size::Size32, Some(expr::UnaryOp::SignExtend(ty::Size::Size32))),
+        Operator::F32Load { memarg } => gen_load(block, stack, memarg, ty::Size::Size32, Some(expr::UnaryOp::BitsToFloat(ty::Size::Size32))),
+        Operator::F64Load { memarg } => gen_load(block, stack, memarg, ty::Size::Size64, Some(expr::UnaryOp::BitsToFloat(ty::Size::Size64))),
+        Operator::MemorySize { .. } => gen_builtin(block, stack, expr::BuiltIn::MemorySize, 0),
+        Operator::MemoryGrow { .. } => gen_builtin(block, stack, expr::BuiltIn::MemoryGrow, 1),
+        Operator::MemoryCopy { .. } => gen_builtin_effect(block, stack, expr::BuiltIn::MemoryCopy, 3),
+        Operator::MemoryFill { .. } => gen_builtin_effect(block, stack, expr::BuiltIn::MemoryFill, 3),
+        Operator::TableGet { table } => {
+            let index = stack.pop();
             let dst = stack.push();
-
             block.push(lir::Lir::Assign {
                 dst: dst.expr(),
-                src: expr::Expr::Call {
-                    func: Box::new(expr::Expr::BuiltIn(match insn {
-                        Operator::I32Ctz | Operator::I64Ctz => expr::BuiltIn::Ctz,
-                        Operator::I32Clz | Operator::I64Clz => expr::BuiltIn::Clz,
-                        _ => unreachable!()
-                    })),
-                    args: vec![src1.expr()]
-                }
+                src: expr::Expr::Table { table, index: index.bexpr(), candidates: vec![] }
             });
             Ok(())
         }
-        Operator::GlobalGet { global_index } => {
-            let dst = stack.push();
+        Operator::TableSet { table } => {
+            let src = stack.pop();
+            let index = stack.pop();
             block.push(lir::Lir::Assign {
-                src: expr::Expr::Name(match global_index {
-                    0 => "sp".to_string(),
-                    _ => format!("g{global_index}").to_string()
-                }),
-                dst: dst.expr()
+                dst: expr::Expr::Table { table, index: index.bexpr(), candidates: vec![] },
+                src: src.expr()
             });
             Ok(())
         }
-        Operator::GlobalSet { global_index } => {
-            let src = stack.pop();
-            block.push(lir::Lir::Assign {
-                src: src.expr(),
-                dst: expr::Expr::Name(match global_index {
-                    0 => "sp".to_string(),
-                    _ => format!("g{global_index}").to_string()
-                }) // FIXME: Mark as being global somehow
-            });
+        // References are only to functions here, so null can be the 0 that empty table entries hold
+        Operator::RefNull { .. } => {
+            let dst = stack.push();
+            block.push(lir::Lir::Assign { dst: dst.expr(), src: expr::Expr::Num(0) });
             Ok(())
         }
-        Operator::I64Store { memarg: wasmparser::MemArg { offset, .. } } |
-        Operator::I32Store { memarg: wasmparser::MemArg { offset, .. } } |
-        Operator::I32Store8 { memarg: wasmparser::MemArg { offset, .. } } |
-        Operator::I32Store16 { memarg: wasmparser::MemArg { offset, .. } } => {
-            let src = stack.pop();
-            let addr = stack.pop();
-            block.push(lir::Lir::Assign {
-                dst: expr::Expr::Deref {
-                    ptr: Box::new(expr::Expr::Binary {
-                        op: expr::BinaryOp::Add,
-                        lhs: addr.bexpr(),
-                        rhs: Box::new(expr::Expr::Num(offset as i64)),
-                    }),
-                    size: match insn {
-                        Operator::I64Store { .. } => ty::Size::Size64,
-                        Operator::I32Store { .. } => ty::Size::Size32,
-                        Operator::I32Store8 { .. } => ty::Size::Size8,
-                        Operator::I32Store16 { .. } => ty::Size::Size16,
-                        _ => unreachable!()
-                    }
-                },
-                src: src.expr()
-            });
+        Operator::RefFunc { function_index } => {
+            let dst = stack.push();
+            block.push(lir::Lir::Assign { dst: dst.expr(), src: expr::Expr::Func(expr::FuncId(function_index as usize)) });
             Ok(())
         }
-        Operator::I64Load { memarg: wasmparser::MemArg { offset, .. } } |
-        Operator::I32Load { memarg: wasmparser::MemArg { offset, .. } } |
-        Operator::I32Load8U { memarg: wasmparser::MemArg { offset, .. } } |
-        Operator::I32Load16U { memarg: wasmparser::MemArg { offset, .. } } => {
-            let addr = stack.pop();
+        Operator::RefIsNull => {
+            let src = stack.pop();
             let dst = stack.push();
             block.push(lir::Lir::Assign {
                 dst: dst.expr(),
-                src: expr::Expr::Deref {
-                    ptr: Box::new(expr::Expr::Binary {
-                        op: expr::BinaryOp::Add,
-                        lhs: addr.bexpr(),
-                        rhs: Box::new(expr::Expr::Num(offset as i64)),
-                    }),
-                    size: match insn {
-                        Operator::I64Load { .. } => ty::Size::Size64,
-                        Operator::I32Load { .. } => ty::Size::Size32,
-                        Operator::I32Load8U { .. } => ty::Size::Size8,
-                        Operator::I32Load16U { .. } => ty::Size::Size16,
-                        _ => unreachable!()
-                    }
-                }
+                src: expr::Expr::Binary { op: expr::BinaryOp::Eq, lhs: src.bexpr(), rhs: Box::new(expr::Expr::Num(0)) }
             });
             Ok(())
         }
         Operator::Return => {
-            block.push(lir::Lir::Branch { cond: None, target: blocks.ret() });
-            block.push(lir::Lir::Label(blocks.tmp_label()));
+            gen_return(block, blocks, stack, ty);
             Ok(())
         }
-        Operator::Block { blockty: wasmparser::BlockType::Empty } => {
-            let start = blocks.push_block(false);
+        Operator::Block { blockty } => {
+            let (params, results) = block_arity(blockty, module.raw_types)?;
+            let height = stack.height() - params;
+            let params = stack.peek_n(params).iter().map(|x| x.0).collect();
+            let results = (0..results).map(|_| stack.fresh()).collect();
+
+            let start = blocks.push_block(BlockKind::Block, height, params, results);
             block.push(lir::Lir::Label(start));
             Ok(())
         }
-        Operator::Loop { blockty: wasmparser::BlockType::Empty } => {
-            let start = blocks.push_block(true);
+        Operator::Loop { blockty } => {
+            let (params, results) = block_arity(blockty, module.raw_types)?;
+            let height = stack.height() - params;
+
+            // Branches back to the start pass new params, so they need names of their own
+            let entry = stack.peek_n(params);
+            let params: Vec<_> = (0..params).map(|_| stack.fresh()).collect();
+            copy_values(block, &entry, &params);
+            stack.truncate(height);
+            for param in &params {
+                stack.push_name(*param);
+            }
+            let results = (0..results).map(|_| stack.fresh()).collect();
+
+            let start = blocks.push_block(BlockKind::Loop, height, params, results);
             block.push(lir::Lir::Label(start));
             Ok(())
         }
-        Operator::I32LtS | Operator::I32LeS |
-        Operator::I32LtU | Operator::I32LeU |
-        Operator::I32GtS | Operator::I32GeS |
-        Operator::I32GtU | Operator::I32GeU |
-        Operator::I32Eq | Operator::I32Ne => {
-            let src2 = stack.pop();
-            let src1 = stack.pop();
-            let dst = stack.push();
-            block.push(lir::Lir::Assign {
-                src: expr::Expr::Binary {
-                    op: match insn {
-                        Operator::I32LtS => expr::BinaryOp::Lt,
-                        Operator::I32LtU => expr::BinaryOp::Lt,
-                        Operator::I32LeS => expr::BinaryOp::Le,
-                        Operator::I32LeU => expr::BinaryOp::Le,
-                        Operator::I32GtS => expr::BinaryOp::Gt,
-                        Operator::I32GtU => expr::BinaryOp::Gt,
-                        Operator::I32GeS => expr::BinaryOp::Ge,
-                        Operator::I32GeU => expr::BinaryOp::Ge,
-                        Operator::I32Eq => expr::BinaryOp::Eq,
-                        Operator::I32Ne => expr::BinaryOp::Ne,
-                        _ => unreachable!()
-                    },
-                    lhs: src1.bexpr(),
-                    rhs: src2.bexpr(),
-                },
-                dst: dst.expr()
-            });
-            Ok(())
-        }
-        Operator::I32Eqz => {
-            let src = stack.pop();
-            let dst = stack.push();
-            block.push(lir::Lir::Assign {
-                src: expr::Expr::Unary {
-                    op: expr::UnaryOp::Not,
-                    expr: src.bexpr(),
-                },
-                dst: dst.expr()
+        Operator::If { blockty } => {
+            let cond = stack.pop();
+            let (params, results) = block_arity(blockty, module.raw_types)?;
+            let height = stack.height() - params;
+            let params = stack.peek_n(params).iter().map(|x| x.0).collect();
+            let results = (0..results).map(|_| stack.fresh()).collect();
+
+            let otherwise = blocks.tmp_label();
+            block.push(lir::Lir::Branch {
+                cond: Some(expr::Expr::Unary { op: expr::UnaryOp::Not, expr: cond.bexpr() }),
+                target: otherwise
             });
+
+            let start = blocks.push_block(BlockKind::If { otherwise, has_else: false }, height, params, results);
+            block.push(lir::Lir::Label(start));
             Ok(())
         }
+        Operator::I32Eq | Operator::I64Eq => gen_binary(block, stack, expr::BinaryOp::Eq),
+        Operator::I32Ne | Operator::I64Ne => gen_binary(block, stack, expr::BinaryOp::Ne),
+        Operator::I32LtS | Operator::I64LtS => gen_binary(block, stack, expr::BinaryOp::Lt),
+        Operator::I32LtU | Operator::I64LtU => gen_binary(block, stack, expr::BinaryOp::LtU),
+        Operator::I32LeS | Operator::I64LeS => gen_binary(block, stack, expr::BinaryOp::Le),
+        Operator::I32LeU | Operator::I64LeU => gen_binary(block, stack, expr::BinaryOp::LeU),
+        Operator::I32GtS | Operator::I64GtS => gen_binary(block, stack, expr::BinaryOp::Gt),
+        Operator::I32GtU | Operator::I64GtU => gen_binary(block, stack, expr::BinaryOp::GtU),
+        Operator::I32GeS | Operator::I64GeS => gen_binary(block, stack, expr::BinaryOp::Ge),
+        Operator::I32GeU | Operator::I64GeU => gen_binary(block, stack, expr::BinaryOp::GeU),
+        Operator::I32Eqz | Operator::I64Eqz => gen_unary(block, stack, expr::UnaryOp::Not),
+        Operator::F32Eq | Operator::F64Eq => gen_binary(block, stack, expr::BinaryOp::FEq),
+        Operator::F32Ne | Operator::F64Ne => gen_binary(block, stack, expr::BinaryOp::FNe),
+        Operator::F32Lt | Operator::F64Lt => gen_binary(block, stack, expr::BinaryOp::FLt),
+        Operator::F32Le | Operator::F64Le => gen_binary(block, stack, expr::BinaryOp::FLe),
+        Operator::F32Gt | Operator::F64Gt => gen_binary(block, stack, expr::BinaryOp::FGt),
+        Operator::F32Ge | Operator::F64Ge => gen_binary(block, stack, expr::BinaryOp::FGe),
         Operator::BrIf { relative_depth } => {
-            let target = blocks.branch_target_rel(relative_depth as usize);
             let src = stack.pop();
-            block.push(lir::Lir::Branch {
-                cond: Some(src.expr()),
-                target
-            });
-            block.push(lir::Lir::Label(blocks.tmp_label()));
+            gen_branch(block, blocks, stack, Some(src.expr()), relative_depth as usize);
             Ok(())
         }
         Operator::Br { relative_depth } => {
-            let target = blocks.branch_target_rel(relative_depth as usize);
-            block.push(lir::Lir::Branch {
-                cond: None,
-                target
-            });
-            block.push(lir::Lir::Label(blocks.tmp_label()));
+            gen_branch(block, blocks, stack, None, relative_depth as usize);
+            blocks.dead = true;
             Ok(())
         }
-        Operator::Call { function_index } => {
-            if function_index as usize >= func_types.len() {
-                return Err(TranslationError::BadFunctionIndex)
+        Operator::BrTable { targets } => {
+            let src = stack.pop();
+            let default = blocks.branch_target_rel(targets.default() as usize);
+
+            // Indices sharing a target are tested together, and those going to the default need no test
+            let mut cases: Vec<(usize, lir::Label, Vec<i64>)> = vec![];
+            for (idx, rel) in targets.targets().enumerate() {
+                let rel = rel.map_err(|_| TranslationError::Decode)? as usize;
+                let target = blocks.branch_target_rel(rel);
+                if target == default {
+                    continue;
+                }
+
+                match cases.iter_mut().find(|(_, label, _)| *label == target) {
+                    Some((_, _, values)) => values.push(idx as i64),
+                    None => cases.push((rel, target, vec![idx as i64]))
+                }
             }
 
-            let mut args = Vec::new();
-            for _ in func_types[function_index as usize].params() {
-                args.push(stack.pop().expr());
+            for (rel, _, values) in cases {
+                let cond = values.into_iter().map(|value| expr::Expr::Binary {
+                    op: expr::BinaryOp::Eq,
+                    lhs: src.bexpr(),
+                    rhs: Box::new(expr::Expr::Num(value))
+                }).reduce(|lhs, rhs| expr::Expr::Binary {
+                    op: expr::BinaryOp::Or,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs)
+                }).unwrap();
+
+                gen_branch(block, blocks, stack, Some(cond), rel);
             }
 
-            if func_types[function_index as usize].results().len() == 0 {
-                block.push(lir::Lir::Do(expr::Expr::Call {
-                    func: Box::new(expr::Expr::Func(expr::FuncId(function_index as usize))),
-                    args
-                }));
-            } else {
-                let res = stack.push();
-                assert_eq!(func_types[function_index as usize].results().len(), 1);
-                block.push(lir::Lir::Assign {
-                    dst: res.expr(),
-                    src: expr::Expr::Call {
-                        func: Box::new(expr::Expr::Func(expr::FuncId(function_index as usize))),
-                        args
-                    }
-                });
+            gen_branch(block, blocks, stack, None, targets.default() as usize);
+            blocks.dead = true;
+            Ok(())
+        }
+        Operator::Call { function_index } => {
+            let ty = module.func_types.get(function_index as usize).ok_or(TranslationError::BadFunctionIndex)?;
+            gen_call(block, stack, expr::Expr::Func(expr::FuncId(function_index as usize)), ty)
+        }
+        Operator::CallIndirect { type_index, table_index, .. } => gen_call_indirect(block, stack, module, type_index, table_index),
+        // Tail calls are returns of what the call gives
+        Operator::ReturnCall { function_index } => {
+            let callee = module.func_types.get(function_index as usize).ok_or(TranslationError::BadFunctionIndex)?;
+            gen_call(block, stack, expr::Expr::Func(expr::FuncId(function_index as usize)), callee)?;
+            gen_return(block, blocks, stack, ty);
+            Ok(())
+        }
+        Operator::ReturnCallIndirect { type_index, table_index } => {
+            gen_call_indirect(block, stack, module, type_index, table_index)?;
+            gen_return(block, blocks, stack, ty);
+            Ok(())
+        }
+        Operator::Try { blockty } => {
+            let (params, results) = block_arity(blockty, module.raw_types)?;
+            let height = stack.height() - params;
+            let params = stack.peek_n(params).iter().map(|x| x.0).collect();
+            let results = (0..results).map(|_| stack.fresh()).collect();
+
+            let at = block.next_index();
+            let start = blocks.push_block(BlockKind::Try { at, handlers: vec![] }, height, params, results);
+            block.push(lir::Lir::Label(start));
+            Ok(())
+        }
+        // The labels of catches are counted from outside the try_table
+        Operator::TryTable { try_table } => {
+            let catches = try_table.catches.iter().map(|catch| {
+                let (tag, with_ref, label) = match *catch {
+                    wasmparser::Catch::One { tag, label } => (Some(tag), false, label),
+                    wasmparser::Catch::OneRef { tag, label } => (Some(tag), true, label),
+                    wasmparser::Catch::All { label } => (None, false, label),
+                    wasmparser::Catch::AllRef { label } => (None, true, label)
+                };
+                let target = blocks.get_rel(label as usize);
+                TableCatch { tag, with_ref, target: target.target(), values: target.target_values().to_vec() }
+            }).collect();
+
+            let (params, results) = block_arity(try_table.ty, module.raw_types)?;
+            let height = stack.height() - params;
+            let params = stack.peek_n(params).iter().map(|x| x.0).collect();
+            let results = (0..results).map(|_| stack.fresh()).collect();
+
+            let at = block.next_index();
+            let start = blocks.push_block(BlockKind::TryTable { at, catches }, height, params, results);
+            block.push(lir::Lir::Label(start));
+            Ok(())
+        }
+        Operator::Throw { tag_index } => {
+            let tag = module.tags.get(tag_index as usize).ok_or(TranslationError::BadTagIndex)?;
+            let mut args = vec![];
+            for _ in tag.params() {
+                args.insert(0, stack.pop().expr());
             }
+            args.insert(0, expr::Expr::Num(tag_index as i64));
 
+            block.push(lir::Lir::Do(exception_call(expr::BuiltIn::Throw, args)));
+            block.push(lir::Lir::Label(blocks.tmp_label()));
+            blocks.dead = true;
+            Ok(())
+        }
+        // Which exception is thrown again only matters with catches inside catches, so the depth, or the exception
+        // given to throw_ref, is left out
+        Operator::Rethrow { .. } | Operator::ThrowRef => {
+            if matches!(insn, Operator::ThrowRef) {
+                stack.pop();
+            }
+            block.push(lir::Lir::Do(exception_call(expr::BuiltIn::Rethrow, vec![])));
+            block.push(lir::Lir::Label(blocks.tmp_label()));
+            blocks.dead = true;
             Ok(())
         }
         Operator::Drop => {
@@ -466,32 +1161,6 @@ fn gen_insn<'a>(
                 Ok(())
             }
         }
-        // FIXME: Add real support for this
-        Operator::CallIndirect { type_index, table_index, .. } => {
-            let mut args = Vec::new();
-            for _ in raw_types[type_index as usize].params() {
-                args.push(stack.pop().expr());
-            }
-
-            if raw_types[type_index as usize].results().len() == 0 {
-                block.push(lir::Lir::Do(expr::Expr::Call {
-                    func: Box::new(expr::Expr::Num(table_index as i64)),
-                    args
-                }));
-            } else {
-                let res = stack.push();
-                assert_eq!(raw_types[type_index as usize].results().len(), 1);
-                block.push(lir::Lir::Assign {
-                    dst: res.expr(),
-                    src: expr::Expr::Call {
-                        func: Box::new(expr::Expr::Num(table_index as i64)),
-                        args
-                    }
-                });
-            }
-
-            Ok(())
-        }
         Operator::I32WrapI64 => {
             let src = stack.pop();
             let dst = stack.push();
@@ -505,13 +1174,110 @@ fn gen_insn<'a>(
             });
             Ok(())
         },
-        // Types currently don't exist, so this is I guess meaningless
-        Operator::I64Extend32S | Operator::I64ExtendI32S | Operator::I64ExtendI32U |
-        Operator::I64Extend16S => Ok(()),
+        // i32s are taken to already be zero extended in the 64 bit names
+        Operator::I64ExtendI32U => Ok(()),
+        Operator::I64ExtendI32S | Operator::I64Extend32S => gen_unary(block, stack, expr::UnaryOp::SignExtend(ty::Size::Size32)),
+        Operator::I32Extend16S | Operator::I64Extend16S => gen_unary(block, stack, expr::UnaryOp::SignExtend(ty::Size::Size16)),
+        Operator::I32Extend8S | Operator::I64Extend8S => gen_unary(block, stack, expr::UnaryOp::SignExtend(ty::Size::Size8)),
+        // The saturating forms only differ from the others for values out of range, where the others trap
+        Operator::I32TruncF32S | Operator::I32TruncF64S | Operator::I32TruncSatF32S | Operator::I32TruncSatF64S =>
+            gen_unary(block, stack, expr::UnaryOp::FloatToInt(ty::Size::Size32, true)),
+        Operator::I32TruncF32U | Operator::I32TruncF64U | Operator::I32TruncSatF32U | Operator::I32TruncSatF64U =>
+            gen_unary(block, stack, expr::UnaryOp::FloatToInt(ty::Size::Size32, false)),
+        Operator::I64TruncF32S | Operator::I64TruncF64S | Operator::I64TruncSatF32S | Operator::I64TruncSatF64S =>
+            gen_unary(block, stack, expr::UnaryOp::FloatToInt(ty::Size::Size64, true)),
+        Operator::I64TruncF32U | Operator::I64TruncF64U | Operator::I64TruncSatF32U | Operator::I64TruncSatF64U =>
+            gen_unary(block, stack, expr::UnaryOp::FloatToInt(ty::Size::Size64, false)),
+        Operator::F32ConvertI32S | Operator::F32ConvertI64S => gen_unary(block, stack, expr::UnaryOp::IntToFloat(ty::Size::Size32, true)),
+        Operator::F32ConvertI32U | Operator::F32ConvertI64U => gen_unary(block, stack, expr::UnaryOp::IntToFloat(ty::Size::Size32, false)),
+        Operator::F64ConvertI32S | Operator::F64ConvertI64S => gen_unary(block, stack, expr::UnaryOp::IntToFloat(ty::Size::Size64, true)),
+        Operator::F64ConvertI32U | Operator::F64ConvertI64U => gen_unary(block, stack, expr::UnaryOp::IntToFloat(ty::Size::Size64, false)),
+        Operator::F32DemoteF64 => gen_unary(block, stack, expr::UnaryOp::FloatToFloat(ty::Size::Size32)),
+        Operator::F64PromoteF32 => gen_unary(block, stack, expr::UnaryOp::FloatToFloat(ty::Size::Size64)),
+        Operator::I32ReinterpretF32 => gen_unary(block, stack, expr::UnaryOp::FloatToBits(ty::Size::Size32)),
+        Operator::I64ReinterpretF64 => gen_unary(block, stack, expr::UnaryOp::FloatToBits(ty::Size::Size64)),
+        Operator::F32ReinterpretI32 => gen_unary(block, stack, expr::UnaryOp::BitsToFloat(ty::Size::Size32)),
+        Operator::F64ReinterpretI64 => gen_unary(block, stack, expr::UnaryOp::BitsToFloat(ty::Size::Size64)),
+        Operator::Nop => Ok(()),
         Operator::Unreachable => {
             // FIXME: Add something here
+            blocks.dead = true;
             Ok(())
         },
-        _ => Err(TranslationError::UnknownInstruction(insn))
+        Operator::V128Const { value } => {
+            let half = |bytes: &[u8]| expr::Expr::Num(i64::from_le_bytes(bytes.try_into().unwrap()));
+            let (lo, hi) = value.bytes().split_at(8);
+            let dst = stack.push();
+            block.push(lir::Lir::Assign { dst: dst.expr(), src: simd_call(expr::SimdOp::Build(ty::Shape::I64x2), vec![half(lo), half(hi)]) });
+            Ok(())
+        }
+        Operator::I8x16Shuffle { lanes } => {
+            let b = stack.pop();
+            let a = stack.pop();
+            let dst = stack.push();
+            let indices = simd_call(expr::SimdOp::Build(ty::Shape::I8x16), lanes.iter().map(|x| expr::Expr::Num(*x as i64)).collect());
+            block.push(lir::Lir::Assign { dst: dst.expr(), src: simd_call(expr::SimdOp::Shuffle, vec![a.expr(), b.expr(), indices]) });
+            Ok(())
+        }
+        Operator::I8x16Splat => gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Splat(ty::Shape::I8x16)), 1),
+        Operator::I16x8Splat => gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Splat(ty::Shape::I16x8)), 1),
+        Operator::I32x4Splat => gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Splat(ty::Shape::I32x4)), 1),
+        Operator::I64x2Splat => gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Splat(ty::Shape::I64x2)), 1),
+        Operator::F32x4Splat => gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Splat(ty::Shape::F32x4)), 1),
+        Operator::F64x2Splat => gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Splat(ty::Shape::F64x2)), 1),
+        Operator::I8x16ExtractLaneS { lane } => gen_extract_lane(block, stack, ty::Shape::I8x16, lane, true),
+        Operator::I8x16ExtractLaneU { lane } => gen_extract_lane(block, stack, ty::Shape::I8x16, lane, false),
+        Operator::I16x8ExtractLaneS { lane } => gen_extract_lane(block, stack, ty::Shape::I16x8, lane, true),
+        Operator::I16x8ExtractLaneU { lane } => gen_extract_lane(block, stack, ty::Shape::I16x8, lane, false),
+        Operator::I32x4ExtractLane { lane } => gen_extract_lane(block, stack, ty::Shape::I32x4, lane, false),
+        Operator::I64x2ExtractLane { lane } => gen_extract_lane(block, stack, ty::Shape::I64x2, lane, false),
+        Operator::F32x4ExtractLane { lane } => gen_extract_lane(block, stack, ty::Shape::F32x4, lane, false),
+        Operator::F64x2ExtractLane { lane } => gen_extract_lane(block, stack, ty::Shape::F64x2, lane, false),
+        Operator::I8x16ReplaceLane { lane } => gen_replace_lane(block, stack, ty::Shape::I8x16, lane),
+        Operator::I16x8ReplaceLane { lane } => gen_replace_lane(block, stack, ty::Shape::I16x8, lane),
+        Operator::I32x4ReplaceLane { lane } => gen_replace_lane(block, stack, ty::Shape::I32x4, lane),
+        Operator::I64x2ReplaceLane { lane } => gen_replace_lane(block, stack, ty::Shape::I64x2, lane),
+        Operator::F32x4ReplaceLane { lane } => gen_replace_lane(block, stack, ty::Shape::F32x4, lane),
+        Operator::F64x2ReplaceLane { lane } => gen_replace_lane(block, stack, ty::Shape::F64x2, lane),
+        Operator::V128Load { memarg } | Operator::V128Load8x8S { memarg } | Operator::V128Load8x8U { memarg } |
+        Operator::V128Load16x4S { memarg } | Operator::V128Load16x4U { memarg } | Operator::V128Load32x2S { memarg } |
+        Operator::V128Load32x2U { memarg } | Operator::V128Load8Splat { memarg } | Operator::V128Load16Splat { memarg } |
+        Operator::V128Load32Splat { memarg } | Operator::V128Load64Splat { memarg } | Operator::V128Load32Zero { memarg } |
+        Operator::V128Load64Zero { memarg } => gen_simd_load(block, stack, memarg, simd_name(&insn).unwrap(), None),
+        Operator::V128Load8Lane { memarg, lane } | Operator::V128Load16Lane { memarg, lane } |
+        Operator::V128Load32Lane { memarg, lane } | Operator::V128Load64Lane { memarg, lane } =>
+            gen_simd_load(block, stack, memarg, simd_name(&insn).unwrap(), Some(lane)),
+        Operator::V128Store { memarg } => gen_simd_store(block, stack, memarg, simd_name(&insn).unwrap(), None),
+        Operator::V128Store8Lane { memarg, lane } | Operator::V128Store16Lane { memarg, lane } |
+        Operator::V128Store32Lane { memarg, lane } | Operator::V128Store64Lane { memarg, lane } =>
+            gen_simd_store(block, stack, memarg, simd_name(&insn).unwrap(), Some(lane)),
+        // Everything else on vectors is kept as a call to an intrinsic named after it
+        Operator::V128AnyTrue | Operator::I8x16AllTrue | Operator::I16x8AllTrue | Operator::I32x4AllTrue | Operator::I64x2AllTrue |
+        Operator::I8x16Bitmask | Operator::I16x8Bitmask | Operator::I32x4Bitmask | Operator::I64x2Bitmask =>
+            gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Named(simd_name(&insn).unwrap(), false)), 1),
+        Operator::V128Not | Operator::I8x16Abs | Operator::I8x16Neg | Operator::I8x16Popcnt |
+        Operator::I16x8ExtAddPairwiseI8x16S | Operator::I16x8ExtAddPairwiseI8x16U | Operator::I16x8Abs | Operator::I16x8Neg |
+        Operator::I16x8ExtendLowI8x16S | Operator::I16x8ExtendHighI8x16S | Operator::I16x8ExtendLowI8x16U | Operator::I16x8ExtendHighI8x16U |
+        Operator::I32x4ExtAddPairwiseI16x8S | Operator::I32x4ExtAddPairwiseI16x8U | Operator::I32x4Abs | Operator::I32x4Neg |
+        Operator::I32x4ExtendLowI16x8S | Operator::I32x4ExtendHighI16x8S | Operator::I32x4ExtendLowI16x8U | Operator::I32x4ExtendHighI16x8U |
+        Operator::I64x2Abs | Operator::I64x2Neg |
+        Operator::I64x2ExtendLowI32x4S | Operator::I64x2ExtendHighI32x4S | Operator::I64x2ExtendLowI32x4U | Operator::I64x2ExtendHighI32x4U |
+        Operator::F32x4Ceil | Operator::F32x4Floor | Operator::F32x4Trunc | Operator::F32x4Nearest | Operator::F32x4Abs | Operator::F32x4Neg | Operator::F32x4Sqrt |
+        Operator::F64x2Ceil | Operator::F64x2Floor | Operator::F64x2Trunc | Operator::F64x2Nearest | Operator::F64x2Abs | Operator::F64x2Neg | Operator::F64x2Sqrt |
+        Operator::I32x4TruncSatF32x4S | Operator::I32x4TruncSatF32x4U | Operator::F32x4ConvertI32x4S | Operator::F32x4ConvertI32x4U |
+        Operator::I32x4TruncSatF64x2SZero | Operator::I32x4TruncSatF64x2UZero | Operator::F64x2ConvertLowI32x4S | Operator::F64x2ConvertLowI32x4U |
+        Operator::F32x4DemoteF64x2Zero | Operator::F64x2PromoteLowF32x4 |
+        Operator::I32x4RelaxedTruncF32x4S | Operator::I32x4RelaxedTruncF32x4U | Operator::I32x4RelaxedTruncF64x2SZero | Operator::I32x4RelaxedTruncF64x2UZero =>
+            gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Named(simd_name(&insn).unwrap(), true)), 1),
+        Operator::V128Bitselect | Operator::F32x4RelaxedMadd | Operator::F32x4RelaxedNmadd | Operator::F64x2RelaxedMadd | Operator::F64x2RelaxedNmadd |
+        Operator::I8x16RelaxedLaneselect | Operator::I16x8RelaxedLaneselect | Operator::I32x4RelaxedLaneselect | Operator::I64x2RelaxedLaneselect |
+        Operator::I32x4RelaxedDotI8x16I7x16AddS =>
+            gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Named(simd_name(&insn).unwrap(), true)), 3),
+        // What is left of SIMD all takes two vectors and gives one
+        _ => match (lanewise(&insn), simd_name(&insn)) {
+            (Some((shape, op)), _) => gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Lanewise(shape, op)), 2),
+            (None, Some(name)) => gen_builtin(block, stack, expr::BuiltIn::Simd(expr::SimdOp::Named(name, true)), 2),
+            (None, None) => Err(TranslationError::UnknownInstruction(insn))
+        }
     }
 }
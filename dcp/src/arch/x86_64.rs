@@ -0,0 +1,615 @@
+use std::collections::{HashMap, HashSet};
+
+use capstone::{
+    arch::{
+        x86::{X86Insn, X86OpMem, X86Operand, X86OperandType, X86Reg},
+        ArchOperand,
+    },
+    prelude::*,
+};
+
+use crate::{expr, lir, ty, dataflow::Abi, Image};
+
+const CMP: &str = "cmp";
+
+pub const R: &[&str] = &[
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi",
+    "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+];
+
+const RAX: &str = "rax";
+const RDX: &str = "rdx";
+const RSP: &str = "rsp";
+const RBP: &str = "rbp";
+
+pub fn abi() -> Abi {
+    Abi {
+        callee_saved: vec![R[3], R[4], R[5], R[12], R[13], R[14], R[15]],
+        global: vec![],
+        args: vec![R[7], R[6], R[2], R[1], R[8], R[9]],
+        float_args: vec![],
+        results: vec![RAX],
+        float_results: vec![],
+        eliminate: vec![RBP, RSP],
+        base_reg: Some(RSP),
+        calls_clobber: true
+    }
+}
+
+fn reg_name(reg: RegId) -> Result<&'static str, String> {
+    use X86Reg::*;
+
+    Ok(match reg.0 as u32 {
+        // AH, CH, DH and BH are bits 8 to 16 of theirs, which can't be named as a whole register
+        X86_REG_RAX | X86_REG_EAX | X86_REG_AX | X86_REG_AL => R[0],
+        X86_REG_RCX | X86_REG_ECX | X86_REG_CX | X86_REG_CL => R[1],
+        X86_REG_RDX | X86_REG_EDX | X86_REG_DX | X86_REG_DL => R[2],
+        X86_REG_RBX | X86_REG_EBX | X86_REG_BX | X86_REG_BL => R[3],
+        X86_REG_RSP | X86_REG_ESP | X86_REG_SP | X86_REG_SPL => R[4],
+        X86_REG_RBP | X86_REG_EBP | X86_REG_BP | X86_REG_BPL => R[5],
+        X86_REG_RSI | X86_REG_ESI | X86_REG_SI | X86_REG_SIL => R[6],
+        X86_REG_RDI | X86_REG_EDI | X86_REG_DI | X86_REG_DIL => R[7],
+        x @ X86_REG_R8..=X86_REG_R15 => R[(x - X86_REG_R8) as usize + 8],
+        x @ X86_REG_R8B..=X86_REG_R15B => R[(x - X86_REG_R8B) as usize + 8],
+        x @ X86_REG_R8D..=X86_REG_R15D => R[(x - X86_REG_R8D) as usize + 8],
+        x @ X86_REG_R8W..=X86_REG_R15W => R[(x - X86_REG_R8W) as usize + 8],
+        X86_REG_FS => "fs",
+        X86_REG_GS => "gs",
+        _ => return Err(format!("Unsupported register {:?}", reg))
+    })
+}
+
+fn name(reg: RegId) -> Result<expr::Expr, String> {
+    reg_name(reg).map(str::to_string).map(expr::Expr::Name)
+}
+
+fn size_of(op: &X86Operand) -> ty::Size {
+    match op.size {
+        1 => ty::Size::Size8,
+        2 => ty::Size::Size16,
+        4 => ty::Size::Size32,
+        _ => ty::Size::Size64,
+    }
+}
+
+fn is_reg(op: &ArchOperand) -> bool {
+    matches!(x86_op(op).op_type, X86OperandType::Reg(_))
+}
+
+/// The low 32 bits of value, zero extended
+fn low_32(value: expr::Expr) -> expr::Expr {
+    match value {
+        expr::Expr::Num(n) => expr::Expr::Num(n & 0xffffffff),
+        expr::Expr::Deref { size: ty::Size::Size8 | ty::Size::Size16 | ty::Size::Size32, .. } => value,
+        value => expr::Expr::Binary { op: expr::BinaryOp::And, lhs: Box::new(value), rhs: Box::new(expr::Expr::Num(0xffffffff)) }
+    }
+}
+
+/// The value left in dst by writing value to it. Writing the low 32 bits of a register clears the rest
+fn written(dst: &ArchOperand, value: expr::Expr) -> expr::Expr {
+    if is_reg(dst) {
+        written_sized(size_of(x86_op(dst)), value)
+    } else {
+        value
+    }
+}
+
+/// The value left in a register by writing size bytes of value to it
+fn written_sized(size: ty::Size, value: expr::Expr) -> expr::Expr {
+    if size == ty::Size::Size32 {
+        low_32(value)
+    } else {
+        value
+    }
+}
+
+fn x86_op(op: &ArchOperand) -> &X86Operand {
+    match op {
+        ArchOperand::X86Operand(op) => op,
+        _ => panic!("not x86?")
+    }
+}
+
+fn mem_to_lir_addr(mem: X86OpMem, insn: &capstone::Insn) -> Result<expr::Expr, String> {
+    // rip relative addressing is relative to the end of the instruction
+    if mem.base().0 as u32 == X86Reg::X86_REG_RIP {
+        return Ok(expr::Expr::Num(insn.address() as i64 + insn.bytes().len() as i64 + mem.disp()))
+    }
+
+    let mut expr = None;
+    if mem.segment().0 != 0 {
+        expr = Some(name(mem.segment())?);
+    }
+
+    if mem.base().0 != 0 {
+        let base = name(mem.base())?;
+        expr = Some(match expr {
+            Some(expr) => expr::Expr::Binary { op: expr::BinaryOp::Add, lhs: Box::new(expr), rhs: Box::new(base) },
+            None => base
+        });
+    }
+
+    if mem.index().0 != 0 {
+        let mut index = name(mem.index())?;
+        if mem.scale() != 1 {
+            index = expr::Expr::Binary {
+                op: expr::BinaryOp::Mul,
+                lhs: Box::new(index),
+                rhs: Box::new(expr::Expr::Num(mem.scale() as i64))
+            };
+        }
+
+        expr = Some(match expr {
+            Some(expr) => expr::Expr::Binary { op: expr::BinaryOp::Add, lhs: Box::new(expr), rhs: Box::new(index) },
+            None => index
+        });
+    }
+
+    if mem.disp() != 0 || expr.is_none() {
+        expr = Some(match expr {
+            Some(expr) if mem.disp() < 0 => expr::Expr::Binary {
+                op: expr::BinaryOp::Sub,
+                lhs: Box::new(expr),
+                rhs: Box::new(expr::Expr::Num(-mem.disp()))
+            },
+            Some(expr) => expr::Expr::Binary {
+                op: expr::BinaryOp::Add,
+                lhs: Box::new(expr),
+                rhs: Box::new(expr::Expr::Num(mem.disp()))
+            },
+            None => expr::Expr::Num(mem.disp())
+        });
+    }
+
+    Ok(expr.unwrap())
+}
+
+fn op_to_mem_addr(op: &ArchOperand, insn: &capstone::Insn) -> Result<expr::Expr, String> {
+    match x86_op(op).op_type {
+        X86OperandType::Mem(mem) => mem_to_lir_addr(mem, insn),
+        _ => Err(format!("Not a mem op: {:?}", op))
+    }
+}
+
+fn op_to_expr(op: &ArchOperand, insn: &capstone::Insn) -> Result<expr::Expr, String> {
+    let op = x86_op(op);
+    match op.op_type {
+        X86OperandType::Reg(reg) => name(reg),
+        X86OperandType::Imm(imm) => Ok(expr::Expr::Num(imm)),
+        X86OperandType::Mem(mem) => Ok(expr::Expr::Deref {
+            ptr: Box::new(mem_to_lir_addr(mem, insn)?),
+            size: size_of(op)
+        }),
+        X86OperandType::Invalid => Err(format!("Operand: {:?}", op))
+    }
+}
+
+fn cc_to_lir(insn: X86Insn) -> Result<expr::UnaryOp, String> {
+    use X86Insn::*;
+
+    Ok(match insn {
+        X86_INS_JE | X86_INS_SETE | X86_INS_CMOVE => expr::UnaryOp::CmpEq,
+        X86_INS_JNE | X86_INS_SETNE | X86_INS_CMOVNE => expr::UnaryOp::CmpNe,
+        X86_INS_JL | X86_INS_SETL | X86_INS_CMOVL |
+        X86_INS_JS | X86_INS_SETS | X86_INS_CMOVS => expr::UnaryOp::CmpLt,
+        X86_INS_JLE | X86_INS_SETLE | X86_INS_CMOVLE => expr::UnaryOp::CmpLe,
+        X86_INS_JG | X86_INS_SETG | X86_INS_CMOVG => expr::UnaryOp::CmpGt,
+        X86_INS_JGE | X86_INS_SETGE | X86_INS_CMOVGE |
+        X86_INS_JNS | X86_INS_SETNS | X86_INS_CMOVNS => expr::UnaryOp::CmpGe,
+        X86_INS_JB | X86_INS_SETB | X86_INS_CMOVB => expr::UnaryOp::CmpLtU,
+        X86_INS_JBE | X86_INS_SETBE | X86_INS_CMOVBE => expr::UnaryOp::CmpLeU,
+        X86_INS_JA | X86_INS_SETA | X86_INS_CMOVA => expr::UnaryOp::CmpGtU,
+        X86_INS_JAE | X86_INS_SETAE | X86_INS_CMOVAE => expr::UnaryOp::CmpGeU,
+        _ => return Err(format!("Unsupported condition code in {:?}", insn))
+    })
+}
+
+fn cond(insn: X86Insn) -> Result<expr::Expr, String> {
+    Ok(expr::Expr::Unary {
+        op: cc_to_lir(insn)?,
+        expr: Box::new(expr::Expr::Name(CMP.to_string())),
+    })
+}
+
+fn set_cmp(block: &mut lir::LirFuncBuilder, lhs: expr::Expr, rhs: expr::Expr) {
+    block.push(lir::Lir::Assign {
+        dst: expr::Expr::Name(CMP.to_string()),
+        src: expr::Expr::Binary {
+            op: expr::BinaryOp::Cmp,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        },
+    });
+}
+
+pub fn to_lir(data: &[u8], base: u64, addr_to_func: &HashMap<u64, expr::FuncId>, _image: &Image) -> Result<lir::LirFunc, String> {
+    let cs = Capstone::new()
+        .x86()
+        .mode(arch::x86::ArchMode::Mode64)
+        .syntax(arch::x86::ArchSyntax::Intel)
+        .detail(true)
+        .build()
+        .expect("Could not build cs object");
+
+    let insns = cs.disasm_all(data, base).expect("Could not disassemble");
+
+    let mut block = lir::LirFuncBuilder::new();
+    let mut addr_to_label = HashMap::new();
+    let range = base..base + data.len() as u64;
+
+    for insn in insns.as_ref() {
+        let detail = cs
+            .insn_detail(insn)
+            .expect("Could not object cs instruction detail");
+        let ops = detail.arch_detail().operands();
+
+        if let Some(label) = addr_to_label.get(&insn.address()) {
+            block.push(lir::Lir::Label(*label));
+        } else {
+            let label = block.new_label();
+            block.push(lir::Lir::Label(label));
+            addr_to_label.insert(insn.address(), label);
+        }
+
+        gen_insn(insn, &ops, &mut block, &range, addr_to_func, &mut addr_to_label)?;
+    }
+
+    // Every branch target must be an instruction within this function
+    let addrs: HashSet<_> = insns.as_ref().iter().map(|insn| insn.address()).collect();
+    for addr in addr_to_label.keys() {
+        if !addrs.contains(addr) {
+            return Err(format!("Branch to {addr:#x}, which is not an instruction in this function"));
+        }
+    }
+
+    Ok(block.block())
+}
+
+fn gen_insn(
+    insn: &capstone::Insn,
+    ops: &[capstone::arch::ArchOperand],
+    block: &mut lir::LirFuncBuilder,
+    range: &std::ops::Range<u64>,
+    addr_to_func: &HashMap<u64, expr::FuncId>, addr_to_label: &mut HashMap<u64, lir::Label>
+) -> Result<(), String> {
+    use X86Insn::*;
+
+    let id = X86Insn::from(insn.id().0);
+    match id {
+        X86_INS_NOP | X86_INS_ENDBR64 | X86_INS_HLT => {}
+        X86_INS_MOV | X86_INS_MOVABS | X86_INS_MOVZX | X86_INS_MOVSX | X86_INS_MOVSXD => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            let src = op_to_expr(&ops[1], insn)?;
+            let src_size = size_of(x86_op(&ops[1]));
+            let src = match id {
+                X86_INS_MOVSX | X86_INS_MOVSXD => expr::Expr::Unary { op: expr::UnaryOp::SignExtend(src_size), expr: Box::new(src) },
+                // Registers are read whole, so only their low part is kept
+                X86_INS_MOVZX if is_reg(&ops[1]) => expr::Expr::Binary {
+                    op: expr::BinaryOp::And,
+                    lhs: Box::new(src),
+                    rhs: Box::new(expr::Expr::Num(if src_size == ty::Size::Size8 { 0xff } else { 0xffff })),
+                },
+                _ => src
+            };
+            block.push(lir::Lir::Assign { dst, src: written(&ops[0], src) });
+        }
+        // Sign extends eax to rax, or ax to eax
+        X86_INS_CDQE => {
+            let src = expr::Expr::Unary { op: expr::UnaryOp::SignExtend(ty::Size::Size32), expr: Box::new(expr::Expr::Name(RAX.to_string())) };
+            block.push(lir::Lir::Assign { dst: expr::Expr::Name(RAX.to_string()), src });
+        }
+        X86_INS_CWDE => {
+            let src = expr::Expr::Unary { op: expr::UnaryOp::SignExtend(ty::Size::Size16), expr: Box::new(expr::Expr::Name(RAX.to_string())) };
+            block.push(lir::Lir::Assign { dst: expr::Expr::Name(RAX.to_string()), src: low_32(src) });
+        }
+        X86_INS_LEA => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            let src = op_to_mem_addr(&ops[1], insn)?;
+            block.push(lir::Lir::Assign { dst, src: written(&ops[0], src) });
+        }
+        X86_INS_XOR if matches!((&x86_op(&ops[0]).op_type, &x86_op(&ops[1]).op_type), (X86OperandType::Reg(a), X86OperandType::Reg(b)) if a == b) => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            set_cmp(block, expr::Expr::Num(0), expr::Expr::Num(0));
+            block.push(lir::Lir::Assign { dst, src: expr::Expr::Num(0) });
+        }
+        X86_INS_ADD | X86_INS_SUB | X86_INS_AND | X86_INS_OR | X86_INS_XOR |
+        X86_INS_SHL | X86_INS_SAL | X86_INS_SHR | X86_INS_SAR |
+        X86_INS_IMUL if ops.len() == 2 => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            let src = op_to_expr(&ops[1], insn)?;
+
+            let op = match id {
+                X86_INS_ADD => expr::BinaryOp::Add,
+                X86_INS_SUB => expr::BinaryOp::Sub,
+                X86_INS_AND => expr::BinaryOp::And,
+                X86_INS_OR => expr::BinaryOp::Or,
+                X86_INS_XOR => expr::BinaryOp::Xor,
+                X86_INS_SHL | X86_INS_SAL => expr::BinaryOp::Shl,
+                X86_INS_SHR => expr::BinaryOp::Shr,
+                X86_INS_SAR => expr::BinaryOp::Asr,
+                X86_INS_IMUL => expr::BinaryOp::Mul,
+                _ => unreachable!()
+            };
+
+            let result = written(&ops[0], expr::Expr::Binary {
+                op,
+                lhs: Box::new(dst.clone()),
+                rhs: Box::new(src.clone()),
+            });
+
+            if id == X86_INS_SUB {
+                set_cmp(block, dst.clone(), src);
+            } else {
+                set_cmp(block, result.clone(), expr::Expr::Num(0));
+            }
+
+            block.push(lir::Lir::Assign { dst, src: result });
+        }
+        X86_INS_IMUL if ops.len() == 3 => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            let src1 = op_to_expr(&ops[1], insn)?;
+            let src2 = op_to_expr(&ops[2], insn)?;
+            block.push(lir::Lir::Assign {
+                dst,
+                src: written(&ops[0], expr::Expr::Binary {
+                    op: expr::BinaryOp::Mul,
+                    lhs: Box::new(src1),
+                    rhs: Box::new(src2),
+                }),
+            });
+        }
+        // FIXME: The high half of the product/dividend in rdx is ignored
+        X86_INS_IMUL | X86_INS_MUL => {
+            let src = op_to_expr(&ops[0], insn)?;
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Name(RAX.to_string()),
+                src: written_sized(size_of(x86_op(&ops[0])), expr::Expr::Binary {
+                    op: expr::BinaryOp::Mul,
+                    lhs: Box::new(expr::Expr::Name(RAX.to_string())),
+                    rhs: Box::new(src),
+                }),
+            });
+        }
+        X86_INS_IDIV | X86_INS_DIV => {
+            let src = op_to_expr(&ops[0], insn)?;
+            let size = size_of(x86_op(&ops[0]));
+            let (div, rem) = if id == X86_INS_IDIV {
+                (expr::BinaryOp::Div, expr::BinaryOp::Rem)
+            } else {
+                (expr::BinaryOp::DivU, expr::BinaryOp::RemU)
+            };
+
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Name(RDX.to_string()),
+                src: written_sized(size, expr::Expr::Binary {
+                    op: rem,
+                    lhs: Box::new(expr::Expr::Name(RAX.to_string())),
+                    rhs: Box::new(src.clone()),
+                }),
+            });
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Name(RAX.to_string()),
+                src: written_sized(size, expr::Expr::Binary {
+                    op: div,
+                    lhs: Box::new(expr::Expr::Name(RAX.to_string())),
+                    rhs: Box::new(src),
+                }),
+            });
+        }
+        X86_INS_CDQ | X86_INS_CQO => {
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Name(RDX.to_string()),
+                src: written_sized(if id == X86_INS_CDQ { ty::Size::Size32 } else { ty::Size::Size64 }, expr::Expr::Binary {
+                    op: expr::BinaryOp::Asr,
+                    lhs: Box::new(expr::Expr::Name(RAX.to_string())),
+                    rhs: Box::new(expr::Expr::Num(if id == X86_INS_CDQ { 31 } else { 63 })),
+                }),
+            });
+        }
+        X86_INS_INC | X86_INS_DEC => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            let result = written(&ops[0], expr::Expr::Binary {
+                op: if id == X86_INS_INC { expr::BinaryOp::Add } else { expr::BinaryOp::Sub },
+                lhs: Box::new(dst.clone()),
+                rhs: Box::new(expr::Expr::Num(1)),
+            });
+            set_cmp(block, result.clone(), expr::Expr::Num(0));
+            block.push(lir::Lir::Assign { dst, src: result });
+        }
+        X86_INS_NEG => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            set_cmp(block, expr::Expr::Num(0), dst.clone());
+            block.push(lir::Lir::Assign {
+                dst: dst.clone(),
+                src: written(&ops[0], expr::Expr::Binary {
+                    op: expr::BinaryOp::Sub,
+                    lhs: Box::new(expr::Expr::Num(0)),
+                    rhs: Box::new(dst),
+                }),
+            });
+        }
+        X86_INS_NOT => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            block.push(lir::Lir::Assign {
+                dst: dst.clone(),
+                src: written(&ops[0], expr::Expr::Binary {
+                    op: expr::BinaryOp::Xor,
+                    lhs: Box::new(dst),
+                    rhs: Box::new(expr::Expr::Num(-1)),
+                }),
+            });
+        }
+        X86_INS_ROL | X86_INS_ROR => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            let src = op_to_expr(&ops[1], insn)?;
+            block.push(lir::Lir::Assign {
+                dst: dst.clone(),
+                src: written(&ops[0], expr::Expr::Call {
+                    func: Box::new(expr::Expr::BuiltIn(if id == X86_INS_ROL { expr::BuiltIn::Rotl } else { expr::BuiltIn::Rotr })),
+                    args: vec![dst, src],
+                }),
+            });
+        }
+        X86_INS_XCHG => {
+            let a = op_to_expr(&ops[0], insn)?;
+            let b = op_to_expr(&ops[1], insn)?;
+            if a != b {
+                block.push(lir::Lir::Assign { dst: expr::Expr::Name("tmp".to_string()), src: a.clone() });
+                block.push(lir::Lir::Assign { dst: a, src: written(&ops[0], b.clone()) });
+                block.push(lir::Lir::Assign { dst: b, src: written(&ops[1], expr::Expr::Name("tmp".to_string())) });
+            }
+        }
+        X86_INS_CMP => {
+            let src1 = op_to_expr(&ops[0], insn)?;
+            let src2 = op_to_expr(&ops[1], insn)?;
+            set_cmp(block, src1, src2);
+        }
+        X86_INS_TEST => {
+            let src1 = op_to_expr(&ops[0], insn)?;
+            let src2 = op_to_expr(&ops[1], insn)?;
+            if src1 == src2 {
+                set_cmp(block, src1, expr::Expr::Num(0));
+            } else {
+                set_cmp(block, expr::Expr::Binary {
+                    op: expr::BinaryOp::And,
+                    lhs: Box::new(src1),
+                    rhs: Box::new(src2),
+                }, expr::Expr::Num(0));
+            }
+        }
+        X86_INS_SETE | X86_INS_SETNE | X86_INS_SETL | X86_INS_SETLE | X86_INS_SETG | X86_INS_SETGE |
+        X86_INS_SETB | X86_INS_SETBE | X86_INS_SETA | X86_INS_SETAE | X86_INS_SETS | X86_INS_SETNS => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            block.push(lir::Lir::Assign { dst, src: cond(id)? });
+        }
+        X86_INS_CMOVE | X86_INS_CMOVNE | X86_INS_CMOVL | X86_INS_CMOVLE | X86_INS_CMOVG | X86_INS_CMOVGE |
+        X86_INS_CMOVB | X86_INS_CMOVBE | X86_INS_CMOVA | X86_INS_CMOVAE | X86_INS_CMOVS | X86_INS_CMOVNS => {
+            let dst = op_to_expr(&ops[0], insn)?;
+            let src = op_to_expr(&ops[1], insn)?;
+
+            // The destination is written even when the condition fails, which clears the top of a 32 bit register
+            if size_of(x86_op(&ops[0])) == ty::Size::Size32 {
+                block.push(lir::Lir::Assign { dst: dst.clone(), src: written(&ops[0], dst.clone()) });
+            }
+
+            let label1 = block.new_label();
+            let label2 = block.new_label();
+
+            block.push(lir::Lir::Branch {
+                cond: Some(cond(id)?.neg()),
+                target: label2,
+            });
+            block.push(lir::Lir::Label(label1));
+            block.push(lir::Lir::Assign { dst, src: written(&ops[0], src) });
+            block.push(lir::Lir::Label(label2));
+        }
+        X86_INS_PUSH => {
+            let src = op_to_expr(&ops[0], insn)?;
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Name(RSP.to_string()),
+                src: expr::Expr::Binary {
+                    op: expr::BinaryOp::Sub,
+                    lhs: Box::new(expr::Expr::Name(RSP.to_string())),
+                    rhs: Box::new(expr::Expr::Num(8)),
+                },
+            });
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Deref {
+                    ptr: Box::new(expr::Expr::Name(RSP.to_string())),
+                    size: ty::Size::Size64
+                },
+                src,
+            });
+        }
+        X86_INS_POP | X86_INS_LEAVE => {
+            let dst = if id == X86_INS_LEAVE {
+                block.push(lir::Lir::Assign {
+                    dst: expr::Expr::Name(RSP.to_string()),
+                    src: expr::Expr::Name(RBP.to_string()),
+                });
+                expr::Expr::Name(RBP.to_string())
+            } else {
+                op_to_expr(&ops[0], insn)?
+            };
+
+            block.push(lir::Lir::Assign {
+                dst,
+                src: expr::Expr::Deref {
+                    ptr: Box::new(expr::Expr::Name(RSP.to_string())),
+                    size: ty::Size::Size64
+                },
+            });
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Name(RSP.to_string()),
+                src: expr::Expr::Binary {
+                    op: expr::BinaryOp::Add,
+                    lhs: Box::new(expr::Expr::Name(RSP.to_string())),
+                    rhs: Box::new(expr::Expr::Num(8)),
+                },
+            });
+        }
+        X86_INS_RET => {
+            block.push(lir::Lir::Return(vec![expr::Expr::Name(RAX.to_string())]));
+        }
+        X86_INS_CALL => {
+            let func = match x86_op(&ops[0]).op_type {
+                X86OperandType::Imm(val) => match addr_to_func.get(&(val as u64)) {
+                    Some(func) => expr::Expr::Func(*func),
+                    None => expr::Expr::Num(val),
+                },
+                _ => op_to_expr(&ops[0], insn)?
+            };
+
+            block.push(lir::Lir::Assign {
+                dst: expr::Expr::Name(RAX.to_string()),
+                src: expr::Expr::Call {
+                    func: Box::new(func),
+                    args: vec![],
+                },
+            });
+        }
+        X86_INS_JMP | X86_INS_JE | X86_INS_JNE | X86_INS_JL | X86_INS_JLE | X86_INS_JG | X86_INS_JGE |
+        X86_INS_JB | X86_INS_JBE | X86_INS_JA | X86_INS_JAE | X86_INS_JS | X86_INS_JNS => {
+            let cond = if id == X86_INS_JMP { None } else { Some(cond(id)?) };
+
+            let X86OperandType::Imm(val) = x86_op(&ops[0]).op_type else {
+                return Err(format!("Indirect jump: {} {}", insn.mnemonic().unwrap(), insn.op_str().unwrap()));
+            };
+
+            let val = val as u64;
+            if !range.contains(&val) {
+                // Jumping to another function is a tail call
+                let (Some(func), None) = (addr_to_func.get(&val), &cond) else {
+                    return Err(format!("Jump outside of function: {} {}", insn.mnemonic().unwrap(), insn.op_str().unwrap()));
+                };
+
+                block.push(lir::Lir::Assign {
+                    dst: expr::Expr::Name(RAX.to_string()),
+                    src: expr::Expr::Call {
+                        func: Box::new(expr::Expr::Func(*func)),
+                        args: vec![],
+                    },
+                });
+                block.push(lir::Lir::Return(vec![expr::Expr::Name(RAX.to_string())]));
+                return Ok(());
+            }
+
+            let target = if let Some(label) = addr_to_label.get(&val) {
+                *label
+            } else {
+                let label = block.new_label();
+                addr_to_label.insert(val, label);
+                label
+            };
+
+            block.push(lir::Lir::Branch { cond, target });
+        }
+        _ => return Err(format!(
+            "Unimplemented instruction: {} {}",
+            insn.mnemonic().unwrap(),
+            insn.op_str().unwrap()
+        )),
+    }
+
+    Ok(())
+}
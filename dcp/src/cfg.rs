@@ -21,6 +21,14 @@ impl Dominators {
     pub fn implies_backwards_edge(&self, src: NodeId, dst: NodeId) -> bool {
         self.dominators.get(&src).expect("Invalid src node").contains(&dst)
     }
+
+    /// The closest strict dominator of node, which is the one dominated by all the others
+    pub fn immediate_dominator(&self, node: NodeId) -> Option<NodeId> {
+        self.dominators.get(&node).expect("Invalid node").iter()
+            .filter(|x| **x != node)
+            .max_by_key(|x| self.dominators[x].len())
+            .copied()
+    }
 }
 
 #[derive(Debug)]
@@ -149,6 +157,43 @@ impl ControlFlowGraph {
         Dominators { dominators }
     }
 
+    pub fn reachable(&self) -> HashSet<NodeId> {
+        let mut visited = HashSet::new();
+        let mut to_visit: Vec<_> = self.entry.into_iter().collect();
+
+        while let Some(node) = to_visit.pop() {
+            if visited.insert(node) {
+                to_visit.extend(self.outgoing_for(node));
+            }
+        }
+
+        visited
+    }
+
+    /// For each node, the nodes where its dominance ends, i.e. those it does not strictly dominate, but dominates a predecessor of
+    pub fn dominance_frontiers(&self, dominators: &Dominators) -> HashMap<NodeId, HashSet<NodeId>> {
+        let reachable = self.reachable();
+        let mut frontiers: HashMap<_, HashSet<_>> = reachable.iter().map(|x| (*x, HashSet::new())).collect();
+
+        for node in &reachable {
+            let incoming: Vec<_> = self.incoming_for(*node).iter().filter(|x| reachable.contains(x)).collect();
+            if incoming.len() < 2 {
+                continue;
+            }
+
+            let idom = dominators.immediate_dominator(*node);
+            for pred in incoming {
+                let mut runner = Some(*pred);
+                while let Some(r) = runner && Some(r) != idom {
+                    frontiers.get_mut(&r).unwrap().insert(*node);
+                    runner = dominators.immediate_dominator(r);
+                }
+            }
+        }
+
+        frontiers
+    }
+
     pub fn consistency_check(&self) {
         for node in self.nodes() {
             for out in self.outgoing_for(node) {
@@ -0,0 +1,154 @@
+use crate::{cfg, lir, expr, dataflow::{Abi, Ssa}};
+
+use super::stack_delta::stack_offset;
+
+enum AccessKind {
+    /// A store of a callee saved register's entry value
+    Spill(String),
+    /// A load straight into a name
+    Restore,
+    Other
+}
+
+struct Access {
+    node: usize,
+    stmt: usize,
+    offset: i64,
+    size: i64,
+    kind: AccessKind
+}
+
+fn find_accesses_in(expr: &expr::Expr, base_reg: &str, node: usize, stmt: usize, accesses: &mut Vec<Access>) {
+    match expr {
+        expr::Expr::Deref { ptr, size } => {
+            if let Some(offset) = stack_offset(ptr, base_reg) {
+                accesses.push(Access { node, stmt, offset, size: size.byte_count() as i64, kind: AccessKind::Other });
+            }
+            find_accesses_in(ptr, base_reg, node, stmt, accesses);
+        }
+        expr::Expr::Binary { lhs, rhs, .. } => {
+            find_accesses_in(lhs, base_reg, node, stmt, accesses);
+            find_accesses_in(rhs, base_reg, node, stmt, accesses);
+        }
+        expr::Expr::Unary { expr, .. } => find_accesses_in(expr, base_reg, node, stmt, accesses),
+        expr::Expr::Ref(value) => find_accesses_in(value, base_reg, node, stmt, accesses),
+        expr::Expr::Table { index, .. } => find_accesses_in(index, base_reg, node, stmt, accesses),
+        expr::Expr::Call { func, args } => {
+            find_accesses_in(func, base_reg, node, stmt, accesses);
+            for arg in args {
+                find_accesses_in(arg, base_reg, node, stmt, accesses);
+            }
+        }
+        expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {}
+    }
+}
+
+/// expr without any reinterpretation between a float and its bits, which saving and restoring float registers goes through
+fn bits_of(expr: &expr::Expr) -> &expr::Expr {
+    match expr {
+        expr::Expr::Unary { op: expr::UnaryOp::FloatToBits(_) | expr::UnaryOp::BitsToFloat(_), expr } => expr,
+        _ => expr
+    }
+}
+
+fn find_accesses(nodes: &[lir::LirNode], ssa: &Ssa, abi: &Abi, base_reg: &str) -> Vec<Access> {
+    let mut accesses = Vec::new();
+
+    for (n, node) in nodes.iter().enumerate() {
+        for (s, stmt) in node.code.iter().enumerate() {
+            if let lir::Lir::Assign { dst: expr::Expr::Deref { ptr, size }, src } = stmt && let Some(offset) = stack_offset(ptr, base_reg) {
+                let kind = match bits_of(src) {
+                    expr::Expr::Name(name) if name != base_reg && abi.callee_saved.contains(&name.as_str()) && ssa.is_entry_value(name) => AccessKind::Spill(name.clone()),
+                    _ => AccessKind::Other
+                };
+                accesses.push(Access { node: n, stmt: s, offset, size: size.byte_count() as i64, kind });
+                find_accesses_in(ptr, base_reg, n, s, &mut accesses);
+                find_accesses_in(src, base_reg, n, s, &mut accesses);
+                continue;
+            }
+
+            if let lir::Lir::Assign { dst: expr::Expr::Name(_), src } = stmt && let expr::Expr::Deref { ptr, size } = bits_of(src) && let Some(offset) = stack_offset(ptr, base_reg) {
+                accesses.push(Access { node: n, stmt: s, offset, size: size.byte_count() as i64, kind: AccessKind::Restore });
+                find_accesses_in(ptr, base_reg, n, s, &mut accesses);
+                continue;
+            }
+
+            let exprs: Vec<&expr::Expr> = match stmt {
+                lir::Lir::Assign { dst, src } => vec![dst, src],
+                lir::Lir::Do(expr) | lir::Lir::Branch { cond: Some(expr), .. } => vec![expr],
+                lir::Lir::Return(ret) => ret.iter().collect(),
+                lir::Lir::Phi { srcs, .. } => srcs.iter().map(|(_, src)| src).collect(),
+                lir::Lir::Branch { .. } | lir::Lir::Label(_) => vec![]
+            };
+
+            for expr in exprs {
+                find_accesses_in(expr, base_reg, n, s, &mut accesses);
+            }
+        }
+    }
+
+    accesses
+}
+
+/// Removes the saving of callee saved registers to the frame, and turns their restores into copies of the entry value,
+/// or drops them when they restore the register they were saved from.
+/// A slot only counts if nothing else writes to it, and everything that reads it reads it whole, after the save.
+/// Saves outside the entry, as when only some paths need them, must come before all their restores, with nothing
+/// but those restores writing to the register, so that it still holds its entry value when saved.
+/// Must be run on SSA form, after `track_stack_pointer`.
+pub fn elim_callee_saved_spills(graph: &cfg::ControlFlowGraph, nodes: &mut [lir::LirNode], ssa: &Ssa, abi: &Abi) {
+    let Some(base_reg) = abi.base_reg else {
+        return;
+    };
+
+    let dominators = graph.dominators();
+    let accesses = find_accesses(nodes, ssa, abi, base_reg);
+    let overlaps = |a: &Access, b: &Access| a.offset < b.offset + b.size && b.offset < a.offset + a.size;
+
+    let mut spills = Vec::new();
+    let mut restores = Vec::new();
+
+    for spill in &accesses {
+        let AccessKind::Spill(saved) = &spill.kind else {
+            continue;
+        };
+
+        let mut loads = Vec::new();
+        let mut valid = true;
+        for other in accesses.iter().filter(|x| !std::ptr::eq(*x, spill) && overlaps(x, spill)) {
+            match other.kind {
+                AccessKind::Restore if
+                    other.offset == spill.offset &&
+                    other.size == spill.size &&
+                    (other.node != spill.node && dominators.dominates(spill.node, other.node) || other.node == spill.node && other.stmt > spill.stmt) => loads.push((other.node, other.stmt)),
+                _ => {
+                    valid = false;
+                    break;
+                }
+            }
+        }
+
+        if valid {
+            spills.push((spill.node, spill.stmt));
+            restores.extend(loads.into_iter().map(|x| (x, saved.clone())));
+        }
+    }
+
+    // Restores into the register saved would leave it assigned to itself, so they go along with the saves
+    let mut removed = spills;
+    for ((node, stmt), saved) in restores {
+        if let lir::Lir::Assign { dst, src } = &mut nodes[node].code[stmt] {
+            if *dst == expr::Expr::Name(saved.clone()) {
+                removed.push((node, stmt));
+            } else {
+                *src = expr::Expr::Name(saved);
+            }
+        }
+    }
+
+    // Go backwards to keep the indices valid
+    removed.sort();
+    for (node, stmt) in removed.into_iter().rev() {
+        nodes[node].code.remove(stmt);
+    }
+}
@@ -3,7 +3,8 @@ use std::collections::HashSet;
 use crate::{cfg, lir, dataflow::Abi, expr};
 
 fn has_reader(cfg: &cfg::ControlFlowGraph, nodes: &[lir::LirNode], node: usize, stmt: usize, name: &str, abi: &Abi, visited: &mut HashSet<usize>) -> bool {
-    if !visited.insert(node) {
+    // Only whole nodes count as visited, as a loop can come back round to the start of this one
+    if stmt == 0 && !visited.insert(node) {
         return false;
     }
 
@@ -41,7 +42,7 @@ fn elim_dead_write_in(cfg: &cfg::ControlFlowGraph, node: usize, nodes: &mut Vec<
             continue;
         };
 
-        if abi.global.contains(&name.as_str()) {
+        if abi.global.contains(name) {
             i += 1;
             continue;
         }
@@ -70,3 +71,24 @@ pub fn elim_dead_writes(graph: &cfg::ControlFlowGraph, nodes: &mut Vec<lir::LirN
         i += 1;
     }
 }
+
+/// `x = f()` can become `f()` if x is not read before it is next written
+fn elim_dead_call_result_in(cfg: &cfg::ControlFlowGraph, node: usize, nodes: &mut [lir::LirNode], abi: &Abi) {
+    for i in 0..nodes[node].code.len() {
+        let lir::Lir::Assign { dst: expr::Expr::Name(name), src: src @ expr::Expr::Call { .. } } = &nodes[node].code[i] else {
+            continue;
+        };
+
+        if abi.global.contains(name) || has_reader(cfg, nodes, node, i + 1, name, abi, &mut HashSet::new()) {
+            continue;
+        }
+
+        nodes[node].code[i] = lir::Lir::Do(src.clone());
+    }
+}
+
+pub fn elim_dead_call_results(graph: &cfg::ControlFlowGraph, nodes: &mut [lir::LirNode], abi: &Abi) {
+    for i in 0..nodes.len() {
+        elim_dead_call_result_in(graph, i, nodes, abi);
+    }
+}
@@ -1,6 +1,6 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use crate::{lir, expr, Module, FunctionDefSet};
+use crate::{lir, expr, Module, FunctionDef, FunctionDefSet};
 
 use super::ReadWrite;
 
@@ -16,9 +16,14 @@ fn stmt_reads_or_writes_recursive(module: &mut Module, defs: &FunctionDefSet, vi
     }
 
     if
-        let lir::Lir::Assign { src: expr::Expr::Call { func, .. }, .. } = stmt &&
-        let expr::Expr::Call { func, .. } = func.as_ref() &&
+        let lir::Lir::Assign { src: expr::Expr::Call { func, args }, .. } | lir::Lir::Do(expr::Expr::Call { func, args }) = stmt &&
+        args.is_empty() &&
         let expr::Expr::Func(funcid) = func.as_ref() {
+        // Without a body there is nothing to say the callee doesn't read the register
+        if defs.find(*funcid).is_none() {
+            return if extern_args(module, *funcid).contains(&name) { ReadWrite::Reads } else { ReadWrite::Writes };
+        }
+
         if module.functions[funcid.0].args.contains(&name) {
             return ReadWrite::Reads;
         } else if visited.insert(*funcid) {
@@ -70,19 +75,77 @@ fn reads_before_writes_recursive(module: &mut Module, defs: &FunctionDefSet, glo
 
 fn visit_func(module: &mut Module, defs: &FunctionDefSet, global_visited: &mut HashSet<expr::FuncId>, global_node: expr::FuncId) -> bool {
     let mut changed = false;
-    for a in module.find_decl(global_node).unwrap().args.len()..module.abi.args.len() {
-        let arg = module.abi.args[a];
-        if reads_before_writes_recursive(module, defs, global_visited, global_node, arg) {
-            module.find_decl_mut(global_node).unwrap().args.push(arg);
+    // Ints and floats each take the first of their own registers
+    let order: Vec<_> = module.abi.args.iter().chain(&module.abi.float_args).copied().collect();
+    for regs in [module.abi.args.clone(), module.abi.float_args.clone()] {
+        // Registers before the last one read are taken too, since they may only be passed on
+        let known = regs.iter().filter(|x| module.find_decl(global_node).unwrap().args.contains(x)).count();
+        let mut last_read = None;
+        for (i, arg) in regs.iter().enumerate().skip(known) {
+            if reads_before_writes_recursive(module, defs, global_visited, global_node, arg) {
+                last_read = Some(i);
+            }
+        }
+
+        // Visiting callees may have come back round to this function and added some already
+        let decl = module.find_decl_mut(global_node).unwrap();
+        if let Some(last) = last_read && !decl.args.contains(&regs[last]) {
+            for arg in &regs[..=last] {
+                if !decl.args.contains(arg) {
+                    decl.args.push(arg);
+                }
+            }
             changed = true;
-        } else {
-            break;
         }
     }
+
+    if changed {
+        module.find_decl_mut(global_node).unwrap().args.sort_by_key(|x| order.iter().position(|y| y == x));
+    }
     changed
 }
 
+/// The argument registers a function without a body reads: those it is seen to be passed, or all of them if it never is
+fn extern_args(module: &Module, funcid: expr::FuncId) -> Vec<&'static str> {
+    match module.find_decl(funcid) {
+        Some(decl) if !decl.args.is_empty() => decl.args.clone(),
+        _ => module.abi.args.iter().chain(&module.abi.float_args).copied().collect()
+    }
+}
+
+/// Gives functions without a body the arguments they are passed at their calls, up to the last register written before any of them
+fn extern_func_args(module: &mut Module, defs: &FunctionDefSet) {
+    let mut passed: HashMap<expr::FuncId, HashSet<&'static str>> = HashMap::new();
+    for function in &module.functions {
+        let Some(def) = defs.find(function.funcid) else {
+            continue;
+        };
+
+        for (node, mut written) in reaching_args(module, def).into_iter().enumerate() {
+            for stmt in &def.local_lirnodes[node].code {
+                if let lir::Lir::Assign { src: expr::Expr::Call { func, args }, .. } | lir::Lir::Do(expr::Expr::Call { func, args }) = stmt &&
+                    args.is_empty() &&
+                    let expr::Expr::Func(funcid) = func.as_ref() &&
+                    defs.find(*funcid).is_none() {
+                    passed.entry(*funcid).or_default().extend(written.iter().copied());
+                }
+                update_written(module, stmt, &mut written);
+            }
+        }
+    }
+
+    for (funcid, written) in passed {
+        let ints = up_to_last_written(&module.abi.args, &written).to_vec();
+        let floats = up_to_last_written(&module.abi.float_args, &written).to_vec();
+        if let Some(decl) = module.find_decl_mut(funcid) && decl.args.is_empty() {
+            decl.args = ints.into_iter().chain(floats).collect();
+        }
+    }
+}
+
 pub fn func_args(module: &mut Module, defs: &FunctionDefSet) {
+    extern_func_args(module, defs);
+
     let mut changed = true;
 
     while changed {
@@ -97,62 +160,134 @@ pub fn func_args(module: &mut Module, defs: &FunctionDefSet) {
     }
 }
 
-fn insert_func_args_in_expr(module: &Module, expr: &mut expr::Expr) {
+/// The registers of regs up to the last one that is written, since those before it may be passed through untouched
+fn up_to_last_written<'a>(regs: &'a [&'static str], written: &HashSet<&str>) -> &'a [&'static str] {
+    match regs.iter().rposition(|arg| written.contains(arg)) {
+        Some(last) => &regs[..=last],
+        None => &[]
+    }
+}
+
+/// Arguments for calls to functions we can't see the body of are guessed from the argument registers written before the call
+fn inferred_args(module: &Module, written: &HashSet<&str>) -> Vec<expr::Expr> {
+    let ints = up_to_last_written(&module.abi.args, written);
+    let floats = up_to_last_written(&module.abi.float_args, written);
+    ints.iter().chain(floats)
+        .map(|arg| expr::Expr::Name(arg.to_string()))
+        .collect()
+}
+
+fn insert_func_args_in_expr(module: &Module, defined: &HashSet<expr::FuncId>, written: &HashSet<&str>, expr: &mut expr::Expr) {
     match expr {
         expr::Expr::Binary { lhs, rhs, .. } => {
-            insert_func_args_in_expr(module, lhs);
-            insert_func_args_in_expr(module, rhs);
+            insert_func_args_in_expr(module, defined, written, lhs);
+            insert_func_args_in_expr(module, defined, written, rhs);
         }
         expr::Expr::Unary { expr, .. } => {
-            insert_func_args_in_expr(module, expr);
+            insert_func_args_in_expr(module, defined, written, expr);
         }
         expr::Expr::Call { func, args } => {
-            if let expr::Expr::Func(funcid) = func.as_ref() && let Some(sig) = module.find_decl(*funcid) {
+            insert_func_args_in_expr(module, defined, written, func);
+            for arg in args.iter_mut() {
+                insert_func_args_in_expr(module, defined, written, arg);
+            }
+
+            // Calls which already carry their arguments (e.g. from wasm), and builtins, which always do, are left alone
+            if !args.is_empty() || matches!(func.as_ref(), expr::Expr::BuiltIn(_)) {
+                return;
+            }
+
+            if let expr::Expr::Func(funcid) = func.as_ref() && defined.contains(funcid) && let Some(sig) = module.find_decl(*funcid) {
                 args.extend(sig.args.iter().cloned().map(str::to_string).map(expr::Expr::Name));
+            } else if let expr::Expr::Func(funcid) = func.as_ref() {
+                args.extend(extern_args(module, *funcid).into_iter().map(str::to_string).map(expr::Expr::Name));
             } else {
-                insert_func_args_in_expr(module, func);
-                for arg in args {
-                    insert_func_args_in_expr(module, arg);
-                }
+                // A register holding the address called is there for the call, not the callee
+                let written: HashSet<_> = written.iter().copied().filter(|arg| func.count_reads(arg) == 0).collect();
+                args.extend(inferred_args(module, &written));
             }
         }
-        expr::Expr::Bool(_) | expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {},
+        expr::Expr::Bool(_) | expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {},
         expr::Expr::Deref { ptr, .. } => {
-            insert_func_args_in_expr(module, ptr);
+            insert_func_args_in_expr(module, defined, written, ptr);
         }
         expr::Expr::Ref(value) => {
-            insert_func_args_in_expr(module, value);
+            insert_func_args_in_expr(module, defined, written, value);
+        }
+        expr::Expr::Table { index, .. } => {
+            insert_func_args_in_expr(module, defined, written, index);
         }
     }
 }
 
-fn insert_func_args_in(module: &Module, node: usize, nodes: &mut Vec<lir::LirNode>) {
+/// Updates the argument registers written since the last call for stmt. Calls to anything but builtins use them up
+fn update_written(module: &Module, stmt: &lir::Lir, written: &mut HashSet<&'static str>) {
+    if let lir::Lir::Assign { src: expr::Expr::Call { func, .. }, .. } | lir::Lir::Do(expr::Expr::Call { func, .. }) = stmt && !matches!(func.as_ref(), expr::Expr::BuiltIn(_)) {
+        written.clear();
+    }
+
+    if let lir::Lir::Assign { dst: expr::Expr::Name(name), .. } = stmt && let Some(arg) = module.abi.args.iter().chain(&module.abi.float_args).find(|x| *x == name) {
+        written.insert(*arg);
+    }
+}
+
+/// The argument registers written on some path to the start of each node, since the function began or the last call.
+/// Those the function was passed don't count, as a call may or may not pass them on
+fn reaching_args(module: &Module, def: &FunctionDef) -> Vec<HashSet<&'static str>> {
+    let mut reaching = vec![HashSet::new(); def.local_lirnodes.len()];
+
+    let mut to_visit: Vec<_> = (0..def.local_lirnodes.len()).collect();
+    while let Some(node) = to_visit.pop() {
+        let mut written = reaching[node].clone();
+        for stmt in &def.local_lirnodes[node].code {
+            update_written(module, stmt, &mut written);
+        }
+
+        for next in def.local_cfg.outgoing_for(node) {
+            if !written.is_subset(&reaching[*next]) {
+                reaching[*next].extend(written.iter().copied());
+                to_visit.push(*next);
+            }
+        }
+    }
+
+    reaching
+}
+
+fn insert_func_args_in(module: &Module, defined: &HashSet<expr::FuncId>, mut written: HashSet<&'static str>, node: usize, nodes: &mut [lir::LirNode]) {
     for stmt in &mut nodes[node].code {
         match stmt {
             lir::Lir::Assign { src, dst } => {
-                insert_func_args_in_expr(module, src);
-                insert_func_args_in_expr(module, dst);
+                insert_func_args_in_expr(module, defined, &written, src);
+                insert_func_args_in_expr(module, defined, &written, dst);
             }
             lir::Lir::Branch { cond: Some(cond), .. } => {
-                insert_func_args_in_expr(module, cond);
+                insert_func_args_in_expr(module, defined, &written, cond);
             }
-            lir::Lir::Return(expr) => insert_func_args_in_expr(module, expr),
-            lir::Lir::Do(expr) => insert_func_args_in_expr(module, expr),
-            lir::Lir::Label(_) | lir::Lir::Branch { .. } => {}
+            lir::Lir::Return(ret) => ret.iter_mut().for_each(|expr| insert_func_args_in_expr(module, defined, &written, expr)),
+            lir::Lir::Do(expr) => insert_func_args_in_expr(module, defined, &written, expr),
+            lir::Lir::Label(_) | lir::Lir::Branch { .. } | lir::Lir::Phi { .. } => {}
         }
+
+        update_written(module, stmt, &mut written);
     }
 }
 
+/// Gives every call the argument expressions it is passed, read from the argument registers of the ABI
 pub fn insert_func_args(module: &Module, defs: &mut FunctionDefSet) {
+    let defined: HashSet<_> = module.functions.iter()
+        .map(|function| function.funcid)
+        .filter(|funcid| defs.find(*funcid).is_some())
+        .collect();
+
     for function in &module.functions {
         let Some(def) = defs.find_mut(function.funcid) else {
             continue;
         };
 
-        let mut i = 0;
-        while i < def.local_lirnodes.len() {
-            insert_func_args_in(module, i, &mut def.local_lirnodes);
-            i += 1;
+        let reaching = reaching_args(module, def);
+        for (i, written) in reaching.into_iter().enumerate() {
+            insert_func_args_in(module, &defined, written, i, &mut def.local_lirnodes);
         }
     }
 }
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{lir, expr, ty, Module, FunctionDef, FunctionDefSet};
+
+/// How a value got into a result register, with later ones more telling of what the function returns
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Reach {
+    None,
+    Call,
+    Computed,
+}
+
+/// Which registers a function returns its results in, the ABI's results or its float results
+type Returning = HashMap<expr::FuncId, Vec<&'static str>>;
+
+/// The function called by stmt, if it calls one. Those calls change every result register, unlike builtins
+fn called(stmt: &lir::Lir) -> Option<&expr::Expr> {
+    match stmt {
+        lir::Lir::Assign { src: expr::Expr::Call { func, .. }, .. } | lir::Lir::Do(expr::Expr::Call { func, .. }) => match func.as_ref() {
+            expr::Expr::BuiltIn(_) => None,
+            func => Some(func)
+        },
+        _ => None
+    }
+}
+
+/// Whether a write to a result register leaves a meaningful value in it
+fn write_produces_value(defs: &FunctionDefSet, returning: &Returning, stmt: &lir::Lir, name: &str) -> Reach {
+    match called(stmt) {
+        // A call to a function we know about only leaves a value if that function returns one there
+        Some(expr::Expr::Func(funcid)) if defs.find(*funcid).is_some() => match returning.get(funcid) {
+            Some(results) if results.contains(&name) => Reach::Call,
+            _ => Reach::None
+        },
+        Some(_) => Reach::Call,
+        None => Reach::Computed
+    }
+}
+
+/// Searches backwards from (node, stmt) for writes to `name`, giving the least telling of them, since a value
+/// only reaches if every path there writes one
+fn value_reaches(defs: &FunctionDefSet, returning: &Returning, def: &FunctionDef, node: usize, stmt: usize, name: &str, visited: &mut HashSet<usize>) -> Reach {
+    for assignment in def.local_lirnodes[node].code[..stmt].iter().rev() {
+        if assignment.writes_to(name) || called(assignment).is_some() {
+            return write_produces_value(defs, returning, assignment, name);
+        }
+    }
+
+    // Whatever the caller left in the register doesn't count. Paths already searched have been counted already
+    let incoming = def.local_cfg.incoming_for(node);
+    if incoming.is_empty() {
+        return Reach::None;
+    }
+
+    let mut reach = Reach::Computed;
+    for incoming in incoming {
+        if visited.insert(*incoming) {
+            reach = reach.min(value_reaches(defs, returning, def, *incoming, def.local_lirnodes[*incoming].code.len(), name, visited));
+        }
+    }
+
+    reach
+}
+
+/// The registers def returns its results in. Ints win over floats unless only floats are computed before returning
+fn returned_regs(module: &Module, defs: &FunctionDefSet, returning: &Returning, def: &FunctionDef) -> Option<Vec<&'static str>> {
+    // Every return has to have a value to give
+    let reach = |results: &[&str]| {
+        let mut reach = None;
+        for (n, node) in def.local_lirnodes.iter().enumerate() {
+            for (i, stmt) in node.code.iter().enumerate() {
+                if matches!(stmt, lir::Lir::Return(_)) {
+                    for result in results {
+                        let result = value_reaches(defs, returning, def, n, i, result, &mut HashSet::new());
+                        reach = Some(reach.map_or(result, |reach: Reach| reach.min(result)));
+                    }
+                }
+            }
+        }
+        reach.unwrap_or(Reach::None)
+    };
+
+    let (ints, floats) = (reach(&module.abi.results), reach(&module.abi.float_results));
+    if ints == Reach::None && floats == Reach::None {
+        None
+    } else if floats > ints {
+        Some(module.abi.float_results.clone())
+    } else {
+        Some(module.abi.results.clone())
+    }
+}
+
+/// The functions whose callers read the int result register after calling them, before anything else writes it.
+/// Returning the value a caller got doesn't count, as that says no more than the call does
+fn used_results(module: &Module, defs: &FunctionDefSet) -> HashSet<expr::FuncId> {
+    let mut used = HashSet::new();
+    let Some(result) = module.abi.results.first() else {
+        return used;
+    };
+
+    for function in &module.functions {
+        let Some(def) = defs.find(function.funcid) else {
+            continue;
+        };
+
+        for node in &def.local_lirnodes {
+            for (s, stmt) in node.code.iter().enumerate() {
+                let Some(expr::Expr::Func(funcid)) = called(stmt) else {
+                    continue;
+                };
+
+                for after in &node.code[s + 1..] {
+                    if !matches!(after, lir::Lir::Return(_)) && after.count_reads(result) > 0 {
+                        used.insert(*funcid);
+                    }
+                    if after.writes_to(result) || called(after).is_some() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    used
+}
+
+/// Works out which functions return a value in the ABI result registers, and which of the ints or floats those are.
+/// Everything starts off void, so a function returning the result of a void call stays void. A function returns
+/// a value if every return has one computed before it, or a caller uses it.
+pub fn func_results(module: &mut Module, defs: &FunctionDefSet) {
+    // Wasm has its results in the function type
+    if module.abi.results.is_empty() {
+        return;
+    }
+
+    let used = used_results(module, defs);
+    let mut returning = HashMap::new();
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for function in &module.functions {
+            let Some(def) = defs.find(function.funcid) else {
+                continue;
+            };
+            if returning.contains_key(&function.funcid) {
+                continue;
+            }
+
+            let regs = returned_regs(module, defs, &returning, def)
+                .or_else(|| used.contains(&function.funcid).then(|| module.abi.results.clone()));
+            if let Some(regs) = regs {
+                returning.insert(function.funcid, regs);
+                changed = true;
+            }
+        }
+    }
+
+    for function in &mut module.functions {
+        // Functions without code are assumed to return something
+        let results = match returning.get(&function.funcid) {
+            Some(regs) if *regs == module.abi.float_results => regs.iter().map(|_| ty::Ty::Float { size: ty::Size::Size64 }).collect(),
+            Some(_) => module.abi.results.iter().map(|_| ty::Ty::Unknown64).collect(),
+            None if defs.find(function.funcid).is_none() => module.abi.results.iter().map(|_| ty::Ty::Unknown64).collect(),
+            None => vec![]
+        };
+        function.results = results;
+    }
+}
+
+/// The registers results of these types are returned in
+fn result_regs(module: &Module, results: &[ty::Ty]) -> Vec<&'static str> {
+    match results.first() {
+        Some(ty::Ty::Float { .. }) => module.abi.float_results.clone(),
+        Some(_) => module.abi.results.clone(),
+        None => vec![]
+    }
+}
+
+/// The registers the function called by expr returns its results in, if it is a call
+fn called_result_regs(module: &Module, expr: &expr::Expr) -> Option<Vec<&'static str>> {
+    let expr::Expr::Call { func, .. } = expr else {
+        return None;
+    };
+
+    match func.as_ref() {
+        expr::Expr::Func(funcid) => module.find_decl(*funcid).map(|decl| result_regs(module, &decl.results)),
+        _ => None
+    }
+}
+
+/// Keeps only the result registers that are returned in returns, and gives calls to void functions and to functions
+/// returning floats the right destination
+pub fn insert_func_results(module: &Module, defs: &mut FunctionDefSet) {
+    if module.abi.results.is_empty() {
+        return;
+    }
+
+    for function in &module.functions {
+        let Some(def) = defs.find_mut(function.funcid) else {
+            continue;
+        };
+
+        let regs = result_regs(module, &function.results);
+        for node in &mut def.local_lirnodes {
+            for stmt in &mut node.code {
+                match stmt {
+                    lir::Lir::Return(ret) => ret.retain(|value| matches!(value, expr::Expr::Name(name) if regs.contains(&name.as_str()))),
+                    lir::Lir::Assign { src, dst } => match called_result_regs(module, src).as_deref() {
+                        Some([]) => *stmt = lir::Lir::Do(src.take()),
+                        Some([reg, ..]) => *dst = expr::Expr::Name(reg.to_string()),
+                        None => {}
+                    },
+                    _ => {}
+                }
+            }
+        }
+    }
+}
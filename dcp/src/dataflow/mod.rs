@@ -5,26 +5,41 @@ pub use dead_writes::*;
 mod single_use;
 pub use single_use::*;
 
-mod every_use;
-pub use every_use::*;
 
 mod func_args;
 pub use func_args::*;
 
+mod func_results;
+pub use func_results::*;
+
 mod stack_frame;
 pub use stack_frame::*;
 
-mod ssaify;
-pub use ssaify::*;
+mod stack_delta;
+pub use stack_delta::*;
+
+mod callee_saved;
+pub use callee_saved::*;
+
+mod ssa;
+pub use ssa::*;
 
 use crate::{cfg, lir};
 
+#[derive(Clone)]
 pub struct Abi {
     pub callee_saved: Vec<&'static str>,
-    pub global: Vec<&'static str>, // FIXME: Don't put this here
+    pub global: Vec<String>, // FIXME: Don't put this here
     pub args: Vec<&'static str>,
+    /// Registers floats are passed in, counted separately from args
+    pub float_args: Vec<&'static str>,
+    pub results: Vec<&'static str>,
+    /// Registers floats are returned in, which returns list after results. A function returns in one or the other
+    pub float_results: Vec<&'static str>,
     pub eliminate: Vec<&'static str>,
     pub base_reg: Option<&'static str>,
+    /// Whether a call may change anything not callee saved, as it can registers. Wasm locals belong to their function
+    pub calls_clobber: bool,
 }
 
 enum ReadWrite {
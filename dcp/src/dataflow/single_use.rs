@@ -20,7 +20,8 @@ struct Reader {
 
 /// Finds the readers of name starting from and including (node, stmt). The readers may not have unique destinations, but will have unique paths.
 fn find_readers(cfg: &cfg::ControlFlowGraph, nodes: &[lir::LirNode], node: usize, stmt: usize, name: &str, visited: &mut HashSet<usize>) -> Vec<Reader> {
-    if !visited.insert(node) {
+    // Only whole nodes count as visited, as a loop can come back round to the start of this one
+    if stmt == 0 && !visited.insert(node) {
         return vec![];
     }
 
@@ -54,7 +55,7 @@ fn find_readers(cfg: &cfg::ControlFlowGraph, nodes: &[lir::LirNode], node: usize
 
 /// Counts the assignments which might write to name, moving backward from and not including (node, stmt)
 fn count_writers(cfg: &cfg::ControlFlowGraph, nodes: &[lir::LirNode], node: usize, stmt: usize, name: &str, visited: &mut HashSet<usize>) -> usize {
-    if !visited.insert(node) {
+    if stmt == nodes[node].code.len() && !visited.insert(node) {
         return 0;
     }
 
@@ -78,16 +79,18 @@ fn reader_path_is_candidate(_cfg: &cfg::ControlFlowGraph, nodes: &[lir::LirNode]
     for (c, component) in reader.path.iter().enumerate() {
         for s in component.1..component.2 {
             let stmt = &nodes[component.0].code[s];
+            let is_reader = s == component.2 - 1 && c == reader.path.len() - 1;
 
+            // The reader itself reads before it writes, as in `g = g + 1`
             for clobber in clobbers {
-                if stmt.writes_to(&clobber) {
+                if !is_reader && stmt.writes_to(&clobber) {
                     return false;
                 }
             }
 
             // Don't allow side effects, unless they are in the last instruction
             // FIXME: This is incorrect in general
-            if (s != component.2 - 1 || c != reader.path.len() - 1) && stmt.has_side_effects() {
+            if !is_reader && stmt.has_side_effects() {
                 return false;
             }
         }
@@ -96,7 +99,7 @@ fn reader_path_is_candidate(_cfg: &cfg::ControlFlowGraph, nodes: &[lir::LirNode]
     true
 }
 
-fn inline_single_use_names_in(cfg: &cfg::ControlFlowGraph, node: usize, nodes: &mut Vec<lir::LirNode>, _abi: &Abi) -> bool {
+fn inline_single_use_names_in(cfg: &cfg::ControlFlowGraph, node: usize, nodes: &mut Vec<lir::LirNode>, abi: &Abi) -> bool {
     let mut changed = false;
 
     let mut s = 0;
@@ -106,6 +109,12 @@ fn inline_single_use_names_in(cfg: &cfg::ControlFlowGraph, node: usize, nodes: &
             continue;
         };
 
+        // Globals may be read outside of what the graph shows
+        if abi.global.contains(name) {
+            s += 1;
+            continue;
+        }
+
         // s + 1, because we don't want to include ourselves
         let readers = find_readers(cfg, nodes, node, s + 1, name, &mut HashSet::new());
         
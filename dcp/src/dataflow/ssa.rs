@@ -0,0 +1,534 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{cfg, lir, expr, dataflow::Abi};
+
+use super::stack_delta::stack_offset;
+
+/// The names introduced by `to_ssa`, each mapped to the name it is a version of
+pub struct Ssa {
+    bases: HashMap<String, String>
+}
+
+impl Ssa {
+    /// Whether name is the value something had on entry, rather than a version introduced by `to_ssa`
+    pub fn is_entry_value(&self, name: &str) -> bool {
+        !self.bases.contains_key(name)
+    }
+}
+
+fn rename_expr(expr: &mut expr::Expr, rename: &mut impl FnMut(&mut String)) {
+    match expr {
+        expr::Expr::Name(name) => rename(name),
+        expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {}
+        expr::Expr::Binary { lhs, rhs, .. } => {
+            rename_expr(lhs, rename);
+            rename_expr(rhs, rename);
+        }
+        expr::Expr::Unary { expr, .. } => rename_expr(expr, rename),
+        expr::Expr::Deref { ptr, .. } => rename_expr(ptr, rename),
+        expr::Expr::Ref(value) => rename_expr(value, rename),
+        expr::Expr::Table { index, .. } => rename_expr(index, rename),
+        expr::Expr::Call { func, args } => {
+            rename_expr(func, rename);
+            for arg in args {
+                rename_expr(arg, rename);
+            }
+        }
+    }
+}
+
+/// Renames every name read by stmt (phi sources are read at the end of their predecessor, so are left alone)
+fn rename_reads(stmt: &mut lir::Lir, rename: &mut impl FnMut(&mut String)) {
+    match stmt {
+        lir::Lir::Assign { dst: expr::Expr::Name(_), src } => rename_expr(src, rename),
+        lir::Lir::Assign { dst, src } => {
+            rename_expr(src, rename);
+            rename_expr(dst, rename);
+        }
+        lir::Lir::Do(expr) => rename_expr(expr, rename),
+        lir::Lir::Return(ret) => ret.iter_mut().for_each(|expr| rename_expr(expr, rename)),
+        lir::Lir::Branch { cond: Some(cond), .. } => rename_expr(cond, rename),
+        lir::Lir::Branch { .. } | lir::Lir::Label(_) | lir::Lir::Phi { .. } => {}
+    }
+}
+
+fn written_name(stmt: &mut lir::Lir) -> Option<&mut String> {
+    match stmt {
+        lir::Lir::Assign { dst: expr::Expr::Name(name), .. } | lir::Lir::Phi { dst: expr::Expr::Name(name), .. } => Some(name),
+        _ => None
+    }
+}
+
+/// Whether stmt calls a function, which can change any register that isn't callee saved when calls clobber them
+fn clobbers(stmt: &lir::Lir, abi: &Abi) -> bool {
+    match stmt {
+        lir::Lir::Assign { src: expr::Expr::Call { func, .. }, .. } | lir::Lir::Do(expr::Expr::Call { func, .. }) if abi.calls_clobber =>
+            !matches!(func.as_ref(), expr::Expr::BuiltIn(_)),
+        _ => false
+    }
+}
+
+/// Whether a call can leave something else in name. The stack and frame registers are put back by the callee
+fn clobbered(name: &str, abi: &Abi) -> bool {
+    !abi.callee_saved.contains(&name) && !abi.eliminate.contains(&name) && abi.base_reg != Some(name) && !abi.global.iter().any(|x| x == name)
+}
+
+/// Names live on entry to each node. Returns implicitly read the callee saved registers.
+fn live_in(graph: &cfg::ControlFlowGraph, nodes: &[lir::LirNode], abi: &Abi) -> HashMap<usize, HashSet<String>> {
+    let reachable = graph.reachable();
+    let mut live_in: HashMap<usize, HashSet<String>> = reachable.iter().map(|x| (*x, HashSet::new())).collect();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for node in &reachable {
+            let mut live = live_out(graph, &live_in, *node);
+            step_back_through(&nodes[*node].code, &mut live, abi, |_, _| {});
+
+            if live != live_in[node] {
+                live_in.insert(*node, live);
+                changed = true;
+            }
+        }
+    }
+
+    live_in
+}
+
+fn live_out(graph: &cfg::ControlFlowGraph, live_in: &HashMap<usize, HashSet<String>>, node: usize) -> HashSet<String> {
+    graph.outgoing_for(node).iter()
+        .filter_map(|x| live_in.get(x))
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Moves live (the names live at the end of code) back to the start of code, calling at_def with the written name and the names live just after each write
+fn step_back_through(code: &[lir::Lir], live: &mut HashSet<String>, abi: &Abi, mut at_def: impl FnMut(&lir::Lir, &HashSet<String>)) {
+    for stmt in code.iter().rev() {
+        if let lir::Lir::Assign { dst: expr::Expr::Name(name), .. } = stmt {
+            at_def(stmt, live);
+            live.remove(name);
+        }
+
+        if let lir::Lir::Return(_) = stmt {
+            live.extend(abi.callee_saved.iter().map(|x| x.to_string()));
+        }
+
+        live.extend(stmt.read_names().into_iter().map(str::to_string));
+    }
+}
+
+struct Renaming<'a> {
+    graph: &'a cfg::ControlFlowGraph,
+    children: HashMap<usize, Vec<usize>>,
+    abi: &'a Abi,
+    ssa: Ssa,
+    /// Per name, the number of versions so far and the stack of versions visible at this point in the dominator tree
+    versions: HashMap<String, (usize, Vec<String>)>,
+    /// Callee saved registers which need restoring before a return, as (node, stmt, register, version)
+    exits: Vec<(usize, usize, String, String)>
+}
+
+impl<'a> Renaming<'a> {
+    fn current(&self, name: &str) -> Option<&String> {
+        self.versions.get(name).and_then(|x| x.1.last())
+    }
+
+    /// Makes a new version of name, visible until the end of the node it is made in
+    fn new_version(&mut self, name: &str) -> String {
+        let (count, stack) = self.versions.get_mut(name).unwrap();
+        *count += 1;
+        let version = format!("{name}_{count}");
+        self.ssa.bases.insert(version.clone(), name.to_string());
+        stack.push(version.clone());
+        version
+    }
+
+    fn rename_node(&mut self, node: usize, nodes: &mut [lir::LirNode]) {
+        let mut pushed = Vec::new();
+
+        for (s, stmt) in nodes[node].code.iter_mut().enumerate() {
+            rename_reads(stmt, &mut |name| if let Some(version) = self.current(name) {
+                *name = version.clone();
+            });
+
+            // The caller expects callee saved registers to be restored, so this is an implicit read
+            if let lir::Lir::Return(_) = stmt {
+                for saved in &self.abi.callee_saved {
+                    if let Some(version) = self.current(saved) {
+                        self.exits.push((node, s, saved.to_string(), version.clone()));
+                    }
+                }
+            }
+
+            // What the call leaves in the other registers is a new value, with no statement defining it
+            if clobbers(stmt, self.abi) {
+                let written = written_name(stmt).cloned();
+                let mut names: Vec<_> = self.versions.keys().filter(|x| clobbered(x, self.abi) && Some(*x) != written.as_ref()).cloned().collect();
+                names.sort();
+                for name in names {
+                    self.new_version(&name);
+                    pushed.push(name);
+                }
+            }
+
+            if let Some(name) = written_name(stmt) && self.versions.contains_key(name.as_str()) {
+                *name = self.new_version(name);
+                pushed.push(self.ssa.bases[name.as_str()].clone());
+            }
+        }
+
+        for outgoing in self.graph.outgoing_for(node) {
+            for stmt in &mut nodes[*outgoing].code {
+                let lir::Lir::Phi { srcs, .. } = stmt else {
+                    break;
+                };
+
+                for (pred, src) in srcs.iter_mut() {
+                    if *pred == node && let expr::Expr::Name(name) = src && let Some(version) = self.current(name) {
+                        *name = version.clone();
+                    }
+                }
+            }
+        }
+
+        for child in self.children.get(&node).cloned().unwrap_or_default() {
+            self.rename_node(child, nodes);
+        }
+
+        for name in pushed {
+            self.versions.get_mut(&name).unwrap().1.pop();
+        }
+    }
+}
+
+/// Puts the function into SSA form, so every name (other than globals) is written once.
+/// Names keep their original spelling for the value they have on entry.
+pub fn to_ssa(graph: &cfg::ControlFlowGraph, nodes: &mut [lir::LirNode], abi: &Abi) -> Ssa {
+    let reachable = graph.reachable();
+    let Some(entry) = graph.get_entry() else {
+        return Ssa { bases: HashMap::new() };
+    };
+
+    // Code that can't run would otherwise have to be renamed as well
+    for (n, node) in nodes.iter_mut().enumerate() {
+        if !reachable.contains(&n) {
+            node.code.clear();
+        }
+    }
+
+    let dominators = graph.dominators();
+    let frontiers = graph.dominance_frontiers(&dominators);
+    let live_in = live_in(graph, nodes, abi);
+
+    let mut def_nodes: HashMap<String, HashSet<usize>> = HashMap::new();
+    for (n, node) in nodes.iter().enumerate() {
+        for stmt in &node.code {
+            if let lir::Lir::Assign { dst: expr::Expr::Name(name), .. } = stmt && !abi.global.contains(name) {
+                def_nodes.entry(name.clone()).or_default().insert(n);
+            }
+        }
+    }
+
+    // Calls define every register they clobber
+    for (n, node) in nodes.iter().enumerate() {
+        if node.code.iter().any(|stmt| clobbers(stmt, abi)) {
+            for (_, defs) in def_nodes.iter_mut().filter(|(name, _)| clobbered(name, abi)) {
+                defs.insert(n);
+            }
+        }
+    }
+
+    // Insert phis where definitions meet, if the name is still needed there
+    let mut names: Vec<_> = def_nodes.keys().cloned().collect();
+    names.sort();
+    for name in &names {
+        let mut has_phi = HashSet::new();
+        let mut to_visit: Vec<_> = def_nodes[name].iter().copied().collect();
+
+        while let Some(node) = to_visit.pop() {
+            for frontier in &frontiers[&node] {
+                if !live_in[frontier].contains(name) || !has_phi.insert(*frontier) {
+                    continue;
+                }
+
+                let mut srcs: Vec<_> = graph.incoming_for(*frontier).iter()
+                    .filter(|x| reachable.contains(x))
+                    .map(|x| (*x, expr::Expr::Name(name.clone())))
+                    .collect();
+                srcs.sort_by_key(|x| x.0);
+
+                nodes[*frontier].code.insert(0, lir::Lir::Phi { dst: expr::Expr::Name(name.clone()), srcs });
+                to_visit.push(*frontier);
+            }
+        }
+    }
+
+    let mut children: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut sorted: Vec<_> = reachable.iter().copied().collect();
+    sorted.sort();
+    for node in sorted {
+        if let Some(idom) = dominators.immediate_dominator(node) && node != entry {
+            children.entry(idom).or_default().push(node);
+        }
+    }
+
+    let mut renaming = Renaming {
+        graph,
+        children,
+        abi,
+        ssa: Ssa { bases: HashMap::new() },
+        versions: names.into_iter().map(|x| (x, (0, vec![]))).collect(),
+        exits: Vec::new()
+    };
+    renaming.rename_node(entry, nodes);
+
+    // Restores are inserted right before their return, so go backwards to keep the indices valid
+    for (node, stmt, saved, version) in renaming.exits.into_iter().rev() {
+        nodes[node].code.insert(stmt, lir::Lir::Assign { src: expr::Expr::Name(version), dst: expr::Expr::Name(saved) });
+    }
+
+    renaming.ssa
+}
+
+fn count_uses(nodes: &[lir::LirNode]) -> HashMap<String, usize> {
+    let mut uses = HashMap::new();
+    for node in nodes {
+        for stmt in &node.code {
+            for name in stmt.read_names() {
+                *uses.entry(name.to_string()).or_default() += 1;
+            }
+        }
+    }
+    uses
+}
+
+/// The base register, if nothing in the function moves it, as is the case once `track_stack_pointer` has run
+fn fixed_base_reg<'a>(nodes: &[lir::LirNode], ssa: &Ssa, abi: &'a Abi) -> Option<&'a str> {
+    let base_reg = abi.base_reg?;
+    let moved = nodes.iter().flat_map(|node| &node.code).any(|stmt| match stmt {
+        lir::Lir::Assign { dst: expr::Expr::Name(name), .. } | lir::Lir::Phi { dst: expr::Expr::Name(name), .. } => ssa.bases.get(name).unwrap_or(name) == base_reg,
+        _ => false
+    });
+    (!moved).then_some(base_reg)
+}
+
+/// What a single definition can be replaced with, if anything
+fn propagated_value(stmt: &lir::Lir, ssa: &Ssa, abi: &Abi, fixed_base: Option<&str>) -> Option<expr::Expr> {
+    match stmt {
+        lir::Lir::Assign { dst: expr::Expr::Name(name), src } => {
+            // A global stack pointer nothing moves is as good as a constant
+            let reads_global = src.read_names_rhs().iter().any(|x| Some(*x) != fixed_base && abi.global.iter().any(|global| global == x));
+
+            match src {
+                expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) => Some(src.clone()),
+                expr::Expr::Name(_) if !reads_global => Some(src.clone()),
+                // So are addresses in its frame, which `mem_to_name` can then find
+                _ if fixed_base.is_some_and(|base| stack_offset(src, base).is_some()) => Some(src.clone()),
+                // Frame registers are only ever offsets from their value on entry, so are always worth substituting
+                _ if abi.eliminate.contains(&ssa.bases[name].as_str()) && !reads_global && !src.has_side_effects() && !has_deref(src) => Some(src.clone()),
+                _ => None
+            }
+        }
+        // A phi which only ever sees one value (other than its own) is a copy
+        lir::Lir::Phi { dst, srcs } => {
+            let mut values = srcs.iter().map(|(_, x)| x).filter(|x| !matches!((x, dst), (expr::Expr::Name(a), expr::Expr::Name(b)) if a == b));
+            let first = values.next()?;
+            match first {
+                expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::Name(_) if values.all(|x| x.to_string() == first.to_string()) => Some(first.clone()),
+                _ => None
+            }
+        }
+        _ => None
+    }
+}
+
+fn has_deref(expr: &expr::Expr) -> bool {
+    match expr {
+        expr::Expr::Deref { .. } | expr::Expr::Table { .. } => true,
+        expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => false,
+        expr::Expr::Binary { lhs, rhs, .. } => has_deref(lhs) || has_deref(rhs),
+        expr::Expr::Unary { expr, .. } => has_deref(expr),
+        expr::Expr::Ref(value) => has_deref(value),
+        expr::Expr::Call { .. } => true
+    }
+}
+
+/// Constant propagation, copy propagation and dead code elimination, until none of them changes anything
+pub fn simplify_ssa(nodes: &mut [lir::LirNode], ssa: &Ssa, abi: &Abi) {
+    let fixed_base = fixed_base_reg(nodes, ssa, abi);
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for n in 0..nodes.len() {
+            let mut s = 0;
+            while s < nodes[n].code.len() {
+                let (lir::Lir::Assign { dst: expr::Expr::Name(name), .. } | lir::Lir::Phi { dst: expr::Expr::Name(name), .. }) = &nodes[n].code[s] else {
+                    s += 1;
+                    continue;
+                };
+
+                let Some(value) = ssa.bases.contains_key(name).then(|| propagated_value(&nodes[n].code[s], ssa, abi, fixed_base)).flatten() else {
+                    s += 1;
+                    continue;
+                };
+
+                let name = name.clone();
+
+                nodes[n].code.remove(s);
+                for node in nodes.iter_mut() {
+                    for stmt in &mut node.code {
+                        stmt.replace_name(&name, &value);
+                    }
+                }
+                changed = true;
+            }
+        }
+
+        let uses = count_uses(nodes);
+        for node in nodes.iter_mut() {
+            let before = node.code.len();
+            node.code.retain(|stmt| match stmt {
+                lir::Lir::Assign { dst: expr::Expr::Name(name), src } if ssa.bases.contains_key(name) && !src.has_side_effects() => uses.contains_key(name),
+                lir::Lir::Phi { dst: expr::Expr::Name(name), .. } => uses.get(name).copied().unwrap_or(0) > stmt.count_reads(name),
+                _ => true
+            });
+            changed |= node.code.len() != before;
+        }
+    }
+}
+
+/// Turns the copies for a set of phis into a sequence of assignments.
+/// They happen in parallel, so a copy can only go once no other copy still needs its destination.
+fn sequentialise_copies(mut copies: Vec<(String, expr::Expr)>) -> Vec<lir::Lir> {
+    let mut code = Vec::new();
+
+    while !copies.is_empty() {
+        let ready = (0..copies.len()).find(|i| copies.iter().enumerate().all(|(j, (_, src))| *i == j || src.count_reads(&copies[*i].0) == 0));
+
+        let (dst, src) = match ready {
+            Some(i) => copies.remove(i),
+            // Every destination is still needed, so save one of them first to break the cycle
+            None => {
+                let (dst, src) = copies.remove(0);
+                let tmp = expr::Expr::Name(format!("{dst}_tmp"));
+                code.push(lir::Lir::Assign { src: expr::Expr::Name(dst.clone()), dst: tmp.clone() });
+                for (_, other) in &mut copies {
+                    other.replace_name(&dst, &tmp);
+                }
+                (dst, src)
+            }
+        };
+
+        code.push(lir::Lir::Assign { src, dst: expr::Expr::Name(dst) });
+    }
+
+    code
+}
+
+/// Replaces phis with copies at the end of their predecessors (splitting edges where the predecessor has other successors)
+fn lower_phis(graph: &mut cfg::ControlFlowGraph, nodes: &mut Vec<lir::LirNode>) {
+    for n in 0..nodes.len() {
+        let mut copies: HashMap<usize, Vec<(String, expr::Expr)>> = HashMap::new();
+        while let Some(lir::Lir::Phi { .. }) = nodes[n].code.first() {
+            let lir::Lir::Phi { dst: expr::Expr::Name(dst), srcs } = nodes[n].code.remove(0) else {
+                unreachable!()
+            };
+
+            for (pred, src) in srcs {
+                copies.entry(pred).or_default().push((dst.clone(), src));
+            }
+        }
+
+        let mut preds: Vec<_> = copies.into_iter().collect();
+        preds.sort_by_key(|x| x.0);
+
+        for (pred, copies) in preds {
+            let code = sequentialise_copies(copies);
+
+            if graph.outgoing_for(pred).len() == 1 {
+                let at = match nodes[pred].code.last() {
+                    Some(lir::Lir::Branch { .. }) => nodes[pred].code.len() - 1,
+                    _ => nodes[pred].code.len()
+                };
+                nodes[pred].code.splice(at..at, code);
+                continue;
+            }
+
+            let split = nodes.len();
+            nodes.push(lir::LirNode { code });
+            nodes[split].code.push(lir::Lir::Branch { cond: None, target: lir::Label(n) });
+
+            graph.add_node(split);
+            graph.remove_edge(pred, n);
+            graph.add_edge(pred, split);
+            graph.add_edge(split, n);
+
+            if let Some(lir::Lir::Branch { target, .. }) = nodes[pred].code.last_mut() && target.0 == n {
+                *target = lir::Label(split);
+            }
+        }
+    }
+}
+
+/// Takes the function back out of SSA form. Versions of a name which are never live at the same time
+/// go back to having the original name, and the rest keep their version.
+pub fn from_ssa(graph: &mut cfg::ControlFlowGraph, nodes: &mut Vec<lir::LirNode>, ssa: Ssa, abi: &Abi) {
+    lower_phis(graph, nodes);
+
+    // Two names interfere if one is written while the other is live, unless it is a copy of the other
+    let live_in = live_in(graph, nodes, abi);
+    let mut interference: HashMap<String, HashSet<String>> = HashMap::new();
+    for node in graph.reachable() {
+        let mut live = live_out(graph, &live_in, node);
+        step_back_through(&nodes[node].code, &mut live, abi, |stmt, live| {
+            let lir::Lir::Assign { dst: expr::Expr::Name(name), src } = stmt else {
+                unreachable!()
+            };
+
+            for other in live {
+                if other == name || matches!(src, expr::Expr::Name(x) if x == other) {
+                    continue;
+                }
+
+                interference.entry(name.clone()).or_default().insert(other.clone());
+                interference.entry(other.clone()).or_default().insert(name.clone());
+            }
+        });
+    }
+
+    let mut versions: Vec<_> = ssa.bases.iter().collect();
+    versions.sort_by_key(|(version, base)| (base.to_string(), version.len(), version.to_string()));
+
+    let mut groups: HashMap<String, Vec<(String, HashSet<String>)>> = HashMap::new();
+    let mut renames = HashMap::new();
+    for (version, base) in versions {
+        let conflicts = interference.get(version).cloned().unwrap_or_default();
+        let groups = groups.entry(base.clone()).or_insert_with(|| vec![(base.clone(), HashSet::from([base.clone()]))]);
+
+        match groups.iter_mut().find(|(_, members)| members.is_disjoint(&conflicts)) {
+            Some((name, members)) => {
+                members.insert(version.clone());
+                renames.insert(version.clone(), name.clone());
+            }
+            None => groups.push((version.clone(), HashSet::from([version.clone()])))
+        }
+    }
+
+    for node in nodes.iter_mut() {
+        for stmt in &mut node.code {
+            let mut rename = |name: &mut String| if let Some(new) = renames.get(name.as_str()) {
+                *name = new.clone();
+            };
+            rename_reads(stmt, &mut rename);
+            if let Some(name) = written_name(stmt) {
+                rename(name);
+            }
+        }
+
+        node.code.retain(|stmt| !matches!(stmt, lir::Lir::Assign { dst: expr::Expr::Name(a), src: expr::Expr::Name(b) } if a == b));
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use crate::{cfg, lir, expr, dataflow::Abi};
+
+/// Registers known to hold the entry stack pointer plus some offset
+type Deltas = HashMap<String, i64>;
+
+/// Names known to hold constants within a node, as wasm puts the operands of `sp - 16` on its stack first
+type Consts = HashMap<String, i64>;
+
+fn constant(expr: &expr::Expr, consts: &Consts) -> Option<i64> {
+    match expr {
+        expr::Expr::Num(n) => Some(*n),
+        expr::Expr::Name(name) => consts.get(name).copied(),
+        _ => None
+    }
+}
+
+fn offset_of(expr: &expr::Expr, deltas: &Deltas, consts: &Consts) -> Option<i64> {
+    match expr {
+        expr::Expr::Name(name) => deltas.get(name).copied(),
+        expr::Expr::Binary { op: expr::BinaryOp::Add, lhs, rhs } => match (constant(lhs, consts), constant(rhs, consts)) {
+            (_, Some(n)) => Some(offset_of(lhs, deltas, consts)? + n),
+            (Some(n), None) => Some(offset_of(rhs, deltas, consts)? + n),
+            (None, None) => None
+        },
+        expr::Expr::Binary { op: expr::BinaryOp::Sub, lhs, rhs } => Some(offset_of(lhs, deltas, consts)? - constant(rhs, consts)?),
+        _ => None
+    }
+}
+
+/// The offset of expr from the base register, if it is one
+pub(super) fn stack_offset(expr: &expr::Expr, base_reg: &str) -> Option<i64> {
+    offset_of(expr, &Deltas::from([(base_reg.to_string(), 0)]), &Consts::new())
+}
+
+/// Updates deltas for the effects of stmt, None if the stack pointer is set to something we can't follow
+fn step(stmt: &lir::Lir, deltas: &mut Deltas, consts: &mut Consts, base_reg: &str, abi: &Abi) -> Option<()> {
+    match stmt {
+        lir::Lir::Assign { src: expr::Expr::Call { .. }, .. } | lir::Lir::Do(expr::Expr::Call { .. }) if abi.calls_clobber => {
+            deltas.retain(|name, _| abi.callee_saved.contains(&name.as_str()));
+            consts.retain(|name, _| abi.callee_saved.contains(&name.as_str()));
+        }
+        _ => {}
+    }
+
+    if let lir::Lir::Assign { dst: expr::Expr::Name(name), src } = stmt {
+        let offset = offset_of(src, deltas, consts);
+        match constant(src, consts) {
+            Some(n) => consts.insert(name.clone(), n),
+            None => consts.remove(name)
+        };
+
+        match offset {
+            Some(offset) => {
+                deltas.insert(name.clone(), offset);
+            }
+            None if name == base_reg => return None,
+            None => {
+                deltas.remove(name);
+            }
+        }
+    }
+
+    Some(())
+}
+
+/// The registers holding stack offsets on entry to each reachable node.
+/// None if the stack pointer isn't a known offset from its entry value everywhere, e.g. with dynamic allocations.
+fn stack_deltas(graph: &cfg::ControlFlowGraph, nodes: &[lir::LirNode], base_reg: &str, abi: &Abi) -> Option<HashMap<usize, Deltas>> {
+    let entry = graph.get_entry()?;
+    let mut at_entry = HashMap::from([(entry, Deltas::from([(base_reg.to_string(), 0)]))]);
+    let mut to_visit = vec![entry];
+
+    while let Some(node) = to_visit.pop() {
+        let mut deltas = at_entry[&node].clone();
+        let mut consts = Consts::new();
+        for stmt in &nodes[node].code {
+            step(stmt, &mut deltas, &mut consts, base_reg, abi)?;
+
+            if let lir::Lir::Return(_) = stmt && deltas[base_reg] != 0 {
+                return None;
+            }
+        }
+
+        for next in graph.outgoing_for(node) {
+            let Some(existing) = at_entry.get_mut(next) else {
+                at_entry.insert(*next, deltas.clone());
+                to_visit.push(*next);
+                continue;
+            };
+
+            if existing[base_reg] != deltas[base_reg] {
+                return None;
+            }
+
+            // Anything other than the stack pointer is only kept if every path agrees on it
+            let before = existing.len();
+            existing.retain(|name, offset| deltas.get(name) == Some(offset));
+            if existing.len() != before {
+                to_visit.push(*next);
+            }
+        }
+    }
+
+    Some(at_entry)
+}
+
+/// Follows the stack pointer through the function, rewriting every read of it to be relative to its value on entry,
+/// and removing the adjustments to it. This leaves the stack pointer constant, so stack slots have fixed addresses.
+/// Returns the size of the frame, or None, leaving the code untouched, if the stack pointer can't be followed.
+pub fn track_stack_pointer(graph: &cfg::ControlFlowGraph, nodes: &mut [lir::LirNode], abi: &Abi) -> Option<u64> {
+    let base_reg = abi.base_reg?;
+    let at_entry = stack_deltas(graph, nodes, base_reg, abi)?;
+    let mut frame_size = 0;
+
+    for (node, mut deltas) in at_entry {
+        let code = std::mem::take(&mut nodes[node].code);
+        let mut consts = Consts::new();
+
+        for mut stmt in code {
+            let offset = deltas[base_reg];
+            frame_size = frame_size.max(-offset);
+            step(&stmt, &mut deltas, &mut consts, base_reg, abi).unwrap();
+
+            if let lir::Lir::Assign { dst: expr::Expr::Name(name), .. } = &stmt && name == base_reg {
+                continue;
+            }
+
+            if offset != 0 {
+                stmt.replace_name(base_reg, &expr::Expr::Binary {
+                    op: expr::BinaryOp::Add,
+                    lhs: Box::new(expr::Expr::Name(base_reg.to_string())),
+                    rhs: Box::new(expr::Expr::Num(offset))
+                });
+            }
+
+            nodes[node].code.push(stmt);
+        }
+    }
+
+    Some(frame_size as u64)
+}
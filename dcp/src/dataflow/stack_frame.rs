@@ -31,7 +31,7 @@ impl NameGen {
     }
 }
 
-// FIXME: I have not proved that sp (or whatever) doesn't change (which it does)...
+/// Only valid once `track_stack_pointer` has made the base register constant
 fn append_expr_to_frame(expr: &mut expr::Expr, base_reg: &str, stack_frame: &mut mir::MirStackFrame, name_gen: &mut NameGen) {
     match expr {
         expr::Expr::Binary { op, lhs, rhs } => {
@@ -49,7 +49,7 @@ fn append_expr_to_frame(expr: &mut expr::Expr, base_reg: &str, stack_frame: &mut
                     *expr = expr::Expr::Ref(Box::new(expr::Expr::Name(local.name.to_string())));
                 } else {
                     let name = name_gen.get();
-                    stack_frame.insert(mir::MirLocal { name: name.clone(), offset: *offset as u64, size: 0 });
+                    stack_frame.insert(mir::MirLocal { name: name.clone(), offset: *offset as u64, size: 0, ty: None });
                     *expr = expr::Expr::Ref(Box::new(expr::Expr::Name(name)));
                 }
             }
@@ -77,13 +77,14 @@ fn append_expr_to_frame(expr: &mut expr::Expr, base_reg: &str, stack_frame: &mut
             }
         }
         expr::Expr::Ref(value) => append_expr_to_frame(value, base_reg, stack_frame, name_gen),
+        expr::Expr::Table { index, .. } => append_expr_to_frame(index, base_reg, stack_frame, name_gen),
         expr::Expr::Unary { expr, .. } => append_expr_to_frame(expr, base_reg, stack_frame, name_gen),
-        expr::Expr::Name(_) | expr::Expr::Bool(_) |  expr::Expr::Num(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {}
+        expr::Expr::Name(_) | expr::Expr::Bool(_) |  expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {}
     }
 }
 
-pub fn mem_to_name(nodes: &mut Vec<lir::LirNode>, abi: &Abi) -> mir::MirStackFrame {
-    let mut stack_frame = mir::MirStackFrame::new();
+pub fn mem_to_name(nodes: &mut Vec<lir::LirNode>, abi: &Abi, frame_size: u64) -> mir::MirStackFrame {
+    let mut stack_frame = mir::MirStackFrame::with_size(frame_size);
 
     let Some(base_reg) = abi.base_reg else {
         return stack_frame;
@@ -94,14 +95,14 @@ pub fn mem_to_name(nodes: &mut Vec<lir::LirNode>, abi: &Abi) -> mir::MirStackFra
     for node in nodes {
         for stmt in &mut node.code {
             match stmt {
-                lir::Lir::Return(expr) => append_expr_to_frame(expr, base_reg, &mut stack_frame, &mut name_gen),
+                lir::Lir::Return(ret) => ret.iter_mut().for_each(|expr| append_expr_to_frame(expr, base_reg, &mut stack_frame, &mut name_gen)),
                 lir::Lir::Do(expr) => append_expr_to_frame(expr, base_reg, &mut stack_frame, &mut name_gen),
                 lir::Lir::Assign { src, dst } => {
                     append_expr_to_frame(src, base_reg, &mut stack_frame, &mut name_gen);
                     append_expr_to_frame(dst, base_reg, &mut stack_frame, &mut name_gen);
                 }
                 lir::Lir::Branch { cond: Some(cond), .. } => append_expr_to_frame(cond, base_reg, &mut stack_frame, &mut name_gen),
-                lir::Lir::Branch { .. } | lir::Lir::Label(_) => {}
+                lir::Lir::Branch { .. } | lir::Lir::Label(_) | lir::Lir::Phi { .. } => {}
             }
         }
     }
@@ -0,0 +1,935 @@
+use std::fmt::Display;
+
+use crate::{mir::{self, Mir, MirVisitor}, expr::{self, Expr, BinaryOp, UnaryOp}, ty, lir, pretty::PrettyPrintContext, Module, FunctionDecl};
+
+const PRELUDE: &str = "\
+#include <stdbool.h>
+#include <stdint.h>
+
+static inline uint32_t dcp_rotl32(uint32_t x, uint32_t n) { return (x << (n & 31)) | (x >> (-n & 31)); }
+static inline uint64_t dcp_rotl64(uint64_t x, uint64_t n) { return (x << (n & 63)) | (x >> (-n & 63)); }
+static inline uint32_t dcp_rotr32(uint32_t x, uint32_t n) { return (x >> (n & 31)) | (x << (-n & 31)); }
+static inline uint64_t dcp_rotr64(uint64_t x, uint64_t n) { return (x >> (n & 63)) | (x << (-n & 63)); }
+static inline uint32_t dcp_ctz32(uint32_t x) { return x ? __builtin_ctz(x) : 32; }
+static inline uint64_t dcp_ctz64(uint64_t x) { return x ? __builtin_ctzll(x) : 64; }
+static inline uint32_t dcp_clz32(uint32_t x) { return x ? __builtin_clz(x) : 32; }
+static inline uint64_t dcp_clz64(uint64_t x) { return x ? __builtin_clzll(x) : 64; }
+static inline uint32_t dcp_popcnt32(uint32_t x) { return __builtin_popcount(x); }
+static inline uint64_t dcp_popcnt64(uint64_t x) { return __builtin_popcountll(x); }
+static inline uint32_t dcp_mulh32(uint32_t x, uint32_t y) { return ((int64_t)(int32_t)x * (int32_t)y) >> 32; }
+static inline uint64_t dcp_mulh64(uint64_t x, uint64_t y) { return ((__int128)(int64_t)x * (int64_t)y) >> 64; }
+static inline uint32_t dcp_mulhu32(uint32_t x, uint32_t y) { return ((uint64_t)x * y) >> 32; }
+static inline uint64_t dcp_mulhu64(uint64_t x, uint64_t y) { return ((unsigned __int128)x * y) >> 64; }
+static inline float dcp_bits_to_f32(uint32_t x) { union { uint32_t i; float f; } u = { x }; return u.f; }
+static inline double dcp_bits_to_f64(uint64_t x) { union { uint64_t i; double f; } u = { x }; return u.f; }
+static inline uint32_t dcp_f32_to_bits(float x) { union { float f; uint32_t i; } u = { x }; return u.i; }
+static inline uint64_t dcp_f64_to_bits(double x) { union { double f; uint64_t i; } u = { x }; return u.i; }
+uint32_t dcp_memory_size(void);
+uint32_t dcp_memory_grow(uint32_t pages);
+static inline void dcp_memory_copy(uintptr_t dst, uintptr_t src, uintptr_t n) { __builtin_memmove((void *)dst, (const void *)src, n); }
+static inline void dcp_memory_fill(uintptr_t dst, uint32_t x, uintptr_t n) { __builtin_memset((void *)dst, x, n); }
+bool dcp_catch();
+uint64_t dcp_exception();
+__attribute__((noreturn)) void dcp_throw();
+__attribute__((noreturn)) void dcp_rethrow(void);
+uint64_t dcp_result();
+typedef int64_t dcp_v128 __attribute__((vector_size(16)));
+typedef int8_t dcp_i8x16 __attribute__((vector_size(16)));
+typedef uint8_t dcp_u8x16 __attribute__((vector_size(16)));
+typedef int16_t dcp_i16x8 __attribute__((vector_size(16)));
+typedef uint16_t dcp_u16x8 __attribute__((vector_size(16)));
+typedef int32_t dcp_i32x4 __attribute__((vector_size(16)));
+typedef uint32_t dcp_u32x4 __attribute__((vector_size(16)));
+typedef int64_t dcp_i64x2 __attribute__((vector_size(16)));
+typedef uint64_t dcp_u64x2 __attribute__((vector_size(16)));
+typedef float dcp_f32x4 __attribute__((vector_size(16)));
+typedef double dcp_f64x2 __attribute__((vector_size(16)));
+";
+
+/// Operator precedences, higher binding tighter
+const LOGICAL_OR: u8 = 4;
+const LOGICAL_AND: u8 = 5;
+const BIT_OR: u8 = 6;
+const BIT_XOR: u8 = 7;
+const BIT_AND: u8 = 8;
+const EQUALITY: u8 = 9;
+const RELATIONAL: u8 = 10;
+const SHIFT: u8 = 11;
+const ADDITIVE: u8 = 12;
+const MULTIPLICATIVE: u8 = 13;
+const UNARY: u8 = 14;
+const POSTFIX: u8 = 15;
+
+/// Names that aren't valid identifiers, like those with dots from some symbol tables, get the offending characters replaced
+const KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum", "extern",
+    "float", "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return", "short", "signed",
+    "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void", "volatile", "while", "bool"
+];
+
+fn ident(name: &str) -> String {
+    let mut ident: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) || KEYWORDS.contains(&ident.as_str()) {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+fn int_type(size: ty::Size, signed: bool) -> String {
+    format!("{}int{}_t", if signed { "" } else { "u" }, size.byte_count() * 8)
+}
+
+/// The type variables of ty are declared as. Pointers and functions are kept as integers,
+/// since the code does byte arithmetic on them, and the pointer type goes in a comment
+fn c_type(ty: &ty::Ty) -> String {
+    match ty {
+        ty::Ty::Int { size, signed } => int_type(*size, *signed),
+        ty::Ty::Float { size: ty::Size::Size32 } => "float".to_string(),
+        ty::Ty::Float { .. } => "double".to_string(),
+        ty::Ty::Bool => "bool".to_string(),
+        ty::Ty::Ptr(_) | ty::Ty::Func => "uintptr_t".to_string(),
+        ty::Ty::Vector(_) => "dcp_v128".to_string(),
+        _ => int_type(ty.size().unwrap(), false)
+    }
+}
+
+/// How ty would be written as a C type, for comments on declarations
+fn c_type_comment(ty: &ty::Ty) -> Option<String> {
+    fn spelling(ty: &ty::Ty) -> String {
+        match ty {
+            ty::Ty::Ptr(inner) if matches!(inner.as_ref(), ty::Ty::Ptr(_)) => format!("{}*", spelling(inner)),
+            ty::Ty::Ptr(inner) => format!("{} *", spelling(inner)),
+            ty::Ty::Func => "void (*)()".to_string(),
+            _ => c_type(ty)
+        }
+    }
+
+    match ty {
+        ty::Ty::Ptr(_) | ty::Ty::Func => Some(spelling(ty)),
+        _ => None
+    }
+}
+
+fn write_num(f: &mut std::fmt::Formatter, x: i64) -> std::fmt::Result {
+    if x == i64::MIN {
+        write!(f, "(-0x7fffffffffffffff - 1)")
+    } else if x >= 4096 || (x > 32 && ((x & (x - 1)) == 0 || ((x + 1) & x) == 0)) {
+        write!(f, "0x{:x}", x)
+    } else {
+        write!(f, "{}", x)
+    }
+}
+
+fn float_type(size: ty::Size) -> &'static str {
+    if size == ty::Size::Size32 { "float" } else { "double" }
+}
+
+/// Float constants are written so they read back as the same value
+fn write_float(f: &mut std::fmt::Formatter, x: f64) -> std::fmt::Result {
+    if x.is_nan() {
+        write!(f, "__builtin_nan(\"\")")
+    } else if x.is_infinite() {
+        write!(f, "{}__builtin_inf()", if x < 0.0 { "-" } else { "" })
+    } else {
+        write!(f, "{x:?}")
+    }
+}
+
+/// A string literal, as an address like every other pointer
+fn write_str(f: &mut std::fmt::Formatter, text: &str) -> std::fmt::Result {
+    write!(f, "(uintptr_t)\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            // Octal escapes have at most three digits, so unlike hex ones they can't run into what follows
+            c if c.is_ascii_control() => write!(f, "\\{:03o}", c as u32)?,
+            // Trigraphs start with ??
+            '?' => write!(f, "\\?")?,
+            c => write!(f, "{c}")?
+        }
+    }
+    write!(f, "\"")
+}
+
+/// The name of the libm function for a float builtin, which is used through the compiler's builtin
+/// so that functions in the program with the same name don't clash with it
+fn float_builtin(builtin: expr::BuiltIn) -> &'static str {
+    match builtin {
+        expr::BuiltIn::Sqrt => "sqrt",
+        expr::BuiltIn::Abs => "fabs",
+        expr::BuiltIn::Ceil => "ceil",
+        expr::BuiltIn::Floor => "floor",
+        expr::BuiltIn::Trunc => "trunc",
+        expr::BuiltIn::Nearest => "nearbyint",
+        expr::BuiltIn::Min => "fmin",
+        expr::BuiltIn::Max => "fmax",
+        expr::BuiltIn::Copysign => "copysign",
+        _ => unreachable!()
+    }
+}
+
+/// The vector type lanes of shape are worked on as, with ints unsigned unless signed is asked for
+fn lanes_type(shape: ty::Shape, signed: bool) -> String {
+    match shape {
+        ty::Shape::F32x4 | ty::Shape::F64x2 => format!("dcp_{shape}"),
+        _ => format!("dcp_{}{}", if signed { "i" } else { "u" }, &shape.to_string()[1..])
+    }
+}
+
+/// The scalar type of a lane of shape
+fn lane_type(shape: ty::Shape) -> String {
+    match shape {
+        ty::Shape::F32x4 | ty::Shape::F64x2 => float_type(shape.lane_size()).to_string(),
+        _ => int_type(shape.lane_size(), false)
+    }
+}
+
+/// Whether expr is a truth value, which decides between the logical and bitwise forms of and and or
+fn is_bool(expr: &Expr) -> bool {
+    match expr {
+        Expr::Bool(_) => true,
+        Expr::Unary { op, .. } => *op == UnaryOp::Not || op.is_cmp(),
+        Expr::Binary { op: BinaryOp::And | BinaryOp::Or, lhs, rhs } => is_bool(lhs) && is_bool(rhs),
+        Expr::Binary { op, .. } => op.is_logical(),
+        _ => false
+    }
+}
+
+struct NameCollector {
+    names: Vec<String>
+}
+
+impl NameCollector {
+    fn collect(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Name(name) => {
+                if !self.names.contains(name) {
+                    self.names.push(name.clone());
+                }
+            }
+            Expr::Num(_) | Expr::Float(_) | Expr::Str(_) | Expr::Func(_) | Expr::BuiltIn(_) | Expr::Bool(_) => {}
+            Expr::Deref { ptr, .. } => self.collect(ptr),
+            Expr::Ref(value) => self.collect(value),
+            Expr::Table { index, .. } => self.collect(index),
+            Expr::Call { func, args } => {
+                self.collect(func);
+                for arg in args {
+                    self.collect(arg);
+                }
+            }
+            Expr::Unary { expr, .. } => self.collect(expr),
+            Expr::Binary { lhs, rhs, .. } => {
+                self.collect(lhs);
+                self.collect(rhs);
+            }
+        }
+    }
+}
+
+impl MirVisitor for NameCollector {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.collect(expr);
+    }
+}
+
+/// The SIMD intrinsics that have no C operator, which need declaring
+struct IntrinsicCollector {
+    ops: Vec<expr::SimdOp>
+}
+
+impl IntrinsicCollector {
+    fn collect(&mut self, expr: &Expr) {
+        match expr {
+            Expr::BuiltIn(expr::BuiltIn::Simd(op @ (expr::SimdOp::Load(_) | expr::SimdOp::Store(_) | expr::SimdOp::Named(..)))) => {
+                if !self.ops.contains(op) {
+                    self.ops.push(*op);
+                }
+            }
+            Expr::Name(_) | Expr::Num(_) | Expr::Float(_) | Expr::Str(_) | Expr::Func(_) | Expr::BuiltIn(_) | Expr::Bool(_) => {}
+            Expr::Deref { ptr, .. } => self.collect(ptr),
+            Expr::Ref(value) => self.collect(value),
+            Expr::Table { index, .. } => self.collect(index),
+            Expr::Call { func, args } => {
+                self.collect(func);
+                for arg in args {
+                    self.collect(arg);
+                }
+            }
+            Expr::Unary { expr, .. } => self.collect(expr),
+            Expr::Binary { lhs, rhs, .. } => {
+                self.collect(lhs);
+                self.collect(rhs);
+            }
+        }
+    }
+}
+
+impl MirVisitor for IntrinsicCollector {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.collect(expr);
+    }
+}
+
+/// Prints a module and the functions decompiled from it as a C translation unit
+pub struct CPrinter<'a> {
+    funcs: &'a [mir::MirFunc],
+    module: &'a Module
+}
+
+impl<'a> CPrinter<'a> {
+    pub fn new(funcs: &'a [mir::MirFunc], module: &'a Module) -> CPrinter<'a> {
+        CPrinter { funcs, module }
+    }
+
+    fn func_name(&self, decl: &FunctionDecl) -> String {
+        match &decl.name {
+            Some(name) => ident(name),
+            None => format!("fn{}", decl.funcid.0)
+        }
+    }
+
+    fn def(&self, funcid: expr::FuncId) -> Option<&'a mir::MirFunc> {
+        self.funcs.iter().find(|x| x.funcid == funcid)
+    }
+
+    fn result_type(&self, decl: &FunctionDecl, results: &[ty::Ty]) -> String {
+        match results {
+            [] => "void".to_string(),
+            [result] => c_type(result),
+            _ => format!("struct {}_result", self.func_name(decl))
+        }
+    }
+
+    fn name_type(&self, func: Option<&mir::MirFunc>, name: &str) -> ty::Ty {
+        func.and_then(|func| func.types.get(name).cloned().or_else(|| func.stack_frame.get_by_name(name).and_then(|x| x.ty.clone())))
+            .unwrap_or(ty::Ty::Unknown64)
+    }
+
+    fn write_prototype(&self, f: &mut std::fmt::Formatter, decl: &FunctionDecl) -> std::fmt::Result {
+        let func = self.def(decl.funcid);
+        let results = func.map_or(decl.results.as_slice(), |x| x.results.as_slice());
+
+        write!(f, "{} {}(", self.result_type(decl, results), self.func_name(decl))?;
+        // Without a body there is nothing to say an empty argument list is really empty
+        if decl.args.is_empty() && func.is_some() {
+            write!(f, "void")?;
+        }
+        for (a, arg) in decl.args.iter().enumerate() {
+            let arg = decl.local_name(arg);
+            // Imports have no body to have inferred types, but may have a signature that gives them
+            let ty = match (func, decl.params.as_ref().and_then(|params| params.get(a))) {
+                (None, Some(param)) => param.clone(),
+                _ => self.name_type(func, arg)
+            };
+            write!(f, "{}{} {}", if a == 0 { "" } else { ", " }, c_type(&ty), ident(arg))?;
+        }
+        write!(f, ")")
+    }
+
+    fn write_function(&self, f: &mut std::fmt::Formatter, func: &mir::MirFunc) -> std::fmt::Result {
+        let Some(decl) = self.module.find_decl(func.funcid) else {
+            return Ok(());
+        };
+
+        self.write_prototype(f, decl)?;
+        write!(f, " {{")?;
+
+        let mut names = NameCollector { names: vec![] };
+        names.visit_block(&func.code);
+
+        let mut ctx = PrettyPrintContext::new(self.module);
+        ctx.push_indent();
+        let locals: Vec<_> = names.names.iter()
+            .filter(|x| !decl.args.iter().any(|arg| decl.local_name(arg) == x.as_str()))
+            .filter(|x| !self.module.globals.iter().any(|global| global.name == **x))
+            .collect();
+        for name in &locals {
+            let ty = self.name_type(Some(func), name);
+            ctx.newline(f)?;
+            write!(f, "{} {};", c_type(&ty), ident(name))?;
+            if let Some(comment) = c_type_comment(&ty) {
+                write!(f, " /* {comment} */")?;
+            }
+        }
+
+        if !locals.is_empty() && !func.code.is_empty() {
+            writeln!(f)?;
+        }
+
+        let printer = FuncPrinter { printer: self, func, decl };
+        for stmt in &func.code {
+            ctx.newline(f)?;
+            printer.stmt(f, stmt, &mut ctx)?;
+        }
+        ctx.pop_indent();
+
+        writeln!(f, "\n}}")
+    }
+}
+
+impl<'a> Display for CPrinter<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(PRELUDE)?;
+
+        // Their arguments are left unspecified, since they differ between intrinsics
+        let mut intrinsics = IntrinsicCollector { ops: vec![] };
+        for func in self.funcs {
+            intrinsics.visit_block(&func.code);
+        }
+        for op in &intrinsics.ops {
+            let result = match op {
+                expr::SimdOp::Store(_) => "void",
+                _ if op.is_vector() => "dcp_v128",
+                _ => "uint32_t"
+            };
+            writeln!(f, "{result} dcp_{}();", ident(&op.to_string()))?;
+        }
+
+        writeln!(f)?;
+        for decl in &self.module.functions {
+            let results = self.def(decl.funcid).map_or(decl.results.as_slice(), |x| x.results.as_slice());
+            if results.len() > 1 {
+                write!(f, "{} {{", self.result_type(decl, results))?;
+                for (r, result) in results.iter().enumerate() {
+                    write!(f, " {} r{r};", c_type(result))?;
+                }
+                writeln!(f, " }};")?;
+            }
+
+            self.write_prototype(f, decl)?;
+            writeln!(f, ";")?;
+        }
+
+        if !self.module.globals.is_empty() || !self.module.tables.is_empty() {
+            writeln!(f)?;
+        }
+        for global in &self.module.globals {
+            match global.len {
+                Some(len) => writeln!(f, "{} {}[{len}];", c_type(&global.ty), ident(&global.name))?,
+                None => writeln!(f, "{} {};", c_type(&global.ty), ident(&global.name))?
+            }
+        }
+        for (t, table) in self.module.tables.iter().enumerate() {
+            write!(f, "uintptr_t table{t}[{}] = {{", table.len().max(1))?;
+            for (e, elem) in table.iter().enumerate() {
+                write!(f, "{}", if e == 0 { " " } else { ", " })?;
+                match elem.and_then(|x| self.module.find_decl(x)) {
+                    Some(decl) => write!(f, "(uintptr_t){}", self.func_name(decl))?,
+                    None => write!(f, "0")?
+                }
+            }
+            writeln!(f, "{}}};", if table.is_empty() { " 0 " } else { " " })?;
+        }
+
+        for func in self.funcs {
+            writeln!(f)?;
+            self.write_function(f, func)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct FuncPrinter<'a> {
+    printer: &'a CPrinter<'a>,
+    func: &'a mir::MirFunc,
+    decl: &'a FunctionDecl
+}
+
+impl<'a> FuncPrinter<'a> {
+    fn func_name(&self, funcid: expr::FuncId) -> String {
+        match self.printer.module.find_decl(funcid) {
+            Some(decl) => self.printer.func_name(decl),
+            None => format!("fn{}", funcid.0)
+        }
+    }
+
+    /// The size of the value of expr, for picking the width of builtins and casts
+    fn size_of(&self, expr: &Expr) -> ty::Size {
+        match expr {
+            Expr::Name(name) => self.printer.name_type(Some(self.func), name).size().unwrap_or(ty::Size::Size64),
+            Expr::Deref { size, .. } => *size,
+            Expr::Unary { op: UnaryOp::IntToFloat(size, _) | UnaryOp::FloatToInt(size, _) | UnaryOp::FloatToFloat(size), .. } => *size,
+            Expr::Unary { op: UnaryOp::BitsToFloat(size) | UnaryOp::FloatToBits(size), .. } => *size,
+            Expr::Unary { op: UnaryOp::Neg, expr } => self.size_of(expr),
+            Expr::Binary { op, lhs, .. } if !op.is_logical() => self.size_of(lhs),
+            Expr::Call { func, args } if matches!(func.as_ref(), Expr::BuiltIn(_)) && !args.is_empty() => self.size_of(&args[0]),
+            _ => ty::Size::Size64
+        }
+    }
+
+    /// Prints expr as an integer of size and the given signedness, casting it unless it already is one
+    fn with_signedness(&self, f: &mut std::fmt::Formatter, expr: &Expr, size: ty::Size, signed: bool) -> std::fmt::Result {
+        let bits = size.byte_count() * 8;
+        let already = match expr {
+            Expr::Num(n) if signed => bits == 64 || (-(1 << (bits - 1))..(1 << (bits - 1))).contains(n),
+            Expr::Num(n) => *n >= 0 && (bits == 64 || *n < 1 << bits),
+            Expr::Unary { op: UnaryOp::SignExtend(_), .. } => signed && bits == 64,
+            Expr::Unary { op: UnaryOp::FloatToInt(to, is_signed), .. } => *to == size && *is_signed == signed,
+            // Loads are printed through a pointer to an unsigned int of their size
+            Expr::Deref { size: load, .. } => !signed && *load == size,
+            Expr::Name(name) => {
+                let ty = self.printer.name_type(Some(self.func), name);
+                ty == ty::Ty::Int { size, signed } || (!signed && c_type(&ty) == int_type(size, false))
+            }
+            _ => false
+        };
+
+        if !already {
+            write!(f, "({})", int_type(size, signed))?;
+        }
+        self.expr(f, expr, UNARY)
+    }
+
+    fn block(&self, f: &mut std::fmt::Formatter, code: &[Mir], ctx: &mut PrettyPrintContext) -> std::fmt::Result {
+        write!(f, "{{")?;
+        ctx.push_indent();
+        for stmt in code {
+            ctx.newline(f)?;
+            self.stmt(f, stmt, ctx)?;
+        }
+        ctx.pop_indent();
+        ctx.newline(f)?;
+        write!(f, "}}")
+    }
+
+    /// An assignment or expression statement as an expression, for the increments of fors
+    fn inline_stmt(&self, f: &mut std::fmt::Formatter, stmt: &Mir) -> std::fmt::Result {
+        match stmt {
+            Mir::Assign { src, dst } => {
+                self.expr(f, dst, UNARY)?;
+                write!(f, " = ")?;
+                self.expr(f, src, LOGICAL_OR)
+            }
+            Mir::Do(expr) => self.expr(f, expr, LOGICAL_OR),
+            _ => unreachable!()
+        }
+    }
+
+    fn stmt(&self, f: &mut std::fmt::Formatter, stmt: &Mir, ctx: &mut PrettyPrintContext) -> std::fmt::Result {
+        match stmt {
+            Mir::Assign { .. } | Mir::Do(_) => {
+                self.inline_stmt(f, stmt)?;
+                write!(f, ";")
+            }
+            Mir::Return(ret) => match ret.as_slice() {
+                [] => write!(f, "return;"),
+                [expr] => {
+                    write!(f, "return ")?;
+                    self.expr(f, expr, 0)?;
+                    write!(f, ";")
+                }
+                ret => {
+                    write!(f, "return ({}) {{ ", self.printer.result_type(self.decl, &self.func.results))?;
+                    for (i, expr) in ret.iter().enumerate() {
+                        write!(f, "{}", if i == 0 { "" } else { ", " })?;
+                        self.expr(f, expr, LOGICAL_OR)?;
+                    }
+                    write!(f, " }};")
+                }
+            },
+            Mir::Branch { cond: Some(cond), target } => {
+                write!(f, "if (")?;
+                self.expr(f, cond, 0)?;
+                write!(f, ") goto {};", label(*target))
+            }
+            Mir::Branch { cond: None, target } => write!(f, "goto {};", label(*target)),
+            Mir::Label(target) => write!(f, "{}:;", label(*target)),
+            Mir::If { cond, true_then, false_then } => {
+                write!(f, "if (")?;
+                self.expr(f, cond, 0)?;
+                write!(f, ") ")?;
+                self.block(f, true_then, ctx)?;
+
+                if !false_then.is_empty() {
+                    write!(f, " else ")?;
+                    self.block(f, false_then, ctx)?;
+                }
+                Ok(())
+            }
+            Mir::Loop { code } => {
+                write!(f, "for (;;) ")?;
+                self.block(f, code, ctx)
+            }
+            Mir::While { guard, code } => {
+                write!(f, "while (")?;
+                self.expr(f, guard, 0)?;
+                write!(f, ") ")?;
+                self.block(f, code, ctx)
+            }
+            Mir::For { guard, inc, code } => {
+                // Only expressions can go in the increment, so anything else is run at the end of the body
+                if !inc.iter().all(|x| matches!(x, Mir::Assign { .. } | Mir::Do(_))) {
+                    let mut body = code.clone();
+                    body.extend(inc.iter().cloned());
+                    return self.stmt(f, &Mir::While { guard: guard.clone(), code: body }, ctx);
+                }
+
+                write!(f, "for (; ")?;
+                self.expr(f, guard, 0)?;
+                write!(f, ";")?;
+                for (s, stmt) in inc.iter().enumerate() {
+                    write!(f, "{}", if s == 0 { " " } else { ", " })?;
+                    self.inline_stmt(f, stmt)?;
+                }
+                write!(f, ") ")?;
+                self.block(f, code, ctx)
+            }
+            Mir::Switch { scrutinee, cases, default } => {
+                // A break in a C switch would leave the switch rather than the loop, so those are written as ifs
+                if cases.iter().any(|(_, code)| mir::break_count(code) > 0) || mir::break_count(default) > 0 {
+                    return self.switch_as_ifs(f, scrutinee, cases, default, ctx);
+                }
+
+                write!(f, "switch (")?;
+                self.expr(f, scrutinee, 0)?;
+                write!(f, ") {{")?;
+                for (values, code) in cases {
+                    ctx.newline(f)?;
+                    for (v, value) in values.iter().enumerate() {
+                        write!(f, "{}case ", if v == 0 { "" } else { " " })?;
+                        write_num(f, *value)?;
+                        write!(f, ":")?;
+                    }
+                    write!(f, " ")?;
+                    self.case_block(f, code, ctx)?;
+                }
+
+                if !default.is_empty() {
+                    ctx.newline(f)?;
+                    write!(f, "default: ")?;
+                    self.case_block(f, default, ctx)?;
+                }
+                ctx.newline(f)?;
+                write!(f, "}}")
+            }
+            // Written the way the handlers are reached, with the body run when nothing has been caught,
+            // and what no handler takes thrown on
+            Mir::Try { body, handlers } => {
+                write!(f, "if (!dcp_catch()) ")?;
+                self.block(f, body, ctx)?;
+                for (tag, code) in handlers {
+                    match tag {
+                        Some(tag) => write!(f, " else if (dcp_catch({tag})) ")?,
+                        None => write!(f, " else ")?
+                    }
+                    self.block(f, code, ctx)?;
+                    if tag.is_none() {
+                        return Ok(());
+                    }
+                }
+                write!(f, " else ")?;
+                self.block(f, &[Mir::Do(Expr::Call { func: Box::new(Expr::BuiltIn(expr::BuiltIn::Rethrow)), args: vec![] })], ctx)
+            }
+            Mir::Break => write!(f, "break;"),
+            Mir::Continue => write!(f, "continue;")
+        }
+    }
+
+    /// The body of a switch case, with a break to stop it falling through
+    fn case_block(&self, f: &mut std::fmt::Formatter, code: &[Mir], ctx: &mut PrettyPrintContext) -> std::fmt::Result {
+        if code.last().is_some_and(Mir::terminating) {
+            self.block(f, code, ctx)
+        } else {
+            let mut code = code.to_vec();
+            code.push(Mir::Break);
+            self.block(f, &code, ctx)
+        }
+    }
+
+    fn switch_as_ifs(&self, f: &mut std::fmt::Formatter, scrutinee: &Expr, cases: &[(Vec<i64>, Vec<Mir>)], default: &[Mir], ctx: &mut PrettyPrintContext) -> std::fmt::Result {
+        for (c, (values, code)) in cases.iter().enumerate() {
+            let cond = values.iter().map(|value| Expr::Binary {
+                op: BinaryOp::Eq,
+                lhs: Box::new(scrutinee.clone()),
+                rhs: Box::new(Expr::Num(*value))
+            }).reduce(|lhs, rhs| Expr::Binary {
+                op: BinaryOp::Or,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs)
+            }).unwrap();
+
+            write!(f, "{}if (", if c == 0 { "" } else { " else " })?;
+            self.expr(f, &cond, 0)?;
+            write!(f, ") ")?;
+            self.block(f, code, ctx)?;
+        }
+
+        if !default.is_empty() {
+            write!(f, " else ")?;
+            self.block(f, default, ctx)?;
+        }
+        Ok(())
+    }
+
+    fn binary_op(&self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> (u8, &'static str) {
+        match op {
+            BinaryOp::Eq | BinaryOp::FEq => (EQUALITY, "=="),
+            BinaryOp::Ne | BinaryOp::FNe => (EQUALITY, "!="),
+            BinaryOp::Lt | BinaryOp::LtU | BinaryOp::FLt => (RELATIONAL, "<"),
+            BinaryOp::Le | BinaryOp::LeU | BinaryOp::FLe => (RELATIONAL, "<="),
+            BinaryOp::Gt | BinaryOp::GtU | BinaryOp::FGt => (RELATIONAL, ">"),
+            BinaryOp::Ge | BinaryOp::GeU | BinaryOp::FGe => (RELATIONAL, ">="),
+            BinaryOp::Add | BinaryOp::FAdd => (ADDITIVE, "+"),
+            BinaryOp::Sub | BinaryOp::FSub | BinaryOp::Cmp | BinaryOp::FCmp => (ADDITIVE, "-"),
+            BinaryOp::Mul | BinaryOp::FMul => (MULTIPLICATIVE, "*"),
+            BinaryOp::Div | BinaryOp::DivU | BinaryOp::FDiv => (MULTIPLICATIVE, "/"),
+            BinaryOp::Rem | BinaryOp::RemU => (MULTIPLICATIVE, "%"),
+            BinaryOp::Shl => (SHIFT, "<<"),
+            BinaryOp::Shr | BinaryOp::Asr => (SHIFT, ">>"),
+            BinaryOp::Xor => (BIT_XOR, "^"),
+            BinaryOp::And if is_bool(lhs) && is_bool(rhs) => (LOGICAL_AND, "&&"),
+            BinaryOp::And => (BIT_AND, "&"),
+            BinaryOp::Or if is_bool(lhs) && is_bool(rhs) => (LOGICAL_OR, "||"),
+            BinaryOp::Or => (BIT_OR, "|"),
+        }
+    }
+
+    fn args(&self, f: &mut std::fmt::Formatter, args: &[Expr]) -> std::fmt::Result {
+        for (a, arg) in args.iter().enumerate() {
+            write!(f, "{}", if a == 0 { "" } else { ", " })?;
+            self.expr(f, arg, LOGICAL_OR)?;
+        }
+        Ok(())
+    }
+
+    /// Vectors are held as dcp_v128, and cast to the shape they are used as, so lanewise operators
+    /// become GCC's vector operators. Anything without one is a call to an intrinsic
+    fn simd(&self, f: &mut std::fmt::Formatter, op: expr::SimdOp, args: &[Expr]) -> std::fmt::Result {
+        match op {
+            expr::SimdOp::Build(shape) => {
+                write!(f, "(dcp_v128)({}){{", lanes_type(shape, false))?;
+                self.args(f, args)?;
+                write!(f, "}}")
+            }
+            expr::SimdOp::Splat(shape) => {
+                write!(f, "(dcp_v128)(({}){{0}} + ({})", lanes_type(shape, false), lane_type(shape))?;
+                self.expr(f, &args[0], UNARY)?;
+                write!(f, ")")
+            }
+            expr::SimdOp::ExtractLane(shape) => {
+                write!(f, "(({})", lanes_type(shape, false))?;
+                self.expr(f, &args[0], UNARY)?;
+                write!(f, ")[")?;
+                self.expr(f, &args[1], 0)?;
+                write!(f, "]")
+            }
+            expr::SimdOp::ReplaceLane(shape) => {
+                write!(f, "({{ {} dcp_lanes = ({})", lanes_type(shape, false), lanes_type(shape, false))?;
+                self.expr(f, &args[0], UNARY)?;
+                write!(f, "; dcp_lanes[")?;
+                self.expr(f, &args[1], 0)?;
+                write!(f, "] = ")?;
+                self.expr(f, &args[2], LOGICAL_OR)?;
+                write!(f, "; (dcp_v128)dcp_lanes; }})")
+            }
+            expr::SimdOp::Shuffle => {
+                write!(f, "(dcp_v128)__builtin_shuffle(")?;
+                for (a, arg) in args.iter().enumerate() {
+                    write!(f, "{}(dcp_u8x16)", if a == 0 { "" } else { ", " })?;
+                    self.expr(f, arg, UNARY)?;
+                }
+                write!(f, ")")
+            }
+            expr::SimdOp::Lanewise(shape, op) => {
+                let signed = op == BinaryOp::Asr || op.cmp_signedness() == Some(true);
+                let (_, symbol) = self.binary_op(op, &args[0], &args[1]);
+                write!(f, "(dcp_v128)(({})", lanes_type(shape, signed))?;
+                self.expr(f, &args[0], UNARY)?;
+                write!(f, " {symbol} ")?;
+                // The shift amount is taken modulo the lane width
+                if matches!(op, BinaryOp::Shl | BinaryOp::Shr | BinaryOp::Asr) {
+                    write!(f, "(int)(")?;
+                    self.expr(f, &args[1], BIT_AND + 1)?;
+                    write!(f, " & {})", shape.lane_size().byte_count() * 8 - 1)?;
+                } else {
+                    write!(f, "({})", lanes_type(shape, signed))?;
+                    self.expr(f, &args[1], UNARY)?;
+                }
+                write!(f, ")")
+            }
+            expr::SimdOp::Load(_) | expr::SimdOp::Store(_) | expr::SimdOp::Named(..) => {
+                write!(f, "dcp_{}(", ident(&op.to_string()))?;
+                self.args(f, args)?;
+                write!(f, ")")
+            }
+        }
+    }
+
+    fn expr(&self, f: &mut std::fmt::Formatter, expr: &Expr, min_prec: u8) -> std::fmt::Result {
+        let prec = match expr {
+            Expr::Name(_) | Expr::BuiltIn(_) | Expr::Bool(_) => POSTFIX,
+            Expr::Func(_) => UNARY,
+            Expr::Num(x) => if *x < 0 { UNARY } else { POSTFIX },
+            Expr::Float(x) => if x.is_sign_negative() { UNARY } else { POSTFIX },
+            Expr::Str(_) => UNARY,
+            Expr::Call { func, .. } if matches!(func.as_ref(), Expr::BuiltIn(expr::BuiltIn::Simd(_))) => UNARY,
+            Expr::Call { .. } | Expr::Table { .. } => POSTFIX,
+            Expr::Deref { .. } | Expr::Ref(_) => UNARY,
+            Expr::Unary { op: UnaryOp::BitsToFloat(_) | UnaryOp::FloatToBits(_), .. } => POSTFIX,
+            Expr::Unary { op, .. } if !op.is_cmp() => UNARY,
+            Expr::Unary { op, .. } => match op.cmp_op_to_binaryop() {
+                BinaryOp::Eq | BinaryOp::Ne => EQUALITY,
+                _ => RELATIONAL
+            },
+            Expr::Binary { op, lhs, rhs } => self.binary_op(*op, lhs, rhs).0
+        };
+
+        if prec < min_prec {
+            write!(f, "(")?;
+            self.expr(f, expr, 0)?;
+            return write!(f, ")");
+        }
+
+        match expr {
+            Expr::Name(name) => write!(f, "{}", ident(name)),
+            Expr::Num(x) => write_num(f, *x),
+            Expr::Float(x) => write_float(f, *x),
+            Expr::Str(text) => write_str(f, text),
+            // Functions are called by name, so anywhere else one is a value, and held like any other
+            Expr::Func(funcid) => write!(f, "(uintptr_t){}", self.func_name(*funcid)),
+            Expr::BuiltIn(builtin) if builtin.is_float() => write!(f, "__builtin_{}", float_builtin(*builtin)),
+            Expr::BuiltIn(expr::BuiltIn::Simd(op)) => write!(f, "dcp_{}", ident(&op.to_string())),
+            Expr::BuiltIn(builtin) if builtin.is_memory() || builtin.is_exception() || *builtin == expr::BuiltIn::Result => write!(f, "dcp_{builtin}"),
+            Expr::BuiltIn(builtin) => write!(f, "dcp_{builtin}64"),
+            Expr::Bool(b) => write!(f, "{b}"),
+            Expr::Deref { ptr, size } => {
+                write!(f, "*({} *)", int_type(*size, false))?;
+                self.expr(f, ptr, UNARY)
+            }
+            Expr::Ref(value) => {
+                write!(f, "(uintptr_t)&")?;
+                self.expr(f, value, UNARY)
+            }
+            Expr::Table { table, index, candidates } => {
+                write!(f, "table{table}[")?;
+                self.expr(f, index, 0)?;
+                if !candidates.is_empty() {
+                    write!(f, " /* ")?;
+                    for (c, candidate) in candidates.iter().enumerate() {
+                        write!(f, "{}", if c == 0 { "" } else { " | " })?;
+                        write!(f, "{}", self.func_name(*candidate))?;
+                    }
+                    write!(f, " */")?;
+                }
+                write!(f, "]")
+            }
+            Expr::Unary { op: UnaryOp::Not, expr } => {
+                write!(f, "!")?;
+                self.expr(f, expr, UNARY)
+            }
+            Expr::Unary { op: UnaryOp::Neg, expr } => {
+                write!(f, "-")?;
+                self.expr(f, expr, UNARY)
+            }
+            Expr::Unary { op: UnaryOp::SignExtend(size), expr } => {
+                write!(f, "({})", int_type(*size, true))?;
+                self.expr(f, expr, UNARY)
+            }
+            Expr::Unary { op: UnaryOp::IntToFloat(size, signed), expr } => {
+                write!(f, "({})({})", float_type(*size), int_type(self.size_of(expr), *signed))?;
+                self.expr(f, expr, UNARY)
+            }
+            Expr::Unary { op: UnaryOp::FloatToInt(size, signed), expr } => {
+                write!(f, "({})", int_type(*size, *signed))?;
+                self.expr(f, expr, UNARY)
+            }
+            Expr::Unary { op: UnaryOp::FloatToFloat(size), expr } => {
+                write!(f, "({})", float_type(*size))?;
+                self.expr(f, expr, UNARY)
+            }
+            Expr::Unary { op: UnaryOp::BitsToFloat(size), expr } => {
+                write!(f, "dcp_bits_to_f{}(", size.byte_count() * 8)?;
+                self.expr(f, expr, LOGICAL_OR)?;
+                write!(f, ")")
+            }
+            Expr::Unary { op: UnaryOp::FloatToBits(size), expr } => {
+                write!(f, "dcp_f{}_to_bits(", size.byte_count() * 8)?;
+                self.expr(f, expr, LOGICAL_OR)?;
+                write!(f, ")")
+            }
+            // Flags from a comparison are tested as the comparison itself, and anything else as a difference from zero.
+            // Either way the signed tests get their operands cast when printed as a binary op
+            Expr::Unary { op, expr } => {
+                let (lhs, rhs) = match expr.as_ref() {
+                    Expr::Binary { op: BinaryOp::FCmp, lhs, rhs } => return self.expr(f, &op.float_cmp(lhs.clone(), rhs.clone()), min_prec),
+                    Expr::Binary { op: BinaryOp::Cmp, lhs, rhs } => (lhs.as_ref().clone(), rhs.as_ref().clone()),
+                    other => (other.clone(), Expr::Num(0))
+                };
+                self.expr(f, &Expr::Binary { op: op.cmp_op_to_binaryop(), lhs: Box::new(lhs), rhs: Box::new(rhs) }, min_prec)
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                let (prec, symbol) = self.binary_op(*op, lhs, rhs);
+                // Mixing these with other operators without brackets is legal, but easy to misread
+                let (lhs_prec, rhs_prec) = match prec {
+                    BIT_OR | BIT_XOR | BIT_AND | SHIFT => (UNARY, UNARY),
+                    LOGICAL_OR => (LOGICAL_AND + 1, LOGICAL_AND + 1),
+                    _ => (prec, prec + 1)
+                };
+
+                // Both sides of operators that depend on signedness are cast to it, and those of comparisons are
+                // compared at the width of the non-constant one
+                let signedness = match op {
+                    BinaryOp::Asr | BinaryOp::Div | BinaryOp::Rem => Some(true),
+                    BinaryOp::Shr | BinaryOp::DivU | BinaryOp::RemU => Some(false),
+                    _ => op.cmp_signedness()
+                };
+                let cmp_size = if matches!(lhs.as_ref(), Expr::Num(_)) { self.size_of(rhs) } else { self.size_of(lhs) };
+                let size = |side: &Expr| if op.cmp_signedness().is_some() { cmp_size } else { self.size_of(side) };
+
+                match signedness {
+                    Some(signed) => self.with_signedness(f, lhs, size(lhs), signed)?,
+                    None => self.expr(f, lhs, lhs_prec)?
+                }
+                write!(f, " {symbol} ")?;
+                match signedness {
+                    Some(signed) => self.with_signedness(f, rhs, size(rhs), signed),
+                    None => self.expr(f, rhs, rhs_prec)
+                }
+            }
+            Expr::Call { func, args } => {
+                match func.as_ref() {
+                    Expr::BuiltIn(expr::BuiltIn::Simd(op)) => return self.simd(f, *op, args),
+                    Expr::Func(funcid) => write!(f, "{}", self.func_name(*funcid))?,
+                    Expr::BuiltIn(builtin) if builtin.is_float() => {
+                        let single = args.first().is_some_and(|x| self.size_of(x) == ty::Size::Size32);
+                        write!(f, "__builtin_{}{}", float_builtin(*builtin), if single { "f" } else { "" })?;
+                    }
+                    Expr::BuiltIn(builtin) if builtin.is_memory() || builtin.is_exception() || *builtin == expr::BuiltIn::Result => write!(f, "dcp_{builtin}")?,
+                    Expr::BuiltIn(builtin) => {
+                        let bits = args.first().map_or(64, |x| self.size_of(x).byte_count() * 8);
+                        write!(f, "dcp_{builtin}{}", if bits <= 32 { 32 } else { 64 })?;
+                    }
+                    // Calls through a computed address go through a pointer to a function taking what was passed
+                    _ => {
+                        write!(f, "((uint64_t (*)(")?;
+                        for a in 0..args.len() {
+                            write!(f, "{}uint64_t", if a == 0 { "" } else { ", " })?;
+                        }
+                        if args.is_empty() {
+                            write!(f, "void")?;
+                        }
+                        write!(f, "))")?;
+                        self.expr(f, func, UNARY)?;
+                        write!(f, ")")?;
+                    }
+                }
+
+                write!(f, "(")?;
+                for (a, arg) in args.iter().enumerate() {
+                    write!(f, "{}", if a == 0 { "" } else { ", " })?;
+                    self.expr(f, arg, LOGICAL_OR)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn label(label: lir::Label) -> String {
+    format!("L{label}")
+}
@@ -5,19 +5,83 @@ use crate::{ty, pretty};
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum UnaryOp {
     Not,
+    Neg,
     CmpEq, CmpNe, CmpLt, CmpLe, CmpGt, CmpGe,
+    CmpLtU, CmpLeU, CmpGtU, CmpGeU,
+    /// Whether the difference of a comparison is negative (or not), which unlike CmpLt (and CmpGe)
+    /// ignores whether working it out overflowed
+    CmpMi, CmpPl,
+    /// Sign extends from the low bits of the given size
+    SignExtend(ty::Size),
+    /// Converts an int, signed if the flag is set, to the nearest float of the given size
+    IntToFloat(ty::Size, bool),
+    /// Converts a float to an int of the given size and signedness, rounding toward zero
+    FloatToInt(ty::Size, bool),
+    /// Converts a float to a float of the given size
+    FloatToFloat(ty::Size),
+    /// Reinterprets the bits of an int as a float of the given size
+    BitsToFloat(ty::Size),
+    /// Reinterprets the bits of a float as an int of the given size
+    FloatToBits(ty::Size)
+}
+
+fn write_float_ty(f: &mut std::fmt::Formatter<'_>, size: ty::Size) -> std::fmt::Result {
+    write!(f, "f{}", size.byte_count() * 8)
+}
+
+fn write_int_ty(f: &mut std::fmt::Formatter<'_>, size: ty::Size, signed: bool) -> std::fmt::Result {
+    write!(f, "{}{}", if signed { "i" } else { "u" }, size.byte_count() * 8)
 }
 
 impl std::fmt::Display for UnaryOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Conversions are written as casts
+        match self {
+            UnaryOp::SignExtend(size) => return write!(f, "(i{})", size.byte_count() * 8),
+            UnaryOp::IntToFloat(size, signed) => {
+                write!(f, "(")?;
+                write_float_ty(f, *size)?;
+                return write!(f, "){}", if *signed { "" } else { "(unsigned)" });
+            }
+            UnaryOp::FloatToInt(size, signed) => {
+                write!(f, "(")?;
+                write_int_ty(f, *size, *signed)?;
+                return write!(f, ")");
+            }
+            UnaryOp::FloatToFloat(size) => {
+                write!(f, "(")?;
+                write_float_ty(f, *size)?;
+                return write!(f, ")");
+            }
+            UnaryOp::BitsToFloat(size) => {
+                write!(f, "(bits ")?;
+                write_float_ty(f, *size)?;
+                return write!(f, ")");
+            }
+            UnaryOp::FloatToBits(size) => {
+                write!(f, "(bits ")?;
+                write_int_ty(f, *size, false)?;
+                return write!(f, ")");
+            }
+            _ => {}
+        }
+
         f.write_str(match self {
             UnaryOp::Not => "!",
+            UnaryOp::Neg => "-",
             UnaryOp::CmpEq => "eq",
             UnaryOp::CmpNe => "ne",
             UnaryOp::CmpLt => "lt",
             UnaryOp::CmpLe => "le",
             UnaryOp::CmpGt => "gt",
             UnaryOp::CmpGe => "ge",
+            UnaryOp::CmpLtU => "ltu",
+            UnaryOp::CmpLeU => "leu",
+            UnaryOp::CmpGtU => "gtu",
+            UnaryOp::CmpGeU => "geu",
+            UnaryOp::CmpMi => "mi",
+            UnaryOp::CmpPl => "pl",
+            _ => unreachable!()
         })
     }
 }
@@ -26,7 +90,9 @@ impl UnaryOp {
     pub fn is_cmp(&self) -> bool {
         match self {
             UnaryOp::CmpEq | UnaryOp::CmpNe | UnaryOp::CmpLt |
-            UnaryOp::CmpLe | UnaryOp::CmpGt | UnaryOp::CmpGe => true,
+            UnaryOp::CmpLe | UnaryOp::CmpGt | UnaryOp::CmpGe |
+            UnaryOp::CmpLtU | UnaryOp::CmpLeU | UnaryOp::CmpGtU | UnaryOp::CmpGeU |
+            UnaryOp::CmpMi | UnaryOp::CmpPl => true,
             _ => false
         }
     }
@@ -39,42 +105,111 @@ impl UnaryOp {
             UnaryOp::CmpLe => BinaryOp::Le,
             UnaryOp::CmpGt => BinaryOp::Gt,
             UnaryOp::CmpGe => BinaryOp::Ge,
+            UnaryOp::CmpLtU => BinaryOp::LtU,
+            UnaryOp::CmpLeU => BinaryOp::LeU,
+            UnaryOp::CmpGtU => BinaryOp::GtU,
+            UnaryOp::CmpGeU => BinaryOp::GeU,
+            // Of the difference against zero
+            UnaryOp::CmpMi => BinaryOp::Lt,
+            UnaryOp::CmpPl => BinaryOp::Ge,
+            _ => panic!("Not a cmpop")
+        }
+    }
+
+    /// The float comparison tested by this on flags set by FCmp, and whether it is negated, as the
+    /// conditions that hold for unordered operands are the negations of ones that don't
+    pub fn cmp_op_to_float_binaryop(&self) -> (BinaryOp, bool) {
+        match self {
+            UnaryOp::CmpEq => (BinaryOp::FEq, false),
+            UnaryOp::CmpNe => (BinaryOp::FNe, false),
+            UnaryOp::CmpMi | UnaryOp::CmpLtU => (BinaryOp::FLt, false),
+            UnaryOp::CmpLeU => (BinaryOp::FLe, false),
+            UnaryOp::CmpGt => (BinaryOp::FGt, false),
+            UnaryOp::CmpGe => (BinaryOp::FGe, false),
+            UnaryOp::CmpLt => (BinaryOp::FGe, true),
+            UnaryOp::CmpLe => (BinaryOp::FGt, true),
+            UnaryOp::CmpGtU => (BinaryOp::FLe, true),
+            UnaryOp::CmpGeU | UnaryOp::CmpPl => (BinaryOp::FLt, true),
             _ => panic!("Not a cmpop")
         }
     }
+
+    /// The test this makes of flags set by FCmp(lhs, rhs)
+    pub fn float_cmp(&self, lhs: Box<Expr>, rhs: Box<Expr>) -> Expr {
+        let (op, negated) = self.cmp_op_to_float_binaryop();
+        let cmp = Expr::Binary { op, lhs, rhs };
+        if negated { Expr::Unary { op: UnaryOp::Not, expr: Box::new(cmp) } } else { cmp }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BinaryOp {
     Eq, Ne, Lt, Le, Gt, Ge,
-    Add, Sub, Mul, Div,
+    LtU, LeU, GtU, GeU,
+    Add, Sub, Mul, Div, DivU, Rem, RemU,
     And, Or, Shl, Shr, Asr, Xor,
-    Cmp
+    Cmp,
+    /// Like Cmp, for flags set by comparing floats
+    FCmp,
+    FAdd, FSub, FMul, FDiv,
+    /// Float comparisons, which are all false when either side is NaN (except FNe, which is then true)
+    FEq, FNe, FLt, FLe, FGt, FGe
 }
 
 impl BinaryOp {
     pub fn is_logical(&self) -> bool {
         match self {
             BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge |
+            BinaryOp::LtU | BinaryOp::LeU | BinaryOp::GtU | BinaryOp::GeU |
+            BinaryOp::FEq | BinaryOp::FNe | BinaryOp::FLt | BinaryOp::FLe | BinaryOp::FGt | BinaryOp::FGe |
             BinaryOp::And | BinaryOp::Or => true,
             _ => false
         }
     }
+
+    /// Whether the operands are floats
+    pub fn is_float(&self) -> bool {
+        matches!(self,
+            BinaryOp::FAdd | BinaryOp::FSub | BinaryOp::FMul | BinaryOp::FDiv |
+            BinaryOp::FEq | BinaryOp::FNe | BinaryOp::FLt | BinaryOp::FLe | BinaryOp::FGt | BinaryOp::FGe
+        )
+    }
+
+    /// Whether this is a comparison of two floats, giving a bool
+    pub fn is_float_cmp(&self) -> bool {
+        matches!(self, BinaryOp::FEq | BinaryOp::FNe | BinaryOp::FLt | BinaryOp::FLe | BinaryOp::FGt | BinaryOp::FGe)
+    }
+
+    /// Some for ordered comparisons, with whether they treat their operands as signed
+    pub fn cmp_signedness(&self) -> Option<bool> {
+        match self {
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => Some(true),
+            BinaryOp::LtU | BinaryOp::LeU | BinaryOp::GtU | BinaryOp::GeU => Some(false),
+            _ => None
+        }
+    }
 }
 
 impl std::fmt::Display for BinaryOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
-            BinaryOp::Eq => "==",
-            BinaryOp::Ne => "!=",
-            BinaryOp::Lt => "<",
-            BinaryOp::Gt => ">",
-            BinaryOp::Le => "<=",
-            BinaryOp::Ge => ">=",
-            BinaryOp::Add => "+",
-            BinaryOp::Sub => "-",
-            BinaryOp::Mul => "*",
-            BinaryOp::Div => "/",
+            BinaryOp::Eq | BinaryOp::FEq => "==",
+            BinaryOp::Ne | BinaryOp::FNe => "!=",
+            BinaryOp::Lt | BinaryOp::FLt => "<",
+            BinaryOp::Gt | BinaryOp::FGt => ">",
+            BinaryOp::Le | BinaryOp::FLe => "<=",
+            BinaryOp::Ge | BinaryOp::FGe => ">=",
+            BinaryOp::LtU => "<u",
+            BinaryOp::GtU => ">u",
+            BinaryOp::LeU => "<=u",
+            BinaryOp::GeU => ">=u",
+            BinaryOp::Add | BinaryOp::FAdd => "+",
+            BinaryOp::Sub | BinaryOp::FSub => "-",
+            BinaryOp::Mul | BinaryOp::FMul => "*",
+            BinaryOp::Div | BinaryOp::FDiv => "/",
+            BinaryOp::DivU => "/u",
+            BinaryOp::Rem => "%",
+            BinaryOp::RemU => "%u",
             BinaryOp::Shl => "<<",
             BinaryOp::Shr => ">>",
             BinaryOp::Asr => ">>>",
@@ -82,6 +217,7 @@ impl std::fmt::Display for BinaryOp {
             BinaryOp::And => "and",
             BinaryOp::Or => "or",
             BinaryOp::Cmp => "cmp",
+            BinaryOp::FCmp => "fcmp",
         })
     }
 }
@@ -91,7 +227,154 @@ pub enum BuiltIn {
     Rotr,
     Rotl,
     Ctz,
-    Clz
+    Clz,
+    Popcnt,
+    /// The high half of the product of two signed ints
+    MulHigh,
+    /// The high half of the product of two unsigned ints
+    MulHighU,
+    Sqrt,
+    Abs,
+    Ceil,
+    Floor,
+    /// Rounds toward zero
+    Trunc,
+    /// Rounds to the nearest integer, with ties going to even
+    Nearest,
+    Min,
+    Max,
+    Copysign,
+    /// Size of wasm linear memory, in 64KiB pages
+    MemorySize,
+    /// Grows wasm linear memory by a number of pages, giving the old size or -1
+    MemoryGrow,
+    /// Copies (dst, src, len), allowing overlap
+    MemoryCopy,
+    /// Sets (dst, byte, len)
+    MemoryFill,
+    /// Whether the exception being handled was thrown with the tag given, or given none whether there is one at all
+    Catch,
+    /// One of the values thrown with the exception being handled, by index, or given none the exception itself
+    Exception,
+    /// Throws (tag, values...)
+    Throw,
+    /// Throws the exception being handled again
+    Rethrow,
+    /// One of the values returned by the call just made, by index, when it returns more than one
+    Result,
+    Simd(SimdOp)
+}
+
+impl BuiltIn {
+    /// Whether this works on floats, rather than ints
+    pub fn is_float(&self) -> bool {
+        matches!(self,
+            BuiltIn::Sqrt | BuiltIn::Abs | BuiltIn::Ceil | BuiltIn::Floor | BuiltIn::Trunc |
+            BuiltIn::Nearest | BuiltIn::Min | BuiltIn::Max | BuiltIn::Copysign
+        )
+    }
+
+    /// Whether this works on memory, rather than just the values it is passed
+    pub fn is_memory(&self) -> bool {
+        matches!(self, BuiltIn::MemorySize | BuiltIn::MemoryGrow | BuiltIn::MemoryCopy | BuiltIn::MemoryFill)
+    }
+
+    /// Whether this throws or looks at wasm exceptions
+    pub fn is_exception(&self) -> bool {
+        matches!(self, BuiltIn::Catch | BuiltIn::Exception | BuiltIn::Throw | BuiltIn::Rethrow)
+    }
+}
+
+/// Operations on 128 bit vectors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimdOp {
+    /// A vector made of its args, one per lane
+    Build(ty::Shape),
+    /// A vector with its arg in every lane
+    Splat(ty::Shape),
+    /// Takes (vector, lane)
+    ExtractLane(ty::Shape),
+    /// Takes (vector, lane, value)
+    ReplaceLane(ty::Shape),
+    /// Takes (a, b, indices), picking each byte from the 32 of a then b by the index in its lane
+    Shuffle,
+    /// A scalar operator on each pair of lanes, or for shifts on each lane and a shared amount
+    Lanewise(ty::Shape, BinaryOp),
+    /// Loads a vector from memory, by its name in the text format with `_` for the `.`
+    Load(&'static str),
+    /// Stores (part of) a vector to memory, named like loads
+    Store(&'static str),
+    /// Anything else, named like loads, and whether it gives a vector rather than an i32
+    Named(&'static str, bool)
+}
+
+impl SimdOp {
+    /// Whether what this gives is a vector
+    pub fn is_vector(&self) -> bool {
+        match self {
+            SimdOp::ExtractLane(_) | SimdOp::Store(_) => false,
+            SimdOp::Named(_, vector) => *vector,
+            _ => true
+        }
+    }
+
+    /// Whether this only works on the values it is passed, rather than memory
+    pub fn is_pure(&self) -> bool {
+        !matches!(self, SimdOp::Load(_) | SimdOp::Store(_))
+    }
+}
+
+/// The name of a lanewise operator in the text format
+fn lanewise_name(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add | BinaryOp::FAdd => "add",
+        BinaryOp::Sub | BinaryOp::FSub => "sub",
+        BinaryOp::Mul | BinaryOp::FMul => "mul",
+        BinaryOp::Div | BinaryOp::FDiv => "div",
+        BinaryOp::DivU => "div_u",
+        BinaryOp::Rem => "rem_s",
+        BinaryOp::RemU => "rem_u",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+        BinaryOp::Xor => "xor",
+        BinaryOp::Shl => "shl",
+        BinaryOp::Asr => "shr_s",
+        BinaryOp::Shr => "shr_u",
+        BinaryOp::Eq | BinaryOp::FEq => "eq",
+        BinaryOp::Ne | BinaryOp::FNe => "ne",
+        BinaryOp::Lt => "lt_s",
+        BinaryOp::Le => "le_s",
+        BinaryOp::Gt => "gt_s",
+        BinaryOp::Ge => "ge_s",
+        BinaryOp::LtU => "lt_u",
+        BinaryOp::LeU => "le_u",
+        BinaryOp::GtU => "gt_u",
+        BinaryOp::GeU => "ge_u",
+        BinaryOp::FLt => "lt",
+        BinaryOp::FLe => "le",
+        BinaryOp::FGt => "gt",
+        BinaryOp::FGe => "ge",
+        BinaryOp::Cmp => "cmp",
+        BinaryOp::FCmp => "fcmp"
+    }
+}
+
+impl Display for SimdOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimdOp::Build(shape) => write!(f, "{shape}"),
+            SimdOp::Splat(shape) => write!(f, "{shape}.splat"),
+            SimdOp::ExtractLane(shape) => write!(f, "{shape}.extract_lane"),
+            SimdOp::ReplaceLane(shape) => write!(f, "{shape}.replace_lane"),
+            SimdOp::Shuffle => write!(f, "i8x16.shuffle"),
+            SimdOp::Lanewise(_, op @ (BinaryOp::And | BinaryOp::Or | BinaryOp::Xor)) => write!(f, "v128.{}", lanewise_name(*op)),
+            SimdOp::Lanewise(shape, op) => write!(f, "{shape}.{}", lanewise_name(*op)),
+            SimdOp::Load(name) | SimdOp::Store(name) | SimdOp::Named(name, _) => match name.split_once('_') {
+                Some((prefix, rest)) => write!(f, "{prefix}.{rest}"),
+                None => write!(f, "{name}")
+            }
+        }
+    }
 }
 
 impl Display for BuiltIn {
@@ -101,6 +384,28 @@ impl Display for BuiltIn {
             BuiltIn::Rotl => write!(f, "rotl"),
             BuiltIn::Ctz => write!(f, "ctz"),
             BuiltIn::Clz => write!(f, "clz"),
+            BuiltIn::Popcnt => write!(f, "popcnt"),
+            BuiltIn::MulHigh => write!(f, "mulh"),
+            BuiltIn::MulHighU => write!(f, "mulhu"),
+            BuiltIn::Sqrt => write!(f, "sqrt"),
+            BuiltIn::Abs => write!(f, "abs"),
+            BuiltIn::Ceil => write!(f, "ceil"),
+            BuiltIn::Floor => write!(f, "floor"),
+            BuiltIn::Trunc => write!(f, "trunc"),
+            BuiltIn::Nearest => write!(f, "nearest"),
+            BuiltIn::Min => write!(f, "min"),
+            BuiltIn::Max => write!(f, "max"),
+            BuiltIn::Copysign => write!(f, "copysign"),
+            BuiltIn::MemorySize => write!(f, "memory_size"),
+            BuiltIn::MemoryGrow => write!(f, "memory_grow"),
+            BuiltIn::MemoryCopy => write!(f, "memory_copy"),
+            BuiltIn::MemoryFill => write!(f, "memory_fill"),
+            BuiltIn::Catch => write!(f, "catch"),
+            BuiltIn::Exception => write!(f, "exception"),
+            BuiltIn::Throw => write!(f, "throw"),
+            BuiltIn::Rethrow => write!(f, "rethrow"),
+            BuiltIn::Result => write!(f, "result"),
+            BuiltIn::Simd(op) => write!(f, "{op}"),
         }
     }
 }
@@ -112,6 +417,9 @@ pub struct FuncId(pub usize);
 pub enum Expr {
     Name(String), // FIXME: Intern this or something some day
     Num(i64),
+    Float(f64),
+    /// The address of a string literal
+    Str(String),
     Func(FuncId),
     BuiltIn(BuiltIn),
     Bool(bool),
@@ -120,6 +428,12 @@ pub enum Expr {
         size: ty::Size
     },
     Ref(Box<Expr>),
+    /// An element of a wasm table, with the functions it could hold if how it's used narrows that down
+    Table {
+        table: u32,
+        index: Box<Expr>,
+        candidates: Vec<FuncId>
+    },
     Call {
         func: Box<Expr>,
         args: Vec<Expr>
@@ -156,6 +470,8 @@ impl Expr {
                     write!(f, "{}", x)
                 }
             },
+            Expr::Float(x) => write!(f, "{:?}", x),
+            Expr::Str(s) => write!(f, "{:?}", s),
             Expr::Func(idx) => {
                 match ctx.func(*idx) {
                     Some(func) if func.name.is_some() => write!(f, "{}", func.name.as_ref().unwrap()),
@@ -184,6 +500,19 @@ impl Expr {
                     value.fmt_with_prec_ctx(f, REF, ctx)
                 }
             }
+            Expr::Table { table, index, candidates } => {
+                write!(f, "table{table}[")?;
+                index.fmt_with_prec_ctx(f, 0, ctx)?;
+                if !candidates.is_empty() {
+                    write!(f, " /* ")?;
+                    for (c, candidate) in candidates.iter().enumerate() {
+                        write!(f, "{}", if c == 0 { "" } else { " | " })?;
+                        Expr::Func(*candidate).fmt_with_prec_ctx(f, 0, ctx)?;
+                    }
+                    write!(f, " */")?;
+                }
+                write!(f, "]")
+            }
             Expr::Unary { op, expr } if op.is_cmp() => {
                 if prec >= UNARY {
                     write!(f, "(")?;
@@ -220,12 +549,22 @@ impl Expr {
             Expr::Call { func, args } => {
                 if prec >= FUNC {
                     write!(f, "(")?;
-                    func.fmt_with_prec_ctx(f, FUNC, ctx)?;
-                    write!(f, "({}))", args.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "))
-                } else {
-                    func.fmt_with_prec_ctx(f, FUNC, ctx)?;
-                    write!(f, "({})", args.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "))
                 }
+
+                func.fmt_with_prec_ctx(f, FUNC, ctx)?;
+                write!(f, "(")?;
+                for (a, arg) in args.iter().enumerate() {
+                    if a != 0 {
+                        write!(f, ", ")?;
+                    }
+                    arg.fmt_with_prec_ctx(f, 0, ctx)?;
+                }
+                write!(f, ")")?;
+
+                if prec >= FUNC {
+                    write!(f, ")")?;
+                }
+                Ok(())
             },
         }
     }
@@ -253,6 +592,12 @@ impl Expr {
             Expr::Unary { op: UnaryOp::CmpGe, expr } => Expr::Unary { op: UnaryOp::CmpLt, expr: expr.clone() },
             Expr::Unary { op: UnaryOp::CmpLe, expr } => Expr::Unary { op: UnaryOp::CmpGt, expr: expr.clone() },
             Expr::Unary { op: UnaryOp::CmpGt, expr } => Expr::Unary { op: UnaryOp::CmpLe, expr: expr.clone() },
+            Expr::Unary { op: UnaryOp::CmpLtU, expr } => Expr::Unary { op: UnaryOp::CmpGeU, expr: expr.clone() },
+            Expr::Unary { op: UnaryOp::CmpGeU, expr } => Expr::Unary { op: UnaryOp::CmpLtU, expr: expr.clone() },
+            Expr::Unary { op: UnaryOp::CmpMi, expr } => Expr::Unary { op: UnaryOp::CmpPl, expr: expr.clone() },
+            Expr::Unary { op: UnaryOp::CmpPl, expr } => Expr::Unary { op: UnaryOp::CmpMi, expr: expr.clone() },
+            Expr::Unary { op: UnaryOp::CmpLeU, expr } => Expr::Unary { op: UnaryOp::CmpGtU, expr: expr.clone() },
+            Expr::Unary { op: UnaryOp::CmpGtU, expr } => Expr::Unary { op: UnaryOp::CmpLeU, expr: expr.clone() },
             
             Expr::Binary { op: BinaryOp::Eq, lhs, rhs } => Expr::Binary { op: BinaryOp::Ne, lhs: lhs.clone(), rhs: rhs.clone() },
             Expr::Binary { op: BinaryOp::Ne, lhs, rhs } => Expr::Binary { op: BinaryOp::Eq, lhs: lhs.clone(), rhs: rhs.clone() },
@@ -260,6 +605,13 @@ impl Expr {
             Expr::Binary { op: BinaryOp::Ge, lhs, rhs } => Expr::Binary { op: BinaryOp::Lt, lhs: lhs.clone(), rhs: rhs.clone() },
             Expr::Binary { op: BinaryOp::Gt, lhs, rhs } => Expr::Binary { op: BinaryOp::Le, lhs: lhs.clone(), rhs: rhs.clone() },
             Expr::Binary { op: BinaryOp::Le, lhs, rhs } => Expr::Binary { op: BinaryOp::Gt, lhs: lhs.clone(), rhs: rhs.clone() },
+            Expr::Binary { op: BinaryOp::LtU, lhs, rhs } => Expr::Binary { op: BinaryOp::GeU, lhs: lhs.clone(), rhs: rhs.clone() },
+            Expr::Binary { op: BinaryOp::GeU, lhs, rhs } => Expr::Binary { op: BinaryOp::LtU, lhs: lhs.clone(), rhs: rhs.clone() },
+            Expr::Binary { op: BinaryOp::GtU, lhs, rhs } => Expr::Binary { op: BinaryOp::LeU, lhs: lhs.clone(), rhs: rhs.clone() },
+            Expr::Binary { op: BinaryOp::LeU, lhs, rhs } => Expr::Binary { op: BinaryOp::GtU, lhs: lhs.clone(), rhs: rhs.clone() },
+            // Only these two can be flipped, as every ordered comparison with NaN is false
+            Expr::Binary { op: BinaryOp::FEq, lhs, rhs } => Expr::Binary { op: BinaryOp::FNe, lhs: lhs.clone(), rhs: rhs.clone() },
+            Expr::Binary { op: BinaryOp::FNe, lhs, rhs } => Expr::Binary { op: BinaryOp::FEq, lhs: lhs.clone(), rhs: rhs.clone() },
             Expr::Binary { op: BinaryOp::And, lhs, rhs } => Expr::Binary { op: BinaryOp::Or, lhs: Box::new(lhs.neg()), rhs: Box::new(rhs.neg()) },
             Expr::Binary { op: BinaryOp::Or, lhs, rhs } => Expr::Binary { op: BinaryOp::And, lhs: Box::new(lhs.neg()), rhs: Box::new(rhs.neg()) },
 
@@ -267,6 +619,11 @@ impl Expr {
         }
     }
 
+    /// Whether control never comes back from this, as with a throw
+    pub fn diverges(&self) -> bool {
+        matches!(self, Expr::Call { func, .. } if matches!(func.as_ref(), Expr::BuiltIn(BuiltIn::Throw | BuiltIn::Rethrow)))
+    }
+
     pub fn has_side_effects(&self) -> bool {
         match self {
             Expr::Name(_) => false,
@@ -274,10 +631,14 @@ impl Expr {
             Expr::Binary { lhs, rhs, .. } => lhs.has_side_effects() || rhs.has_side_effects(),
             Expr::Unary { expr, .. } => expr.has_side_effects(),
             Expr::Bool(_) => false,
-            Expr::Num(_) => false,
+            Expr::Num(_) | Expr::Float(_) | Expr::Str(_) => false,
             Expr::Deref { ptr, .. } => ptr.has_side_effects(),
             Expr::Ref(value) => value.has_side_effects(),
-            Expr::Call { .. } => true,
+            Expr::Table { index, .. } => index.has_side_effects(),
+            Expr::Call { func, args } => match func.as_ref() {
+                Expr::BuiltIn(BuiltIn::Simd(op)) if op.is_pure() => args.iter().any(Expr::has_side_effects),
+                _ => true
+            },
             Expr::BuiltIn(_) => false
         }
     }
@@ -289,9 +650,10 @@ impl Expr {
             Expr::Binary { lhs, rhs, .. } => lhs.count_reads(name) + rhs.count_reads(name),
             Expr::Unary { expr, .. } => expr.count_reads(name),
             Expr::Bool(_) => 0,
-            Expr::Num(_) => 0,
+            Expr::Num(_) | Expr::Float(_) | Expr::Str(_) => 0,
             Expr::Deref { ptr, .. } => ptr.count_reads(name),
             Expr::Ref(value) => value.count_reads(name),
+            Expr::Table { index, .. } => index.count_reads(name),
             Expr::Call { func, args } => args.iter().fold(func.count_reads(name), |prev, x| prev + x.count_reads(name)),
             Expr::BuiltIn(_) => 0
         }
@@ -314,6 +676,7 @@ impl Expr {
             Expr::Name(name) => names.push(name.as_str()),
             Expr::Deref { ptr, .. } => ptr.append_read_names_rhs(names),
             Expr::Ref(value) => value.append_read_names_rhs(names),
+            Expr::Table { index, .. } => index.append_read_names_rhs(names),
             Expr::Unary { expr, .. } => expr.append_read_names_rhs(names),
             Expr::Binary { lhs, rhs, .. } => {
                 lhs.append_read_names_rhs(names);
@@ -325,7 +688,7 @@ impl Expr {
                     arg.append_read_names_rhs(names);
                 }
             }
-            Expr::Num(_) | Expr::Bool(_) | Expr::Func(_) | Expr::BuiltIn(_) => {}
+            Expr::Num(_) | Expr::Float(_) | Expr::Str(_) | Expr::Bool(_) | Expr::Func(_) | Expr::BuiltIn(_) => {}
         }
     }
 
@@ -340,7 +703,7 @@ impl Expr {
     pub fn replace_name(&mut self, name: &str, expr: &Expr) {
         match self {
             Expr::Name(name_) if *name_ == name => *self = expr.clone(),
-            Expr::Bool(_) | Expr::Num(_) | Expr::Name(_) | Expr::Func(_) => (),
+            Expr::Bool(_) | Expr::Num(_) | Expr::Float(_) | Expr::Str(_) | Expr::Name(_) | Expr::Func(_) => (),
             Expr::Binary { lhs, rhs, .. } => {
                 lhs.replace_name(name, expr);
                 rhs.replace_name(name, expr);
@@ -348,6 +711,7 @@ impl Expr {
             Expr::Unary { expr: uexpr, .. } => uexpr.replace_name(name, expr),
             Expr::Deref { ptr, .. } => ptr.replace_name(name, expr),
             Expr::Ref(value) => value.replace_name(name, expr),
+            Expr::Table { index, .. } => index.replace_name(name, expr),
             Expr::Call { func, args } => {
                 func.replace_name(name, expr);
                 for arg in args {
@@ -21,7 +21,12 @@ pub enum Lir {
     },
     Do(expr::Expr),
     Label(Label),
-    Return(expr::Expr)
+    Return(Vec<expr::Expr>),
+    /// Only present while in SSA form, with one source per incoming node
+    Phi {
+        dst: expr::Expr,
+        srcs: Vec<(usize, expr::Expr)>
+    }
 }
 
 impl Lir {
@@ -30,35 +35,52 @@ impl Lir {
             Lir::Assign { src: expr::Expr::Deref { .. }, .. } => true,
             Lir::Assign { src, dst } => src.has_side_effects() || dst.has_side_effects(),
             Lir::Label(_) => false,
-            Lir::Return(ret) => ret.has_side_effects(),
+            Lir::Return(ret) => ret.iter().any(expr::Expr::has_side_effects),
             Lir::Do(x) => x.has_side_effects(),
             Lir::Branch { cond: Some(cond), .. } => cond.has_side_effects(),
             Lir::Branch { .. } => false,
+            Lir::Phi { .. } => false,
         }
     }
 
     pub fn writes_to(&self, name: &str) -> bool {
         match self {
-            Lir::Assign { dst: expr::Expr::Name(nm), .. } => name == nm,
+            Lir::Assign { dst: expr::Expr::Name(nm), .. } | Lir::Phi { dst: expr::Expr::Name(nm), .. } => name == nm,
             _ => false
         }
     }
 
     pub fn count_reads(&self, name: &str) -> usize {
         match self {
-            Lir::Return(expr) => expr.count_reads(name),
+            Lir::Return(ret) => ret.iter().map(|x| x.count_reads(name)).sum(),
             Lir::Do(expr) => expr.count_reads(name),
             Lir::Assign { dst: expr::Expr::Name(_), src } => src.count_reads(name),
             Lir::Assign { src, dst } => src.count_reads(name) + dst.count_reads(name),
             Lir::Branch { cond: Some(cond), .. } => cond.count_reads(name),
             Lir::Branch { .. } => 0,
-            Lir::Label(_) => 0
+            Lir::Label(_) => 0,
+            Lir::Phi { srcs, .. } => srcs.iter().map(|(_, src)| src.count_reads(name)).sum()
+        }
+    }
+
+    pub fn read_names(&self) -> Vec<&str> {
+        match self {
+            Lir::Return(ret) => ret.iter().flat_map(expr::Expr::read_names_rhs).collect(),
+            Lir::Do(expr) => expr.read_names_rhs(),
+            Lir::Assign { src, dst } => {
+                let mut names = src.read_names_rhs();
+                names.extend(dst.read_names_lhs());
+                names
+            }
+            Lir::Branch { cond: Some(cond), .. } => cond.read_names_rhs(),
+            Lir::Branch { .. } | Lir::Label(_) => vec![],
+            Lir::Phi { srcs, .. } => srcs.iter().flat_map(|(_, src)| src.read_names_rhs()).collect()
         }
     }
 
     pub fn replace_name(&mut self, name: &str, expr: &expr::Expr) {
         match self {
-            Lir::Return(ret) => ret.replace_name(name, expr),
+            Lir::Return(ret) => ret.iter_mut().for_each(|x| x.replace_name(name, expr)),
             Lir::Do(ret) => ret.replace_name(name, expr),
             Lir::Assign { dst: expr::Expr::Name(_), src } => src.replace_name(name, expr),
             Lir::Assign { src, dst } => {
@@ -67,7 +89,8 @@ impl Lir {
             },
             Lir::Branch { cond: Some(cond), .. } => cond.replace_name(name, expr),
             Lir::Branch { .. } => (),
-            Lir::Label(_) => ()
+            Lir::Label(_) => (),
+            Lir::Phi { srcs, .. } => srcs.iter_mut().for_each(|(_, src)| src.replace_name(name, expr))
         }
     }
 }
@@ -77,10 +100,23 @@ impl std::fmt::Display for Lir {
         match self {
             Lir::Branch { cond: Some(cond), target } => write!(f, "ifgoto {cond} {target}"),
             Lir::Branch { cond: None, target } => write!(f, "goto {target}"),
-            Lir::Return(expr) => write!(f, "return {expr}"),
+            Lir::Return(ret) => {
+                write!(f, "return")?;
+                for (i, expr) in ret.iter().enumerate() {
+                    write!(f, "{}{expr}", if i == 0 { " " } else { ", " })?;
+                }
+                Ok(())
+            }
             Lir::Do(expr) => write!(f, "{expr}"),
             Lir::Label(label) => write!(f, "{label}:"),
-            Lir::Assign { src, dst } => write!(f, "{dst} = {src}")
+            Lir::Assign { src, dst } => write!(f, "{dst} = {src}"),
+            Lir::Phi { dst, srcs } => {
+                write!(f, "{dst} = phi(")?;
+                for (i, (node, src)) in srcs.iter().enumerate() {
+                    write!(f, "{}{node}: {src}", if i == 0 { "" } else { ", " })?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
@@ -106,14 +142,16 @@ pub type Index = usize;
 
 pub struct LirFuncBuilder {
     code: Vec<Lir>,
-    label_alloc: LabelAllocator
+    label_alloc: LabelAllocator,
+    pinned: Vec<String>
 }
 
 impl LirFuncBuilder {
     pub fn new() -> LirFuncBuilder {
         LirFuncBuilder {
             code: Vec::new(),
-            label_alloc: LabelAllocator::new()
+            label_alloc: LabelAllocator::new(),
+            pinned: Vec::new()
         }
     }
 
@@ -125,12 +163,35 @@ impl LirFuncBuilder {
         self.code.push(code);
     }
 
+    /// Puts code in before the statement at idx
+    pub fn insert(&mut self, idx: Index, code: Vec<Lir>) {
+        self.code.splice(idx..idx, code);
+    }
+
+    /// Marks name as able to be read from where the control flow doesn't show, so its writes must be kept as they are
+    pub fn pin(&mut self, name: String) {
+        if !self.pinned.contains(&name) {
+            self.pinned.push(name);
+        }
+    }
+
+    /// The index the next statement pushed will have
+    pub fn next_index(&self) -> Index {
+        self.code.len()
+    }
+
     pub fn block(self) -> LirFunc {
         LirFunc {
             code: self.code,
+            pinned: self.pinned
         }
     }
 
+    /// The statements pushed from idx on
+    pub fn since(&mut self, idx: Index) -> &mut [Lir] {
+        &mut self.code[idx..]
+    }
+
     pub fn last(&mut self) -> Option<&mut Lir> {
         self.code.last_mut()
     }
@@ -148,12 +209,15 @@ pub struct LirNode {
 #[derive(Debug)]
 pub struct LirFunc {
     code: Vec<Lir>,
+    /// Names whose writes may be read along paths the code doesn't show, as when a wasm exception is caught
+    pinned: Vec<String>
 }
 
 impl LirFunc {
     pub fn new() -> LirFunc {
         LirFunc {
             code: Vec::new(),
+            pinned: Vec::new()
         }
     }
 
@@ -172,6 +236,10 @@ impl LirFunc {
     pub fn at(&self, idx: usize) -> Option<&Lir> {
         self.code.get(idx)
     }
+
+    pub fn pinned(&self) -> &[String] {
+        &self.pinned
+    }
 }
 
 impl std::fmt::Display for LirFunc {
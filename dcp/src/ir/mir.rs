@@ -1,6 +1,6 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use crate::{expr, lir, pretty};
+use crate::{expr, lir, pretty, ty};
 
 #[derive(Clone, Debug)]
 pub enum Mir {
@@ -8,7 +8,7 @@ pub enum Mir {
         src: expr::Expr,
         dst: expr::Expr
     },
-    Return(expr::Expr),
+    Return(Vec<expr::Expr>),
     Do(expr::Expr),
     Branch {
         cond: Option<expr::Expr>,
@@ -32,6 +32,17 @@ pub enum Mir {
         inc: Vec<Mir>,
         code: Vec<Mir>,
     },
+    /// Runs the case whose values include scrutinee, or default if none do. Cases don't fall through
+    Switch {
+        scrutinee: expr::Expr,
+        cases: Vec<(Vec<i64>, Vec<Mir>)>,
+        default: Vec<Mir>,
+    },
+    /// Runs body, and if it throws, the first handler for the tag thrown instead. A handler without a tag catches anything
+    Try {
+        body: Vec<Mir>,
+        handlers: Vec<(Option<u32>, Vec<Mir>)>,
+    },
     Break,
     Continue
 }
@@ -48,7 +59,8 @@ impl Mir {
 pub struct MirLocal {
     pub offset: u64,
     pub size: u64,
-    pub name: String
+    pub name: String,
+    pub ty: Option<ty::Ty>
 }
 
 pub struct MirStackFrame {
@@ -58,8 +70,12 @@ pub struct MirStackFrame {
 
 impl MirStackFrame {
     pub fn new() -> MirStackFrame {
+        MirStackFrame::with_size(0)
+    }
+
+    pub fn with_size(size: u64) -> MirStackFrame {
         MirStackFrame {
-            size: 0,
+            size,
             locals: vec![]
         }
     }
@@ -71,6 +87,10 @@ impl MirStackFrame {
         }
     }
 
+    pub fn locals(&self) -> &[MirLocal] {
+        &self.locals
+    }
+
     pub fn get_by_name(&self, name: &str) -> Option<&MirLocal> {
         self.locals.iter().find(|x| x.name == name)
     }
@@ -83,6 +103,7 @@ impl MirStackFrame {
         match self.locals.binary_search_by(|x| x.offset.cmp(&local.offset)) {
             Ok(_) => panic!("Already exists"),
             Err(idx) => {
+                self.size = self.size.max(local.offset);
                 self.locals.insert(idx, local);
             }
         }
@@ -90,19 +111,22 @@ impl MirStackFrame {
 }
 
 pub struct MirFunc {
-    pub results: Vec<String>,
+    pub results: Vec<ty::Ty>,
     pub code: Vec<Mir>,
     pub stack_frame: MirStackFrame,
-    pub funcid: expr::FuncId
+    pub funcid: expr::FuncId,
+    /// Inferred types of the names used in the code
+    pub types: HashMap<String, ty::Ty>
 }
 
 impl MirFunc {
-    pub fn new(funcid: expr::FuncId, results: Vec<String>, code: Vec<Mir>, stack_frame: MirStackFrame) -> MirFunc {
+    pub fn new(funcid: expr::FuncId, results: Vec<ty::Ty>, code: Vec<Mir>, stack_frame: MirStackFrame) -> MirFunc {
         MirFunc {
             funcid,
             results,
             code,
-            stack_frame
+            stack_frame,
+            types: HashMap::new()
         }
     }
 
@@ -115,21 +139,42 @@ impl MirFunc {
             }
             
             for (a, arg) in func.args.iter().enumerate() {
+                let arg = func.local_name(arg);
                 if a != 0 {
                     write!(f, ", {arg}")?;
                 } else {
                     write!(f, "{arg}")?;
                 }
+
+                if let Some(ty) = self.types.get(arg) {
+                    write!(f, ": {ty}")?;
+                }
             }
         } else {
             write!(f, "func fn{}(", self.funcid.0)?;
         }        
         
-        write!(f, ") {{")?;
+        write!(f, ")")?;
+        match self.results.as_slice() {
+            [] => {}
+            [result] => write!(f, " -> {result}")?,
+            results => {
+                write!(f, " -> (")?;
+                for (r, result) in results.iter().enumerate() {
+                    write!(f, "{}{result}", if r == 0 { "" } else { ", " })?;
+                }
+                write!(f, ")")?;
+            }
+        }
+
+        write!(f, " {{")?;
         if self.stack_frame.locals.len() > 0 {
             write!(f, "{}frame {} {{", crate::NEWLINE_INDENT, self.stack_frame.size)?;
             for local in &self.stack_frame.locals {
-                write!(f, "\n{}{}var {}: {} bytes @ base - {}", crate::INDENT, crate::INDENT, local.name, local.size, local.offset)?;
+                match &local.ty {
+                    Some(ty) => write!(f, "\n{}{}var {}: {} @ base - {}", crate::INDENT, crate::INDENT, local.name, ty, local.offset)?,
+                    None => write!(f, "\n{}{}var {}: {} bytes @ base - {}", crate::INDENT, crate::INDENT, local.name, local.size, local.offset)?
+                }
             }
             write!(f, "{}}}", crate::NEWLINE_INDENT)?;
         }
@@ -155,7 +200,8 @@ impl From<lir::Lir> for Mir {
             lir::Lir::Branch { cond, target } => Mir::Branch { cond, target },
             lir::Lir::Return(expr) => Mir::Return(expr),
             lir::Lir::Do(expr) => Mir::Do(expr),
-            lir::Lir::Label(label) => Mir::Label(label)
+            lir::Lir::Label(label) => Mir::Label(label),
+            lir::Lir::Phi { .. } => panic!("Phi nodes must be removed before leaving LIR")
         }
     }
 }
@@ -168,9 +214,13 @@ impl Mir {
                 write!(f, " = ")?;
                 src.fmt_with_context(f, ctx)
             }
-            Mir::Return(expr) => {
-                write!(f, "return ")?;
-                expr.fmt_with_context(f, ctx)
+            Mir::Return(ret) => {
+                write!(f, "return")?;
+                for (i, expr) in ret.iter().enumerate() {
+                    write!(f, "{}", if i == 0 { " " } else { ", " })?;
+                    expr.fmt_with_context(f, ctx)?;
+                }
+                Ok(())
             },
             Mir::Do(expr) => expr.fmt_with_context(f, ctx),
             Mir::Branch { cond: Some(cond), target } => {
@@ -255,6 +305,67 @@ impl Mir {
                 ctx.newline(f)?;
                 write!(f, "}}")
             }
+            Mir::Switch { scrutinee, cases, default } => {
+                write!(f, "switch ")?;
+                scrutinee.fmt_with_context(f, ctx)?;
+                write!(f, " {{")?;
+                ctx.push_indent();
+                for (values, code) in cases {
+                    ctx.newline(f)?;
+                    write!(f, "case {} {{", values.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "))?;
+                    ctx.push_indent();
+                    for stmt in code {
+                        ctx.newline(f)?;
+                        stmt.fmt_with_context(f, ctx)?;
+                    }
+                    ctx.pop_indent();
+                    ctx.newline(f)?;
+                    write!(f, "}}")?;
+                }
+
+                if !default.is_empty() {
+                    ctx.newline(f)?;
+                    write!(f, "default {{")?;
+                    ctx.push_indent();
+                    for stmt in default {
+                        ctx.newline(f)?;
+                        stmt.fmt_with_context(f, ctx)?;
+                    }
+                    ctx.pop_indent();
+                    ctx.newline(f)?;
+                    write!(f, "}}")?;
+                }
+                ctx.pop_indent();
+                ctx.newline(f)?;
+                write!(f, "}}")
+            }
+            Mir::Try { body, handlers } => {
+                write!(f, "try {{")?;
+                ctx.push_indent();
+                for stmt in body {
+                    ctx.newline(f)?;
+                    stmt.fmt_with_context(f, ctx)?;
+                }
+                ctx.pop_indent();
+                ctx.newline(f)?;
+                write!(f, "}}")?;
+
+                for (tag, code) in handlers {
+                    match tag {
+                        Some(tag) => write!(f, " catch {tag} {{")?,
+                        None => write!(f, " catch_all {{")?
+                    }
+                    ctx.push_indent();
+                    for stmt in code {
+                        ctx.newline(f)?;
+                        stmt.fmt_with_context(f, ctx)?;
+                    }
+                    ctx.pop_indent();
+                    ctx.newline(f)?;
+                    write!(f, "}}")?;
+                }
+                Ok(())
+            }
             Mir::Break => write!(f, "break"),
             Mir::Continue => write!(f, "continue"),
             Mir::Label(label) => write!(f, "{label}:")
@@ -266,7 +377,13 @@ impl std::fmt::Display for Mir {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Mir::Assign { src, dst } => write!(f, "{dst} = {src}"),
-            Mir::Return(expr) => write!(f, "return {expr}"),
+            Mir::Return(ret) => {
+                write!(f, "return")?;
+                for (i, expr) in ret.iter().enumerate() {
+                    write!(f, "{}{expr}", if i == 0 { " " } else { ", " })?;
+                }
+                Ok(())
+            }
             Mir::Do(expr) => write!(f, "{expr}"),
             Mir::Branch { cond: Some(cond), target } => write!(f, "ifgoto {cond} #{target}"),
             Mir::Branch { cond: None, target } => write!(f, "goto #{target}"),
@@ -314,6 +431,46 @@ impl std::fmt::Display for Mir {
                 }
                 write!(f, "\n}}")
             }
+            Mir::Switch { scrutinee, cases, default } => {
+                write!(f, "switch {} {{", scrutinee)?;
+                for (values, code) in cases {
+                    let mut case = format!("\ncase {} {{", values.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "));
+                    for stmt in code {
+                        case += &format!("\n{}", stmt).replace('\n', crate::NEWLINE_INDENT);
+                    }
+                    case += "\n}";
+                    f.write_str(&case.replace('\n', crate::NEWLINE_INDENT))?;
+                }
+
+                if !default.is_empty() {
+                    let mut case = "\ndefault {".to_string();
+                    for stmt in default {
+                        case += &format!("\n{}", stmt).replace('\n', crate::NEWLINE_INDENT);
+                    }
+                    case += "\n}";
+                    f.write_str(&case.replace('\n', crate::NEWLINE_INDENT))?;
+                }
+                write!(f, "\n}}")
+            }
+            Mir::Try { body, handlers } => {
+                write!(f, "try {{")?;
+                for stmt in body {
+                    f.write_str(&format!("\n{}", stmt).replace('\n', crate::NEWLINE_INDENT))?;
+                }
+                write!(f, "\n}}")?;
+
+                for (tag, code) in handlers {
+                    match tag {
+                        Some(tag) => write!(f, " catch {tag} {{")?,
+                        None => write!(f, " catch_all {{")?
+                    }
+                    for stmt in code {
+                        f.write_str(&format!("\n{}", stmt).replace('\n', crate::NEWLINE_INDENT))?;
+                    }
+                    write!(f, "\n}}")?;
+                }
+                Ok(())
+            }
             Mir::Break => write!(f, "break"),
             Mir::Continue => write!(f, "continue"),
             Mir::Label(label) => write!(f, "{label}:")
@@ -341,6 +498,8 @@ pub trait MirVisitor {
             Mir::Loop { code } => self.visit_loop(code),
             Mir::While { guard, code } => self.visit_while(guard, code),
             Mir::For { guard, inc, code } => self.visit_for(guard, inc, code),
+            Mir::Switch { scrutinee, cases, default } => self.visit_switch(scrutinee, cases, default),
+            Mir::Try { body, handlers } => self.visit_try(body, handlers),
         }
     }
 
@@ -348,8 +507,10 @@ pub trait MirVisitor {
 
     fn visit_break(&mut self) {}
     fn visit_continue(&mut self) {}
-    fn visit_return(&mut self, expr: &expr::Expr) {
-        self.visit_expr(expr);
+    fn visit_return(&mut self, ret: &[expr::Expr]) {
+        for expr in ret {
+            self.visit_expr(expr);
+        }
     }
     fn visit_do(&mut self, expr: &expr::Expr) {
         self.visit_expr(expr);
@@ -385,6 +546,21 @@ pub trait MirVisitor {
         self.visit_block(inc);
         self.visit_block(code);
     }
+
+    fn visit_switch(&mut self, scrutinee: &expr::Expr, cases: &[(Vec<i64>, Vec<Mir>)], default: &[Mir]) {
+        self.visit_expr(scrutinee);
+        for (_, code) in cases {
+            self.visit_block(code);
+        }
+        self.visit_block(default);
+    }
+
+    fn visit_try(&mut self, body: &[Mir], handlers: &[(Option<u32>, Vec<Mir>)]) {
+        self.visit_block(body);
+        for (_, code) in handlers {
+            self.visit_block(code);
+        }
+    }
 }
 
 pub enum MVMAction {
@@ -438,6 +614,8 @@ pub trait MirVisitorMut {
             Mir::Loop { code } => self.visit_loop(code),
             Mir::While { guard, code } => self.visit_while(guard, code),
             Mir::For { guard, inc, code } => self.visit_for(guard, inc, code),
+            Mir::Switch { scrutinee, cases, default } => self.visit_switch(scrutinee, cases, default),
+            Mir::Try { body, handlers } => self.visit_try(body, handlers),
         }
     }
 
@@ -445,8 +623,10 @@ pub trait MirVisitorMut {
 
     fn visit_break(&mut self) -> MVMAction { MVMAction::Keep }
     fn visit_continue(&mut self) -> MVMAction { MVMAction::Keep }
-    fn visit_return(&mut self, expr: &mut expr::Expr) -> MVMAction {
-        self.visit_expr(expr);
+    fn visit_return(&mut self, ret: &mut [expr::Expr]) -> MVMAction {
+        for expr in ret {
+            self.visit_expr(expr);
+        }
         MVMAction::Keep
     }
     fn visit_do(&mut self, expr: &mut expr::Expr) -> MVMAction {
@@ -490,6 +670,23 @@ pub trait MirVisitorMut {
         self.visit_block(code);
         MVMAction::Keep
     }
+
+    fn visit_switch(&mut self, scrutinee: &mut expr::Expr, cases: &mut [(Vec<i64>, Vec<Mir>)], default: &mut Vec<Mir>) -> MVMAction {
+        self.visit_expr(scrutinee);
+        for (_, code) in cases {
+            self.visit_block(code);
+        }
+        self.visit_block(default);
+        MVMAction::Keep
+    }
+
+    fn visit_try(&mut self, body: &mut Vec<Mir>, handlers: &mut [(Option<u32>, Vec<Mir>)]) -> MVMAction {
+        self.visit_block(body);
+        for (_, code) in handlers {
+            self.visit_block(code);
+        }
+        MVMAction::Keep
+    }
 }
 
 pub fn used_labels(code: &[Mir]) -> HashSet<lir::Label> {
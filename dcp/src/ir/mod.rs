@@ -3,3 +3,4 @@ pub mod lir;
 pub mod mir;
 pub mod ty;
 pub mod pretty;
+pub mod c;
@@ -7,7 +7,17 @@ pub enum Size {
 }
 
 impl Size {
-    pub fn byte_count(&mut self) -> usize {
+    pub fn from_byte_count(count: u64) -> Option<Size> {
+        match count {
+            8 => Some(Size::Size64),
+            4 => Some(Size::Size32),
+            2 => Some(Size::Size16),
+            1 => Some(Size::Size8),
+            _ => None
+        }
+    }
+
+    pub fn byte_count(&self) -> usize {
         match self {
             Size::Size64 => 8,
             Size::Size32 => 4,
@@ -28,13 +38,92 @@ impl std::fmt::Display for Size {
     }
 }
 
+/// How the 128 bits of a vector are split into lanes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Shape {
+    I8x16,
+    I16x8,
+    I32x4,
+    I64x2,
+    F32x4,
+    F64x2,
+}
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+impl Shape {
+    pub fn lane_size(&self) -> Size {
+        match self {
+            Shape::I8x16 => Size::Size8,
+            Shape::I16x8 => Size::Size16,
+            Shape::I32x4 | Shape::F32x4 => Size::Size32,
+            Shape::I64x2 | Shape::F64x2 => Size::Size64,
+        }
+    }
+
+    pub fn lanes(&self) -> usize {
+        16 / self.lane_size().byte_count()
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Shape::F32x4 | Shape::F64x2)
+    }
+
+    /// The type of a single lane
+    pub fn lane_ty(&self) -> Ty {
+        if self.is_float() {
+            Ty::Float { size: self.lane_size() }
+        } else {
+            Ty::unknown(self.lane_size())
+        }
+    }
+}
+
+impl std::fmt::Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}x{}", if self.is_float() { "f" } else { "i" }, self.lane_size().byte_count() * 8, self.lanes())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Ty {
     Unknown64,
     Unknown32,
     Unknown16,
     Unknown8,
+    Int {
+        size: Size,
+        signed: bool
+    },
+    Ptr(Box<Ty>),
+    Float {
+        size: Size
+    },
+    Bool,
+    Func,
+    /// 128 bits of lanes, with the shape they were last used as when that is known
+    Vector(Option<Shape>),
+}
+
+impl Ty {
+    pub fn unknown(size: Size) -> Ty {
+        match size {
+            Size::Size64 => Ty::Unknown64,
+            Size::Size32 => Ty::Unknown32,
+            Size::Size16 => Ty::Unknown16,
+            Size::Size8 => Ty::Unknown8,
+        }
+    }
+
+    /// The size of values of this type, None for those (like bools) which don't have a fixed one
+    pub fn size(&self) -> Option<Size> {
+        match self {
+            Ty::Unknown64 => Some(Size::Size64),
+            Ty::Unknown32 => Some(Size::Size32),
+            Ty::Unknown16 => Some(Size::Size16),
+            Ty::Unknown8 => Some(Size::Size8),
+            Ty::Int { size, .. } | Ty::Float { size } => Some(*size),
+            Ty::Ptr(_) | Ty::Bool | Ty::Func | Ty::Vector(_) => None,
+        }
+    }
 }
 
 impl std::fmt::Display for Ty {
@@ -44,6 +133,13 @@ impl std::fmt::Display for Ty {
             Ty::Unknown32 => f.write_str("u32"),
             Ty::Unknown16 => f.write_str("u16"),
             Ty::Unknown8 => f.write_str("u8"),
+            Ty::Int { size, signed } => write!(f, "{}{}", if *signed { "i" } else { "u" }, size.byte_count() * 8),
+            Ty::Ptr(ty) => write!(f, "*{ty}"),
+            Ty::Float { size } => write!(f, "f{}", size.byte_count() * 8),
+            Ty::Bool => f.write_str("bool"),
+            Ty::Func => f.write_str("fn"),
+            Ty::Vector(Some(shape)) => write!(f, "{shape}"),
+            Ty::Vector(None) => f.write_str("v128"),
         }
     }
 }
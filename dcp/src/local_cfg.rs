@@ -12,11 +12,16 @@ pub fn gen_local_cfg(blir: &[lir::LirNode]) -> cfg::ControlFlowGraph {
     for (i, node) in blir.iter().enumerate() {
         match node.code.last() {
             Some(lir::Lir::Return(_)) => {},
+            Some(lir::Lir::Do(expr)) if expr.diverges() => {},
             Some(lir::Lir::Branch { cond: Some(_), target }) => {
                 cfg.add_edge(i, target.0);
-                cfg.add_edge(i, i + 1);
+                if i + 1 < blir.len() {
+                    cfg.add_edge(i, i + 1);
+                }
             },
             Some(lir::Lir::Branch { cond: None, target }) => cfg.add_edge(i, target.0),
+            // Code which runs off the end of the function (e.g. into a noreturn call) has no successor
+            _ if i + 1 == blir.len() => {},
             _ => cfg.add_edge(i, i + 1),
         }
     }
@@ -42,6 +47,11 @@ pub fn lir_to_lirnodes(mut lir: lir::LirFunc) -> Vec<lir::LirNode> {
             };
         }
 
+        // Nothing falls through a throw, so what follows starts a node of its own
+        if let lir::Lir::Do(expr) = stmt && expr.diverges() && let Some(lir::Lir::Label(label)) = lir.at(i + 1) {
+            used_labels.insert(*label);
+        }
+
         i += 1;
     }
 
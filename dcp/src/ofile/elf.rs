@@ -0,0 +1,668 @@
+use super::{macho::{CodeResult, OfileErr}, Image};
+
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+const ET_REL: u16 = 1;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHT_REL: u32 = 9;
+const SHT_DYNSYM: u32 = 11;
+
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// Where the sections of relocatable objects are laid out from
+const REL_BASE: u64 = 0x1000;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+
+const R_X86_64_64: u32 = 1;
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+const R_X86_64_GOTPCREL: u32 = 9;
+const R_X86_64_32: u32 = 10;
+const R_X86_64_32S: u32 = 11;
+const R_X86_64_GOTPCRELX: u32 = 41;
+const R_X86_64_REX_GOTPCRELX: u32 = 42;
+
+const R_AARCH64_ABS64: u32 = 257;
+const R_AARCH64_PREL32: u32 = 261;
+const R_AARCH64_ADR_PREL_PG_HI21: u32 = 275;
+const R_AARCH64_ADD_ABS_LO12_NC: u32 = 277;
+const R_AARCH64_LDST8_ABS_LO12_NC: u32 = 278;
+const R_AARCH64_JUMP26: u32 = 282;
+const R_AARCH64_CALL26: u32 = 283;
+const R_AARCH64_LDST16_ABS_LO12_NC: u32 = 284;
+const R_AARCH64_LDST32_ABS_LO12_NC: u32 = 285;
+const R_AARCH64_LDST64_ABS_LO12_NC: u32 = 286;
+const R_AARCH64_ADR_GOT_PAGE: u32 = 311;
+const R_AARCH64_LD64_GOT_LO12_NC: u32 = 312;
+const R_AARCH64_LDST128_ABS_LO12_NC: u32 = 299;
+
+pub enum ElfArch {
+    X8664,
+    Arm64
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    is_64bit: bool,
+    is_bigend: bool
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N], OfileErr> {
+        match self.buf.get(offset..offset + N) {
+            Some(bytes) => Ok(bytes.try_into().unwrap()),
+            None => Err(OfileErr::Invalid)
+        }
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8, OfileErr> {
+        self.buf.get(offset).copied().ok_or(OfileErr::Invalid)
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, OfileErr> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.is_bigend { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, OfileErr> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.is_bigend { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, OfileErr> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.is_bigend { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+
+    /// Reads a field which is 4 bytes wide in ELF32 and 8 bytes wide in ELF64
+    fn word(&self, offset32: usize, offset64: usize) -> Result<u64, OfileErr> {
+        if self.is_64bit {
+            self.u64(offset64)
+        } else {
+            self.u32(offset32).map(u64::from)
+        }
+    }
+
+    fn str(&self, offset: usize) -> Result<&'a str, OfileErr> {
+        let bytes = self.buf.get(offset..).ok_or(OfileErr::Invalid)?;
+        let len = bytes.iter().position(|x| *x == 0).ok_or(OfileErr::Invalid)?;
+        std::str::from_utf8(&bytes[..len]).map_err(|_| OfileErr::Invalid)
+    }
+}
+
+struct Section<'a> {
+    name: &'a str,
+    ty: u32,
+    flags: u64,
+    addr: u64,
+    offset: usize,
+    size: usize,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: usize
+}
+
+impl<'a> Section<'a> {
+    fn is_code(&self) -> bool {
+        self.flags & SHF_EXECINSTR != 0 && self.ty != SHT_NOBITS && self.size > 0
+    }
+
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.addr && addr < self.addr + self.size as u64
+    }
+}
+
+struct FuncSymbol {
+    name: Option<String>,
+    addr: u64,
+    size: u64,
+    section: usize
+}
+
+fn read_sections<'a>(reader: &Reader<'a>, is_rel: bool) -> Result<Vec<Section<'a>>, OfileErr> {
+    let shoff = reader.word(0x20, 0x28)? as usize;
+    let (shentsize, shnum, shstrndx) = if reader.is_64bit {
+        (reader.u16(0x3a)? as usize, reader.u16(0x3c)? as usize, reader.u16(0x3e)? as usize)
+    } else {
+        (reader.u16(0x2e)? as usize, reader.u16(0x30)? as usize, reader.u16(0x32)? as usize)
+    };
+
+    if shnum == 0 {
+        return Ok(vec![])
+    }
+
+    let header = |i: usize| shoff + i * shentsize;
+    let shstrtab = reader.word(header(shstrndx) + 0x10, header(shstrndx) + 0x18)? as usize;
+
+    let mut sections = Vec::new();
+    for i in 0..shnum {
+        let base = header(i);
+
+        let ty = reader.u32(base + 4)?;
+        let offset = reader.word(base + 0x10, base + 0x18)? as usize;
+        let size = reader.word(base + 0x14, base + 0x20)? as usize;
+        if ty != SHT_NOBITS && offset.checked_add(size).filter(|end| *end <= reader.buf.len()).is_none() {
+            return Err(OfileErr::Invalid)
+        }
+
+        sections.push(Section {
+            name: reader.str(shstrtab + reader.u32(base)? as usize)?,
+            ty,
+            flags: reader.word(base + 0x08, base + 0x08)?,
+            addr: reader.word(base + 0x0c, base + 0x10)?,
+            offset,
+            size,
+            link: reader.u32(if reader.is_64bit { base + 0x28 } else { base + 0x18 })?,
+            info: reader.u32(if reader.is_64bit { base + 0x2c } else { base + 0x1c })?,
+            align: reader.word(base + 0x20, base + 0x30)?,
+            entsize: reader.word(base + 0x24, base + 0x38)? as usize
+        });
+    }
+
+    // Relocatable objects leave every section at address 0, so lay the loaded ones out one after another as a linker
+    // would, zero filled ones included
+    if is_rel {
+        let mut addr = REL_BASE;
+        for section in sections.iter_mut().filter(|x| x.flags & SHF_ALLOC != 0) {
+            addr = addr.next_multiple_of(section.align.max(1));
+            section.addr = addr;
+            addr += section.size as u64;
+        }
+    }
+
+    Ok(sections)
+}
+
+/// The first address past the loaded sections, rounded up to a page
+fn end_of(sections: &[Section]) -> u64 {
+    let end = sections.iter().filter(|x| x.flags & SHF_ALLOC != 0).map(|x| x.addr + x.size as u64).max().unwrap_or(0);
+    end.next_multiple_of(0x1000)
+}
+
+struct Symbol {
+    name: u32,
+    info: u8,
+    shndx: u16,
+    value: u64,
+    size: u64
+}
+
+/// Symbol i of symtab
+fn symbol(reader: &Reader, symtab: &Section, i: usize) -> Result<Symbol, OfileErr> {
+    let entsize = if symtab.entsize != 0 { symtab.entsize } else if reader.is_64bit { 24 } else { 16 };
+    if i >= symtab.size / entsize {
+        return Err(OfileErr::Invalid)
+    }
+
+    let base = symtab.offset + i * entsize;
+    Ok(if reader.is_64bit {
+        Symbol { name: reader.u32(base)?, info: reader.u8(base + 4)?, shndx: reader.u16(base + 6)?, value: reader.u64(base + 8)?, size: reader.u64(base + 16)? }
+    } else {
+        Symbol { name: reader.u32(base)?, info: reader.u8(base + 12)?, shndx: reader.u16(base + 14)?, value: reader.u32(base + 4)? as u64, size: reader.u32(base + 8)? as u64 }
+    })
+}
+
+/// Every symbol of symtab
+fn entries<'r>(reader: &'r Reader, symtab: &'r Section) -> impl Iterator<Item = Result<Symbol, OfileErr>> + 'r {
+    let entsize = if symtab.entsize != 0 { symtab.entsize } else if reader.is_64bit { 24 } else { 16 };
+    (0..symtab.size / entsize).map(|i| symbol(reader, symtab, i))
+}
+
+fn read_symbols(reader: &Reader, sections: &[Section], symtab: &Section, is_rel: bool, symbols: &mut Vec<FuncSymbol>) -> Result<(), OfileErr> {
+    let Some(strtab) = sections.get(symtab.link as usize) else {
+        return Err(OfileErr::Invalid)
+    };
+
+    for symbol in entries(reader, symtab) {
+        let Symbol { name, info, shndx, value, size } = symbol?;
+        if info & 0xf != STT_FUNC {
+            continue
+        }
+
+        let Some(section) = sections.get(shndx as usize) else {
+            continue
+        };
+
+        if !section.is_code() {
+            continue
+        }
+
+        // Symbol values in relocatable objects are section relative
+        let addr = if is_rel { section.addr + value } else { value };
+
+        if !section.contains(addr) || symbols.iter().any(|x| x.addr == addr) {
+            continue
+        }
+
+        let name = reader.str(strtab.offset + name as usize)?;
+        symbols.push(FuncSymbol {
+            name: if name.is_empty() { None } else { Some(name.to_string()) },
+            addr,
+            size,
+            section: shndx as usize
+        });
+    }
+
+    Ok(())
+}
+
+/// Adds the variables named in symtab to the image
+fn read_data_symbols(reader: &Reader, sections: &[Section], symtab: &Section, is_rel: bool, image: &mut Image) -> Result<(), OfileErr> {
+    let Some(strtab) = sections.get(symtab.link as usize) else {
+        return Err(OfileErr::Invalid)
+    };
+
+    for symbol in entries(reader, symtab) {
+        let Symbol { name, info, shndx, value, size } = symbol?;
+        if info & 0xf != STT_OBJECT {
+            continue
+        }
+
+        let Some(section) = sections.get(shndx as usize).filter(|x| x.flags & SHF_ALLOC != 0) else {
+            continue
+        };
+
+        let addr = if is_rel { section.addr + value } else { value };
+        let name = reader.str(strtab.offset + name as usize)?;
+        if image.symbols().iter().all(|x| x.addr != addr) {
+            image.add_symbol(name, addr, size);
+        }
+    }
+
+    Ok(())
+}
+
+/// A symbol a relocatable object uses but doesn't define. It is given a made up address past the end of the file,
+/// so references to it can be named
+struct Import<'a> {
+    name: &'a str,
+    index: usize,
+    addr: u64,
+    is_func: bool
+}
+
+struct Rela {
+    offset: u64,
+    sym: usize,
+    ty: u32,
+    addend: i64
+}
+
+fn reader(buf: &[u8]) -> Result<Reader<'_>, OfileErr> {
+    Ok(Reader {
+        buf,
+        is_64bit: match buf.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(OfileErr::Invalid)
+        },
+        is_bigend: match buf.get(5) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(OfileErr::Invalid)
+        }
+    })
+}
+
+fn arch(reader: &Reader) -> Result<Option<ElfArch>, OfileErr> {
+    Ok(match reader.u16(0x12)? {
+        EM_AARCH64 => Some(ElfArch::Arm64),
+        EM_X86_64 => Some(ElfArch::X8664),
+        _ => None
+    })
+}
+
+fn read_relas(reader: &Reader, rela: &Section) -> Result<Vec<Rela>, OfileErr> {
+    let entsize = if rela.entsize != 0 { rela.entsize } else if reader.is_64bit { 24 } else { 12 };
+
+    let mut relas = Vec::new();
+    for i in 0..rela.size / entsize {
+        let base = rela.offset + i * entsize;
+        relas.push(if reader.is_64bit {
+            let info = reader.u64(base + 8)?;
+            Rela { offset: reader.u64(base)?, sym: (info >> 32) as usize, ty: info as u32, addend: reader.u64(base + 16)? as i64 }
+        } else {
+            let info = reader.u32(base + 4)?;
+            Rela { offset: reader.u32(base)? as u64, sym: (info >> 8) as usize, ty: info & 0xff, addend: reader.u32(base + 8)? as i32 as i64 }
+        });
+    }
+
+    Ok(relas)
+}
+
+/// The relocation sections for sections that are loaded. Those for debug info and the like don't matter
+fn relocations<'s, 'a>(sections: &'s [Section<'a>]) -> impl Iterator<Item = &'s Section<'a>> {
+    sections.iter().filter(|x| {
+        (x.ty == SHT_RELA || x.ty == SHT_REL) && sections.get(x.info as usize).is_some_and(|target| target.flags & SHF_ALLOC != 0)
+    })
+}
+
+/// The two bytes before the field of an x86-64 relocation at offset, which are the opcode and ModRM byte of
+/// instructions with a RIP relative operand
+fn x86_opcode(buf: &[u8], offset: usize) -> Option<[u8; 2]> {
+    buf.get(offset.checked_sub(2)?..offset)?.try_into().ok()
+}
+
+/// Whether a relocation of type ty with its field at offset is for a call, including x86-64 calls through the GOT
+fn is_call(buf: &[u8], arch: &ElfArch, ty: u32, offset: usize) -> bool {
+    match arch {
+        ElfArch::X8664 => ty == R_X86_64_PLT32 || is_got(arch, ty) && is_got_call(buf, offset),
+        ElfArch::Arm64 => ty == R_AARCH64_CALL26 || ty == R_AARCH64_JUMP26
+    }
+}
+
+/// The undefined symbols that relocations refer to, which are functions if they are called
+fn imports<'a>(reader: &Reader<'a>, sections: &[Section], arch: &ElfArch) -> Result<Vec<Import<'a>>, OfileErr> {
+    let mut imports: Vec<Import> = Vec::new();
+    let mut addr = end_of(sections);
+
+    for rela in relocations(sections).filter(|x| x.ty == SHT_RELA) {
+        let target = &sections[rela.info as usize];
+        let symtab = sections.get(rela.link as usize).ok_or(OfileErr::Invalid)?;
+        let strtab = sections.get(symtab.link as usize).ok_or(OfileErr::Invalid)?;
+
+        for reloc in read_relas(reader, rela)? {
+            let Symbol { name, shndx, .. } = symbol(reader, symtab, reloc.sym)?;
+            if reloc.sym == 0 || shndx != SHN_UNDEF {
+                continue
+            }
+
+            let is_func = is_call(reader.buf, arch, reloc.ty, target.offset + reloc.offset as usize);
+            match imports.iter_mut().find(|x| x.index == reloc.sym) {
+                Some(import) => import.is_func |= is_func,
+                None => {
+                    imports.push(Import { name: reader.str(strtab.offset + name as usize)?, index: reloc.sym, addr, is_func });
+                    addr += 8;
+                }
+            }
+        }
+    }
+
+    Ok(imports)
+}
+
+/// The address that symbol sym of symtab resolves to
+fn symbol_addr(reader: &Reader, sections: &[Section], symtab: &Section, imports: &[Import], sym: usize) -> Result<u64, OfileErr> {
+    let symbol = symbol(reader, symtab, sym)?;
+    Ok(match symbol.shndx {
+        _ if sym == 0 => 0,
+        SHN_UNDEF => imports.iter().find(|x| x.index == sym).map_or(0, |x| x.addr),
+        SHN_ABS => symbol.value,
+        shndx => sections.get(shndx as usize).ok_or(OfileErr::Invalid)?.addr + symbol.value
+    })
+}
+
+fn is_got(arch: &ElfArch, ty: u32) -> bool {
+    matches!((arch, ty), (ElfArch::X8664, R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX))
+}
+
+/// Whether the x86-64 instruction with its displacement at offset is a CALL or JMP through a GOT entry. code_from sees
+/// them after apply has made them direct, so those forms are recognised too
+fn is_got_call(buf: &[u8], offset: usize) -> bool {
+    matches!(x86_opcode(buf, offset), Some([0xff, 0x15 | 0x25] | [0x67, 0xe8] | [0xe9, _]))
+}
+
+/// Whether the x86-64 instruction with its displacement at offset can be changed to use a symbol directly rather than
+/// through its GOT entry, as linkers do: a MOV from a RIP relative address becomes a LEA of it, and a CALL or JMP
+/// through one becomes a direct one
+fn is_relaxable(buf: &[u8], offset: usize) -> bool {
+    match x86_opcode(buf, offset) {
+        Some([0x8b | 0x8d, modrm]) => modrm & 0xc7 == 0x05,
+        _ => is_got_call(buf, offset)
+    }
+}
+
+/// An entry of the made up GOT that GOT relocations which can't be relaxed are resolved to
+struct GotEntry {
+    sym: usize,
+    addr: u64,
+    value: u64
+}
+
+/// The GOT entries that relocations need, which are placed after the imports
+fn got(reader: &Reader, sections: &[Section], arch: &ElfArch, imports: &[Import]) -> Result<Vec<GotEntry>, OfileErr> {
+    let mut got: Vec<GotEntry> = Vec::new();
+    let mut addr = imports.last().map_or(end_of(sections), |x| x.addr + 8);
+
+    for rela in relocations(sections).filter(|x| x.ty == SHT_RELA) {
+        let target = &sections[rela.info as usize];
+        let symtab = sections.get(rela.link as usize).ok_or(OfileErr::Invalid)?;
+
+        for reloc in read_relas(reader, rela)? {
+            if !is_got(arch, reloc.ty) || is_relaxable(reader.buf, target.offset + reloc.offset as usize) || got.iter().any(|x| x.sym == reloc.sym) {
+                continue
+            }
+
+            got.push(GotEntry { sym: reloc.sym, addr, value: symbol_addr(reader, sections, symtab, imports, reloc.sym)? });
+            addr += 8;
+        }
+    }
+
+    Ok(got)
+}
+
+/// Writes the low len bytes of value at offset
+fn write(reader: &Reader, buf: &mut [u8], offset: usize, value: u64, len: usize) -> Result<(), OfileErr> {
+    let field = buf.get_mut(offset..offset + len).ok_or(OfileErr::Invalid)?;
+    if reader.is_bigend {
+        field.copy_from_slice(&value.to_be_bytes()[8 - len..]);
+    } else {
+        field.copy_from_slice(&value.to_le_bytes()[..len]);
+    }
+    Ok(())
+}
+
+/// Replaces the bits of mask in the instruction at offset with those of bits. Instructions are always little endian
+fn patch_insn(buf: &mut [u8], offset: usize, mask: u32, bits: u64) -> Result<(), OfileErr> {
+    let field: &mut [u8; 4] = buf.get_mut(offset..offset + 4).and_then(|x| x.try_into().ok()).ok_or(OfileErr::Invalid)?;
+    let insn = (u32::from_le_bytes(*field) & !mask) | (bits as u32 & mask);
+    *field = insn.to_le_bytes();
+    Ok(())
+}
+
+/// Applies a relocation of type ty to the field at offset, for the value S + A at the place P. GOT relocations change
+/// the instruction to use the symbol itself
+fn apply(reader: &Reader, buf: &mut [u8], arch: &ElfArch, ty: u32, offset: usize, value: u64, place: u64) -> Result<(), OfileErr> {
+    let lo12 = |shift: u32| ((value & 0xfff) >> shift) << 10;
+    match (arch, ty) {
+        (ElfArch::X8664, R_X86_64_64) | (ElfArch::Arm64, R_AARCH64_ABS64) => write(reader, buf, offset, value, 8),
+        (ElfArch::X8664, R_X86_64_32 | R_X86_64_32S) => write(reader, buf, offset, value, 4),
+        (ElfArch::X8664, R_X86_64_PC32 | R_X86_64_PLT32) | (ElfArch::Arm64, R_AARCH64_PREL32) => write(reader, buf, offset, value.wrapping_sub(place), 4),
+        (ElfArch::X8664, R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX) => match x86_opcode(buf, offset) {
+            // jmp [rip + sym@GOTPCREL] becomes jmp sym; nop, which moves the displacement a byte earlier
+            Some([0xff, 0x25]) => {
+                buf[offset - 2] = 0xe9;
+                buf[offset + 3] = 0x90;
+                write(reader, buf, offset - 1, value.wrapping_sub(place).wrapping_add(1), 4)
+            }
+            // call [rip + sym@GOTPCREL] becomes addr32 call sym
+            Some([0xff, 0x15]) => {
+                buf[offset - 2] = 0x67;
+                buf[offset - 1] = 0xe8;
+                write(reader, buf, offset, value.wrapping_sub(place), 4)
+            }
+            // mov reg, [rip + sym@GOTPCREL] becomes lea reg, [rip + sym]
+            Some([0x8b, _]) => {
+                buf[offset - 2] = 0x8d;
+                write(reader, buf, offset, value.wrapping_sub(place), 4)
+            }
+            _ => Err(OfileErr::Invalid)
+        }
+        (ElfArch::Arm64, R_AARCH64_CALL26 | R_AARCH64_JUMP26) => patch_insn(buf, offset, 0x03ffffff, value.wrapping_sub(place) >> 2),
+        (ElfArch::Arm64, R_AARCH64_ADR_PREL_PG_HI21 | R_AARCH64_ADR_GOT_PAGE) => {
+            let pages = (value & !0xfff).wrapping_sub(place & !0xfff) >> 12;
+            patch_insn(buf, offset, 0x60ffffe0, ((pages & 3) << 29) | (((pages >> 2) & 0x7ffff) << 5))
+        }
+        (ElfArch::Arm64, R_AARCH64_ADD_ABS_LO12_NC | R_AARCH64_LDST8_ABS_LO12_NC) => patch_insn(buf, offset, 0x003ffc00, lo12(0)),
+        (ElfArch::Arm64, R_AARCH64_LDST16_ABS_LO12_NC) => patch_insn(buf, offset, 0x003ffc00, lo12(1)),
+        (ElfArch::Arm64, R_AARCH64_LDST32_ABS_LO12_NC) => patch_insn(buf, offset, 0x003ffc00, lo12(2)),
+        (ElfArch::Arm64, R_AARCH64_LDST64_ABS_LO12_NC) => patch_insn(buf, offset, 0x003ffc00, lo12(3)),
+        (ElfArch::Arm64, R_AARCH64_LDST128_ABS_LO12_NC) => patch_insn(buf, offset, 0x003ffc00, lo12(4)),
+        (ElfArch::Arm64, R_AARCH64_LD64_GOT_LO12_NC) => {
+            // ldr xd, [xn, :got_lo12:sym] becomes add xd, xn, :lo12:sym, since the page was made that of sym itself
+            let insn = buf.get(offset..offset + 4).ok_or(OfileErr::Invalid)?;
+            if u32::from_le_bytes(insn.try_into().unwrap()) & 0xffc00000 != 0xf9400000 {
+                return Err(OfileErr::Unsupported("GOT relocations of instructions other than LDR".to_string()))
+            }
+            patch_insn(buf, offset, 0xffc00000 | 0x003ffc00, 0x91000000 | lo12(0))
+        }
+        _ => Err(OfileErr::Unsupported(format!("relocation type {ty}")))
+    }
+}
+
+/// A copy of buf with the relocations of a relocatable object applied, or None if it isn't one. Sections are relocated
+/// to the addresses read_sections lays them out at
+pub fn relocated(buf: &[u8]) -> Result<Option<Vec<u8>>, OfileErr> {
+    if !buf.starts_with(b"\x7fELF") {
+        return Ok(None)
+    }
+
+    let reader = reader(buf)?;
+    if reader.u16(0x10)? != ET_REL {
+        return Ok(None)
+    }
+
+    let Some(arch) = arch(&reader)? else {
+        return Ok(None)
+    };
+
+    let sections = read_sections(&reader, true)?;
+    let imports = imports(&reader, &sections, &arch)?;
+    let got = got(&reader, &sections, &arch, &imports)?;
+
+    let mut relocated = buf.to_vec();
+    for rela in relocations(&sections) {
+        if rela.ty == SHT_REL {
+            return Err(OfileErr::Unsupported("relocations without addends".to_string()))
+        }
+
+        let target = &sections[rela.info as usize];
+        if target.ty == SHT_NOBITS {
+            continue
+        }
+
+        let symtab = sections.get(rela.link as usize).ok_or(OfileErr::Invalid)?;
+        for reloc in read_relas(&reader, rela)? {
+            if reloc.offset >= target.size as u64 {
+                return Err(OfileErr::Invalid)
+            }
+
+            let offset = target.offset + reloc.offset as usize;
+            let value = symbol_addr(&reader, &sections, symtab, &imports, reloc.sym)?.wrapping_add_signed(reloc.addend);
+
+            // A GOT relocation that can't be relaxed is a PC relative reference to the GOT entry
+            let (ty, value) = match got.iter().find(|x| x.sym == reloc.sym && is_got(&arch, reloc.ty) && !is_relaxable(buf, offset)) {
+                Some(entry) => (R_X86_64_PC32, entry.addr.wrapping_add_signed(reloc.addend)),
+                None => (reloc.ty, value)
+            };
+            apply(&reader, &mut relocated, &arch, ty, offset, value, target.addr + reloc.offset)?;
+        }
+    }
+
+    Ok(Some(relocated))
+}
+
+pub fn code_from(buf: &[u8]) -> Result<(CodeResult<'_>, Image, Option<ElfArch>), OfileErr> {
+    if !buf.starts_with(b"\x7fELF") {
+        return Err(OfileErr::UnknownFormat)
+    }
+
+    let reader = reader(buf)?;
+    let is_rel = reader.u16(0x10)? == ET_REL;
+    let arch = arch(&reader)?;
+
+    let sections = read_sections(&reader, is_rel)?;
+    if !sections.iter().any(Section::is_code) {
+        return Err(OfileErr::NoCode)
+    }
+
+    let mut image = Image::default();
+    for section in sections.iter().filter(|x| x.flags & SHF_ALLOC != 0 && x.ty != SHT_NOBITS) {
+        image.add_section(section.addr, &buf[section.offset..section.offset + section.size]);
+    }
+
+    // .symtab first, since it has the most complete naming, then fill in the gaps with .dynsym
+    let mut symbols = Vec::new();
+    for ty in [SHT_SYMTAB, SHT_DYNSYM] {
+        for symtab in sections.iter().filter(|x| x.ty == ty) {
+            read_symbols(&reader, &sections, symtab, is_rel, &mut symbols)?;
+            read_data_symbols(&reader, &sections, symtab, is_rel, &mut image)?;
+        }
+    }
+
+    if symbols.is_empty() {
+        let section = sections.iter().find(|x| x.is_code() && x.name == ".text")
+            .or_else(|| sections.iter().find(|x| x.is_code()))
+            .unwrap();
+
+        return Ok((CodeResult::UnknownBlock(&buf[section.offset..section.offset + section.size], section.addr), image, arch))
+    }
+
+    symbols.sort_by_key(|x| x.addr);
+
+    let mut functions = Vec::new();
+    for (i, symbol) in symbols.iter().enumerate() {
+        let section = &sections[symbol.section];
+        let section_end = section.addr + section.size as u64;
+
+        // Without a size, assume the function runs until the next one (or the end of the section)
+        let mut end = match symbols.get(i + 1) {
+            Some(next) if next.section == symbol.section => next.addr,
+            _ => section_end
+        };
+        if symbol.size != 0 {
+            end = end.min(symbol.addr + symbol.size);
+        }
+
+        let start = section.offset + (symbol.addr - section.addr) as usize;
+        let end = section.offset + (end - section.addr) as usize;
+        functions.push((symbol.name.clone(), &buf[start..end], symbol.addr));
+    }
+
+    // What relocatable objects use from elsewhere has no code here, but is still named
+    if is_rel && let Some(arch) = &arch {
+        let imports = imports(&reader, &sections, arch)?;
+
+        let got = got(&reader, &sections, arch, &imports)?;
+        if let Some(first) = got.first() {
+            let entries: Vec<u8> = got.iter()
+                .flat_map(|x| if reader.is_bigend { x.value.to_be_bytes() } else { x.value.to_le_bytes() })
+                .collect();
+            image.add_section(first.addr, &entries);
+        }
+
+        // PC relative fields on x86-64 are relative to their own end, so their addends take off the 4 bytes of the field
+        for rela in relocations(&sections).filter(|x| x.ty == SHT_RELA) {
+            let symtab = sections.get(rela.link as usize).ok_or(OfileErr::Invalid)?;
+            for reloc in read_relas(&reader, rela)? {
+                let bias = match (arch, reloc.ty) {
+                    (ElfArch::X8664, R_X86_64_PC32 | R_X86_64_PLT32) => 4,
+                    _ => 0
+                };
+                let addr = symbol_addr(&reader, &sections, symtab, &imports, reloc.sym)?.wrapping_add_signed(reloc.addend + bias);
+                image.add_target(addr);
+            }
+        }
+
+        for import in imports {
+            if import.is_func {
+                functions.push((Some(import.name.to_string()), &[], import.addr));
+            } else {
+                image.add_symbol(import.name, import.addr, 0);
+            }
+        }
+    }
+
+    Ok((CodeResult::Functions(functions), image, arch))
+}
@@ -2,11 +2,14 @@ use std::io::{Cursor, Seek, SeekFrom};
 
 use mach_object::{OFile, MachCommand, LoadCommand, SymbolIter, Symbol};
 
+use super::Image;
+
 #[derive(Debug)]
 pub enum OfileErr {
     UnknownFormat,
     NoCode,
-    Invalid
+    Invalid,
+    Unsupported(String)
 }
 
 #[derive(Debug)]
@@ -20,11 +23,12 @@ pub enum MachoArch {
     Arm64
 }
 
-pub fn code_from(buf: &[u8]) -> Result<(CodeResult, Option<MachoArch>), OfileErr> {
+pub fn code_from(buf: &[u8]) -> Result<(CodeResult, Image, Option<MachoArch>), OfileErr> {
     let mut cursor = Cursor::new(buf);
 
     let mut code = None;
     let mut symbols = None;
+    let mut image = Image::default();
 
     fn extract_header_commands(ofile: OFile) -> Result<(mach_object::MachHeader, Vec<mach_object::MachCommand>), OfileErr> {
         match ofile {
@@ -65,6 +69,15 @@ pub fn code_from(buf: &[u8]) -> Result<(CodeResult, Option<MachoArch>), OfileErr
     };
 
     for MachCommand(cmd, ..) in &commands {
+        if let LoadCommand::Segment { sections, .. } | LoadCommand::Segment64 { sections, .. } = cmd {
+            // Zero filled sections have no contents in the file
+            for section in sections.iter().filter(|x| x.offset != 0) {
+                if let Some(data) = buf.get(section.offset as usize..section.offset as usize + section.size) {
+                    image.add_section(section.addr as u64, data);
+                }
+            }
+        }
+
         if let &LoadCommand::Segment64 { segname, sections, .. } = &cmd {
             if segname != "__TEXT" {
                 continue
@@ -102,12 +115,24 @@ pub fn code_from(buf: &[u8]) -> Result<(CodeResult, Option<MachoArch>), OfileErr
                     header.is_bigend(),
                     header.is_64bit(),
                 );
+                let mut data = vec![];
                 symbols = Some(iter.filter_map(|x| match x {
                     Symbol::Defined { name, section: Some(section), entry, .. } if section.sectname == "__text" => Some({
                         (name.map(str::to_string), entry)
                     }),
+                    Symbol::Defined { name: Some(name), section: Some(section), entry, .. } if section.sectname != "__stubs" => {
+                        data.push((name, entry as u64, section.addr as u64 + section.size as u64));
+                        None
+                    }
                     _ => None
                 }).collect::<Vec<_>>());
+
+                // Symbols have no size, so each is taken to run until the next one or the end of its section
+                data.sort_by_key(|(_, addr, _)| *addr);
+                for (i, (name, addr, section_end)) in data.iter().enumerate() {
+                    let end = data.get(i + 1).map_or(*section_end, |(_, next, _)| (*next).min(*section_end));
+                    image.add_symbol(name, *addr, end.saturating_sub(*addr));
+                }
             }
         }
     }
@@ -119,7 +144,7 @@ pub fn code_from(buf: &[u8]) -> Result<(CodeResult, Option<MachoArch>), OfileErr
     let code = &buf[code];
 
     let Some(mut syms) = symbols else {
-        return Ok((CodeResult::UnknownBlock(code, code_vaddr as u64), arch))
+        return Ok((CodeResult::UnknownBlock(code, code_vaddr as u64), image, arch))
     };
 
     syms.sort_by_key(|(_, x)| *x);
@@ -132,5 +157,5 @@ pub fn code_from(buf: &[u8]) -> Result<(CodeResult, Option<MachoArch>), OfileErr
         }
     }
 
-    Ok((CodeResult::Functions(functions), arch))
+    Ok((CodeResult::Functions(functions), image, arch))
 }
@@ -1,25 +1,117 @@
 use std::collections::HashMap;
 
-use crate::{dataflow::Abi, expr, armv8, lir_to_lirnodes, gen_local_cfg, wasm, lir, cfg::ControlFlowGraph};
+use crate::{dataflow::Abi, expr, ty, armv8, x86_64, lir_to_lirnodes, gen_local_cfg, wasm, lir, cfg::ControlFlowGraph};
 
+pub mod elf;
 pub mod macho;
 pub mod wasmmod;
 
 pub struct FunctionDecl {
     pub name: Option<String>,
     pub args: Vec<&'static str>,
-    pub funcid: expr::FuncId
+    /// The types of args, where the format gives them as wasm does. Results are then exact too
+    pub params: Option<Vec<ty::Ty>>,
+    pub results: Vec<ty::Ty>,
+    pub funcid: expr::FuncId,
+    /// Names from debug info for names in the lifted code, applied once the code is otherwise finished
+    pub local_names: HashMap<String, String>
+}
+
+impl FunctionDecl {
+    /// The name to show for name, which the lifted code calls it
+    pub fn local_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.local_names.get(name).map_or(name, String::as_str)
+    }
+}
+
+/// A variable outside of any function, which code refers to by name
+pub struct GlobalDecl {
+    pub name: String,
+    pub ty: ty::Ty,
+    /// Where it is in the image, for globals in memory rather than wasm ones
+    pub addr: Option<u64>,
+    /// How many of ty it holds, for globals which are arrays
+    pub len: Option<usize>
+}
+
+impl GlobalDecl {
+    /// The bytes it takes up in the image
+    pub fn byte_count(&self) -> u64 {
+        (self.ty.size().map_or(1, |x| x.byte_count()) * self.len.unwrap_or(1)) as u64
+    }
 }
 
 pub struct FunctionDef {
     pub funcid: expr::FuncId,
     pub local_cfg: ControlFlowGraph,
     pub local_lirnodes: Vec<lir::LirNode>,
+    /// Names to treat like globals, which dataflow mustn't propagate or drop writes to
+    pub pinned: Vec<String>
+}
+
+/// The loaded contents of a binary, for reading data the code refers to by address
+#[derive(Default)]
+pub struct Image {
+    sections: Vec<(u64, Vec<u8>)>,
+    symbols: Vec<DataSymbol>,
+    /// Addresses relocations point at
+    targets: Vec<u64>
+}
+
+/// A named piece of data in the image, such as a global variable
+pub struct DataSymbol {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64
+}
+
+impl Image {
+    pub fn add_section(&mut self, addr: u64, data: &[u8]) {
+        self.sections.push((addr, data.to_vec()));
+    }
+
+    pub fn add_symbol(&mut self, name: &str, addr: u64, size: u64) {
+        self.symbols.push(DataSymbol { name: name.to_string(), addr, size });
+    }
+
+    pub fn symbols(&self) -> &[DataSymbol] {
+        &self.symbols
+    }
+
+    pub fn add_target(&mut self, addr: u64) {
+        self.targets.push(addr);
+    }
+
+    /// Whether something says addr is an address, either a symbol there or a relocation pointing at it
+    pub fn is_target(&self, addr: u64) -> bool {
+        self.targets.contains(&addr) || self.symbols.iter().any(|x| x.addr == addr)
+    }
+
+    /// The len bytes at addr, if they are all within one section
+    pub fn read(&self, addr: u64, len: usize) -> Option<&[u8]> {
+        self.sections.iter().find_map(|(start, data)| {
+            let offset = addr.checked_sub(*start)? as usize;
+            data.get(offset..offset.checked_add(len)?)
+        })
+    }
+
+    /// Everything from addr to the end of its section
+    pub fn read_from(&self, addr: u64) -> Option<&[u8]> {
+        self.sections.iter().find_map(|(start, data)| {
+            let offset = addr.checked_sub(*start)? as usize;
+            if offset < data.len() { Some(&data[offset..]) } else { None }
+        })
+    }
 }
 
 pub struct Module {
     pub abi: Abi,
     pub functions: Vec<FunctionDecl>,
+    /// Memory the code can refer to by address, from sections or data segments
+    pub image: Image,
+    pub globals: Vec<GlobalDecl>,
+    /// Functions held by each wasm table, which code calls indirectly by index
+    pub tables: Vec<Vec<Option<expr::FuncId>>>
 }
 
 pub struct FunctionDefSet(Vec<FunctionDef>);
@@ -76,7 +168,8 @@ pub enum DecodeError {
     UnknownFormat,
     UnknownArch,
     NoCode,
-    Invalid
+    Invalid,
+    Unsupported(String)
 }
 
 impl std::fmt::Display for DecodeError {
@@ -86,14 +179,44 @@ impl std::fmt::Display for DecodeError {
             DecodeError::UnknownArch => write!(f, "unrecognised architecture"),
             DecodeError::NoCode => write!(f, "contains no code"),
             DecodeError::Invalid => write!(f, "corrupt file"),
+            DecodeError::Unsupported(what) => write!(f, "uses {what}, which is not supported"),
         }
     }
 }
 
-fn decode_arm64(mut functions: Vec<(Option<String>, &[u8], u64)>) -> Result<(Module, FunctionDefSet), DecodeError> {
+type NativeLifter = fn(&[u8], u64, &HashMap<u64, expr::FuncId>, &Image) -> Result<lir::LirFunc, String>;
+
+/// Whether name could be mistaken for a register or a stack variable in native code
+fn is_native_made_up(name: &str) -> bool {
+    (name.len() <= 3 && name.chars().all(|c| c.is_ascii_lowercase())) ||
+        (name.starts_with(|c: char| c.is_ascii_lowercase()) && name[1..].starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Globals for the data symbols of a binary. Those whose names are missing, repeated or could be mistaken
+/// for something else are left as addresses
+fn native_globals(image: &Image) -> Vec<GlobalDecl> {
+    let mut taken = std::collections::HashSet::new();
+    image.symbols().iter()
+        .filter(|symbol| !symbol.name.is_empty() && !is_native_made_up(&symbol.name) && taken.insert(symbol.name.as_str()))
+        .map(|symbol| {
+            let scalar = ty::Size::from_byte_count(symbol.size);
+            GlobalDecl {
+                name: symbol.name.clone(),
+                ty: ty::Ty::unknown(scalar.unwrap_or(ty::Size::Size8)),
+                addr: Some(symbol.addr),
+                len: if scalar.is_some() || symbol.size == 0 { None } else { Some(symbol.size as usize) }
+            }
+        })
+        .collect()
+}
+
+fn decode_native(mut functions: Vec<(Option<String>, &[u8], u64)>, image: Image, abi: Abi, to_lir: NativeLifter) -> Result<(Module, FunctionDefSet), DecodeError> {
     let mut module = Module {
-        abi: armv8::abi(),
+        abi,
         functions: vec![],
+        globals: native_globals(&image),
+        image,
+        tables: vec![]
     };
     let mut defs = Vec::new();
 
@@ -103,76 +226,171 @@ fn decode_arm64(mut functions: Vec<(Option<String>, &[u8], u64)>) -> Result<(Mod
 
         module.functions.push(FunctionDecl {
             args: vec![],
+            params: None,
+            results: vec![],
             funcid: expr::FuncId(i),
+            local_names: HashMap::new(),
             name: name.take()
         });
     }
 
     for (i, (_, code, addr)) in functions.into_iter().enumerate() {
-        let lir = armv8::to_lir(code, addr, &function_ids).expect("Could not convert to LIR");
+        // Functions from other objects have no code to lift
+        if code.is_empty() {
+            continue
+        }
+
+        let lir = match to_lir(code, addr, &function_ids, &module.image) {
+            Ok(lir) => lir,
+            Err(err) => {
+                eprintln!("Could not translate function {:?}: {err}", module.functions[i].name);
+                continue
+            }
+        };
 
         let lirnodes = lir_to_lirnodes(lir);
         defs.push(FunctionDef {
             funcid: expr::FuncId(i),
             local_cfg: gen_local_cfg(&lirnodes),
-            local_lirnodes: lirnodes
+            local_lirnodes: lirnodes,
+            pinned: vec![]
         });
     }
 
     Ok((module, FunctionDefSet(defs)))
 }
 
-fn decode_macho(code: macho::CodeResult, arch: Option<macho::MachoArch>) -> Result<(Module, FunctionDefSet), DecodeError> {
+fn decode_macho(code: macho::CodeResult, image: Image, arch: Option<macho::MachoArch>) -> Result<(Module, FunctionDefSet), DecodeError> {
     let functions = match code {
         macho::CodeResult::UnknownBlock(unknown, addr) => vec![(None, unknown, addr)],
         macho::CodeResult::Functions(functions) => functions,
     };
 
     match arch {
-        Some(macho::MachoArch::Arm64) => decode_arm64(functions),
-        _ => Err(DecodeError::UnknownArch)
+        Some(macho::MachoArch::Arm64) => decode_native(functions, image, armv8::abi(), armv8::to_lir),
+        Some(macho::MachoArch::X8664) => decode_native(functions, image, x86_64::abi(), x86_64::to_lir),
+        None => Err(DecodeError::UnknownArch)
     }
 }
 
+fn decode_elf(code: macho::CodeResult, image: Image, arch: Option<elf::ElfArch>) -> Result<(Module, FunctionDefSet), DecodeError> {
+    let functions = match code {
+        macho::CodeResult::UnknownBlock(unknown, addr) => vec![(None, unknown, addr)],
+        macho::CodeResult::Functions(functions) => functions,
+    };
+
+    match arch {
+        Some(elf::ElfArch::Arm64) => decode_native(functions, image, armv8::abi(), armv8::to_lir),
+        Some(elf::ElfArch::X8664) => decode_native(functions, image, x86_64::abi(), x86_64::to_lir),
+        None => Err(DecodeError::UnknownArch)
+    }
+}
+
+/// Wasm functions say what they take, so their args needn't be guessed from how registers are used
+fn wasm_args(ty: &wasmparser::FuncType) -> Vec<&'static str> {
+    wasm::abi().args.into_iter().take(ty.params().len()).collect()
+}
+
+fn wasm_params(ty: &wasmparser::FuncType) -> Vec<ty::Ty> {
+    ty.params().iter().cloned().map(wasm::valtype_to_ty).collect()
+}
+
+/// Whether name could be mistaken for one the lifter makes up for a local, stack value or global
+fn is_made_up(name: &str) -> bool {
+    name == wasm::STACK_POINTER || (name.starts_with(['l', 's', 'g']) && name[1..].starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Names from the name section for the locals of func. Any that are repeated, or that could be mistaken
+/// for a name the lifter makes up or a global, are left out
+fn wasm_local_names(func: &wasmmod::Function, globals: &[String]) -> HashMap<String, String> {
+    let mut names = HashMap::new();
+    let mut taken = std::collections::HashSet::new();
+    let mut locals: Vec<_> = func.local_names.iter().collect();
+    locals.sort();
+    for (idx, name) in locals {
+        if !name.is_empty() && !is_made_up(name) && !globals.contains(name) && taken.insert(name) {
+            names.insert(wasm::local_name(*idx), name.clone());
+        }
+    }
+    names
+}
+
+/// Names for the globals of a module, as the lifted code refers to them. The stack pointer gets the name the abi knows it by,
+/// and the rest their own names, unless those are missing, repeated or could be mistaken for something else
+fn wasm_global_names(wmodule: &wasmmod::Module) -> Vec<String> {
+    let mut taken = std::collections::HashSet::new();
+    wmodule.globals().iter().enumerate().map(|(idx, global)| match &global.name {
+        _ if Some(idx) == wmodule.stack_pointer() => wasm::STACK_POINTER.to_string(),
+        Some(name) if !name.is_empty() && !is_made_up(name) && taken.insert(name) => name.clone(),
+        _ => format!("g{idx}")
+    }).collect()
+}
+
 fn decode_wasm(wmodule: wasmmod::Module) -> Result<(Module, FunctionDefSet), DecodeError> {
+    let mut image = Image::default();
+    for segment in wmodule.data_segments() {
+        image.add_section(segment.offset, segment.data);
+    }
+
+    let global_names = wasm_global_names(&wmodule);
     let mut module = Module {
         abi: wasm::abi(),
         functions: vec![],
+        image,
+        globals: wmodule.globals().iter().zip(&global_names)
+            .map(|(global, name)| GlobalDecl { name: name.clone(), ty: wasm::valtype_to_ty(global.ty), addr: None, len: None })
+            .collect(),
+        tables: wmodule.tables().iter()
+            .map(|table| table.iter().map(|func| func.map(expr::FuncId)).collect())
+            .collect()
+    };
+    module.abi.global.extend(global_names.iter().filter(|x| x.as_str() != wasm::STACK_POINTER).cloned());
+
+    let info = wasm::ModuleInfo {
+        func_types: wmodule.types(),
+        raw_types: wmodule.raw_types(),
+        globals: &global_names,
+        tables: wmodule.tables(),
+        tags: wmodule.tags()
     };
     let mut defs = Vec::new();
 
     for import in wmodule.imports() {
         module.functions.push(FunctionDecl {
             name: Some(import.name.clone()),
-            args: vec![],
-            funcid: expr::FuncId(import.idx)
+            args: wasm_args(&wmodule.types()[import.idx]),
+            params: Some(wasm_params(&wmodule.types()[import.idx])),
+            results: wmodule.types()[import.idx].results().iter().cloned().map(wasm::valtype_to_ty).collect(),
+            funcid: expr::FuncId(import.idx),
+            local_names: HashMap::new()
         });
     }
 
     for func in wmodule.functions() {
-        if !func.exported {
-            continue;
-        }
+        module.functions.push(FunctionDecl {
+            name: func.name.clone(),
+            args: wasm_args(&wmodule.types()[func.idx]),
+            params: Some(wasm_params(&wmodule.types()[func.idx])),
+            results: wmodule.types()[func.idx].results().iter().cloned().map(wasm::valtype_to_ty).collect(),
+            funcid: expr::FuncId(func.idx),
+            local_names: wasm_local_names(func, &global_names)
+        });
 
-        let lir = match wasm::to_lir(&func.body, wmodule.types(), wmodule.raw_types()) {
+        let lir = match wasm::to_lir(&func.body, &wmodule.types()[func.idx], &info) {
             Ok(lir) => lir,
             Err(err) => {
-                eprintln!("Could not translate wasm function {:?}: {err}", func.name);
-                return Err(DecodeError::Invalid)
+                eprintln!("Could not translate function {:?}: {err}", func.name);
+                continue
             }
         };
 
-        module.functions.push(FunctionDecl {
-            name: func.name.clone(),
-            args: vec![],
-            funcid: expr::FuncId(func.idx)
-        });
-
+        let pinned = lir.pinned().to_vec();
         let lirnodes = lir_to_lirnodes(lir);
         defs.push(FunctionDef {
             funcid: expr::FuncId(func.idx),
             local_cfg: gen_local_cfg(&lirnodes),
-            local_lirnodes: lirnodes
+            local_lirnodes: lirnodes,
+            pinned
         });
     }
 
@@ -181,10 +399,25 @@ fn decode_wasm(wmodule: wasmmod::Module) -> Result<(Module, FunctionDefSet), Dec
 
 pub fn load_lir_from_binary(buf: &[u8]) -> Result<(Module, FunctionDefSet), DecodeError> {
     match macho::code_from(&buf) {
-        Ok((code, arch)) => return decode_macho(code, arch),
+        Ok((code, image, arch)) => return decode_macho(code, image, arch),
+        Err(macho::OfileErr::NoCode) => return Err(DecodeError::NoCode),
+        Err(macho::OfileErr::UnknownFormat) => {}
+        Err(macho::OfileErr::Invalid) => return Err(DecodeError::Invalid),
+        Err(macho::OfileErr::Unsupported(what)) => return Err(DecodeError::Unsupported(what))
+    }
+
+    let relocated = match elf::relocated(buf) {
+        Ok(relocated) => relocated,
+        Err(macho::OfileErr::Unsupported(what)) => return Err(DecodeError::Unsupported(what)),
+        Err(_) => return Err(DecodeError::Invalid)
+    };
+
+    match elf::code_from(relocated.as_deref().unwrap_or(buf)) {
+        Ok((code, image, arch)) => return decode_elf(code, image, arch),
         Err(macho::OfileErr::NoCode) => return Err(DecodeError::NoCode),
         Err(macho::OfileErr::UnknownFormat) => {}
-        Err(macho::OfileErr::Invalid) => return Err(DecodeError::Invalid)
+        Err(macho::OfileErr::Invalid) => return Err(DecodeError::Invalid),
+        Err(macho::OfileErr::Unsupported(what)) => return Err(DecodeError::Unsupported(what))
     }
 
     match wasmmod::module_from(&buf) {
@@ -11,7 +11,15 @@ pub struct Function<'a> {
     pub body: wasmparser::FunctionBody<'a>,
     pub name: Option<String>,
     pub idx: usize,
-    pub exported: bool
+    pub exported: bool,
+    /// Names of locals by index, from the name section
+    pub local_names: HashMap<usize, String>
+}
+
+/// An active data segment, which is copied into memory at offset when the module is instantiated
+pub struct DataSegment<'a> {
+    pub offset: u64,
+    pub data: &'a [u8]
 }
 
 pub struct Import {
@@ -19,11 +27,22 @@ pub struct Import {
     pub idx: usize
 }
 
+pub struct Global {
+    pub name: Option<String>,
+    pub ty: wasmparser::ValType,
+    pub mutable: bool
+}
+
 pub struct Module<'a> {
     functions: Vec<Function<'a>>,
     types: Vec<wasmparser::FuncType>,
     imports: Vec<Import>,
     raw_types: Vec<wasmparser::FuncType>,
+    data_segments: Vec<DataSegment<'a>>,
+    globals: Vec<Global>,
+    stack_pointer: Option<usize>,
+    tables: Vec<Vec<Option<usize>>>,
+    tags: Vec<wasmparser::FuncType>
 }
 
 impl<'a> Module<'a> {
@@ -42,6 +61,101 @@ impl<'a> Module<'a> {
     pub fn raw_types(&self) -> &[wasmparser::FuncType] {
         &self.raw_types
     }
+
+    pub fn data_segments(&self) -> &[DataSegment<'a>] {
+        &self.data_segments
+    }
+
+    /// Imported globals first, as with functions
+    pub fn globals(&self) -> &[Global] {
+        &self.globals
+    }
+
+    /// The functions each table holds once active element segments are applied, by function index
+    pub fn tables(&self) -> &[Vec<Option<usize>>] {
+        &self.tables
+    }
+
+    /// The index of the global holding the top of the shadow stack, which LLVM keeps locals in memory on
+    pub fn stack_pointer(&self) -> Option<usize> {
+        self.stack_pointer
+    }
+
+    /// The types of the values thrown with each exception tag, imported ones first
+    pub fn tags(&self) -> &[wasmparser::FuncType] {
+        &self.tags
+    }
+}
+
+/// The global a function moves down to make room for its frame, as LLVM's prologue does with `__stack_pointer`:
+/// a `global.get`, a subtraction, then a `global.set` of the same global
+fn prologue_stack_pointer(body: &wasmparser::FunctionBody) -> Option<usize> {
+    const PROLOGUE_LEN: usize = 12;
+
+    let ops = body.get_operators_reader().ok()?.into_iter().take(PROLOGUE_LEN).collect::<Result<Vec<_>, _>>().ok()?;
+    let get = ops.iter().position(|op| matches!(op, wasmparser::Operator::GlobalGet { .. }))?;
+    let wasmparser::Operator::GlobalGet { global_index } = ops[get] else {
+        return None;
+    };
+
+    let sub = get + ops[get..].iter().position(|op| matches!(op, wasmparser::Operator::I32Sub))?;
+    ops[sub..].iter().any(|op| matches!(op, wasmparser::Operator::GlobalSet { global_index: set } if *set == global_index))
+        .then_some(global_index as usize)
+}
+
+/// Puts the functions of an element segment into table from offset on
+fn init_table(table: &mut Vec<Option<usize>>, offset: usize, items: wasmparser::ElementItems) -> wasmparser::Result<()> {
+    let funcs = match items {
+        wasmparser::ElementItems::Functions(reader) => reader.into_iter().map(|x| x.map(|idx| Some(idx as usize))).collect::<Result<Vec<_>, _>>()?,
+        wasmparser::ElementItems::Expressions(_, reader) => reader.into_iter().map(|expr| {
+            Ok(match expr?.get_operators_reader().read()? {
+                wasmparser::Operator::RefFunc { function_index } => Some(function_index as usize),
+                _ => None
+            })
+        }).collect::<Result<Vec<_>, _>>()?
+    };
+
+    if table.len() < offset + funcs.len() {
+        table.resize(offset + funcs.len(), None);
+    }
+    for (i, func) in funcs.into_iter().enumerate() {
+        table[offset + i] = func;
+    }
+
+    Ok(())
+}
+
+fn read_names(reader: wasmparser::CustomSectionReader, names: &mut HashMap<usize, String>, local_names: &mut HashMap<usize, HashMap<usize, String>>, global_names: &mut HashMap<usize, String>) -> wasmparser::Result<()> {
+    for subsection in wasmparser::NameSectionReader::new(wasmparser::BinaryReader::new(reader.data(), reader.data_offset())) {
+        match subsection? {
+            wasmparser::Name::Function(map) => {
+                for naming in map {
+                    let naming = naming?;
+                    names.insert(naming.index as usize, naming.name.to_string());
+                }
+            }
+            wasmparser::Name::Local(map) => {
+                for func in map {
+                    let func = func?;
+                    let locals = local_names.entry(func.index as usize).or_default();
+                    for naming in func.names {
+                        let naming = naming?;
+                        locals.insert(naming.index as usize, naming.name.to_string());
+                    }
+                }
+            }
+            wasmparser::Name::Global(map) => {
+                for naming in map {
+                    let naming = naming?;
+                    global_names.insert(naming.index as usize, naming.name.to_string());
+                }
+            }
+            // Labels name wasm blocks, which are gone once control flow is structured again
+            _ => {}
+        }
+    }
+
+    Ok(())
 }
 
 pub fn module_from(buf: &[u8]) -> Result<Module, WasmDecodeError> {
@@ -53,24 +167,38 @@ pub fn module_from(buf: &[u8]) -> Result<Module, WasmDecodeError> {
         functions: Vec::new(),
         types: Vec::new(),
         imports: Vec::new(),
-        raw_types: Vec::new()
+        raw_types: Vec::new(),
+        data_segments: Vec::new(),
+        globals: Vec::new(),
+        stack_pointer: None,
+        tables: Vec::new(),
+        tags: Vec::new()
     };
     let mut import_count = 0;
     let mut names = HashMap::new();
+    let mut exports = HashMap::new();
+    let mut local_names = HashMap::new();
+    let mut global_names = HashMap::new();
 
     for payload in wasmparser::Parser::new(0).parse_all(&buf) {
         match payload {
             Ok(Payload::CodeSectionEntry(body)) => res.functions.push(Function {
                 body,
-                name: names.get(&(res.functions.len() + import_count)).map(|x| str::to_string(*x)),
+                name: None,
                 idx: res.functions.len() + import_count,
-                exported: names.contains_key(&(res.functions.len() + import_count))
+                exported: exports.contains_key(&(res.functions.len() + import_count)),
+                local_names: HashMap::new()
             }),
             Ok(Payload::TypeSection(reader)) => {
-                for ty in reader {
-                    match ty {
-                        Ok(wasmparser::Type::Func(func)) => res.raw_types.push(func),
-                        Ok(_) => {},
+                for group in reader {
+                    match group {
+                        Ok(group) => {
+                            for ty in group.into_types() {
+                                if let wasmparser::CompositeInnerType::Func(func) = ty.composite_type.inner {
+                                    res.raw_types.push(func);
+                                }
+                            }
+                        }
                         Err(err) => {
                             eprintln!("wasmparser err: {err}");
                             return Err(WasmDecodeError::Invalid)
@@ -79,7 +207,7 @@ pub fn module_from(buf: &[u8]) -> Result<Module, WasmDecodeError> {
                 }
             },
             Ok(Payload::ImportSection(reader)) => {
-                for import in reader {
+                for import in reader.into_imports() {
                     match import {
                         Ok(x) => match x.ty {
                             wasmparser::TypeRef::Func(func) => {
@@ -90,6 +218,16 @@ pub fn module_from(buf: &[u8]) -> Result<Module, WasmDecodeError> {
                                 });
                                 import_count += 1;
                             },
+                            wasmparser::TypeRef::Table(ty) => res.tables.push(vec![None; ty.initial as usize]),
+                            wasmparser::TypeRef::Tag(ty) => match res.raw_types.get(ty.func_type_idx as usize) {
+                                Some(ty) => res.tags.push(ty.clone()),
+                                None => return Err(WasmDecodeError::Invalid)
+                            },
+                            wasmparser::TypeRef::Global(ty) => res.globals.push(Global {
+                                name: Some(x.name.to_string()),
+                                ty: ty.content_type,
+                                mutable: ty.mutable
+                            }),
                             _ => {}
                         },
                         Err(err) => {
@@ -115,7 +253,10 @@ pub fn module_from(buf: &[u8]) -> Result<Module, WasmDecodeError> {
                     match export {
                         Ok(x) => match x.kind {
                             wasmparser::ExternalKind::Func => {
-                                names.insert(x.index as usize, x.name);
+                                exports.insert(x.index as usize, x.name);
+                            },
+                            wasmparser::ExternalKind::Global => {
+                                global_names.entry(x.index as usize).or_insert(x.name.to_string());
                             },
                             _ => {}
                         },
@@ -126,6 +267,92 @@ pub fn module_from(buf: &[u8]) -> Result<Module, WasmDecodeError> {
                     }
                 }
             }
+            Ok(Payload::GlobalSection(reader)) => {
+                for global in reader {
+                    match global {
+                        Ok(x) => res.globals.push(Global {
+                            name: None,
+                            ty: x.ty.content_type,
+                            mutable: x.ty.mutable
+                        }),
+                        Err(err) => {
+                            eprintln!("wasmparser err: {err}");
+                            return Err(WasmDecodeError::Invalid)
+                        }
+                    }
+                }
+            }
+            Ok(Payload::TableSection(reader)) => {
+                for table in reader {
+                    match table {
+                        Ok(x) => res.tables.push(vec![None; x.ty.initial as usize]),
+                        Err(err) => {
+                            eprintln!("wasmparser err: {err}");
+                            return Err(WasmDecodeError::Invalid)
+                        }
+                    }
+                }
+            }
+            Ok(Payload::TagSection(reader)) => {
+                for tag in reader {
+                    match tag.map(|x| res.raw_types.get(x.func_type_idx as usize)) {
+                        Ok(Some(ty)) => res.tags.push(ty.clone()),
+                        Ok(None) => return Err(WasmDecodeError::Invalid),
+                        Err(err) => {
+                            eprintln!("wasmparser err: {err}");
+                            return Err(WasmDecodeError::Invalid)
+                        }
+                    }
+                }
+            }
+            Ok(Payload::ElementSection(reader)) => {
+                for element in reader {
+                    match element {
+                        Ok(wasmparser::Element { kind: wasmparser::ElementKind::Active { table_index, offset_expr }, items, .. }) => {
+                            // As with data, segments placed by a global's value can't be put anywhere yet
+                            let Some(Ok(wasmparser::Operator::I32Const { value })) = offset_expr.get_operators_reader().into_iter().next() else {
+                                continue;
+                            };
+                            let Some(table) = res.tables.get_mut(table_index.unwrap_or(0) as usize) else {
+                                return Err(WasmDecodeError::Invalid)
+                            };
+
+                            if let Err(err) = init_table(table, value as u32 as usize, items) {
+                                eprintln!("wasmparser err: {err}");
+                                return Err(WasmDecodeError::Invalid)
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            eprintln!("wasmparser err: {err}");
+                            return Err(WasmDecodeError::Invalid)
+                        }
+                    }
+                }
+            }
+            Ok(Payload::DataSection(reader)) => {
+                for data in reader {
+                    match data {
+                        Ok(wasmparser::Data { kind: wasmparser::DataKind::Active { offset_expr, .. }, data, .. }) => {
+                            // Segments placed by a global's value can't be put anywhere until the module is instantiated
+                            if let Some(Ok(wasmparser::Operator::I32Const { value })) = offset_expr.get_operators_reader().into_iter().next() {
+                                res.data_segments.push(DataSegment { offset: value as u32 as u64, data });
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            eprintln!("wasmparser err: {err}");
+                            return Err(WasmDecodeError::Invalid)
+                        }
+                    }
+                }
+            }
+            // The name section is only debug info, so a malformed one is ignored rather than failing the module
+            Ok(Payload::CustomSection(reader)) if reader.name() == "name" => {
+                if let Err(err) = read_names(reader, &mut names, &mut local_names, &mut global_names) {
+                    eprintln!("wasmparser err in name section: {err}");
+                }
+            }
             Ok(_) => (),
             Err(err) => {
                 eprintln!("wasmparser err: {err}");
@@ -134,6 +361,27 @@ pub fn module_from(buf: &[u8]) -> Result<Module, WasmDecodeError> {
         }
     }
 
+    // The name section comes after the code, and names functions whether or not they are exported
+    for import in &mut res.imports {
+        if let Some(name) = names.remove(&import.idx) {
+            import.name = name;
+        }
+    }
+    for func in &mut res.functions {
+        func.name = names.remove(&func.idx).or_else(|| exports.get(&func.idx).map(|x| x.to_string()));
+        func.local_names = local_names.remove(&func.idx).unwrap_or_default();
+    }
+    for (idx, global) in res.globals.iter_mut().enumerate() {
+        if let Some(name) = global_names.remove(&idx) {
+            global.name = Some(name);
+        }
+    }
+
+    // Without names, the stack pointer is the global that function prologues allocate their frames from
+    let is_stack_pointer = |idx: usize| res.globals.get(idx).is_some_and(|x| x.mutable && x.ty == wasmparser::ValType::I32);
+    res.stack_pointer = res.globals.iter().position(|x| x.name.as_deref() == Some("__stack_pointer"))
+        .or_else(|| res.functions.iter().filter_map(|func| prologue_stack_pointer(&func.body)).find(|idx| is_stack_pointer(*idx)));
+
     // assert_eq!(res.types.len(), res.functions.len());
 
     Ok(res)
@@ -59,10 +59,18 @@ pub fn compress_control_flow(block: &mut MirFunc) {
 
 struct UnreachableControlFlow;
 
+/// Whether nothing after stmt can run, counting trys which jump away at the end of their body and every handler
+fn ends_flow(stmt: &Mir) -> bool {
+    match stmt {
+        Mir::Try { body, handlers } => body.last().is_some_and(Mir::terminating) && handlers.iter().all(|(_, code)| code.last().is_some_and(Mir::terminating)),
+        _ => stmt.terminating()
+    }
+}
+
 impl MirVisitorMut for UnreachableControlFlow {
     fn pre_block_visit(&mut self, code: &mut Vec<Mir>) {
         for (s, stmt) in code.iter_mut().enumerate() {
-            if stmt.terminating() {
+            if ends_flow(stmt) {
                 code.drain(s + 1..);
                 break;
             }
@@ -84,7 +92,7 @@ fn cull_fallthrough_jumps_with_end_scope(code: &mut Vec<Mir>, end: Option<&HashS
     let mut i = 0;
     while i < code.len() {
         match &mut code[i] {
-            Mir::If { .. } => {
+            Mir::If { .. } | Mir::Switch { .. } | Mir::Try { .. } => {
                 let mut new =
                     if i == code.len() - 1 {
                         end.map_or_else(HashSet::new, HashSet::clone)
@@ -98,12 +106,25 @@ fn cull_fallthrough_jumps_with_end_scope(code: &mut Vec<Mir>, end: Option<&HashS
                     j += 1;
                 }
         
-                let Mir::If { true_then, false_then, .. } = &mut code[i] else {
-                    unreachable!()
-                };
-                
-                cull_fallthrough_jumps_with_end_scope(true_then, Some(&new));
-                cull_fallthrough_jumps_with_end_scope(false_then, Some(&new));
+                match &mut code[i] {
+                    Mir::If { true_then, false_then, .. } => {
+                        cull_fallthrough_jumps_with_end_scope(true_then, Some(&new));
+                        cull_fallthrough_jumps_with_end_scope(false_then, Some(&new));
+                    }
+                    Mir::Switch { cases, default, .. } => {
+                        for (_, code) in cases {
+                            cull_fallthrough_jumps_with_end_scope(code, Some(&new));
+                        }
+                        cull_fallthrough_jumps_with_end_scope(default, Some(&new));
+                    }
+                    Mir::Try { body, handlers } => {
+                        cull_fallthrough_jumps_with_end_scope(body, Some(&new));
+                        for (_, code) in handlers {
+                            cull_fallthrough_jumps_with_end_scope(code, Some(&new));
+                        }
+                    }
+                    _ => unreachable!()
+                }
             }
             Mir::Loop { code } | Mir::While { code, .. } => {
                 cull_fallthrough_jumps_with_end_scope(code, None);
@@ -1,12 +1,21 @@
-use crate::{expr::{self, UnaryOp}, mir::{MirVisitorMut, self}, lir};
+use crate::{expr::{self, UnaryOp}, mir::{MirVisitorMut, self}, lir, ty};
 
 fn collapse_cmp_in(sexpr: &mut expr::Expr) {
     match sexpr {
-        expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {},
+        expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {},
         expr::Expr::Unary { expr, op } if op.is_cmp() => {
-            if let expr::Expr::Binary { op: expr::BinaryOp::Cmp, lhs, rhs } = expr.as_mut() {
+            if let expr::Expr::Binary { op: expr::BinaryOp::FCmp, lhs, rhs } = expr.as_mut() {
                 collapse_cmp_in(lhs);
                 collapse_cmp_in(rhs);
+                *sexpr = op.float_cmp(lhs.clone(), rhs.clone());
+            } else if let expr::Expr::Binary { op: expr::BinaryOp::Cmp, lhs, rhs } = expr.as_mut() {
+                collapse_cmp_in(lhs);
+                collapse_cmp_in(rhs);
+                // The sign of the difference is tested against zero
+                if matches!(op, UnaryOp::CmpMi | UnaryOp::CmpPl) && **rhs != expr::Expr::Num(0) {
+                    **lhs = expr::Expr::Binary { op: expr::BinaryOp::Sub, lhs: lhs.clone(), rhs: rhs.clone() };
+                    **rhs = expr::Expr::Num(0);
+                }
                 *sexpr = expr::Expr::Binary { op: op.cmp_op_to_binaryop(), lhs: lhs.clone(), rhs: rhs.clone() };
             } else {
                 collapse_cmp_in(expr);
@@ -19,6 +28,7 @@ fn collapse_cmp_in(sexpr: &mut expr::Expr) {
         }
         expr::Expr::Deref { ptr, .. } => collapse_cmp_in(ptr),
         expr::Expr::Ref(value) => collapse_cmp_in(value),
+        expr::Expr::Table { index, .. } => collapse_cmp_in(index),
         expr::Expr::Call { func, args } => {
             collapse_cmp_in(func);
             for arg in args {
@@ -43,11 +53,17 @@ pub fn collapse_cmp(code: &mut mir::MirFunc) {
 fn reduce_binops_in(sexpr: &mut expr::Expr) {
     use expr::{Expr::*, BinaryOp::*};
     match sexpr {
-        expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {},
+        expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {},
         expr::Expr::Unary { expr, op: UnaryOp::Not } => {
             reduce_binops_in(expr.as_mut());
             *sexpr = expr.neg();
         }
+        expr::Expr::Unary { expr, op: UnaryOp::Neg } => {
+            reduce_binops_in(expr.as_mut());
+            if let Num(n) = expr.as_ref() {
+                *sexpr = Num(n.wrapping_neg());
+            }
+        }
         expr::Expr::Unary { expr, .. } => {
             reduce_binops_in(expr.as_mut());
         }
@@ -103,25 +119,57 @@ fn reduce_binops_in(sexpr: &mut expr::Expr) {
             x!(lhs, Sub, Num(0) => *sexpr = lhs.take());
             x!(lhs, Mul, Num(1) => *sexpr = lhs.take());
             x!(_, Mul, Num(0) => *sexpr = Num(0));
-            x!(!(lhs2, Add, Num(n)), Add, Num(n2) => *sexpr = expr::Expr::Binary {
-                op: expr::BinaryOp::Add,
-                lhs: Box::new(lhs2.take()),
-                rhs: Box::new(expr::Expr::Num(*n + *n2))
+            x!(!(lhs2, Add, Num(n)), Add, Num(n2) => {
+                *sexpr = expr::Expr::Binary {
+                    op: expr::BinaryOp::Add,
+                    lhs: Box::new(lhs2.take()),
+                    rhs: Box::new(expr::Expr::Num(*n + *n2))
+                };
+                reduce_binops_in(sexpr)
+            });
+            x!(!(lhs2, Add, Num(n)), Sub, Num(n2) => {
+                *sexpr = expr::Expr::Binary {
+                    op: expr::BinaryOp::Add,
+                    lhs: Box::new(lhs2.take()),
+                    rhs: Box::new(expr::Expr::Num(*n - *n2))
+                };
+                reduce_binops_in(sexpr)
             });
-            x!(!(lhs2, Add, Num(n)), Sub, Num(n2) => *sexpr = expr::Expr::Binary {
-                op: expr::BinaryOp::Add,
-                lhs: Box::new(lhs2.take()),
-                rhs: Box::new(expr::Expr::Num(*n - *n2))
+            x!(!(lhs2, Sub, Num(n)), Add, Num(n2) => {
+                *sexpr = expr::Expr::Binary {
+                    op: expr::BinaryOp::Sub,
+                    lhs: Box::new(lhs2.take()),
+                    rhs: Box::new(expr::Expr::Num(*n - *n2))
+                };
+                reduce_binops_in(sexpr)
             });
-            x!(!(lhs2, Sub, Num(n)), Add, Num(n2) => *sexpr = expr::Expr::Binary {
-                op: expr::BinaryOp::Sub,
-                lhs: Box::new(lhs2.take()),
-                rhs: Box::new(expr::Expr::Num(*n - *n2))
+            x!(!(lhs2, Sub, Num(n)), Sub, Num(n2) => {
+                *sexpr = expr::Expr::Binary {
+                    op: expr::BinaryOp::Sub,
+                    lhs: Box::new(lhs2.take()),
+                    rhs: Box::new(expr::Expr::Num(*n + *n2))
+                };
+                reduce_binops_in(sexpr)
             });
             x!(_, Sub, Num(n2) if *n2 < 0 => {
                 *rhs = Box::new(Num(-*n2));
                 *op = Add;
             });
+            x!(_, Add, Num(n2) if *n2 < 0 => {
+                **rhs = Num(-*n2);
+                *op = Sub;
+            });
+            x!(Num(n1), And, Num(n2) => *sexpr = Num(*n1 & *n2));
+            x!(Num(n1), Or, Num(n2) => *sexpr = Num(*n1 | *n2));
+            x!(Num(n1), Xor, Num(n2) => *sexpr = Num(*n1 ^ *n2));
+            x!(!(lhs2, And, Num(n)), And, Num(n2) => {
+                *sexpr = expr::Expr::Binary {
+                    op: expr::BinaryOp::And,
+                    lhs: Box::new(lhs2.take()),
+                    rhs: Box::new(expr::Expr::Num(*n & *n2))
+                };
+                reduce_binops_in(sexpr)
+            });
             x!(Num(1), And, ! (lhs, op, rhs) if op.is_logical() => {
                 *sexpr = expr::Expr::Binary {
                     op: *op,
@@ -146,6 +194,7 @@ fn reduce_binops_in(sexpr: &mut expr::Expr) {
         }
         expr::Expr::Deref { ptr, .. } => reduce_binops_in(ptr.as_mut()),
         expr::Expr::Ref(value) => reduce_binops_in(value.as_mut()),
+        expr::Expr::Table { index, .. } => reduce_binops_in(index.as_mut()),
         expr::Expr::Call { func, args } => {
             reduce_binops_in(func.as_mut());
             for arg in args {
@@ -167,6 +216,11 @@ pub fn reduce_binops(code: &mut mir::MirFunc) {
     ReduceBinOpVisitor.visit_block(&mut code.code)
 }
 
+/// The bits a value of the given size covers
+fn low_mask(size: ty::Size) -> i64 {
+    (1 << (size.byte_count() * 8)) - 1
+}
+
 pub fn reduce_binops_lir(blocks: &mut [lir::LirNode]) {
     for block in blocks {
         for stmt in &mut block.code {
@@ -174,11 +228,18 @@ pub fn reduce_binops_lir(blocks: &mut [lir::LirNode]) {
                 lir::Lir::Assign { src, dst } => {
                     reduce_binops_in(src);
                     reduce_binops_in(dst);
+                    // Masking the value to the width of the store changes nothing
+                    if let expr::Expr::Deref { size, .. } = dst &&
+                        let expr::Expr::Binary { op: expr::BinaryOp::And, lhs, rhs } = src &&
+                        let expr::Expr::Num(mask) = rhs.as_ref() &&
+                        size.byte_count() < 8 && *mask & low_mask(*size) == low_mask(*size) {
+                        *src = lhs.take();
+                    }
                 }
-                lir::Lir::Return(expr) => reduce_binops_in(expr),
+                lir::Lir::Return(ret) => ret.iter_mut().for_each(reduce_binops_in),
                 lir::Lir::Do(expr) => reduce_binops_in(expr),
                 lir::Lir::Branch { cond: Some(cond), .. } => reduce_binops_in(cond),
-                lir::Lir::Branch { .. } | lir::Lir::Label(_) => {}
+                lir::Lir::Branch { .. } | lir::Lir::Label(_) | lir::Lir::Phi { .. } => {}
             }
         }
     }
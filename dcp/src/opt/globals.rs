@@ -0,0 +1,103 @@
+use crate::{mir::{MirVisitorMut, MirFunc, MVMAction}, expr, GlobalDecl, Module};
+
+struct GlobalVisitor<'a> {
+    globals: &'a [GlobalDecl]
+}
+
+impl<'a> GlobalVisitor<'a> {
+    /// The global in memory that addr is within
+    fn find(&self, addr: i64) -> Option<(&GlobalDecl, u64)> {
+        let addr = u64::try_from(addr).ok()?;
+        self.globals.iter().find_map(|global| {
+            let offset = addr.checked_sub(global.addr?)?;
+            (offset < global.byte_count()).then_some((global, offset))
+        })
+    }
+
+    /// addr as the address of the global it is within, plus how far into it
+    fn address(&self, addr: i64) -> Option<expr::Expr> {
+        let (global, offset) = self.find(addr)?;
+        let reference = expr::Expr::Ref(Box::new(expr::Expr::Name(global.name.clone())));
+        Some(match offset {
+            0 => reference,
+            _ => expr::Expr::Binary {
+                op: expr::BinaryOp::Add,
+                lhs: Box::new(reference),
+                rhs: Box::new(expr::Expr::Num(offset as i64))
+            }
+        })
+    }
+
+    /// As with strings, only constants standing as a value in their own right are taken for addresses
+    fn value(&self, expr: &mut expr::Expr) {
+        if let expr::Expr::Num(addr) = expr && let Some(address) = self.address(*addr) {
+            *expr = address;
+        } else {
+            self.expr(expr);
+        }
+    }
+
+    /// Constants added together to make a pointer, such as the start of an array being indexed
+    fn ptr(&self, ptr: &mut expr::Expr) {
+        match ptr {
+            expr::Expr::Binary { op: expr::BinaryOp::Add, lhs, rhs } => {
+                self.ptr(lhs);
+                self.ptr(rhs);
+            }
+            _ => self.value(ptr)
+        }
+    }
+
+    fn expr(&self, expr: &mut expr::Expr) {
+        // A whole global read or written at once is the global itself
+        if let expr::Expr::Deref { ptr, size } = expr && let expr::Expr::Num(addr) = ptr.as_ref() &&
+            let Some((global, 0)) = self.find(*addr) && global.len.is_none() && global.ty.size() == Some(*size) {
+            *expr = expr::Expr::Name(global.name.clone());
+            return;
+        }
+
+        match expr {
+            expr::Expr::Call { func, args } => {
+                self.expr(func);
+                for arg in args {
+                    self.value(arg);
+                }
+            }
+            expr::Expr::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            expr::Expr::Unary { expr, .. } => self.expr(expr),
+            expr::Expr::Deref { ptr, .. } => self.ptr(ptr),
+            expr::Expr::Ref(value) => self.expr(value),
+            expr::Expr::Table { index, .. } => self.expr(index),
+            expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) |
+            expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {}
+        }
+    }
+}
+
+impl<'a> MirVisitorMut for GlobalVisitor<'a> {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        self.expr(expr);
+    }
+
+    fn visit_assign(&mut self, dst: &mut expr::Expr, src: &mut expr::Expr) -> MVMAction {
+        self.expr(dst);
+        self.value(src);
+        MVMAction::Keep
+    }
+
+    fn visit_return(&mut self, ret: &mut [expr::Expr]) -> MVMAction {
+        for expr in ret {
+            self.value(expr);
+        }
+        MVMAction::Keep
+    }
+}
+
+/// Replaces constant addresses within the globals of a binary's data with references to them,
+/// and reads and writes of a whole global with its name
+pub fn insert_globals(func: &mut MirFunc, module: &Module) {
+    GlobalVisitor { globals: &module.globals }.visit_block(&mut func.code)
+}
@@ -21,6 +21,18 @@ fn insert_loops_without(code: &mut Vec<mir::Mir>, ignore: &mut HashSet<lir::Labe
                 insert_loops_without(code, ignore);
                 insert_loops_without(inc, ignore);
             }
+            mir::Mir::Switch { cases, default, .. } => {
+                for (_, code) in cases {
+                    insert_loops_without(code, ignore);
+                }
+                insert_loops_without(default, ignore);
+            }
+            mir::Mir::Try { body, handlers } => {
+                insert_loops_without(body, ignore);
+                for (_, code) in handlers {
+                    insert_loops_without(code, ignore);
+                }
+            }
             mir::Mir::Assign { .. } | mir::Mir::Break | mir::Mir::Continue | mir::Mir::Return(_) |
             mir::Mir::Branch { .. } | mir::Mir::Label(_) | mir::Mir::Do(_) => {}
         }
@@ -251,6 +263,20 @@ fn final_continues_with(code: &mut Vec<mir::Mir>, mut is_end: bool) {
                 final_continues_with(false_then, is_end);
                 is_end = false;
             }
+            mir::Mir::Switch { cases, default, .. } => {
+                for (_, code) in cases {
+                    final_continues_with(code, is_end);
+                }
+                final_continues_with(default, is_end);
+                is_end = false;
+            }
+            mir::Mir::Try { body, handlers } => {
+                final_continues_with(body, is_end);
+                for (_, code) in handlers {
+                    final_continues_with(code, is_end);
+                }
+                is_end = false;
+            }
             mir::Mir::Assign { .. } | mir::Mir::Branch { .. } | mir::Mir::Return(_) |
             mir::Mir::Label(_) | mir::Mir::Break | mir::Mir::Continue | mir::Mir::Do(_) => {
                 is_end = false;
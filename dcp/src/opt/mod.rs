@@ -15,3 +15,24 @@ pub use control::*;
 
 mod expr;
 pub use expr::*;
+
+mod types;
+pub use types::*;
+
+mod switches;
+pub use switches::*;
+
+mod names;
+pub use names::*;
+
+mod strings;
+pub use strings::*;
+
+mod tables;
+pub use tables::*;
+
+mod trys;
+pub use trys::*;
+
+mod globals;
+pub use globals::*;
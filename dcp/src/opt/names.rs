@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use crate::{mir::{MirVisitorMut, MirFunc}, expr, Module};
+
+/// The new name for name, which may be a version of a named local made by SSA construction
+fn renamed(names: &HashMap<String, String>, name: &str) -> Option<String> {
+    if let Some(new) = names.get(name) {
+        return Some(new.clone());
+    }
+
+    let (base, version) = name.split_once('_')?;
+    names.get(base).map(|new| format!("{new}_{version}"))
+}
+
+fn rename_expr(names: &HashMap<String, String>, expr: &mut expr::Expr) {
+    match expr {
+        expr::Expr::Name(name) => if let Some(new) = renamed(names, name) {
+            *name = new;
+        },
+        expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {}
+        expr::Expr::Binary { lhs, rhs, .. } => {
+            rename_expr(names, lhs);
+            rename_expr(names, rhs);
+        }
+        expr::Expr::Unary { expr, .. } => rename_expr(names, expr),
+        expr::Expr::Deref { ptr, .. } => rename_expr(names, ptr),
+        expr::Expr::Ref(value) => rename_expr(names, value),
+        expr::Expr::Table { index, .. } => rename_expr(names, index),
+        expr::Expr::Call { func, args } => {
+            rename_expr(names, func);
+            for arg in args {
+                rename_expr(names, arg);
+            }
+        }
+    }
+}
+
+struct RenameVisitor<'a> {
+    names: &'a HashMap<String, String>
+}
+
+impl<'a> MirVisitorMut for RenameVisitor<'a> {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        rename_expr(self.names, expr);
+    }
+}
+
+/// Gives locals the names debug info has for them
+pub fn name_locals(func: &mut MirFunc, module: &Module) {
+    let Some(decl) = module.find_decl(func.funcid) else {
+        return;
+    };
+    if decl.local_names.is_empty() {
+        return;
+    }
+
+    RenameVisitor { names: &decl.local_names }.visit_block(&mut func.code);
+
+    func.types = std::mem::take(&mut func.types).into_iter()
+        .map(|(name, ty)| (renamed(&decl.local_names, &name).unwrap_or(name), ty))
+        .collect();
+}
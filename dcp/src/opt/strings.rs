@@ -0,0 +1,111 @@
+use crate::{mir::{MirVisitorMut, MirFunc, MVMAction}, expr, Image, Module};
+
+/// Longest string taken to be a literal
+const MAX_LEN: usize = 4096;
+
+/// Whether text reads as a string, rather than binary data which happens to be valid UTF-8
+fn is_text(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| !c.is_control() || matches!(c, '\n' | '\t' | '\r'))
+}
+
+/// The NUL-terminated string at addr, if one starts there
+fn c_string(image: &Image, addr: u64) -> Option<String> {
+    // Anything but a NUL just before means addr is somewhere in the middle
+    if addr.checked_sub(1).and_then(|prev| image.read(prev, 1)).is_some_and(|prev| prev[0] != 0) {
+        return None;
+    }
+
+    let bytes = image.read_from(addr)?;
+    let len = bytes.iter().take(MAX_LEN).position(|x| *x == 0)?;
+    let text = std::str::from_utf8(&bytes[..len]).ok()?;
+    is_text(text).then(|| text.to_string())
+}
+
+/// The string of len bytes at addr, for strings passed along with their length
+fn sized_string(image: &Image, addr: i64, len: i64) -> Option<String> {
+    if addr <= 0 || len <= 0 || len as usize > MAX_LEN {
+        return None;
+    }
+
+    let text = std::str::from_utf8(image.read(addr as u64, len as usize)?).ok()?;
+    is_text(text).then(|| text.to_string())
+}
+
+struct StringVisitor<'a> {
+    image: &'a Image
+}
+
+impl<'a> StringVisitor<'a> {
+    /// Constants used in arithmetic or compared against are left alone, so only those standing
+    /// as a value in their own right (assigned, passed or returned) are taken for addresses.
+    /// Small numbers land in the image often enough that outside of call arguments, the image
+    /// has to say the number is an address too
+    fn value(&self, expr: &mut expr::Expr, arg: bool) {
+        if let expr::Expr::Num(addr) = expr && *addr > 0 && (arg || self.image.is_target(*addr as u64)) &&
+            let Some(text) = c_string(self.image, *addr as u64) {
+            *expr = expr::Expr::Str(text);
+        } else {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&self, expr: &mut expr::Expr) {
+        match expr {
+            // Builtins take numbers, such as the lanes of a vector, rather than addresses
+            expr::Expr::Call { func, args } if matches!(func.as_ref(), expr::Expr::BuiltIn(_)) => {
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            expr::Expr::Call { func, args } => {
+                self.expr(func);
+
+                let mut a = 0;
+                while a < args.len() {
+                    if let expr::Expr::Num(addr) = args[a] && let Some(expr::Expr::Num(len)) = args.get(a + 1) && let Some(text) = sized_string(self.image, addr, *len) {
+                        args[a] = expr::Expr::Str(text);
+                        a += 2;
+                    } else {
+                        self.value(&mut args[a], true);
+                        a += 1;
+                    }
+                }
+            }
+            expr::Expr::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            expr::Expr::Unary { expr, .. } => self.expr(expr),
+            expr::Expr::Deref { ptr, .. } => self.expr(ptr),
+            expr::Expr::Ref(value) => self.expr(value),
+            expr::Expr::Table { index, .. } => self.expr(index),
+            expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) |
+            expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {}
+        }
+    }
+}
+
+impl<'a> MirVisitorMut for StringVisitor<'a> {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        self.expr(expr);
+    }
+
+    fn visit_assign(&mut self, dst: &mut expr::Expr, src: &mut expr::Expr) -> MVMAction {
+        self.expr(dst);
+        self.value(src, false);
+        MVMAction::Keep
+    }
+
+    fn visit_return(&mut self, ret: &mut [expr::Expr]) -> MVMAction {
+        for expr in ret {
+            self.value(expr, false);
+        }
+        MVMAction::Keep
+    }
+}
+
+/// Replaces constant addresses of strings in the image, either NUL-terminated or passed
+/// along with their length, with string literals
+pub fn insert_strings(func: &mut MirFunc, module: &Module) {
+    StringVisitor { image: &module.image }.visit_block(&mut func.code)
+}
@@ -0,0 +1,131 @@
+use crate::{mir::{Mir, MirVisitorMut, MirFunc}, expr};
+
+/// Chains shorter than this read better as ifs
+const MIN_CASES: usize = 3;
+
+/// The values cond tests scrutinee against, for `x == k` and ors of them
+fn case_values(cond: &expr::Expr) -> Option<(&expr::Expr, Vec<i64>)> {
+    match cond {
+        expr::Expr::Binary { op: expr::BinaryOp::Eq, lhs, rhs } => match (lhs.as_ref(), rhs.as_ref()) {
+            (expr::Expr::Num(_), expr::Expr::Num(_)) => None,
+            (x, expr::Expr::Num(n)) | (expr::Expr::Num(n), x) if !x.has_side_effects() => Some((x, vec![*n])),
+            _ => None
+        },
+        expr::Expr::Binary { op: expr::BinaryOp::Or, lhs, rhs } => {
+            let (x, mut values) = case_values(lhs)?;
+            let (y, more) = case_values(rhs)?;
+            if x != y {
+                return None;
+            }
+
+            values.extend(more);
+            Some((x, values))
+        }
+        _ => None
+    }
+}
+
+/// A case, dropping values an earlier case already takes
+fn push_case(cases: &mut Vec<(Vec<i64>, Vec<Mir>)>, values: Vec<i64>, code: Vec<Mir>) {
+    let mut kept = vec![];
+    for value in values {
+        if !kept.contains(&value) && !cases.iter().any(|(taken, _)| taken.contains(&value)) {
+            kept.push(value);
+        }
+    }
+
+    if !kept.is_empty() {
+        cases.push((kept, code));
+    }
+}
+
+/// The value cond tests scrutinee is not
+fn excluded_value(cond: &expr::Expr) -> Option<(&expr::Expr, i64)> {
+    match cond {
+        expr::Expr::Binary { op: expr::BinaryOp::Ne, lhs, rhs } => match (lhs.as_ref(), rhs.as_ref()) {
+            (expr::Expr::Num(_), expr::Expr::Num(_)) => None,
+            (x, expr::Expr::Num(n)) | (expr::Expr::Num(n), x) if !x.has_side_effects() => Some((x, *n)),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+struct Case<'a> {
+    scrutinee: &'a expr::Expr,
+    values: Vec<i64>,
+    then: &'a [Mir],
+    otherwise: &'a [Mir]
+}
+
+/// The case at the start of code. Besides `if x == k {..} else {..}`,
+/// this takes an if that returns early as having the rest of the block for its else
+fn peel_case(code: &[Mir]) -> Option<Case<'_>> {
+    let Some(Mir::If { cond, true_then, false_then }) = code.first() else {
+        return None;
+    };
+
+    let (then, otherwise) = if code.len() == 1 {
+        (true_then.as_slice(), false_then.as_slice())
+    } else if false_then.is_empty() && true_then.last().is_some_and(Mir::terminating) {
+        (true_then.as_slice(), &code[1..])
+    } else {
+        return None;
+    };
+
+    if let Some((scrutinee, values)) = case_values(cond) {
+        Some(Case { scrutinee, values, then, otherwise })
+    } else if let Some((scrutinee, value)) = excluded_value(cond) {
+        Some(Case { scrutinee, values: vec![value], then: otherwise, otherwise: then })
+    } else {
+        None
+    }
+}
+
+/// A switch made from the chain of cases at the start of code, to replace all of code.
+/// When every case ends in a return, break or the like, whatever is left goes after the switch rather than in its default
+fn switch_from_chain(code: &[Mir]) -> Option<Vec<Mir>> {
+    let scrutinee = peel_case(code)?.scrutinee;
+
+    let mut cases = vec![];
+    let mut count = 0;
+    let mut rest = code;
+    while let Some(case) = peel_case(rest) && case.scrutinee == scrutinee {
+        push_case(&mut cases, case.values, case.then.to_vec());
+        rest = case.otherwise;
+        count += 1;
+    }
+
+    if count < MIN_CASES {
+        return None;
+    }
+
+    let scrutinee = scrutinee.clone();
+    if cases.iter().all(|(_, code)| code.last().is_some_and(Mir::terminating)) {
+        let mut new = vec![Mir::Switch { scrutinee, cases, default: vec![] }];
+        new.extend(rest.iter().cloned());
+        Some(new)
+    } else {
+        Some(vec![Mir::Switch { scrutinee, cases, default: rest.to_vec() }])
+    }
+}
+
+struct SwitchVisitor;
+
+impl MirVisitorMut for SwitchVisitor {
+    fn pre_block_visit(&mut self, code: &mut Vec<Mir>) {
+        let mut i = 0;
+        while i < code.len() {
+            if let Some(new) = switch_from_chain(&code[i..]) {
+                code.truncate(i);
+                code.extend(new);
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Turns chains of comparisons of one value against constants into switches
+pub fn insert_switches(code: &mut MirFunc) {
+    SwitchVisitor.visit_block(&mut code.code)
+}
@@ -0,0 +1,53 @@
+use crate::{mir::{MirVisitorMut, MirFunc, MVMAction}, expr, Module};
+
+fn resolve_in(tables: &[Vec<Option<expr::FuncId>>], expr: &mut expr::Expr) {
+    match expr {
+        expr::Expr::Table { table, index, .. } => {
+            resolve_in(tables, index);
+
+            if let expr::Expr::Num(idx) = index.as_ref() && let Some(Some(func)) = tables.get(*table as usize).and_then(|x| x.get(*idx as usize)) {
+                *expr = expr::Expr::Func(*func);
+            }
+        }
+        expr::Expr::Name(_) | expr::Expr::Num(_) | expr::Expr::Float(_) | expr::Expr::Str(_) | expr::Expr::Bool(_) | expr::Expr::Func(_) | expr::Expr::BuiltIn(_) => {}
+        expr::Expr::Binary { lhs, rhs, .. } => {
+            resolve_in(tables, lhs);
+            resolve_in(tables, rhs);
+        }
+        expr::Expr::Unary { expr, .. } => resolve_in(tables, expr),
+        expr::Expr::Deref { ptr, .. } => resolve_in(tables, ptr),
+        expr::Expr::Ref(value) => resolve_in(tables, value),
+        expr::Expr::Call { func, args } => {
+            resolve_in(tables, func);
+            for arg in args {
+                resolve_in(tables, arg);
+            }
+        }
+    }
+}
+
+struct TableVisitor<'a> {
+    tables: &'a [Vec<Option<expr::FuncId>>]
+}
+
+impl<'a> MirVisitorMut for TableVisitor<'a> {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        resolve_in(self.tables, expr);
+    }
+
+    fn visit_assign(&mut self, dst: &mut expr::Expr, src: &mut expr::Expr) -> MVMAction {
+        // An entry being set stays an entry
+        match dst {
+            expr::Expr::Table { index, .. } => resolve_in(self.tables, index),
+            _ => resolve_in(self.tables, dst)
+        }
+        resolve_in(self.tables, src);
+        MVMAction::Keep
+    }
+}
+
+/// Replaces table entries at constant indices with the functions the element segments put there,
+/// which turns indirect calls through them into direct ones
+pub fn resolve_table_entries(func: &mut MirFunc, module: &Module) {
+    TableVisitor { tables: &module.tables }.visit_block(&mut func.code)
+}
@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+
+use crate::{mir::{self, Mir, MirFunc}, expr, lir};
+
+/// The tag cond tests the exception being handled for, as `catch(tag)`, or None for `catch()`,
+/// and whether the test is negated
+fn catch_test(cond: &expr::Expr) -> Option<(Option<u32>, bool)> {
+    match cond {
+        expr::Expr::Unary { op: expr::UnaryOp::Not, expr } => catch_test(expr).map(|(tag, negated)| (tag, !negated)),
+        expr::Expr::Call { func, args } if **func == expr::Expr::BuiltIn(expr::BuiltIn::Catch) => match args.as_slice() {
+            [] => Some((None, false)),
+            [expr::Expr::Num(tag)] => Some((Some(*tag as u32), false)),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+/// code without the labels at its start which nothing branches to
+fn skip_unused_labels<'a>(code: &'a [Mir], used: &HashSet<lir::Label>) -> &'a [Mir] {
+    let start = code.iter().position(|x| !matches!(x, Mir::Label(label) if !used.contains(label))).unwrap_or(code.len());
+    &code[start..]
+}
+
+/// The handlers chosen between by code, which is ifs on `catch(tag)` for each tag caught,
+/// ending in either a catch_all or throwing the exception on
+fn handlers(mut code: &[Mir], used: &HashSet<lir::Label>) -> Vec<(Option<u32>, Vec<Mir>)> {
+    let mut handlers = vec![];
+    loop {
+        let rest = skip_unused_labels(code, used);
+        if let [Mir::If { cond, true_then, false_then }] = rest && let Some((Some(tag), negated)) = catch_test(cond) {
+            let (handler, otherwise) = if negated { (false_then, true_then) } else { (true_then, false_then) };
+            handlers.push((Some(tag), handler.clone()));
+            code = otherwise;
+        } else if let [Mir::Do(expr::Expr::Call { func, .. })] = rest && **func == expr::Expr::BuiltIn(expr::BuiltIn::Rethrow) {
+            return handlers;
+        } else {
+            handlers.push((None, code.to_vec()));
+            return handlers;
+        }
+    }
+}
+
+fn insert_trys_in(code: &mut [Mir], used: &HashSet<lir::Label>) {
+    for stmt in code.iter_mut() {
+        if let Mir::If { cond, true_then, false_then } = stmt && let Some((None, negated)) = catch_test(cond) {
+            let (body, dispatch) = if negated { (true_then, false_then) } else { (false_then, true_then) };
+            *stmt = Mir::Try { body: std::mem::take(body), handlers: handlers(dispatch, used) };
+        }
+
+        match stmt {
+            Mir::If { true_then, false_then, .. } => {
+                insert_trys_in(true_then, used);
+                insert_trys_in(false_then, used);
+            }
+            Mir::Loop { code } | Mir::While { code, .. } => insert_trys_in(code, used),
+            Mir::For { inc, code, .. } => {
+                insert_trys_in(inc, used);
+                insert_trys_in(code, used);
+            }
+            Mir::Switch { cases, default, .. } => {
+                for (_, code) in cases {
+                    insert_trys_in(code, used);
+                }
+                insert_trys_in(default, used);
+            }
+            Mir::Try { body, handlers } => {
+                insert_trys_in(body, used);
+                for (_, code) in handlers {
+                    insert_trys_in(code, used);
+                }
+            }
+            Mir::Assign { .. } | Mir::Break | Mir::Continue | Mir::Return(_) |
+            Mir::Branch { .. } | Mir::Label(_) | Mir::Do(_) => {}
+        }
+    }
+}
+
+/// Wasm trys start by testing whether their body threw, with `catch()`, and which handler
+/// takes it if so. This puts those tests back together into trys
+pub fn insert_trys(code: &mut MirFunc) {
+    let used = mir::used_labels(&code.code);
+    insert_trys_in(&mut code.code, &used);
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+
+use crate::{mir::{self, MirVisitor}, expr, ty, Module};
+
+/// What sort of value a variable holds, ordered so the strongest evidence wins when two disagree
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Bool,
+    Int,
+    Float,
+    Ptr,
+    Func,
+    Vector
+}
+
+#[derive(Clone, Default)]
+struct Info {
+    size: Option<ty::Size>,
+    /// Whether size comes from a signature, so narrower uses don't change it
+    exact: bool,
+    signed: Option<bool>,
+    /// Whether it has been used as both signed and unsigned, so neither can be relied on
+    mixed_sign: bool,
+    kind: Option<Kind>,
+    /// What it points to, one variable per size it is accessed as, with the first being its type
+    pointees: Vec<usize>,
+    shape: Option<ty::Shape>
+}
+
+/// Registers are often wider than the values kept in them, so the narrowest size seen is the most telling
+fn narrowest(a: Option<ty::Size>, b: Option<ty::Size>) -> Option<ty::Size> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a.byte_count() < b.byte_count() { a } else { b }),
+        _ => a.or(b)
+    }
+}
+
+/// Union-find over type variables, where each set collects everything the code says about its values
+struct Inference<'a> {
+    module: &'a Module,
+    parent: Vec<usize>,
+    info: Vec<Info>,
+    names: HashMap<String, usize>,
+    results: Vec<usize>
+}
+
+impl<'a> Inference<'a> {
+    fn new_var(&mut self) -> usize {
+        self.parent.push(self.parent.len());
+        self.info.push(Info::default());
+        self.parent.len() - 1
+    }
+
+    fn find(&mut self, var: usize) -> usize {
+        let mut root = var;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        self.parent[var] = root;
+        root
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        if let Some(var) = self.names.get(name) {
+            return *var;
+        }
+
+        let var = self.new_var();
+        self.names.insert(name.to_string(), var);
+        var
+    }
+
+    fn unify(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+
+        self.parent[b] = a;
+        let other = std::mem::take(&mut self.info[b]);
+        let info = &mut self.info[a];
+        info.size = match (info.exact, other.exact) {
+            (true, _) => info.size,
+            (false, true) => other.size,
+            (false, false) => narrowest(info.size, other.size)
+        };
+        info.exact |= other.exact;
+        info.mixed_sign |= other.mixed_sign || info.signed.zip(other.signed).is_some_and(|(a, b)| a != b);
+        info.signed = info.signed.or(other.signed);
+        info.kind = info.kind.max(other.kind);
+        info.shape = info.shape.or(other.shape);
+
+        // Accesses of the same size are the same value, and the rest stay apart
+        for y in other.pointees {
+            let size = self.size_of(y);
+            let root = self.find(a);
+            let pointees = self.info[root].pointees.clone();
+            match pointees.into_iter().find(|x| self.size_of(*x) == size) {
+                Some(x) => self.unify(x, y),
+                None => {
+                    let a = self.find(a);
+                    self.info[a].pointees.push(y);
+                }
+            }
+        }
+    }
+
+    fn size_of(&mut self, var: usize) -> Option<ty::Size> {
+        let var = self.find(var);
+        self.info[var].size
+    }
+
+    fn kind_of(&mut self, var: usize) -> Option<Kind> {
+        let var = self.find(var);
+        self.info[var].kind
+    }
+
+    fn set_kind(&mut self, var: usize, kind: Kind) {
+        let var = self.find(var);
+        self.info[var].kind = self.info[var].kind.max(Some(kind));
+    }
+
+    fn set_size(&mut self, var: usize, size: ty::Size) {
+        let var = self.find(var);
+        if !self.info[var].exact {
+            self.info[var].size = narrowest(self.info[var].size, Some(size));
+        }
+    }
+
+    /// Constrains var to ty, from a signature which gives its size for certain
+    fn constrain_exact(&mut self, var: usize, ty: &ty::Ty) {
+        self.constrain(var, ty);
+        if let Some(size) = ty.size() {
+            let var = self.find(var);
+            self.info[var].size = Some(size);
+            self.info[var].exact = true;
+        }
+    }
+
+    fn set_signed(&mut self, var: usize, signed: bool) {
+        let var = self.find(var);
+        let info = &mut self.info[var];
+        info.mixed_sign |= info.signed.is_some_and(|x| x != signed);
+        info.signed.get_or_insert(signed);
+    }
+
+    fn pointee(&mut self, ptr: usize) -> usize {
+        self.set_kind(ptr, Kind::Ptr);
+
+        let ptr = self.find(ptr);
+        if let Some(pointee) = self.info[ptr].pointees.first() {
+            return *pointee;
+        }
+
+        let pointee = self.new_var();
+        self.info[ptr].pointees.push(pointee);
+        pointee
+    }
+
+    /// What ptr points to when accessed as size. A byte store through a pointer says nothing about an int loaded through it
+    fn pointee_of_size(&mut self, ptr: usize, size: ty::Size) -> usize {
+        self.set_kind(ptr, Kind::Ptr);
+
+        let ptr = self.find(ptr);
+        let pointees = self.info[ptr].pointees.clone();
+        let found = pointees.iter().copied().find(|x| self.size_of(*x) == Some(size))
+            .or_else(|| pointees.iter().copied().find(|x| self.size_of(*x).is_none()));
+        if let Some(pointee) = found {
+            self.set_size(pointee, size);
+            return pointee;
+        }
+
+        let pointee = self.new_var();
+        self.set_size(pointee, size);
+        let ptr = self.find(ptr);
+        self.info[ptr].pointees.push(pointee);
+        pointee
+    }
+
+    /// Whether expr is a load or store narrower than what other is known to hold, which only keeps part of it
+    fn narrower_access(&mut self, expr: &expr::Expr, other: usize) -> bool {
+        matches!(expr, expr::Expr::Deref { size, .. } if self.size_of(other).is_some_and(|x| x.byte_count() > size.byte_count()))
+    }
+
+    /// Unifies the values of two expressions, unless one is only part of the other
+    fn join(&mut self, a: &expr::Expr, a_var: usize, b: &expr::Expr, b_var: usize) {
+        if !self.narrower_access(a, b_var) && !self.narrower_access(b, a_var) {
+            self.unify(a_var, b_var);
+        }
+    }
+
+    fn constrain(&mut self, var: usize, ty: &ty::Ty) {
+        match ty {
+            ty::Ty::Unknown64 | ty::Ty::Unknown32 | ty::Ty::Unknown16 | ty::Ty::Unknown8 => self.set_size(var, ty.size().unwrap()),
+            ty::Ty::Int { size, signed } => {
+                self.set_kind(var, Kind::Int);
+                self.set_size(var, *size);
+                self.set_signed(var, *signed);
+            }
+            ty::Ty::Float { size } => {
+                self.set_kind(var, Kind::Float);
+                self.set_size(var, *size);
+            }
+            ty::Ty::Ptr(inner) => {
+                let pointee = self.pointee(var);
+                self.constrain(pointee, inner);
+            }
+            ty::Ty::Bool => self.set_kind(var, Kind::Bool),
+            ty::Ty::Func => self.set_kind(var, Kind::Func),
+            ty::Ty::Vector(shape) => {
+                self.set_kind(var, Kind::Vector);
+                let var = self.find(var);
+                self.info[var].shape = self.info[var].shape.or(*shape);
+            }
+        }
+    }
+
+    /// Ints put in lanes are truncated to fit, so only floats say anything about what goes in
+    fn lane(&mut self, var: usize, shape: ty::Shape) {
+        if shape.is_float() {
+            self.constrain(var, &shape.lane_ty());
+        }
+    }
+
+    /// The variable for what a SIMD builtin gives, from what it is passed
+    fn simd(&mut self, op: expr::SimdOp, args: &[expr::Expr]) -> usize {
+        let args: Vec<_> = args.iter().map(|arg| self.expr(arg)).collect();
+        let result = self.new_var();
+        let shape = match op {
+            expr::SimdOp::Build(shape) | expr::SimdOp::Splat(shape) => {
+                for arg in &args {
+                    self.lane(*arg, shape);
+                }
+                Some(shape)
+            }
+            expr::SimdOp::ExtractLane(shape) => {
+                self.constrain(args[0], &ty::Ty::Vector(Some(shape)));
+                self.constrain(result, &shape.lane_ty());
+                None
+            }
+            expr::SimdOp::ReplaceLane(shape) => {
+                self.constrain(args[0], &ty::Ty::Vector(Some(shape)));
+                self.lane(args[2], shape);
+                Some(shape)
+            }
+            expr::SimdOp::Shuffle => {
+                self.constrain(args[0], &ty::Ty::Vector(None));
+                self.constrain(args[1], &ty::Ty::Vector(None));
+                None
+            }
+            // Shifts take the amount as a scalar, and comparisons give lanes of all ones or zeros whatever they compare
+            expr::SimdOp::Lanewise(shape, op) => {
+                self.constrain(args[0], &ty::Ty::Vector(Some(shape)));
+                if !matches!(op, expr::BinaryOp::Shl | expr::BinaryOp::Shr | expr::BinaryOp::Asr) {
+                    self.constrain(args[1], &ty::Ty::Vector(Some(shape)));
+                }
+                (op.cmp_signedness().is_none() && !op.is_float_cmp() && !matches!(op, expr::BinaryOp::Eq | expr::BinaryOp::Ne)).then_some(shape)
+            }
+            expr::SimdOp::Load(_) | expr::SimdOp::Store(_) => {
+                self.set_kind(args[0], Kind::Ptr);
+                None
+            }
+            expr::SimdOp::Named(..) => None
+        };
+
+        if op.is_vector() {
+            self.constrain(result, &ty::Ty::Vector(shape));
+        } else if let expr::SimdOp::Named(..) = op {
+            self.constrain(result, &ty::Ty::Int { size: ty::Size::Size32, signed: false });
+        }
+        result
+    }
+
+    fn comparison(&mut self, op: expr::BinaryOp, lhs: &expr::Expr, rhs: &expr::Expr) -> usize {
+        let (lhs, _) = self.operands(lhs, rhs);
+
+        if let Some(signed) = op.cmp_signedness() {
+            self.set_kind(lhs, Kind::Int);
+            self.set_signed(lhs, signed);
+        }
+
+        let result = self.new_var();
+        self.set_kind(result, Kind::Bool);
+        result
+    }
+
+    /// The variables for two operands which are used as the same type, the left one standing for both
+    fn operands(&mut self, lhs: &expr::Expr, rhs: &expr::Expr) -> (usize, usize) {
+        let (l, r) = (self.expr(lhs), self.expr(rhs));
+        self.join(lhs, l, rhs, r);
+        (l, r)
+    }
+
+    /// The variable for the value of expr
+    fn expr(&mut self, expr: &expr::Expr) -> usize {
+        match expr {
+            expr::Expr::Name(name) => self.name(name),
+            // Nothing but 0 and 1 fits in a bool
+            expr::Expr::Num(n) if *n != 0 && *n != 1 => {
+                let var = self.new_var();
+                self.set_kind(var, Kind::Int);
+                var
+            }
+            expr::Expr::Num(_) | expr::Expr::BuiltIn(_) => self.new_var(),
+            expr::Expr::Str(_) => {
+                let var = self.new_var();
+                self.constrain(var, &ty::Ty::Ptr(Box::new(ty::Ty::Int { size: ty::Size::Size8, signed: false })));
+                var
+            }
+            expr::Expr::Float(_) => {
+                let var = self.new_var();
+                self.set_kind(var, Kind::Float);
+                var
+            }
+            expr::Expr::Bool(_) => {
+                let var = self.new_var();
+                self.set_kind(var, Kind::Bool);
+                var
+            }
+            expr::Expr::Func(_) => {
+                let var = self.new_var();
+                self.set_kind(var, Kind::Func);
+                var
+            }
+            expr::Expr::Deref { ptr, size } => {
+                let ptr = self.expr(ptr);
+                self.pointee_of_size(ptr, *size)
+            }
+            expr::Expr::Table { index, .. } => {
+                let index = self.expr(index);
+                self.set_kind(index, Kind::Int);
+                let var = self.new_var();
+                self.set_kind(var, Kind::Func);
+                var
+            }
+            expr::Expr::Ref(value) => {
+                let value = self.expr(value);
+                let ptr = self.new_var();
+                let pointee = self.pointee(ptr);
+                self.unify(pointee, value);
+                ptr
+            }
+            expr::Expr::Call { func, args } => {
+                if let expr::Expr::BuiltIn(expr::BuiltIn::Simd(op)) = func.as_ref() {
+                    return self.simd(*op, args);
+                }
+
+                // Float builtins give a float like the ones they are passed
+                if let expr::Expr::BuiltIn(builtin) = func.as_ref() && builtin.is_float() {
+                    let result = self.new_var();
+                    self.set_kind(result, Kind::Float);
+                    for arg in args {
+                        let arg = self.expr(arg);
+                        self.unify(result, arg);
+                    }
+                    return result;
+                }
+
+                let callee = self.expr(func);
+                self.set_kind(callee, Kind::Func);
+                for arg in args {
+                    self.expr(arg);
+                }
+
+                let result = self.new_var();
+                if let expr::Expr::Func(funcid) = func.as_ref() && let Some(decl) = self.module.find_decl(*funcid) {
+                    if let Some(ty) = decl.results.first() {
+                        self.constrain(result, ty);
+                    }
+                    // Signatures which give types say what the arguments are, for imports as much as anything
+                    for (arg, param) in args.iter().zip(decl.params.iter().flatten()) {
+                        let arg = self.expr(arg);
+                        self.constrain(arg, param);
+                    }
+                }
+                result
+            }
+            expr::Expr::Unary { op, expr } if op.is_cmp() => match expr.as_ref() {
+                expr::Expr::Binary { op: expr::BinaryOp::Cmp, lhs, rhs } => self.comparison(op.cmp_op_to_binaryop(), lhs, rhs),
+                expr::Expr::Binary { op: expr::BinaryOp::FCmp, lhs, rhs } => self.expr(&op.float_cmp(lhs.clone(), rhs.clone())),
+                _ => {
+                    self.expr(expr);
+                    let result = self.new_var();
+                    self.set_kind(result, Kind::Bool);
+                    result
+                }
+            },
+            expr::Expr::Unary { op: expr::UnaryOp::Neg, expr } => self.expr(expr),
+            expr::Expr::Unary { op: expr::UnaryOp::Not, expr } => {
+                self.expr(expr);
+                let result = self.new_var();
+                self.set_kind(result, Kind::Bool);
+                result
+            }
+            expr::Expr::Unary { op, expr } => {
+                let (from, to) = match op {
+                    expr::UnaryOp::SignExtend(size) => (ty::Ty::Int { size: *size, signed: true }, None),
+                    expr::UnaryOp::IntToFloat(size, signed) => (ty::Ty::Int { size: ty::Size::Size64, signed: *signed }, Some(ty::Ty::Float { size: *size })),
+                    expr::UnaryOp::FloatToInt(size, signed) => (ty::Ty::Float { size: ty::Size::Size64 }, Some(ty::Ty::Int { size: *size, signed: *signed })),
+                    expr::UnaryOp::FloatToFloat(size) => (ty::Ty::Float { size: ty::Size::Size64 }, Some(ty::Ty::Float { size: *size })),
+                    expr::UnaryOp::BitsToFloat(size) => (ty::Ty::unknown(*size), Some(ty::Ty::Float { size: *size })),
+                    expr::UnaryOp::FloatToBits(size) => (ty::Ty::Float { size: *size }, Some(ty::Ty::unknown(*size))),
+                    _ => unreachable!()
+                };
+
+                // The operand's size is only known for reinterpretations, so only its kind is taken from the others
+                let operand = self.expr(expr);
+                match (op, from) {
+                    (expr::UnaryOp::BitsToFloat(_) | expr::UnaryOp::FloatToBits(_), from) => self.constrain(operand, &from),
+                    (_, ty::Ty::Float { .. }) => self.set_kind(operand, Kind::Float),
+                    (_, ty::Ty::Int { signed, .. }) => {
+                        self.set_kind(operand, Kind::Int);
+                        self.set_signed(operand, signed);
+                    }
+                    _ => {}
+                }
+
+                let result = self.new_var();
+                match to {
+                    Some(to) => self.constrain(result, &to),
+                    None => {
+                        self.set_kind(result, Kind::Int);
+                        self.set_signed(result, true);
+                    }
+                }
+                result
+            }
+            expr::Expr::Binary { op, lhs, rhs } => match op {
+                _ if op.cmp_signedness().is_some() => self.comparison(*op, lhs, rhs),
+                _ if op.is_float() => {
+                    let (lhs, _) = self.operands(lhs, rhs);
+                    self.set_kind(lhs, Kind::Float);
+                    if op.is_float_cmp() {
+                        let result = self.new_var();
+                        self.set_kind(result, Kind::Bool);
+                        result
+                    } else {
+                        lhs
+                    }
+                }
+                expr::BinaryOp::Eq | expr::BinaryOp::Ne => self.comparison(*op, lhs, rhs),
+                expr::BinaryOp::Cmp | expr::BinaryOp::FCmp => {
+                    let (lhs, _) = self.operands(lhs, rhs);
+                    if *op == expr::BinaryOp::FCmp {
+                        self.set_kind(lhs, Kind::Float);
+                    }
+                    self.new_var()
+                }
+                // Pointer arithmetic has the type of the pointer, when one side is already known to be one. Otherwise the
+                // sum is like the non-constant side, but using it as a pointer doesn't make that side one, since it
+                // could as well be an index into something else
+                expr::BinaryOp::Add | expr::BinaryOp::Sub => {
+                    let (l, r) = (self.expr(lhs), self.expr(rhs));
+                    if self.kind_of(l) == Some(Kind::Ptr) {
+                        return l;
+                    }
+                    if *op == expr::BinaryOp::Add && self.kind_of(r) == Some(Kind::Ptr) {
+                        return r;
+                    }
+
+                    let side = if let expr::Expr::Num(_) = lhs.as_ref() { r } else { l };
+                    let (side, result) = (self.find(side), self.new_var());
+                    let Info { size, signed, kind, .. } = self.info[side].clone();
+                    self.info[result] = Info { size, signed, kind: kind.filter(|x| *x != Kind::Ptr), ..Info::default() };
+                    result
+                }
+                expr::BinaryOp::Shl | expr::BinaryOp::Shr | expr::BinaryOp::Asr => {
+                    let lhs = self.expr(lhs);
+                    self.expr(rhs);
+                    self.set_kind(lhs, Kind::Int);
+                    match op {
+                        expr::BinaryOp::Shr => self.set_signed(lhs, false),
+                        expr::BinaryOp::Asr => self.set_signed(lhs, true),
+                        _ => {}
+                    }
+                    lhs
+                }
+                expr::BinaryOp::Mul | expr::BinaryOp::Div | expr::BinaryOp::DivU | expr::BinaryOp::Rem | expr::BinaryOp::RemU | expr::BinaryOp::Xor => {
+                    let (lhs, _) = self.operands(lhs, rhs);
+                    self.set_kind(lhs, Kind::Int);
+                    match op {
+                        expr::BinaryOp::Div | expr::BinaryOp::Rem => self.set_signed(lhs, true),
+                        expr::BinaryOp::DivU | expr::BinaryOp::RemU => self.set_signed(lhs, false),
+                        _ => {}
+                    }
+                    lhs
+                }
+                _ => self.operands(lhs, rhs).0
+            }
+        }
+    }
+
+    fn resolve(&mut self, var: usize, depth: usize) -> Option<ty::Ty> {
+        let var = self.find(var);
+        let info = self.info[var].clone();
+
+        Some(match info.kind {
+            Some(Kind::Func) => ty::Ty::Func,
+            Some(Kind::Vector) => ty::Ty::Vector(info.shape),
+            // Anything pointed to that we know nothing about is treated as bytes
+            Some(Kind::Ptr) => {
+                let pointee = info.pointees.first().filter(|_| depth < 4).and_then(|x| self.resolve(*x, depth + 1));
+                ty::Ty::Ptr(Box::new(pointee.unwrap_or(ty::Ty::Unknown8)))
+            }
+            Some(Kind::Float) => ty::Ty::Float { size: info.size.unwrap_or(ty::Size::Size64) },
+            Some(Kind::Bool) => ty::Ty::Bool,
+            // Ints used as both signed and unsigned are taken to be unsigned
+            Some(Kind::Int) => ty::Ty::Int { size: info.size?, signed: info.signed.unwrap_or(false) && !info.mixed_sign },
+            None => match info.signed {
+                Some(signed) if !info.mixed_sign => ty::Ty::Int { size: info.size?, signed },
+                _ => ty::Ty::unknown(info.size?)
+            }
+        })
+    }
+}
+
+impl<'a> MirVisitor for Inference<'a> {
+    fn visit_expr(&mut self, expr: &expr::Expr) {
+        self.expr(expr);
+    }
+
+    fn visit_assign(&mut self, dst: &expr::Expr, src: &expr::Expr) {
+        let (dst_var, src_var) = (self.expr(dst), self.expr(src));
+
+        // A store only keeps as much of the value as fits, so unless it is known to be the value's whole width it
+        // says nothing about the value
+        if let expr::Expr::Deref { size, .. } = dst && *size != ty::Size::Size64 && self.size_of(src_var) != Some(*size) {
+            return;
+        }
+        self.join(dst, dst_var, src, src_var);
+    }
+
+    fn visit_return(&mut self, ret: &[expr::Expr]) {
+        for (expr, result) in ret.iter().zip(self.results.clone()) {
+            let var = self.expr(expr);
+            if !self.narrower_access(expr, result) {
+                self.unify(result, var);
+            }
+        }
+    }
+}
+
+/// Works out the types of names, locals and results from how they are used:
+/// sizes from memory accesses, signedness from comparisons and shifts, and pointers from dereferences
+pub fn infer_types(func: &mut mir::MirFunc, module: &Module) {
+    let mut inference = Inference {
+        module,
+        parent: vec![],
+        info: vec![],
+        names: HashMap::new(),
+        results: vec![]
+    };
+
+    // Signatures which give types, as wasm's do, are taken as they are
+    let params = module.find_decl(func.funcid).and_then(|decl| decl.params.as_ref().map(|params| (&decl.args, params)));
+    for result in &func.results {
+        let var = inference.new_var();
+        if params.is_some() {
+            inference.constrain_exact(var, result);
+        } else {
+            inference.constrain(var, result);
+        }
+        inference.results.push(var);
+    }
+
+    if let Some((args, params)) = params {
+        for (arg, param) in args.iter().zip(params) {
+            let var = inference.name(arg);
+            inference.constrain_exact(var, param);
+        }
+    }
+
+    for local in func.stack_frame.locals() {
+        if let Some(size) = ty::Size::from_byte_count(local.size) {
+            let var = inference.name(&local.name);
+            inference.set_size(var, size);
+        }
+    }
+
+    inference.visit_block(&func.code);
+
+    let names: Vec<_> = inference.names.iter().map(|(name, var)| (name.clone(), *var)).collect();
+    for (name, var) in names {
+        if let Some(ty) = inference.resolve(var, 0) {
+            if let Some(local) = func.stack_frame.get_mut_by_name(&name) {
+                local.ty = Some(ty.clone());
+            }
+            func.types.insert(name, ty);
+        }
+    }
+
+    for (result, var) in func.results.iter_mut().zip(inference.results.clone()) {
+        if let Some(ty) = inference.resolve(var, 0) {
+            *result = ty;
+        }
+    }
+}
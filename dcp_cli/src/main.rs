@@ -5,9 +5,19 @@ use std::{fs::File, io::Read};
 use clap::Parser;
 use dcp::pretty;
 
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum Lang {
+    /// The decompiler's own notation, closest to the IR
+    Pseudo,
+    C
+}
+
 #[derive(clap::Parser, Debug)]
 struct Args {
-    path: String
+    path: String,
+    /// Language to write the decompiled code in
+    #[clap(long, value_enum, default_value = "pseudo")]
+    lang: Lang
 }
 
 fn main() {
@@ -41,36 +51,46 @@ fn main() {
 
     let mut mir_func_defs = Vec::new();
 
-    // Add signatures to functions
+    // Add signatures to functions. Results come first, so returns of void functions don't read the result register
+    dcp::dataflow::func_results(&mut module, &defs);
+    dcp::dataflow::insert_func_results(&module, &mut defs);
     dcp::dataflow::func_args(&mut module, &defs);
     dcp::dataflow::insert_func_args(&module, &mut defs);
 
     for mut function in defs.into_iter() {
+        // Names the code can reach in ways its graph doesn't show are treated like globals
+        let mut abi = module.abi.clone();
+        abi.global.extend(function.pinned.iter().cloned());
+
         dcp::dataflow::compress_cfg(&mut function.local_cfg, &mut function.local_lirnodes);
         dcp::dataflow::inline_short_returns(&mut function.local_cfg, &mut function.local_lirnodes);
-        
-        // Eliminate frame pointers
-        // FIXME: fp/sp should not be eliminated entirely, as they are needed upon return
-        for eliminate in &module.abi.eliminate {
-            for loc in dcp::dataflow::ssaify(&function.local_cfg, &mut function.local_lirnodes, eliminate, &module.abi) {
-                dcp::dataflow::elim_ssa_loc(&mut function.local_lirnodes, loc);
-            }
-        }
+        let frame_size = dcp::dataflow::track_stack_pointer(&function.local_cfg, &mut function.local_lirnodes, &abi);
+
+        // Remove callee saved spills, propagate constants, copies and frame pointers, and remove dead code
+        let ssa = dcp::dataflow::to_ssa(&function.local_cfg, &mut function.local_lirnodes, &abi);
+        dcp::dataflow::elim_callee_saved_spills(&function.local_cfg, &mut function.local_lirnodes, &ssa, &abi);
+        dcp::dataflow::simplify_ssa(&mut function.local_lirnodes, &ssa, &abi);
+        dcp::opt::reduce_binops_lir(&mut function.local_lirnodes);
+        dcp::dataflow::from_ssa(&mut function.local_cfg, &mut function.local_lirnodes, ssa, &abi);
         
         // Clean up code
-        // FIXME: This should all probably be in a loop of some sort
-        dcp::dataflow::elim_dead_writes(&function.local_cfg, &mut function.local_lirnodes, &module.abi);
-        dcp::dataflow::inline_single_use_names(&function.local_cfg, &mut function.local_lirnodes, &module.abi);
+        dcp::dataflow::elim_dead_call_results(&function.local_cfg, &mut function.local_lirnodes, &abi);
+        dcp::dataflow::inline_single_use_names(&function.local_cfg, &mut function.local_lirnodes, &abi);
         dcp::opt::reduce_binops_lir(&mut function.local_lirnodes);
 
         // Mem to reg, then cleanup again
-        let stack_frame = dcp::dataflow::mem_to_name(&mut function.local_lirnodes, &module.abi);
-        dcp::dataflow::elim_dead_writes(&function.local_cfg, &mut function.local_lirnodes, &module.abi);
-        dcp::dataflow::inline_single_use_names(&function.local_cfg, &mut function.local_lirnodes, &module.abi);
+        let stack_frame = match frame_size {
+            Some(size) => dcp::dataflow::mem_to_name(&mut function.local_lirnodes, &abi, size),
+            None => dcp::mir::MirStackFrame::new()
+        };
+        dcp::dataflow::elim_dead_writes(&function.local_cfg, &mut function.local_lirnodes, &abi);
+        dcp::dataflow::inline_single_use_names(&function.local_cfg, &mut function.local_lirnodes, &abi);
 
         // Place code down, and get MIR
         let code = dcp::reorder_code(&function.local_cfg, &function.local_cfg.dominators(), function.local_lirnodes);
-        let mut mir = dcp::mir::MirFunc::new(function.funcid, vec![], code, stack_frame);
+        let results = module.find_decl(function.funcid).map(|decl| decl.results.clone()).unwrap_or_default();
+        let mut mir = dcp::mir::MirFunc::new(function.funcid, results, code, stack_frame);
+        dcp::opt::insert_trys(&mut mir);
         
         // Remove redundant jumps (FIXME: Are both really necessary?)
         dcp::opt::compress_control_flow(&mut mir);
@@ -103,12 +123,21 @@ fn main() {
         // Final prettification
         dcp::opt::collapse_cmp(&mut mir);
         dcp::opt::reduce_binops(&mut mir);
+        dcp::opt::insert_switches(&mut mir);
+        dcp::opt::resolve_table_entries(&mut mir, &module);
+        dcp::opt::insert_strings(&mut mir, &module);
+        dcp::opt::insert_globals(&mut mir, &module);
+        dcp::opt::infer_types(&mut mir, &module);
+        dcp::opt::name_locals(&mut mir, &module);
 
         mir_func_defs.push(mir);
     }
 
-    for def in mir_func_defs {
-        let printer = pretty::PrettyPrinter::new(&def, &module);
-        println!("{}", printer);
+    match args.lang {
+        Lang::Pseudo => for def in mir_func_defs {
+            let printer = pretty::PrettyPrinter::new(&def, &module);
+            println!("{}", printer);
+        },
+        Lang::C => print!("{}", dcp::c::CPrinter::new(&mir_func_defs, &module))
     }
 }